@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use meshtastic::protobufs::{FromRadio, PortNum, from_radio, mesh_packet};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::broadcast,
+};
+use tracing::{info, warn};
+
+use crate::bbs::service::Post;
+use crate::config::Config;
+use crate::mesh::service::{State, Status, StatusReceiver};
+
+const SOFTWARE: &str = "meshboard";
+// Destination of our packets, the generic APRS tocall
+const TOCALL: &str = "APRS";
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+// APRS-IS drops clients that say nothing for long
+const KEEPALIVE: Duration = Duration::from_secs(5 * 60);
+// Longest text of an APRS message
+const MAX_MESSAGE_LEN: usize = 67;
+// Least time between two reports of the same mesh node
+const NODE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Symbols, table and code: BBS, and a person for the mesh nodes
+const BBS_SYMBOL: (char, char) = ('/', 'B');
+const NODE_SYMBOL: (char, char) = ('/', '[');
+// Bit of Data::bitfield set by the nodes whose owners let their packets
+// leave the mesh
+const OK_TO_MQTT: u32 = 1;
+
+/// Builds the APRS-IS lines of a callsign
+pub struct Gateway {
+    callsign: String,
+    path: String,
+}
+
+/// `DDMM.mmN/DDDMM.mmE`, with the symbol table and code around the longitude
+fn format_position((lat, lon): (f64, f64), (table, code): (char, char)) -> String {
+    let dm = |value: f64, degrees: usize| {
+        let hundredths = (value.abs() * 6000.0).round() as u64;
+        format!(
+            "{:0degrees$}{:02}.{:02}",
+            hundredths / 6000,
+            hundredths % 6000 / 100,
+            hundredths % 100
+        )
+    };
+    format!(
+        "{}{}{table}{}{}{code}",
+        dm(lat, 2),
+        if lat < 0.0 { 'S' } else { 'N' },
+        dm(lon, 3),
+        if lon < 0.0 { 'W' } else { 'E' },
+    )
+}
+
+/// Printable ASCII, without the chars messages can not have
+fn sanitize(text: &str, max: usize) -> String {
+    text.chars()
+        .map(|c| match c {
+            '|' | '~' | '{' => '-',
+            ' '..='~' => c,
+            _ => '?',
+        })
+        .take(max)
+        .collect()
+}
+
+impl Gateway {
+    pub fn new(callsign: &str, path: &str) -> Self {
+        Self {
+            callsign: callsign.to_uppercase(),
+            path: path.to_string(),
+        }
+    }
+
+    fn header(&self) -> String {
+        match self.path.is_empty() {
+            true => format!("{}>{TOCALL}", self.callsign),
+            false => format!("{}>{TOCALL},{}", self.callsign, self.path),
+        }
+    }
+
+    pub fn login(&self, passcode: i32) -> String {
+        format!(
+            "user {} pass {passcode} vers {SOFTWARE} {}",
+            self.callsign,
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    /// Position of the BBS node, without timestamp
+    pub fn position(&self, position: (f64, f64), comment: &str) -> String {
+        format!(
+            "{}:={}{}",
+            self.header(),
+            format_position(position, BBS_SYMBOL),
+            sanitize(comment, 43)
+        )
+    }
+
+    /// Object report of another node, named after it
+    pub fn object(&self, name: &str, position: (f64, f64), at: DateTime<Utc>) -> String {
+        format!(
+            "{}:;{:<9}*{}{}",
+            self.header(),
+            sanitize(name, 9),
+            at.format("%d%H%Mz"),
+            format_position(position, NODE_SYMBOL)
+        )
+    }
+
+    /// Message to the addressee, e.g. a bulletin to `BLN1MESH`
+    pub fn message(&self, to: &str, text: &str) -> String {
+        format!(
+            "{}::{:<9}:{}",
+            self.header(),
+            sanitize(&to.to_uppercase(), 9),
+            sanitize(text, MAX_MESSAGE_LEN)
+        )
+    }
+}
+
+struct Bridge {
+    gateway: Gateway,
+    config: Config,
+    state: State,
+    // When each mesh node was last reported
+    reported: HashMap<u32, Instant>,
+}
+
+impl Bridge {
+    fn post(&self, post: &Post) -> Option<String> {
+        if !self.config.aprs_channels.contains(&post.channel) {
+            return None;
+        }
+        let text = format!("#{} {}: {}", post.channel, post.author, post.text);
+        Some(self.gateway.message(&self.config.aprs_to, &text))
+    }
+
+    async fn beacon(&self) -> Option<String> {
+        let position = match self.config.aprs_position {
+            Some(position) => position,
+            None => {
+                let state = self.state.read().await;
+                state.get_position_by_node_id(state.my_node_num().ok()?)?
+            }
+        };
+        Some(self.gateway.position(position, "MeshBoard BBS"))
+    }
+
+    async fn node_position(&mut self, from_radio: FromRadio) -> Option<String> {
+        let Some(from_radio::PayloadVariant::Packet(packet)) = from_radio.payload_variant else {
+            return None;
+        };
+        let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+            return None;
+        };
+        if !self.config.aprs_nodes
+            || PortNum::try_from(data.portnum) != Ok(PortNum::PositionApp)
+            || data.bitfield.unwrap_or(0) & OK_TO_MQTT == 0
+        {
+            return None;
+        }
+        let now = Instant::now();
+        if self
+            .reported
+            .get(&packet.from)
+            .is_some_and(|last| now.duration_since(*last) < NODE_INTERVAL)
+        {
+            return None;
+        }
+        let state = self.state.read().await;
+        if state.my_node_num().ok() == Some(packet.from) {
+            return None;
+        }
+        let position = state.get_position_by_node_id(packet.from)?;
+        let name = state.get_short_name_by_node_id(packet.from)?;
+        self.reported.insert(packet.from, now);
+        Some(self.gateway.object(&name, position, Utc::now()))
+    }
+
+    /// Relays until the mesh service is gone, fails when the connection does
+    async fn run(
+        &mut self,
+        stream: TcpStream,
+        status_rx: &mut StatusReceiver,
+        posts_rx: &mut broadcast::Receiver<Post>,
+    ) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let login = self.gateway.login(self.config.aprs_passcode);
+        write.write_all(format!("{login}\r\n").as_bytes()).await?;
+
+        let beacon_every = Duration::from_secs(self.config.aprs_beacon_mins.max(1) * 60);
+        let mut beacon_interval = tokio::time::interval(beacon_every);
+        let mut keepalive_interval = tokio::time::interval(KEEPALIVE);
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => {
+                        if line.starts_with("# logresp") {
+                            info!("APRS-IS: {}", line.trim_start_matches("# "));
+                        }
+                        continue;
+                    }
+                    None => bail!("Disconnected"),
+                },
+                status = status_rx.recv() => match status {
+                    Some(Status::FromRadio(from_radio)) => self.node_position(from_radio).await,
+                    Some(_) => None,
+                    None => return Ok(()),
+                },
+                Ok(post) = posts_rx.recv() => self.post(&post),
+                _ = beacon_interval.tick(), if self.config.aprs_beacon_mins > 0 => self.beacon().await,
+                _ = keepalive_interval.tick() => Some(format!("# {SOFTWARE}")),
+            };
+            if let Some(line) = line {
+                write.write_all(format!("{line}\r\n").as_bytes()).await?;
+            }
+        }
+    }
+}
+
+/// Gates the BBS to APRS-IS as `APRS_CALLSIGN`, reconnecting when the
+/// connection drops: posts of `APRS_CHANNELS` go as messages to `APRS_TO`,
+/// the position of the BBS node is beaconed and, with `APRS_NODES`, the
+/// positions heard on the mesh are reported as objects. Runs until the mesh
+/// service is gone.
+pub async fn run_bridge(
+    config: Config,
+    mut status_rx: StatusReceiver,
+    state: State,
+    mut posts_rx: broadcast::Receiver<Post>,
+) -> Result<()> {
+    if config.aprs_passcode < 0 {
+        bail!(
+            "APRS-IS needs the APRS_PASSCODE of {}",
+            config.aprs_callsign
+        );
+    }
+    let mut bridge = Bridge {
+        gateway: Gateway::new(&config.aprs_callsign, &config.aprs_path),
+        config,
+        state,
+        reported: HashMap::new(),
+    };
+    loop {
+        let server = &bridge.config.aprs_server;
+        let result = match TcpStream::connect(server).await {
+            Ok(stream) => {
+                info!("APRS-IS connected to {server}");
+                bridge.run(stream, &mut status_rx, &mut posts_rx).await
+            }
+            Err(err) => Err(err.into()),
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(err) => warn!("APRS-IS connection error: {err}"),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_gateway() {
+        let gateway = Gateway::new("ea3abc-10", "TCPIP*");
+        assert_eq!(
+            gateway.login(12345),
+            format!(
+                "user EA3ABC-10 pass 12345 vers meshboard {}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(
+            gateway.position((41.5, 2.1), "MeshBoard BBS"),
+            "EA3ABC-10>APRS,TCPIP*:=4130.00N/00206.00EBMeshBoard BBS"
+        );
+        assert_eq!(
+            format_position((-33.8688, -151.2093), NODE_SYMBOL),
+            "3352.13S/15112.56W["
+        );
+        let at = Utc.with_ymd_and_hms(2025, 6, 1, 9, 5, 0).unwrap();
+        assert_eq!(
+            gateway.object("ann", (41.5, 2.1), at),
+            "EA3ABC-10>APRS,TCPIP*:;ann      *010905z4130.00N/00206.00E["
+        );
+        assert_eq!(
+            Gateway::new("EA3ABC", "").message("bln1mesh", "#general ann: hi | ñ"),
+            "EA3ABC>APRS::BLN1MESH :#general ann: hi - ?"
+        );
+        assert_eq!(sanitize(&"x".repeat(100), MAX_MESSAGE_LEN).len(), 67);
+    }
+}
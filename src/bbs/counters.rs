@@ -0,0 +1,266 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::bbs::storage::{Storage, UserId};
+
+// Settings keeping the tally of the last day counted and of the one before,
+// as JSON
+const TODAY: &str = "stats.today";
+const YESTERDAY: &str = "stats.yesterday";
+// Counts kept in memory at most this long before they are written
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// Replaced by the digest of the day before in scheduled texts, e.g.
+/// `daily 00:00 general {stats}`
+pub const PLACEHOLDER: &str = "{stats}";
+
+/// What went on in the board during a day
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tally {
+    // YYYY-MM-DD, local
+    pub date: String,
+    /// Posts per channel
+    pub posts: BTreeMap<String, u32>,
+    /// Users that sent a command
+    pub users: BTreeSet<UserId>,
+    /// Packets heard from the mesh
+    pub packets: u64,
+    /// Reply packets acked by their node, and out of retries
+    pub acked: u32,
+    pub failed: u32,
+    /// Texts that could not be answered
+    #[serde(default)]
+    pub errors: u32,
+}
+
+impl Tally {
+    pub fn new(date: NaiveDate) -> Self {
+        Self {
+            date: date.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Percent of the reply packets that were acked, None before any
+    /// was acked or failed
+    pub fn ack_rate(&self) -> Option<u32> {
+        let ended = self.acked + self.failed;
+        (ended > 0).then(|| self.acked * 100 / ended)
+    }
+}
+
+impl fmt::Display for Tally {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} posts, {} users, {} packets, ",
+            self.posts.values().sum::<u32>(),
+            self.users.len(),
+            self.packets
+        )?;
+        match self.ack_rate() {
+            Some(rate) => write!(f, "{rate}% acked")?,
+            None => write!(f, "no acks")?,
+        }
+        if self.errors > 0 {
+            write!(f, ", {} errors", self.errors)?;
+        }
+        let channels: Vec<String> = self
+            .posts
+            .iter()
+            .map(|(channel, posts)| format!("{channel} {posts}"))
+            .collect();
+        if !channels.is_empty() {
+            write!(f, "\n{}", channels.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn load(storage: &Storage, key: &str) -> Result<Option<Tally>> {
+    match storage.get_setting(key)? {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => Ok(None),
+    }
+}
+
+/// The tally of the day, empty before anything is counted
+pub fn today(storage: &Storage, date: NaiveDate) -> Result<Tally> {
+    Ok(load(storage, TODAY)?
+        .filter(|tally| tally.date == date.to_string())
+        .unwrap_or_else(|| Tally::new(date)))
+}
+
+/// The tally of the day before, empty if nothing was counted then
+pub fn yesterday(storage: &Storage, date: NaiveDate) -> Result<Tally> {
+    let Some(day_before) = date.pred_opt() else {
+        return Ok(Tally::new(date));
+    };
+    // Nothing counted yet today leaves the day before as the last one
+    for key in [TODAY, YESTERDAY] {
+        if let Some(tally) = load(storage, key)?
+            && tally.date == day_before.to_string()
+        {
+            return Ok(tally);
+        }
+    }
+    Ok(Tally::new(day_before))
+}
+
+/// Writes the tally of a day, the one of the last day counted becomes the
+/// day before
+fn store(storage: &Storage, tally: &Tally) -> Result<()> {
+    if let Some(last) = load(storage, TODAY)?
+        && last.date != tally.date
+    {
+        storage.set_setting(YESTERDAY, &serde_json::to_string(&last)?)?;
+    }
+    storage.set_setting(TODAY, &serde_json::to_string(tally)?)
+}
+
+/// Updates the tally of the day in the storage
+pub fn count(storage: &Storage, date: NaiveDate, f: impl FnOnce(&mut Tally)) -> Result<()> {
+    let mut tally = today(storage, date)?;
+    f(&mut tally);
+    store(storage, &tally)
+}
+
+/// The tally of the day kept in memory, so counting costs no write, and
+/// written to the storage every minute and when the day changes
+#[derive(Debug)]
+pub struct Counter {
+    tally: Option<Tally>,
+    // Counted since the last write
+    dirty: bool,
+    flushed: Instant,
+}
+
+impl Counter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            tally: None,
+            dirty: false,
+            flushed: now,
+        }
+    }
+
+    /// Updates the tally of the day, a failed write keeps it in memory
+    pub fn count(
+        &mut self,
+        storage: &Storage,
+        date: NaiveDate,
+        now: Instant,
+        f: impl FnOnce(&mut Tally),
+    ) -> Result<()> {
+        if self
+            .tally
+            .as_ref()
+            .is_some_and(|tally| tally.date != date.to_string())
+        {
+            self.flush(storage, now)?;
+            self.tally = None;
+        }
+        if self.tally.is_none() {
+            self.tally = Some(today(storage, date)?);
+        }
+        if let Some(tally) = &mut self.tally {
+            f(tally);
+            self.dirty = true;
+        }
+        if now.duration_since(self.flushed) >= FLUSH_INTERVAL {
+            self.flush(storage, now)?;
+        }
+        Ok(())
+    }
+
+    /// Writes what was counted since the last write
+    pub fn flush(&mut self, storage: &Storage, now: Instant) -> Result<()> {
+        self.flushed = now;
+        if let Some(tally) = &self.tally
+            && self.dirty
+        {
+            store(storage, tally)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// The tally of the day, with what is not written yet
+    pub fn today(&self, storage: &Storage, date: NaiveDate) -> Result<Tally> {
+        match &self.tally {
+            Some(tally) if tally.date == date.to_string() => Ok(tally.clone()),
+            _ => today(storage, date),
+        }
+    }
+
+    /// The tally of the day before, with what is not written yet
+    pub fn yesterday(&self, storage: &Storage, date: NaiveDate) -> Result<Tally> {
+        match (&self.tally, date.pred_opt()) {
+            (Some(tally), Some(day_before)) if tally.date == day_before.to_string() => {
+                Ok(tally.clone())
+            }
+            _ => yesterday(storage, date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counters() -> Result<()> {
+        let s = Storage::memory();
+        let day = |d| NaiveDate::from_ymd_opt(2025, 6, d).unwrap();
+        assert_eq!(today(&s, day(1))?, Tally::new(day(1)));
+        assert_eq!(
+            Tally::new(day(1)).to_string(),
+            "0 posts, 0 users, 0 packets, no acks"
+        );
+
+        count(&s, day(1), |tally| {
+            *tally.posts.entry("general".into()).or_default() += 2;
+            tally.users.insert(1);
+            tally.packets += 10;
+            tally.acked += 3;
+            tally.failed += 1;
+        })?;
+        count(&s, day(1), |tally| {
+            tally.users.insert(1);
+            tally.errors += 1;
+        })?;
+        let first = today(&s, day(1))?;
+        assert_eq!(
+            first.to_string(),
+            "2 posts, 1 users, 10 packets, 75% acked, 1 errors\ngeneral 2"
+        );
+        assert_eq!(yesterday(&s, day(2))?, first);
+
+        count(&s, day(2), |tally| tally.packets += 1)?;
+        assert_eq!(today(&s, day(2))?.packets, 1);
+        assert_eq!(yesterday(&s, day(2))?, first);
+        assert_eq!(yesterday(&s, day(4))?, Tally::new(day(3)));
+
+        // Written once a minute, read with what is not written yet
+        let now = Instant::now();
+        let mut counter = Counter::new(now);
+        counter.count(&s, day(2), now, |tally| tally.packets += 1)?;
+        assert_eq!(today(&s, day(2))?.packets, 1);
+        assert_eq!(counter.today(&s, day(2))?.packets, 2);
+        counter.count(&s, day(2), now + FLUSH_INTERVAL, |tally| tally.packets += 1)?;
+        assert_eq!(today(&s, day(2))?.packets, 3);
+        counter.count(&s, day(3), now + FLUSH_INTERVAL, |tally| tally.packets += 1)?;
+        assert_eq!(counter.yesterday(&s, day(3))?.packets, 3);
+        assert_eq!(counter.today(&s, day(3))?.packets, 1);
+        counter.flush(&s, now + FLUSH_INTERVAL)?;
+        assert_eq!(today(&s, day(3))?.packets, 1);
+        assert_eq!(yesterday(&s, day(3))?.packets, 3);
+        Ok(())
+    }
+}
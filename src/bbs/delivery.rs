@@ -0,0 +1,307 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Texts to each node kept for its round trips
+const ROUND_TRIPS: usize = 50;
+/// Nodes whose last reply batch is kept, the oldest goes first
+const BATCHES: usize = 256;
+/// Upper bounds of the round trip buckets, in seconds, the last bucket is
+/// open
+pub const BUCKETS: [u64; 4] = [5, 15, 30, 60];
+
+/// What became of a packet sent to a user
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery {
+    /// Waiting its turn in the radio queue
+    Queued,
+    Sent,
+    /// A neighbour rebroadcast it, the destination did not ack it yet
+    Relayed,
+    Delivered,
+    Retrying(u32),
+    /// Routing error reported by the mesh, it is retried
+    Error(String),
+    /// Out of retries
+    Failed,
+}
+
+impl Delivery {
+    pub fn is_pending(&self) -> bool {
+        !matches!(self, Delivery::Delivered | Delivery::Failed)
+    }
+}
+
+struct Packet {
+    // Index of the reply it is a chunk of
+    reply: usize,
+    text: String,
+    // Known once the radio sends it
+    id: Option<u32>,
+    state: Delivery,
+}
+
+/// Replies to the last command of a user, as the packets they went out in
+pub struct Batch {
+    pub ts: u64,
+    pub replies: Vec<String>,
+    packets: Vec<Packet>,
+}
+
+impl Batch {
+    /// Packets (delivered, pending, failed)
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let count = |f: fn(&Delivery) -> bool| {
+            self.packets
+                .iter()
+                .filter(|packet| f(&packet.state))
+                .count()
+        };
+        (
+            count(|state| *state == Delivery::Delivered),
+            count(Delivery::is_pending),
+            count(|state| *state == Delivery::Failed),
+        )
+    }
+
+    /// Replies with a packet that ran out of retries
+    pub fn failed(&self) -> Vec<String> {
+        self.replies
+            .iter()
+            .enumerate()
+            .filter(|(n, _)| {
+                self.packets
+                    .iter()
+                    .any(|packet| packet.reply == *n && packet.state == Delivery::Failed)
+            })
+            .map(|(_, reply)| reply.clone())
+            .collect()
+    }
+}
+
+/// Delivery of the last reply batch of each node
+#[derive(Default)]
+pub struct Deliveries(HashMap<u32, Batch>);
+
+impl Deliveries {
+    /// Tracks the replies to the node, each with the texts of the packets it
+    /// is sent in
+    pub fn replied(&mut self, node: u32, ts: u64, replies: Vec<(String, Vec<String>)>) {
+        let mut batch = Batch {
+            ts,
+            replies: Vec::new(),
+            packets: Vec::new(),
+        };
+        for (n, (reply, chunks)) in replies.into_iter().enumerate() {
+            batch.replies.push(reply);
+            batch.packets.extend(chunks.into_iter().map(|text| Packet {
+                reply: n,
+                text,
+                id: None,
+                state: Delivery::Queued,
+            }));
+        }
+        if self.0.len() >= BATCHES
+            && !self.0.contains_key(&node)
+            && let Some(oldest) = self
+                .0
+                .iter()
+                .min_by_key(|(_, batch)| batch.ts)
+                .map(|(node, _)| *node)
+        {
+            self.0.remove(&oldest);
+        }
+        self.0.insert(node, batch);
+    }
+
+    /// A packet with `text` went out to the node, false if it is not part of
+    /// its batch
+    pub fn sent(&mut self, node: u32, id: u32, text: &str) -> bool {
+        let Some(batch) = self.0.get_mut(&node) else {
+            return false;
+        };
+        let Some(packet) = batch
+            .packets
+            .iter_mut()
+            .find(|packet| packet.id.is_none() && packet.text == text)
+        else {
+            return false;
+        };
+        packet.id = Some(id);
+        packet.state = Delivery::Sent;
+        true
+    }
+
+    /// Updates the packet, a delivered one stays delivered. Returns whether
+    /// its state changed.
+    pub fn update(&mut self, id: u32, state: Delivery) -> bool {
+        let packet = self
+            .0
+            .values_mut()
+            .flat_map(|batch| batch.packets.iter_mut())
+            .find(|packet| packet.id == Some(id));
+        match packet {
+            Some(packet) if packet.state != Delivery::Delivered && packet.state != state => {
+                packet.state = state;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn batch(&self, node: u32) -> Option<&Batch> {
+        self.0.get(&node)
+    }
+}
+
+/// How the last texts to a node ended
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Link {
+    pub acked: usize,
+    pub failed: usize,
+    /// Acked texts by round trip, see [BUCKETS]
+    pub histogram: [usize; BUCKETS.len() + 1],
+    /// Median round trip in ms, None if none was acked
+    pub median: Option<u64>,
+}
+
+impl Link {
+    pub fn total(&self) -> usize {
+        self.acked + self.failed
+    }
+}
+
+/// The last texts to each node that ended, by packet id: the ms from going
+/// out to the ack, None if they ran out of retries
+#[derive(Default)]
+pub struct RoundTrips(HashMap<u32, VecDeque<(u32, Option<u64>)>>);
+
+impl RoundTrips {
+    /// A text to the node ended, once per packet
+    pub fn record(&mut self, node: u32, id: u32, round_trip: Option<u64>) {
+        let ended = self.0.entry(node).or_default();
+        if ended.iter().any(|(ended_id, _)| *ended_id == id) {
+            return;
+        }
+        if ended.len() == ROUND_TRIPS {
+            ended.pop_front();
+        }
+        ended.push_back((id, round_trip));
+    }
+
+    /// The links to the nodes, the one with fewer texts acked first
+    pub fn links(&self) -> Vec<(u32, Link)> {
+        let mut links: Vec<(u32, Link)> = self
+            .0
+            .iter()
+            .map(|(node, ended)| {
+                let mut round_trips: Vec<u64> = ended
+                    .iter()
+                    .filter_map(|(_, round_trip)| *round_trip)
+                    .collect();
+                round_trips.sort_unstable();
+                let mut histogram = [0; BUCKETS.len() + 1];
+                for ms in &round_trips {
+                    let bucket = BUCKETS
+                        .iter()
+                        .position(|secs| *ms < secs * 1000)
+                        .unwrap_or(BUCKETS.len());
+                    histogram[bucket] += 1;
+                }
+                let link = Link {
+                    acked: round_trips.len(),
+                    failed: ended.len() - round_trips.len(),
+                    histogram,
+                    median: round_trips.get(round_trips.len() / 2).copied(),
+                };
+                (*node, link)
+            })
+            .collect();
+        // By the ratio acked, without dividing
+        links.sort_by(|(a_node, a), (b_node, b)| {
+            (a.acked * b.total())
+                .cmp(&(b.acked * a.total()))
+                .then(a_node.cmp(b_node))
+        });
+        links
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deliveries() {
+        let mut deliveries = Deliveries::default();
+        deliveries.replied(
+            2,
+            100,
+            vec![
+                ("short".into(), vec!["short".into()]),
+                ("long".into(), vec!["1/2 lo".into(), "2/2 ng".into()]),
+            ],
+        );
+        assert_eq!(deliveries.batch(2).unwrap().counts(), (0, 3, 0));
+
+        assert!(!deliveries.sent(3, 10, "short"));
+        assert!(!deliveries.sent(2, 10, "other"));
+        assert!(deliveries.sent(2, 10, "short"));
+        assert!(deliveries.sent(2, 11, "1/2 lo"));
+        assert!(deliveries.sent(2, 12, "2/2 ng"));
+
+        assert!(deliveries.update(10, Delivery::Delivered));
+        assert!(!deliveries.update(10, Delivery::Relayed));
+        deliveries.update(11, Delivery::Error("NO_ROUTE".into()));
+        deliveries.update(11, Delivery::Failed);
+        deliveries.update(99, Delivery::Failed);
+        let batch = deliveries.batch(2).unwrap();
+        assert_eq!(batch.counts(), (1, 1, 1));
+        assert_eq!(batch.failed(), vec!["long"]);
+
+        deliveries.replied(2, 200, vec![]);
+        assert_eq!(deliveries.batch(2).unwrap().counts(), (0, 0, 0));
+
+        for node in 0..BATCHES as u32 {
+            deliveries.replied(100 + node, 300 + node as u64, vec![]);
+        }
+        assert!(deliveries.batch(2).is_none());
+        assert!(deliveries.batch(100).is_some());
+        assert_eq!(deliveries.0.len(), BATCHES);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let mut round_trips = RoundTrips::default();
+        round_trips.record(2, 10, Some(3_000));
+        round_trips.record(2, 11, Some(20_000));
+        round_trips.record(2, 11, Some(20_000));
+        round_trips.record(3, 12, Some(1_000));
+        round_trips.record(3, 13, None);
+        assert_eq!(
+            round_trips.links(),
+            vec![
+                (
+                    3,
+                    Link {
+                        acked: 1,
+                        failed: 1,
+                        histogram: [1, 0, 0, 0, 0],
+                        median: Some(1_000),
+                    }
+                ),
+                (
+                    2,
+                    Link {
+                        acked: 2,
+                        failed: 0,
+                        histogram: [1, 0, 1, 0, 0],
+                        median: Some(20_000),
+                    }
+                ),
+            ]
+        );
+        for id in 0..100 {
+            round_trips.record(4, 100 + id, Some(70_000));
+        }
+        assert_eq!(round_trips.links()[2].1.histogram, [0, 0, 0, 0, 50]);
+    }
+}
@@ -0,0 +1,366 @@
+use std::{collections::BTreeMap, collections::HashMap, fmt, time::Duration};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+use crate::bbs::storage::Storage;
+use crate::mesh::service::format_node_id;
+
+/// Start of the texts boards exchange, e.g. `~fed sync general=0`
+pub const PREFIX: &str = "~fed ";
+// Most posts sent for a sync request, the peer asks again for more
+const BATCH: usize = 5;
+// Posts being received in parts, older ones are dropped
+const MAX_PENDING: usize = 50;
+/// Posts older than this are not synced, a new peer does not pull the whole
+/// history
+pub const BACKFILL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long the hashes of synced posts are kept to drop them when they
+/// come again
+pub const KEEP_HASHES: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// Setting keeping the random id of this board, part of the identity of the
+// posts first posted on it
+const ORIGIN_KEY: &str = "federation.origin";
+
+/// The boards a board syncs with, and what
+#[derive(Debug, Clone, Default)]
+pub struct FederationOptions {
+    /// Node ids of the peer boards, with the hash of their public key. Their
+    /// frames are only taken encrypted with that key, node ids are easy to
+    /// spoof
+    pub peers: BTreeMap<u32, [u8; 32]>,
+    /// Channels synced, by name, the same on every board
+    pub channels: Vec<String>,
+    /// Largest text sent in a packet, in bytes
+    pub max_payload: usize,
+}
+
+/// A text of the sync protocol, each fits a packet:
+///
+/// - `~fed sync <channel>=<ts>,...` asks for the posts of the channels newer
+///   than the timestamps, in the clock of the board asked
+/// - `~fed post <channel> <ts> <prev> <hash> <part>/<parts> <text>` is a
+///   part of a post, its text as `<author>: <text>`. `prev` is the timestamp
+///   of the post sent before it, or the one asked for with the first post
+///   of the channel, so the peer knows whether some got lost on the way
+/// - `~fed more` tells there are more posts, to sync again
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Sync(BTreeMap<String, u64>),
+    Post {
+        channel: String,
+        ts: u64,
+        prev: u64,
+        hash: u32,
+        part: usize,
+        parts: usize,
+        text: String,
+    },
+    More,
+}
+
+impl Frame {
+    /// None for texts that are not frames
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.strip_prefix(PREFIX)?;
+        let (kind, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        match kind {
+            "sync" => {
+                let mut cursors = BTreeMap::new();
+                for cursor in rest.split(',').filter(|cursor| !cursor.is_empty()) {
+                    let (channel, ts) = cursor.split_once('=')?;
+                    cursors.insert(channel.to_string(), ts.parse().ok()?);
+                }
+                Some(Frame::Sync(cursors))
+            }
+            "post" => {
+                let mut fields = rest.splitn(6, ' ');
+                let channel = fields.next()?.to_string();
+                let ts = fields.next()?.parse().ok()?;
+                let prev = fields.next()?.parse().ok()?;
+                let hash = u32::from_str_radix(fields.next()?, 16).ok()?;
+                let (part, parts) = fields.next()?.split_once('/')?;
+                let (part, parts) = (part.parse().ok()?, parts.parse().ok()?);
+                if part == 0 || part > parts {
+                    return None;
+                }
+                Some(Frame::Post {
+                    channel,
+                    ts,
+                    prev,
+                    hash,
+                    part,
+                    parts,
+                    text: fields.next().unwrap_or_default().to_string(),
+                })
+            }
+            "more" => Some(Frame::More),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Sync(cursors) => {
+                let cursors: Vec<String> = cursors
+                    .iter()
+                    .map(|(channel, ts)| format!("{channel}={ts}"))
+                    .collect();
+                write!(f, "{PREFIX}sync {}", cursors.join(","))
+            }
+            Frame::Post {
+                channel,
+                ts,
+                prev,
+                hash,
+                part,
+                parts,
+                text,
+            } => write!(
+                f,
+                "{PREFIX}post {channel} {ts} {prev} {hash:08x} {part}/{parts} {text}"
+            ),
+            Frame::More => write!(f, "{PREFIX}more"),
+        }
+    }
+}
+
+/// Random id of this board, picked the first time it is needed
+pub fn origin(storage: &Storage) -> Result<u64> {
+    if let Some(origin) = storage.get_setting(ORIGIN_KEY)? {
+        return Ok(origin.parse()?);
+    }
+    let origin = rand::random::<u64>();
+    storage.set_setting(ORIGIN_KEY, &origin.to_string())?;
+    Ok(origin)
+}
+
+/// Identity of a post on every board, from the board it was first posted
+/// on, its channel, its timestamp there and its text. The same text posted
+/// twice is two posts.
+pub fn hash(origin: u64, channel: &str, ts: u64, text: &str) -> u32 {
+    let digest = Sha256::digest(format!("{origin:016x}\n{channel}\n{ts}\n{text}").as_bytes());
+    u32::from_be_bytes(digest[..4].try_into().unwrap())
+}
+
+/// The frames of a post, as many parts as it takes to fit `max_payload`.
+/// Parts are cut at any char, they join back as they were.
+pub fn post_frames(
+    channel: &str,
+    ts: u64,
+    prev: u64,
+    hash: u32,
+    text: &str,
+    max_payload: usize,
+) -> Vec<String> {
+    // The header with the widest part numbers a post may have
+    let header = Frame::Post {
+        channel: channel.to_string(),
+        ts,
+        prev,
+        hash,
+        part: 99,
+        parts: 99,
+        text: String::new(),
+    };
+    let room = max_payload.saturating_sub(header.to_string().len()).max(4);
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = room.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest
+                .chars()
+                .next()
+                .map(char::len_utf8)
+                .unwrap_or(rest.len());
+        }
+        chunks.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    let parts = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(n, chunk)| {
+            Frame::Post {
+                channel: channel.to_string(),
+                ts,
+                prev,
+                hash,
+                part: n + 1,
+                parts,
+                text: chunk.to_string(),
+            }
+            .to_string()
+        })
+        .collect()
+}
+
+/// Joins the parts of the posts peers send, in any order
+#[derive(Default)]
+pub struct Assembler {
+    pending: HashMap<(u32, u32), Vec<Option<String>>>,
+    // Order the posts started coming, to drop the oldest
+    order: Vec<(u32, u32)>,
+}
+
+impl Assembler {
+    /// Adds a part of the post of the peer, the whole text once every part
+    /// came
+    pub fn add(
+        &mut self,
+        peer: u32,
+        hash: u32,
+        part: usize,
+        parts: usize,
+        text: &str,
+    ) -> Option<String> {
+        if parts == 1 {
+            return Some(text.to_string());
+        }
+        let key = (peer, hash);
+        if !self.pending.contains_key(&key) {
+            if self.order.len() == MAX_PENDING {
+                let oldest = self.order.remove(0);
+                self.pending.remove(&oldest);
+            }
+            self.order.push(key);
+        }
+        let received = self.pending.entry(key).or_insert_with(|| vec![None; parts]);
+        if received.len() != parts {
+            return None;
+        }
+        received[part - 1] = Some(text.to_string());
+        if received.iter().any(Option::is_none) {
+            return None;
+        }
+        self.order.retain(|pending| *pending != key);
+        let received = self.pending.remove(&key)?;
+        Some(received.into_iter().flatten().collect())
+    }
+}
+
+// Setting keeping the timestamps of the last posts of the peer, per channel,
+// as JSON
+fn cursors_key(peer: u32) -> String {
+    format!("federation.{}", format_node_id(peer))
+}
+
+/// Timestamps, in the clock of the peer, of the last post received from it
+/// in each channel
+pub fn cursors(storage: &Storage, peer: u32) -> Result<BTreeMap<String, u64>> {
+    match storage.get_setting(&cursors_key(peer))? {
+        Some(json) => Ok(serde_json::from_str(&json)?),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// A post of the channel with the timestamp came from the peer. The cursor
+/// only moves to it from the post sent before it, `prev`, so posts lost on
+/// the way are asked for again.
+pub fn advance(storage: &Storage, peer: u32, channel: &str, prev: u64, ts: u64) -> Result<()> {
+    let mut cursors = cursors(storage, peer)?;
+    let cursor = cursors.entry(channel.to_string()).or_default();
+    if *cursor != prev || ts <= *cursor {
+        return Ok(());
+    }
+    *cursor = ts;
+    storage.set_setting(&cursors_key(peer), &serde_json::to_string(&cursors)?)
+}
+
+/// The request for the posts of the channels the peer has and we do not
+pub fn sync_request(storage: &Storage, peer: u32, channels: &[String]) -> Result<String> {
+    let cursors = cursors(storage, peer)?;
+    let request = channels
+        .iter()
+        .map(|channel| (channel.clone(), cursors.get(channel).copied().unwrap_or(0)))
+        .collect();
+    Ok(Frame::Sync(request).to_string())
+}
+
+/// Whether a sync answer with this many posts leaves some out
+pub fn is_full(posts: usize) -> bool {
+    posts >= BATCH
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let sync = Frame::parse("~fed sync general=0,news=1717").unwrap();
+        assert_eq!(sync.to_string(), "~fed sync general=0,news=1717");
+        assert_eq!(Frame::parse("~fed more"), Some(Frame::More));
+        assert_eq!(Frame::parse("~fed post general x"), None);
+        assert_eq!(Frame::parse("~fed post general 1 0 00000001 3/2 hi"), None);
+        assert_eq!(Frame::parse("hello"), None);
+
+        let post = "ann: the bridge  is out, take the ford ñ";
+        let post_hash = hash(1, "general", 1717, post);
+        assert_ne!(post_hash, hash(1, "general", 1718, post));
+        assert_ne!(post_hash, hash(2, "general", 1717, post));
+        let frames = post_frames("general", 1717, 1000, post_hash, post, 60);
+        assert!(frames.len() > 1);
+        assert!(frames.iter().all(|frame| frame.len() <= 60));
+        let mut assembler = Assembler::default();
+        let mut joined = None;
+        for frame in frames.iter().rev() {
+            let Some(Frame::Post {
+                channel,
+                ts,
+                prev,
+                hash: frame_hash,
+                part,
+                parts,
+                text,
+            }) = Frame::parse(frame)
+            else {
+                panic!("Not a post frame: {frame}");
+            };
+            assert_eq!((channel.as_str(), ts, prev), ("general", 1717, 1000));
+            assert_eq!(frame_hash, post_hash);
+            joined = assembler.add(7, frame_hash, part, parts, &text);
+        }
+        assert_eq!(joined.as_deref(), Some(post));
+        assert_eq!(
+            post_frames("general", 1717, 0, 0x3fa2c01b, "ann: hi", 200),
+            vec!["~fed post general 1717 0 3fa2c01b 1/1 ann: hi"]
+        );
+    }
+
+    #[test]
+    fn test_cursors() -> Result<()> {
+        let s = Storage::memory();
+        let channels = vec!["general".to_string(), "news".to_string()];
+        assert_eq!(
+            sync_request(&s, 7, &channels)?,
+            "~fed sync general=0,news=0"
+        );
+        advance(&s, 7, "news", 0, 1717)?;
+        advance(&s, 7, "news", 1717, 1000)?;
+        // The post before it did not come
+        advance(&s, 7, "news", 1800, 1900)?;
+        assert_eq!(
+            sync_request(&s, 7, &channels)?,
+            "~fed sync general=0,news=1717"
+        );
+        advance(&s, 7, "news", 1717, 1800)?;
+        assert_eq!(
+            sync_request(&s, 7, &channels)?,
+            "~fed sync general=0,news=1800"
+        );
+        assert_eq!(
+            sync_request(&s, 8, &channels)?,
+            "~fed sync general=0,news=0"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::bbs::mistake::mistake;
+use crate::bbs::storage::{Document, Storage, UserId};
+use crate::mesh::chunker;
+
+/// Largest document, in bytes
+pub const MAX_SIZE: usize = 16 * 1024;
+/// Bytes sent for each `get`, a few texts over the mesh
+pub const PART_SIZE: usize = 600;
+
+/// Adds the text file as a document, named after the file unless `name` is
+/// given. Returns its number.
+pub fn add(storage: &Storage, path: &Path, name: Option<&str>) -> Result<u32> {
+    let text = fs::read_to_string(path)?;
+    if text.trim().is_empty() {
+        bail!("{} is empty", path.display());
+    }
+    if text.len() > MAX_SIZE {
+        bail!("{} is over {} bytes", path.display(), MAX_SIZE);
+    }
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid file {}", path.display()))?
+            .to_string(),
+    };
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    storage.add_document(Document {
+        id: 0,
+        name,
+        text,
+        ts,
+    })
+}
+
+/// The parts the document is sent in, one for each `get`
+pub fn parts(document: &Document) -> Vec<String> {
+    chunker::split_raw(&document.text, PART_SIZE)
+}
+
+// Preference keeping the next part the user gets of a document
+fn resume_key(id: u32) -> String {
+    format!("file.{id}")
+}
+
+/// The part of the document for the user, from 1, or the one after the last
+/// they got, so a transfer cut by the mesh goes on where it stopped. Returns
+/// (part, parts, text).
+pub fn fetch(
+    storage: &Storage,
+    user_id: UserId,
+    document: &Document,
+    part: Option<usize>,
+) -> Result<(usize, usize, String)> {
+    let parts = parts(document);
+    let key = resume_key(document.id);
+    let part = match part {
+        Some(part) => part,
+        None => storage
+            .get_preference(user_id, &key)?
+            .and_then(|next| next.parse().ok())
+            .filter(|next| *next <= parts.len())
+            .unwrap_or(1),
+    };
+    if part == 0 || part > parts.len() {
+        mistake!("Parts are 1 to {}", parts.len());
+    }
+    if part == parts.len() {
+        storage.remove_preference(user_id, &key)?;
+    } else {
+        storage.set_preference(user_id, &key, &(part + 1).to_string())?;
+    }
+    Ok((part, parts.len(), parts[part - 1].clone()))
+}
+
+/// `#1 rules.txt 2KB 4 parts`, a line per document
+pub fn list(storage: &Storage) -> Result<Vec<String>> {
+    Ok(storage
+        .get_documents()?
+        .iter()
+        .map(|document| {
+            format!(
+                "#{} {} {}KB {} parts",
+                document.id,
+                document.name,
+                document.text.len().div_ceil(1024),
+                parts(document).len()
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_files() -> Result<()> {
+        let s = Storage::memory();
+        let dir = std::env::temp_dir().join(format!("meshboard-files-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("rules.txt");
+        fs::write(&path, "Be kind. ".repeat(100))?;
+
+        assert_eq!(add(&s, &path, None)?, 1);
+        assert_eq!(add(&s, &path, Some("rules-copy"))?, 2);
+        fs::write(&path, "x".repeat(MAX_SIZE + 1))?;
+        assert!(add(&s, &path, None).is_err());
+        fs::write(&path, " \n")?;
+        assert!(add(&s, &path, None).is_err());
+        fs::remove_dir_all(&dir)?;
+
+        let rules = s.get_document(1)?.unwrap();
+        let parts = parts(&rules);
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|part| part.len() <= PART_SIZE));
+        assert_eq!(
+            list(&s)?,
+            vec!["#1 rules.txt 1KB 2 parts", "#2 rules-copy 1KB 2 parts"]
+        );
+        assert!(s.remove_document(2)?);
+        assert!(!s.remove_document(2)?);
+        assert_eq!(list(&s)?.len(), 1);
+
+        let part = |n| fetch(&s, 1, &rules, n).map(|(part, parts, _)| (part, parts));
+        assert_eq!(part(None)?, (1, 2));
+        assert_eq!(part(None)?, (2, 2));
+        assert_eq!(part(None)?, (1, 2));
+        assert_eq!(part(Some(2))?, (2, 2));
+        assert!(part(Some(3)).is_err());
+        Ok(())
+    }
+}
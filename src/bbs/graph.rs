@@ -0,0 +1,131 @@
+use std::{collections::BTreeSet, fmt::Write, fs, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::bbs::storage::Storage;
+use crate::mesh::service::format_node_id;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphNode {
+    pub num: u32,
+    pub id: String,
+    // Short name, the node id when unknown
+    pub name: String,
+}
+
+/// `from` hears `to` at `snr`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphLink {
+    pub from: u32,
+    pub to: u32,
+    pub snr: f32,
+    pub ts: u64,
+}
+
+/// The mesh as the nodes told it with their NeighborInfo packets
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+    pub links: Vec<GraphLink>,
+}
+
+impl Graph {
+    /// The nodes and links kept, named after the sightings
+    pub fn load(storage: &Storage) -> Result<Self> {
+        let links: Vec<GraphLink> = storage
+            .get_neighbor_links()?
+            .into_iter()
+            .map(|neighbor| GraphLink {
+                from: neighbor.node_neighbor.0,
+                to: neighbor.node_neighbor.1,
+                snr: neighbor.snr,
+                ts: neighbor.ts,
+            })
+            .collect();
+        let nums: BTreeSet<u32> = links.iter().flat_map(|link| [link.from, link.to]).collect();
+        let mut nodes = Vec::new();
+        for num in nums {
+            let id = format_node_id(num);
+            let name = match storage.get_sighting(num)? {
+                Some(sighting) if !sighting.short_name.is_empty() => sighting.short_name,
+                _ => id.clone(),
+            };
+            nodes.push(GraphNode { num, id, name });
+        }
+        Ok(Self { nodes, links })
+    }
+
+    /// Graphviz source, `dot -Tsvg mesh.dot -o mesh.svg` draws it
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph mesh {\n");
+        for node in &self.nodes {
+            let _ = writeln!(dot, "  {:?} [label={:?}];", node.id, node.name);
+        }
+        for link in &self.links {
+            let _ = writeln!(
+                dot,
+                "  {:?} -> {:?} [label=\"{:.1}dB\"];",
+                format_node_id(link.from),
+                format_node_id(link.to),
+                link.snr
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Writes the graph to `path`, as JSON if it ends in `.json` and as DOT
+/// otherwise
+pub fn export(storage: &Storage, path: &Path) -> Result<Graph> {
+    let graph = Graph::load(storage)?;
+    let text = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => graph.to_json()?,
+        _ => graph.to_dot(),
+    };
+    fs::write(path, text)?;
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bbs::storage::{Neighbor, Sighting};
+
+    #[test]
+    fn test_graph() -> Result<()> {
+        let s = Storage::memory();
+        s.upsert_sighting(Sighting {
+            num: 1,
+            short_name: "ann".into(),
+            long_name: "Ann".into(),
+            last_heard: 0,
+            snr: 0.0,
+            rssi: 0,
+            hops: None,
+        })?;
+        s.set_neighbors(
+            1,
+            vec![Neighbor {
+                node_neighbor: (1, 2),
+                snr: 6.5,
+                ts: 100,
+            }],
+        )?;
+
+        let graph = Graph::load(&s)?;
+        assert_eq!(
+            graph.to_dot(),
+            "digraph mesh {\n  \"!00000001\" [label=\"ann\"];\n  \"!00000002\" [label=\"!00000002\"];\n  \"!00000001\" -> \"!00000002\" [label=\"6.5dB\"];\n}\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json()?)?;
+        assert_eq!(json["links"][0]["to"], 2);
+        assert_eq!(json["nodes"][0]["name"], "ann");
+        Ok(())
+    }
+}
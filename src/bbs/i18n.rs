@@ -0,0 +1,165 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+
+/// Language of the help and replies of the board, per user with `lang`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    En,
+    Es,
+}
+
+impl FromStr for Lang {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "en" => Lang::En,
+            "es" => Lang::Es,
+            _ => bail!("Expected en or es"),
+        })
+    }
+}
+
+impl fmt::Display for Lang {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Lang::En => "en",
+            Lang::Es => "es",
+        })
+    }
+}
+
+pub const HELP: &str = "h(elp) [cmd] | c(hannels)  | j(oin) ch [pw] | p(ost) msg | r(eply) n msg | like n | react n emoji | l(list) [page] | next | s(earch) [all] kw | sub ch | unsub ch | checkin [note] | whohere [lat lon] [km] | nick name | whoami | status | set field [text] | profile [user] | poll new \"q\" opt1 opt2 | vote poll# n | poll results poll# | email addr subject | text | mail | read mail# | files | get file# [part] | notify [on|off|mentions|mail-only] | who | where node | neighbors node | wp [list] | wp add name lat lon | fav ch | unfav ch | lang [en|es]";
+pub const ADMIN_HELP: &str = "mkchan ch | rmchan ch | ban user | unban user | banlist | purge ch | prune | stats [delivery] | fleet | watch [node] | unwatch node | announce add|del|list | telemetry node | snapshot | motd [set text|reset] | dmlog [page] | acl ch [public|private|password pw|allow user|deny user] | broadcast text | sessions [reset user|all]";
+const HELP_ES: &str = "ayuda [comando] | canales | unir canal [clave] | publicar msg | responder n msg | like n | react n emoji | lista [pág] | siguiente | buscar [all] palabra | sub canal | unsub canal | checkin [nota] | whohere [lat lon] [km] | nick nombre | whoami | status | set campo [texto] | perfil [usuario] | poll new \"pregunta\" op1 op2 | votar poll# n | poll results poll# | email dirección asunto | texto | correo | leer mail# | archivos | get file# [parte] | notify [on|off|mentions|mail-only] | quien | donde nodo | neighbors nodo | wp [list] | wp add nombre lat lon | fav canal | unfav canal | idioma [en|es]";
+const ADMIN_HELP_ES: &str = "mkchan canal | rmchan canal | ban usuario | unban usuario | banlist | purge canal | prune | stats [delivery] | fleet | watch [nodo] | unwatch nodo | announce add|del|list | telemetry nodo | snapshot | motd [set texto|reset] | dmlog [pág] | acl canal [public|private|password clave|allow usuario|deny usuario] | broadcast texto | sessions [reset usuario|all]";
+
+pub fn help(lang: Lang) -> &'static str {
+    match lang {
+        Lang::En => HELP,
+        Lang::Es => HELP_ES,
+    }
+}
+
+pub fn admin_help(lang: Lang) -> &'static str {
+    match lang {
+        Lang::En => ADMIN_HELP,
+        Lang::Es => ADMIN_HELP_ES,
+    }
+}
+
+// Replies and errors in Spanish, by their English text
+const ES: &[(&str, &str)] = &[
+    ("Ack", "Hecho"),
+    ("Not allowed", "No permitido"),
+    ("Invalid command", "Comando no válido"),
+    ("Channel not found", "Canal no encontrado"),
+    ("Channel already exists", "El canal ya existe"),
+    ("Missing channel name", "Falta el nombre del canal"),
+    ("Missing message number", "Falta el número del mensaje"),
+    ("Missing user name", "Falta el nombre del usuario"),
+    ("Missing node", "Falta el nodo"),
+    ("Missing text", "Falta el texto"),
+    ("Missing keyword", "Falta la palabra"),
+    ("Private channel", "Canal privado"),
+    ("Password required", "Hace falta la clave"),
+    ("Wrong password", "Clave incorrecta"),
+    ("Not a member of the channel", "No eres miembro del canal"),
+    ("User not found", "Usuario no encontrado"),
+    ("Node not found", "Nodo no encontrado"),
+    ("Nothing listed yet", "Aún no has listado nada"),
+    ("Not an emoji", "No es un emoji"),
+    ("Reaction removed", "Reacción quitada"),
+    ("Checked in", "Registrado"),
+    ("No mail", "No hay correo"),
+    ("No files", "No hay archivos"),
+    ("No waypoints", "No hay puntos de referencia"),
+    ("No nodes seen", "No se ha visto ningún nodo"),
+    (
+        "No replies sent yet",
+        "Aún no se ha enviado ninguna respuesta",
+    ),
+    (
+        "No position known, enable position sharing",
+        "Posición desconocida, activa compartir la posición",
+    ),
+    (
+        "No position known, use whohere lat lon",
+        "Posición desconocida, usa whohere lat lon",
+    ),
+    (
+        "Email is not enabled",
+        "El correo electrónico no está activado",
+    ),
+    (
+        "Verified, you can post now",
+        "Verificado, ya puedes publicar",
+    ),
+    ("Expected en or es", "Se esperaba en o es"),
+];
+
+/// The reply in the language, None if it has no translation
+pub fn translate(lang: Lang, reply: &str) -> Option<&'static str> {
+    let catalog = match lang {
+        Lang::En => return None,
+        Lang::Es => ES,
+    };
+    catalog
+        .iter()
+        .find(|(english, _)| *english == reply)
+        .map(|(_, translated)| *translated)
+}
+
+// Command words in other languages, and the command each stands for
+const ALIASES: &[(&str, &str)] = &[
+    ("ayuda", "help"),
+    ("canales", "channels"),
+    ("unir", "join"),
+    ("publicar", "post"),
+    ("responder", "reply"),
+    ("lista", "list"),
+    ("siguiente", "next"),
+    ("buscar", "search"),
+    ("perfil", "profile"),
+    ("votar", "vote"),
+    ("correo", "mail"),
+    ("leer", "read"),
+    ("archivos", "files"),
+    ("quien", "who"),
+    ("quién", "who"),
+    ("donde", "where"),
+    ("dónde", "where"),
+    ("idioma", "lang"),
+];
+
+/// The command with its first word in English, users type commands in any
+/// language
+pub fn unalias(command: &str) -> Cow<'_, str> {
+    let trimmed = command.trim_start();
+    let (word, rest) = trimmed
+        .split_once(char::is_whitespace)
+        .unwrap_or((trimmed, ""));
+    match ALIASES.iter().find(|(alias, _)| *alias == word) {
+        Some((_, english)) => Cow::Owned(format!("{english} {rest}")),
+        None => Cow::Borrowed(command),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_i18n() {
+        assert_eq!("es".parse::<Lang>().unwrap(), Lang::Es);
+        assert!("fr".parse::<Lang>().is_err());
+        assert_eq!(translate(Lang::Es, "Ack"), Some("Hecho"));
+        assert_eq!(translate(Lang::Es, "Ack!"), None);
+        assert_eq!(translate(Lang::En, "Ack"), None);
+        assert_eq!(unalias("publicar hola a todos"), "post hola a todos");
+        assert_eq!(unalias("canales"), "channels ");
+        assert_eq!(unalias("p hola"), "p hola");
+    }
+}
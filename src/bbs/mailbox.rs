@@ -0,0 +1,228 @@
+use anyhow::{Result, bail};
+
+use crate::bbs::mistake::mistake;
+use crate::bbs::storage::{Storage, UserId};
+
+/// Longest body of an email sent from the mesh, and kept of a reply, in chars
+pub const MAX_BODY: usize = 500;
+// Preference counting the emails of the user today, `<day> <count>`
+const QUOTA_KEY: &str = "email_quota";
+// Preference keeping the last emails of the user replies are taken for, a
+// `<token> <to>` line each
+const THREADS_KEY: &str = "email_threads";
+const THREADS_MAX: usize = 10;
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// An email a user sends from the mesh
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingEmail {
+    pub uid: UserId,
+    // Goes in the subject, see [tag]
+    pub tag: String,
+    // Name of the user on the board
+    pub author: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// An email that came to the gateway
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomingEmail {
+    pub from: String,
+    pub subject: String,
+    pub text: String,
+}
+
+/// What the operator lets users email
+#[derive(Debug, Clone, Default)]
+pub struct EmailOptions {
+    /// Addresses, or domains as `@example.com`
+    pub allow: Vec<String>,
+    /// Emails per user and day
+    pub daily_quota: u32,
+}
+
+impl EmailOptions {
+    pub fn allows(&self, to: &str) -> bool {
+        let to = to.to_lowercase();
+        self.allow.iter().any(|allowed| {
+            let allowed = allowed.to_lowercase();
+            match allowed.starts_with('@') {
+                true => to.ends_with(&allowed),
+                false => to == allowed,
+            }
+        })
+    }
+}
+
+/// `<to> <subject> | <body>`, into (to, subject, body)
+pub fn parse_email(s: &str) -> Result<(String, String, String)> {
+    let Some((to, rest)) = s.trim().split_once(char::is_whitespace) else {
+        bail!("Usage: email to@example.com subject | text");
+    };
+    let Some((subject, body)) = rest.split_once('|') else {
+        bail!("Usage: email to@example.com subject | text");
+    };
+    let (subject, body) = (subject.trim(), body.trim());
+    if !to.contains('@') || to.contains(['<', '>', ',']) {
+        mistake!("Invalid address {to}");
+    }
+    if subject.is_empty() || body.is_empty() {
+        bail!("Missing subject or text");
+    }
+    if body.chars().count() > MAX_BODY {
+        mistake!("Up to {MAX_BODY} chars per email");
+    }
+    Ok((to.to_string(), subject.to_string(), body.to_string()))
+}
+
+/// Tag of the subject of an email, so its replies find the user. The token
+/// can not be guessed, see [open_thread].
+pub fn tag(uid: UserId, token: &str) -> String {
+    format!("[mb{uid}.{token}]")
+}
+
+/// The user and token tagged in the subject, and the subject without the tag
+pub fn untag(subject: &str) -> Option<(UserId, String, String)> {
+    let start = subject.rfind("[mb")?;
+    let end = start + subject[start..].find(']')?;
+    let (uid, token) = subject[start + 3..end].split_once('.')?;
+    let uid = uid.parse().ok()?;
+    let untagged = format!("{}{}", &subject[..start], &subject[end + 1..]);
+    Some((uid, token.to_string(), untagged.trim().to_string()))
+}
+
+fn threads(storage: &Storage, uid: UserId) -> Result<Vec<(String, String)>> {
+    Ok(storage
+        .get_preference(uid, THREADS_KEY)?
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(token, to)| (token.to_string(), to.to_string()))
+        .collect())
+}
+
+/// Keeps the recipient of an email of the user, returns the token of its
+/// tag. Replies are taken for the last [THREADS_MAX] emails only.
+pub fn open_thread(storage: &Storage, uid: UserId, to: &str) -> Result<String> {
+    let token = format!("{:016x}", rand::random::<u64>());
+    let mut threads = threads(storage, uid)?;
+    threads.push((token.clone(), to.to_string()));
+    let skip = threads.len().saturating_sub(THREADS_MAX);
+    let lines: Vec<String> = threads
+        .iter()
+        .skip(skip)
+        .map(|(token, to)| format!("{token} {to}"))
+        .collect();
+    storage.set_preference(uid, THREADS_KEY, &lines.join("\n"))?;
+    Ok(token)
+}
+
+/// Who the email of the user with the token went to, None if there is no
+/// such email
+pub fn thread_recipient(storage: &Storage, uid: UserId, token: &str) -> Result<Option<String>> {
+    Ok(threads(storage, uid)?
+        .into_iter()
+        .find(|(kept, _)| kept == token)
+        .map(|(_, to)| to))
+}
+
+/// Counts an email of the user, false if they are over the daily quota
+pub fn take_quota(storage: &Storage, uid: UserId, now: u64, quota: u32) -> Result<bool> {
+    let today = now / DAY_MS;
+    let sent = storage
+        .get_preference(uid, QUOTA_KEY)?
+        .and_then(|value| {
+            let (day, count) = value.split_once(' ')?;
+            (day.parse::<u64>().ok()? == today).then(|| count.parse::<u32>().ok())?
+        })
+        .unwrap_or(0);
+    if sent >= quota {
+        return Ok(false);
+    }
+    storage.set_preference(uid, QUOTA_KEY, &format!("{today} {}", sent + 1))?;
+    Ok(true)
+}
+
+// Whether the line starts the quoted email, or the signature
+fn starts_quote(line: &str) -> bool {
+    line.starts_with('>')
+        || line.starts_with("-----Original Message-----")
+        || line == "--"
+        || (line.starts_with("On ") && line.ends_with("wrote:"))
+}
+
+/// The text of a reply in a line, without the quoted email, cut to
+/// [MAX_BODY] chars
+pub fn reply_text(text: &str) -> String {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !starts_quote(line))
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join(" ").chars().take(MAX_BODY).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mailbox() -> Result<()> {
+        assert_eq!(
+            parse_email("ann@example.com Trail report | Bridge is out, take the ford")?,
+            (
+                "ann@example.com".into(),
+                "Trail report".into(),
+                "Bridge is out, take the ford".into()
+            )
+        );
+        assert!(parse_email("ann@example.com no body").is_err());
+        assert!(parse_email("ann subject | text").is_err());
+        assert!(parse_email("ann@example.com | text").is_err());
+
+        let options = EmailOptions {
+            allow: vec!["ann@example.com".into(), "@ranger.org".into()],
+            daily_quota: 2,
+        };
+        assert!(options.allows("Ann@Example.com"));
+        assert!(options.allows("bob@ranger.org"));
+        assert!(!options.allows("bob@example.com"));
+
+        assert_eq!(tag(12, "a1b2"), "[mb12.a1b2]");
+        assert_eq!(
+            untag("Re: Trail report [mb12.a1b2]"),
+            Some((12, "a1b2".into(), "Re: Trail report".into()))
+        );
+        assert_eq!(untag("Re: Trail report"), None);
+        assert_eq!(untag("Re: [mb12]"), None);
+        assert_eq!(untag("Re: [mbx.a1b2]"), None);
+
+        let s = Storage::memory();
+        let token = open_thread(&s, 1, "ann@example.com")?;
+        assert_eq!(token.len(), 16);
+        assert_eq!(
+            thread_recipient(&s, 1, &token)?.as_deref(),
+            Some("ann@example.com")
+        );
+        assert_eq!(thread_recipient(&s, 2, &token)?, None);
+        for _ in 0..THREADS_MAX {
+            open_thread(&s, 1, "bob@example.com")?;
+        }
+        assert_eq!(thread_recipient(&s, 1, &token)?, None);
+
+        assert!(take_quota(&s, 1, 0, 2)?);
+        assert!(take_quota(&s, 1, 1000, 2)?);
+        assert!(!take_quota(&s, 1, 2000, 2)?);
+        assert!(take_quota(&s, 2, 2000, 2)?);
+        assert!(take_quota(&s, 1, DAY_MS, 2)?);
+
+        let reply =
+            "Thanks, see you there.\n\nAnn\n\nOn Sun, Jun 1, 2025 you wrote:\n> Bridge is out";
+        assert_eq!(reply_text(reply), "Thanks, see you there. Ann");
+        assert_eq!(reply_text(&"x".repeat(1000)).len(), MAX_BODY);
+        Ok(())
+    }
+}
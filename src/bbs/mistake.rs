@@ -0,0 +1,23 @@
+use std::fmt;
+
+/// A mistake in a command, e.g. a channel that does not exist, replied to
+/// the user. Any other error is a failure of the board.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mistake(pub String);
+
+impl fmt::Display for Mistake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Mistake {}
+
+/// Returns early with a [Mistake], as `bail!` does with an error
+macro_rules! mistake {
+    ($($arg:tt)*) => {
+        return Err($crate::bbs::mistake::Mistake(format!($($arg)*)).into())
+    };
+}
+
+pub(crate) use mistake;
@@ -1,82 +1,1064 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Result, bail};
-use log::info;
+use tracing::{Instrument, debug, info, info_span, warn};
 
-use crate::mesh::service::Destination;
+use meshtastic::{
+    Message,
+    protobufs::{
+        DeviceMetadata, FromRadio, HardwareModel, NeighborInfo, PortNum, Position, Telemetry, User,
+        Waypoint, config::device_config::Role, from_radio, mesh_packet,
+    },
+};
+
+use crate::codec::{self, TextCodec};
+use crate::config::Config;
+use crate::mesh::chunker;
+use crate::mesh::service::{
+    BROADCAST_ADDR, Destination, Handler, HandlerState, Heard, Metrics, ServiceFinished, State,
+    Status, StatusReceiver, TextMessageStatus, Transport, Urgency, contact_url, coordinates,
+    format_node_id,
+};
 use crate::screen::Screen;
+use crate::screen::pages::Pages;
+use crate::screen::qr::Qr;
+use crate::sysinfo::SysInfo;
 
-// pub mod repl;
+pub mod counters;
+pub mod delivery;
+pub mod federation;
+pub mod files;
+pub mod graph;
+pub mod i18n;
+pub mod mailbox;
+pub mod mistake;
+pub mod plugins;
+pub mod prefs;
+pub mod radios;
+pub mod ratelimit;
+#[cfg(feature = "repl")]
+pub mod repl;
+pub mod retention;
+pub mod schedule;
+#[cfg(feature = "scripts")]
+pub mod scripts;
+pub mod sender;
 pub mod service;
+pub mod snapshot;
 pub mod storage;
+pub mod usage;
+pub mod watchdog;
+
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(5);
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_RELAYED_POSTS: usize = 64;
+const WATCH_INTERVAL: Duration = Duration::from_secs(60);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const BUTTON_INTERVAL: Duration = Duration::from_millis(100);
+const SYSINFO_INTERVAL: Duration = Duration::from_secs(60);
+const PACE_INTERVAL: Duration = Duration::from_millis(250);
+// Texts in a row that cannot be answered before the board stops, to be
+// restarted
+const MAX_FAILURES: u32 = 10;
+
+fn show<D: Screen>(pages: &mut Pages<D>, codec: &dyn TextCodec) {
+    if let Err(err) = pages.draw(codec) {
+        debug!(target: "screen", "Cannot refresh the display: {err}");
+    }
+}
+
+/// Logs the line and shows it, row 0 is the status and the others go to the
+/// messages page
+fn info<D: Screen>(pages: &mut Pages<D>, codec: &dyn TextCodec, row: usize, message: &str) {
+    info!(target: "screen", row, "{}", message);
+    if row == 0 {
+        pages.set_status(message);
+    } else {
+        pages.message(message);
+    }
+    show(pages, codec);
+}
+
+/// Refreshes the nodes and stats pages
+async fn update_pages<D: Screen>(
+    pages: &mut Pages<D>,
+    bbs: &service::BBS,
+    radios: &radios::Radios,
+) -> Result<()> {
+    let now = now_ms();
+    // Newest sighting of each node by any radio
+    let mut heard: HashMap<u32, (u64, String)> = HashMap::new();
+    for handler in radios.iter() {
+        let state = handler.state.read().await;
+        for (num, h) in state.heard() {
+            if heard.get(&num).is_some_and(|(ts, _)| *ts >= h.ts) {
+                continue;
+            }
+            let line = format!(
+                "{} {}m {:.1}dB",
+                state.node_name(num),
+                now.saturating_sub(h.ts) / 60_000,
+                h.snr
+            );
+            heard.insert(num, (h.ts, line));
+        }
+    }
+    let mut nodes: Vec<_> = heard.into_values().collect();
+    nodes.sort_by_key(|(ts, _)| Reverse(*ts));
+    pages.set_nodes(nodes.into_iter().map(|(_, line)| line).collect());
+
+    let stats = bbs.stats()?;
+    pages.set_stats(vec![
+        format!("Users {}", stats.users),
+        format!("Channels {}", stats.channels),
+        format!("Messages {}", stats.messages),
+        format!("Radios {}", radios.iter().count()),
+    ]);
+    Ok(())
+}
+
+/// Refreshes the system page with the host readings and the last battery
+/// and channel utilization the primary radio reported
+async fn update_system<D: Screen>(
+    pages: &mut Pages<D>,
+    radios: &radios::Radios,
+    db_path: &Path,
+) -> Result<()> {
+    let dir = db_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let mut lines = tokio::task::spawn_blocking(move || SysInfo::collect(&dir))
+        .await?
+        .lines();
+
+    let state = radios.primary().state.read().await;
+    if let Ok(num) = state.my_node_num() {
+        match state
+            .telemetry(num)
+            .rev()
+            .find_map(|(_, metrics)| metrics.battery_level)
+        {
+            Some(level) if level > 100 => lines.push("Battery powered".to_string()),
+            Some(level) => lines.push(format!("Battery {level}%")),
+            None => {}
+        }
+        if let Some(utilization) = state
+            .telemetry(num)
+            .rev()
+            .find_map(|(_, metrics)| metrics.channel_utilization)
+        {
+            lines.push(format!("ChUtil {utilization:.1}%"));
+        }
+    }
+    pages.set_system(lines);
+    Ok(())
+}
+
+/// Fills the contact page with the names of the primary radio and the QR code
+/// of its contact URL
+async fn show_contact<D: Screen>(pages: &mut Pages<D>, radios: &radios::Radios) -> Result<()> {
+    let state = radios.primary().state.read().await;
+    let (Ok(num), Some(user)) = (state.my_node_num(), state.my_user()) else {
+        return Ok(());
+    };
+    let qr = Qr::new(&contact_url(num, user))?;
+    pages.set_contact(
+        vec![
+            user.long_name.clone(),
+            user.short_name.clone(),
+            format_node_id(num),
+            "Scan to add".to_string(),
+        ],
+        qr,
+    );
+    Ok(())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn enum_name<E: TryFrom<i32>>(value: i32, name: fn(&E) -> &'static str) -> String {
+    E::try_from(value)
+        .map(|e| name(&e).to_string())
+        .unwrap_or_default()
+}
 
-const SPINNER: [&str; 4] = ["-", "\\", "", ""];
+fn node_from_user(num: u32, user: &User) -> storage::Node {
+    storage::Node {
+        num,
+        short_name: user.short_name.clone(),
+        hw_model: enum_name(user.hw_model, HardwareModel::as_str_name),
+        role: enum_name(user.role, Role::as_str_name),
+        firmware: None,
+        last_seen: now_ms(),
+    }
+}
+
+fn node_from_metadata(num: u32, metadata: &DeviceMetadata) -> storage::Node {
+    storage::Node {
+        num,
+        short_name: String::new(),
+        hw_model: enum_name(metadata.hw_model, HardwareModel::as_str_name),
+        role: enum_name(metadata.role, Role::as_str_name),
+        firmware: Some(metadata.firmware_version.clone()),
+        last_seen: now_ms(),
+    }
+}
 
-fn info<D: Screen>(display: &mut D, row: usize, message: &str) {
-    info!("{}", message);
-    let padded = format!("{:<42}", message);
-    display.draw_text_at(&padded, row as i32, 0);
-    let _ = display.refresh();
+fn sighting(state: &HandlerState, num: u32, heard: &Heard) -> storage::Sighting {
+    let user = state.user(num);
+    storage::Sighting {
+        num,
+        short_name: user.map(|user| user.short_name.clone()).unwrap_or_default(),
+        long_name: user.map(|user| user.long_name.clone()).unwrap_or_default(),
+        last_heard: heard.ts,
+        snr: heard.snr,
+        rssi: heard.rssi,
+        hops: heard.hops,
+    }
 }
 
-pub(crate) async fn run_bbs<D: Screen>(mut display: D) -> Result<()> {
-    let mut spinner = 0;
+/// Fleet inventory data carried by a radio packet, if any
+fn node_report(from_radio: &FromRadio) -> Option<storage::Node> {
+    match from_radio.payload_variant.as_ref()? {
+        from_radio::PayloadVariant::NodeInfo(node_info) => {
+            Some(node_from_user(node_info.num, node_info.user.as_ref()?))
+        }
+        from_radio::PayloadVariant::Packet(packet) => {
+            let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+                return None;
+            };
+            if PortNum::try_from(data.portnum) != Ok(PortNum::NodeinfoApp) {
+                return None;
+            }
+            let user = User::decode(data.payload.as_slice()).ok()?;
+            Some(node_from_user(packet.from, &user))
+        }
+        _ => None,
+    }
+}
+
+/// (node, (latitude, longitude)) of a position packet with a fix
+fn position_report(from_radio: &FromRadio) -> Option<(u32, (f64, f64))> {
+    let Some(from_radio::PayloadVariant::Packet(packet)) = &from_radio.payload_variant else {
+        return None;
+    };
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+        return None;
+    };
+    if PortNum::try_from(data.portnum) != Ok(PortNum::PositionApp) {
+        return None;
+    }
+    let position = Position::decode(data.payload.as_slice()).ok()?;
+    Some((packet.from, coordinates(&position)?))
+}
+
+/// (node, metrics) of a telemetry packet
+fn telemetry_report(from_radio: &FromRadio) -> Option<(u32, Metrics)> {
+    let Some(from_radio::PayloadVariant::Packet(packet)) = &from_radio.payload_variant else {
+        return None;
+    };
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+        return None;
+    };
+    if PortNum::try_from(data.portnum) != Ok(PortNum::TelemetryApp) {
+        return None;
+    }
+    let telemetry = Telemetry::decode(data.payload.as_slice()).ok()?;
+    Some((packet.from, Metrics::from_telemetry(&telemetry)?))
+}
+
+/// Nodes a node hears and their SNR, from its NeighborInfo
+fn neighbor_report(from_radio: &FromRadio) -> Option<(u32, Vec<(u32, f32)>)> {
+    let Some(from_radio::PayloadVariant::Packet(packet)) = &from_radio.payload_variant else {
+        return None;
+    };
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+        return None;
+    };
+    if PortNum::try_from(data.portnum) != Ok(PortNum::NeighborinfoApp) {
+        return None;
+    }
+    let info = NeighborInfo::decode(data.payload.as_slice()).ok()?;
+    let neighbors = info
+        .neighbors
+        .iter()
+        .map(|neighbor| (neighbor.node_id, neighbor.snr))
+        .collect();
+    Some((packet.from, neighbors))
+}
+
+/// Waypoint a node shared, to keep
+fn waypoint_report(from_radio: &FromRadio) -> Option<storage::Waypoint> {
+    let Some(from_radio::PayloadVariant::Packet(packet)) = &from_radio.payload_variant else {
+        return None;
+    };
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+        return None;
+    };
+    if PortNum::try_from(data.portnum) != Ok(PortNum::WaypointApp) {
+        return None;
+    }
+    let waypoint = Waypoint::decode(data.payload.as_slice()).ok()?;
+    Some(storage::Waypoint {
+        id: waypoint.id,
+        name: waypoint.name,
+        description: waypoint.description,
+        latitude_i: waypoint.latitude_i?,
+        longitude_i: waypoint.longitude_i?,
+        expire: waypoint.expire,
+        from: packet.from,
+        ts: now_ms(),
+        locked_to: waypoint.locked_to,
+    })
+}
+
+/// The waypoint as the radios send it
+fn waypoint_packet(waypoint: storage::Waypoint) -> Waypoint {
+    Waypoint {
+        id: waypoint.id,
+        name: waypoint.name,
+        description: waypoint.description,
+        latitude_i: Some(waypoint.latitude_i),
+        longitude_i: Some(waypoint.longitude_i),
+        expire: waypoint.expire,
+        locked_to: waypoint.locked_to,
+        ..Default::default()
+    }
+}
+
+fn alert_text(bbs: &service::BBS, alert: &watchdog::Alert) -> Result<String> {
+    Ok(match alert {
+        watchdog::Alert::Silent { node, since } => format!(
+            "Watch: {} not heard for {}m",
+            bbs.node_name(*node)?,
+            since.as_secs() / 60
+        ),
+        watchdog::Alert::Back { node } => format!("Watch: {} is back", bbs.node_name(*node)?),
+        watchdog::Alert::LowBattery { node, level } => {
+            format!("Watch: {} battery at {}%", bbs.node_name(*node)?, level)
+        }
+    })
+}
+
+/// Radio to alert the sysop through, the one that heard their node last
+async fn sysop_radio<'a>(radios: &'a radios::Radios, sysop_node: &str) -> &'a Handler {
+    for handler in radios.iter() {
+        if let Some(node) = handler.state.read().await.resolve_node(sysop_node) {
+            return radios.route(node);
+        }
+    }
+    radios.primary()
+}
+
+/// Delivery of a reply as the mesh reports it, None for received texts
+fn delivery_state(status: &TextMessageStatus) -> Option<delivery::Delivery> {
+    Some(match status {
+        TextMessageStatus::Sent => delivery::Delivery::Sent,
+        TextMessageStatus::Recieved => return None,
+        TextMessageStatus::ImplicitAck => delivery::Delivery::Relayed,
+        TextMessageStatus::ExplicitAck => delivery::Delivery::Delivered,
+        TextMessageStatus::RoutingError(error) => {
+            delivery::Delivery::Error(error.as_str_name().to_string())
+        }
+        TextMessageStatus::Retrying(attempt) => delivery::Delivery::Retrying(*attempt),
+        TextMessageStatus::Failed => delivery::Delivery::Failed,
+    })
+}
+
+/// Logs the texts that ran out of retries
+async fn log_failed_deliveries(mut status_rx: StatusReceiver, state: State) {
+    while let Some(status) = status_rx.recv().await {
+        if let Status::UpdatedMessage(id) = status
+            && let Some(msg) = state.read().await.message(id)
+            && matches!(msg.status, TextMessageStatus::Failed)
+        {
+            warn!(target: "bbs", "Delivery to {} failed: {}", msg.to, msg.text);
+        }
+    }
+}
+
+/// What the board tells systemd
+#[derive(Debug, Clone, Copy)]
+enum Systemd {
+    /// The radio is configured
+    Ready,
+    /// The mesh service is alive, see `WatchdogSec=`
+    Watchdog,
+}
+
+/// Reports to systemd when built with the `systemd` feature and run as a
+/// `Type=notify` service, does nothing otherwise
+fn notify_systemd(state: Systemd) {
+    #[cfg(feature = "systemd")]
+    {
+        let state = match state {
+            Systemd::Ready => sd_notify::NotifyState::Ready,
+            Systemd::Watchdog => sd_notify::NotifyState::Watchdog,
+        };
+        if let Err(err) = sd_notify::notify(false, &[state]) {
+            warn!(target: "bbs", "Cannot notify systemd: {err}");
+        }
+    }
+    #[cfg(not(feature = "systemd"))]
+    let _ = state;
+}
+
+// Ctrl+C, or SIGTERM from systemd or docker
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+pub(crate) async fn run_bbs<D: Screen>(config: Config, display: D) -> Result<()> {
+    let transports = radios::ble_devices(&config)?
+        .into_iter()
+        .map(Transport::Ble)
+        .collect();
+    run_bbs_on(config, display, transports).await
+}
+
+/// Runs the board on the radios or replay given, it returns once a handler
+/// is cancelled
+pub(crate) async fn run_bbs_on<D: Screen>(
+    config: Config,
+    display: D,
+    transports: Vec<Transport>,
+) -> Result<()> {
+    let mut pages = Pages::new(display);
     let mut packet_count = 0;
+    let mesh_codec = codec::by_name(&config.mesh_codec)?;
+    let display_codec = codec::by_name(&config.display_codec)?;
+    let display_codec = display_codec.as_ref();
 
-    info(&mut display, 0, "Starting MeshBoard");
+    info(&mut pages, display_codec, 0, "Starting MeshBoard");
 
-    let storage = storage::Storage::open(Path::new("./meshboard.db"))?;
-    let mut bbs = service::BBS::new(storage);
+    let storage = storage::Storage::with_backend(config.storage, Path::new(&config.db_path))?;
+    let stats = storage.stats()?;
+    info!(
+        target: "storage",
+        "Storage {} at {}: {} users, {} channels, {} messages",
+        config.storage, config.db_path, stats.users, stats.channels, stats.messages
+    );
+    let mut bbs = service::BBS::new(storage, config.bbs_options());
     bbs.init().await?;
 
-    let ble_device = std::env::var("BLE_DEVICE")?;
-    info(&mut display, 0, &format!("Connect {ble_device}..."));
+    let mut schedule_entries = schedule::load(Path::new(&config.schedule_path))?;
+    if !config.digest_channel.is_empty() {
+        schedule_entries.push(
+            format!(
+                "daily 00:00 {} {}",
+                config.digest_channel,
+                counters::PLACEHOLDER
+            )
+            .parse()?,
+        );
+    }
+    info!(
+        target: "bbs",
+        "{} scheduled broadcasts, {} announcements",
+        schedule_entries.len(),
+        bbs.announcements()?.len()
+    );
+    let mut scheduler = schedule::Scheduler::new(
+        schedule::AirtimeBudget::new(config.broadcast_budget),
+        chrono::Local::now().naive_local(),
+    );
+
+    let sources: Vec<_> = transports
+        .iter()
+        .map(|transport| match transport {
+            Transport::Ble(device) => device.clone(),
+            Transport::Replay(path) => path.display().to_string(),
+        })
+        .collect();
+    info(
+        &mut pages,
+        display_codec,
+        0,
+        &format!("Connect {}...", sources.join(",")),
+    );
 
-    let mut handler = crate::mesh::service::Service::from_ble(&ble_device).await?;
-    info(&mut display, 0, "Booting...");
-    if let Err(err) = handler.wait_for_boot_ready(30).await {
-        println!("Error: {}", err);
+    let (mut radios, mut status_rx) =
+        radios::Radios::connect(&transports, config.mesh_options()).await?;
+    info(&mut pages, display_codec, 0, "Booting...");
+    for handler in radios.iter_mut() {
+        if let Err(err) = handler.wait_for_boot_ready(30).await {
+            warn!(target: "bbs", "Radio not ready: {err}");
+        }
+    }
+    info(&mut pages, display_codec, 0, "Ready");
+    if let Err(err) = show_contact(&mut pages, &radios).await {
+        warn!(target: "screen", "Cannot show the contact QR code: {err}");
     }
-    info(&mut display, 0, "Ready");
+
+    // The node databases were loaded while booting
+    let mut heard_on = Vec::new();
+    for (radio, handler) in radios.iter().enumerate() {
+        let state = handler.state.read().await;
+        let mut nodes: Vec<_> = state
+            .users()
+            .map(|(num, user)| node_from_user(num, user))
+            .collect();
+        if let Some(metadata) = state.my_metadata()
+            && let Ok(num) = state.my_node_num()
+        {
+            nodes.push(node_from_metadata(num, metadata));
+        }
+        for node in nodes {
+            bbs.node_seen(node)?;
+        }
+        for (num, heard) in state.heard() {
+            bbs.node_heard(sighting(&state, num, heard))?;
+            heard_on.push((heard.ts, num, radio));
+        }
+        for (num, ts, coordinates) in state.last_positions() {
+            bbs.record_position(num, ts, coordinates)?;
+        }
+    }
+    // Oldest first, so the radio that heard a node last wins
+    heard_on.sort();
+    for (_, num, radio) in heard_on {
+        radios.heard(num, radio);
+    }
+
+    // Posts relayed to the bridges, and posts coming from them
+    let (posts_tx, _) = tokio::sync::broadcast::channel::<service::Post>(MAX_RELAYED_POSTS);
+    let (alerts_tx, _) = tokio::sync::broadcast::channel::<String>(MAX_RELAYED_POSTS);
+    let (inbound_tx, mut inbound_rx) = tokio::sync::mpsc::unbounded_channel::<service::Post>();
+    let primary = radios.primary();
+    if !config.mqtt_host.is_empty() {
+        let bridge = crate::mqtt::run_bridge(
+            config.clone(),
+            primary.subscribe(),
+            primary.state.clone(),
+            posts_tx.subscribe(),
+            inbound_tx.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = bridge.await {
+                warn!(target: "bbs", "MQTT bridge stopped: {err}");
+            }
+        });
+    }
+    if config.telegram {
+        let bridge = crate::telegram::run_bridge(
+            config.clone(),
+            primary.subscribe(),
+            primary.state.clone(),
+            posts_tx.subscribe(),
+            alerts_tx.subscribe(),
+            inbound_tx.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = bridge.await {
+                warn!(target: "bbs", "Telegram bridge stopped: {err}");
+            }
+        });
+    }
+    let (rooms_tx, mut rooms_rx) = tokio::sync::mpsc::unbounded_channel();
+    if config.matrix {
+        // The rooms of the config win over the ones kept
+        let mut rooms = bbs.matrix_rooms()?;
+        rooms.extend(config.matrix_rooms.clone());
+        let bridge = crate::matrix::run_bridge(
+            config.clone(),
+            rooms,
+            posts_tx.subscribe(),
+            inbound_tx.clone(),
+            rooms_tx.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = bridge.await {
+                warn!(target: "bbs", "Matrix bridge stopped: {err}");
+            }
+        });
+    }
+    if !config.aprs_callsign.is_empty() {
+        #[cfg(feature = "aprs")]
+        {
+            let bridge = crate::aprs::run_bridge(
+                config.clone(),
+                primary.subscribe(),
+                primary.state.clone(),
+                posts_tx.subscribe(),
+            );
+            tokio::spawn(async move {
+                if let Err(err) = bridge.await {
+                    warn!(target: "bbs", "APRS-IS gateway stopped: {err}");
+                }
+            });
+        }
+        #[cfg(not(feature = "aprs"))]
+        warn!(target: "bbs", "APRS_CALLSIGN is set, but the aprs feature is not built");
+    }
+    // Emails of the users, and the replies to them
+    let (email_tx, email_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (mail_tx, mut mail_rx) = tokio::sync::mpsc::unbounded_channel();
+    if config.email {
+        #[cfg(feature = "email")]
+        {
+            let gateway = crate::email::run_gateway(config.clone(), email_rx, mail_tx);
+            tokio::spawn(async move {
+                if let Err(err) = gateway.await {
+                    warn!(target: "bbs", "Email gateway stopped: {err}");
+                }
+            });
+        }
+        #[cfg(not(feature = "email"))]
+        {
+            warn!(target: "bbs", "EMAIL is set, but the email feature is not built");
+            drop((email_rx, mail_tx));
+        }
+    }
+    let (forecast_tx, mut forecast_rx) = tokio::sync::mpsc::unbounded_channel();
+    if let Some(location) = config.weather_location {
+        let updater = crate::weather::run_updater(
+            location,
+            config.weather_days,
+            Duration::from_secs(config.weather_hours.max(1) * 60 * 60),
+            forecast_tx.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = updater.await {
+                warn!(target: "bbs", "Weather updates stopped: {err}");
+            }
+        });
+    }
+    for handler in radios.iter() {
+        tokio::spawn(log_failed_deliveries(
+            handler.subscribe(),
+            handler.state.clone(),
+        ));
+    }
+    let mut notify_interval = tokio::time::interval(NOTIFY_INTERVAL);
+    let mut schedule_interval = tokio::time::interval(SCHEDULE_INTERVAL);
+    let mut watch_interval = tokio::time::interval(WATCH_INTERVAL);
+    let mut prune_interval = tokio::time::interval(PRUNE_INTERVAL);
+    let mut page_interval = tokio::time::interval(Duration::from_secs(config.page_secs.max(1)));
+    let mut button_interval = tokio::time::interval(BUTTON_INTERVAL);
+    let mut sysinfo_interval = tokio::time::interval(SYSINFO_INTERVAL);
+    let mut sync_interval =
+        tokio::time::interval(Duration::from_secs(config.federation_sync_mins.max(1) * 60));
+    let mut watchdog =
+        watchdog::Watchdog::new(config.watch_silence, config.watch_battery, Instant::now());
+    let mut pace_interval = tokio::time::interval(PACE_INTERVAL);
+    let mut pacer = sender::Pacer::new(config.reply_delay);
+    // Texts in a row that could not be answered, and replies that could not
+    // be sent
+    let mut answer_failures = 0;
+    let mut reply_failures = 0;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
+        let mut alerts = Vec::new();
+        // Near the duty cycle of a radio, texts that can wait do
+        let congested = radios.is_congested().await;
         tokio::select! {
-            status = handler.status_rx.recv() => {
-                use crate::mesh::service::Status;
-                let Some(status) = status else { bail!("Channel closed"); };
+            Some((radio, status)) = status_rx.recv() => {
+                let handler = radios.get(radio);
+                let Some(status) = status else {
+                    if handler.cancel.is_cancelled() {
+                        break;
+                    }
+                    bail!("Channel closed");
+                };
                 match status {
                     Status::NewMessage(id) => {
-                        let (msg, short_name) = {
-                            let state = handler.state.read().await;
-                            let msg = state.messages.get(&id).unwrap().clone();
-                            if msg.to != state.my_node_num().await {
-                                continue;
+                        // A text that fails is logged and counted, the board
+                        // goes on with the next one. Only the storage failing
+                        // stops it, what the radio lost is skipped.
+                        let answered: Result<()> = async {
+                            let (msg, short_name, position) = {
+                                let state = handler.state.read().await;
+                                let (Some(msg), Ok(me)) = (state.message(id), state.my_node_num()) else {
+                                    warn!(target: "bbs", "Cannot answer text {id}: gone from the radio state");
+                                    bbs.text_failed();
+                                    return Ok(());
+                                };
+                                if msg.from == me {
+                                    bbs.reply_sent(msg.to, id, &msg.text);
+                                    return Ok(());
+                                }
+                                if msg.to != me {
+                                    return Ok(());
+                                }
+                                let short_name = state.get_short_name_by_node_id(msg.from).unwrap_or("?".to_string());
+                                let position = state.get_position_by_node_id(msg.from);
+                                (msg, short_name, position)
+                            };
+                            let pk_hash = msg.pk_hash;
+                            let sender = service::Sender {
+                                node: msg.from,
+                                pk_hash,
+                                short_name: short_name.clone(),
+                                position,
+                                signal: msg.signal,
+                                encrypted: msg.pki_encrypted,
+                                received: Some(msg.ts),
+                                packet: Some(id),
+                            };
+                            if bbs.is_blocked(&sender)? {
+                                debug!(target: "bbs", "Dropped text from banned {}", format_node_id(msg.from));
+                                return Ok(());
+                            }
+                            if let Err(err) = bbs.keep_packet(id, msg.from, &msg.raw) {
+                                warn!(target: "bbs", "Cannot keep packet {id}: {err}");
+                            }
+                            let span = info_span!(target: "bbs", "command", node = %format_node_id(msg.from), user = %short_name, radio);
+                            let command = mesh_codec.decode(&msg.text);
+                            // Commands run even when congested, so rate limits and
+                            // federation frames are taken, what they answer may wait
+                            let mut response_msgs = bbs.handle(&sender, &command).instrument(span).await?;
+                            if handler.state.write().await.is_congested(Instant::now()) {
+                                response_msgs = pacer.congested(msg.from, response_msgs, Instant::now());
+                            }
+                            // The command may have been a nick change
+                            let nickname = bbs.nickname(&storage::UserPkHash(pk_hash))?;
+                            for handler in radios.iter() {
+                                handler.state.write().await.set_nickname(msg.from, &nickname);
+                            }
+                            info(&mut pages, display_codec, 1, &format!("{}:{}", short_name, hex::encode(pk_hash)));
+                            info(&mut pages, display_codec, 2, &format!("> {}", msg.text));
+                            // Replies go back through the radio the command came in on,
+                            // paced by the loop
+                            let response_msgs = sender::coalesce(&response_msgs, config.max_payload);
+                            let mut replies = Vec::new();
+                            for (n, response_msg) in response_msgs.iter().enumerate() {
+                                info(&mut pages, display_codec, 3+n, &format!("< {}", response_msg));
+                                let text = mesh_codec.encode(response_msg);
+                                replies.push((response_msg.clone(), chunker::split(&text, config.max_payload)));
+                                let urgency = sender::urgency(response_msg);
+                                pacer.push(sender::Outgoing { node: msg.from, radio, channel: msg.channel, text, urgency }, Instant::now());
+                            }
+                            bbs.replied(msg.from, &command, replies);
+                            Ok(())
+                        }.await;
+                        match answered {
+                            Ok(()) => answer_failures = 0,
+                            Err(err) => {
+                                answer_failures += 1;
+                                warn!(target: "bbs", "Cannot answer text {id}: {err}");
+                                bbs.text_failed();
+                                // Nothing is read or written, the storage is likely gone
+                                if answer_failures >= MAX_FAILURES {
+                                    bail!("{answer_failures} texts in a row could not be answered, last: {err}");
+                                }
+                            }
+                        }
+                    },
+                    Status::UpdatedMessage(id) => {
+                        let msg = handler.state.read().await.message(id);
+                        if let Some(delivery) = msg.as_ref().and_then(|msg| delivery_state(&msg.status))
+                            && let Err(err) = bbs.reply_delivery(id, delivery)
+                        {
+                            warn!(target: "bbs", "Cannot count a reply delivery: {err}");
+                        }
+                        // Acks of texts no longer tracked have no round trip
+                        if let Some(msg) = &msg {
+                            let ended = match msg.status {
+                                TextMessageStatus::ExplicitAck => msg.round_trip.map(Some),
+                                TextMessageStatus::Failed => Some(None),
+                                _ => None,
+                            };
+                            if let Some(round_trip) = ended {
+                                bbs.delivery_ended(msg.to, id, round_trip);
                             }
-                            let short_name = state.get_short_name_by_node_id(msg.from).unwrap_or("?".to_string());
-                            (msg, short_name)
-                        };
-                        let pk_hash = msg.pk_hash;
-                        let response_msgs = bbs.handle(pk_hash,&short_name, &msg.text).await?;
-                        info(&mut display, 1, &format!("{}:{}", short_name, hex::encode(pk_hash)));
-                        info(&mut display, 2, &format!("> {}", msg.text));
-                        for (n, response_msg) in response_msgs.iter().enumerate() {
-                            info(&mut display, 3+n, &format!("< {}", response_msg));
-                            handler.send_text(response_msg, Destination::Node(msg.from)).await?;
                         }
                     },
-                    Status::UpdatedMessage(_msg) => {},
                     Status::Heartbeat(_packet_count) => {
-                        info(&mut display, 0, &format!("Stats {} {} ", SPINNER[spinner], packet_count));
-                        spinner = (spinner + 1) % SPINNER.len();
+                        notify_systemd(Systemd::Watchdog);
+                        pages.heartbeat(packet_count);
+                        show(&mut pages, display_codec);
                     },
-                    Status::FromRadio(_) => {
+                    Status::FromRadio(from_radio) => {
                         packet_count += 1;
+                        if let Some(node) = node_report(&from_radio)
+                            && let Err(err) = bbs.node_seen(node)
+                        {
+                            warn!(target: "bbs", "Cannot update node inventory: {err}");
+                        }
+                        if let Some(from_radio::PayloadVariant::Packet(packet)) = &from_radio.payload_variant {
+                            alerts.extend(watchdog.heard(packet.from, Instant::now()));
+                            let heard = Heard::from_packet(packet, now_ms());
+                            let sighting = sighting(&*handler.state.read().await, packet.from, &heard);
+                            radios.heard(packet.from, radio);
+                            if let Err(err) = bbs.node_heard(sighting) {
+                                warn!(target: "bbs", "Cannot record node sighting: {err}");
+                            }
+                        }
+                        if let Some((node, coordinates)) = position_report(&from_radio)
+                            && let Err(err) = bbs.record_position(node, now_ms(), coordinates)
+                        {
+                            warn!(target: "bbs", "Cannot record position: {err}");
+                        }
+                        if let Some((node, neighbors)) = neighbor_report(&from_radio)
+                            && let Err(err) = bbs.record_neighbors(node, &neighbors)
+                        {
+                            warn!(target: "bbs", "Cannot record neighbors: {err}");
+                        }
+                        if let Some(waypoint) = waypoint_report(&from_radio)
+                            && let Err(err) = bbs.record_waypoint(waypoint)
+                        {
+                            warn!(target: "bbs", "Cannot record waypoint: {err}");
+                        }
+                        if let Some((node, metrics)) = telemetry_report(&from_radio) {
+                            if let Some(level) = metrics.battery_level {
+                                match bbs.watched() {
+                                    Ok(watched) if watched.contains(&node) => alerts.extend(watchdog.battery(node, level)),
+                                    Ok(_) => {}
+                                    Err(err) => warn!(target: "bbs", "Cannot read the watched nodes: {err}"),
+                                }
+                            }
+                            if let Err(err) = bbs.record_telemetry(node, now_ms(), metrics) {
+                                warn!(target: "bbs", "Cannot record telemetry: {err}");
+                            }
+                        }
                     },
-                    Status::Ready => {},
+                    Status::Ready => notify_systemd(Systemd::Ready),
+                    Status::Dropped(lost) => {
+                        warn!(target: "bbs", "{lost} events or texts lost, the board fell behind the radio");
+                    }
+                }
+            }
+            _ = notify_interval.tick(), if !congested => {
+                match bbs.next_notification() {
+                    // From the admin broadcast command, to the mesh of every radio
+                    Some(notification) if notification.to == BROADCAST_ADDR => {
+                        let text = mesh_codec.encode(&notification.text);
+                        for handler in radios.iter() {
+                            if let Err(err) = handler.send_text_as(text.clone(), Destination::Broadcast, 0, Urgency::Notification).await {
+                                warn!(target: "bbs", "Cannot broadcast: {err}");
+                            }
+                        }
+                    }
+                    Some(notification) => {
+                        if let Err(err) = radios.route(notification.to).send_text_as(mesh_codec.encode(&notification.text), Destination::Node(notification.to), 0, Urgency::Notification).await {
+                            warn!(target: "bbs", "Cannot notify {}: {err}", format_node_id(notification.to));
+                        }
+                    }
+                    None => {}
                 }
             }
-            _ = handler.cancel.cancelled() => break,
+            _ = schedule_interval.tick() => {
+                let mut entries = schedule_entries.clone();
+                match bbs.announcements() {
+                    Ok(announcements) => entries.extend(announcements),
+                    Err(err) => warn!(target: "bbs", "Cannot read the announcements: {err}"),
+                }
+                let forecast = bbs.forecast().unwrap_or_else(|err| {
+                    warn!(target: "bbs", "Cannot read the forecast: {err}");
+                    None
+                });
+                // Texts kept back while congested go first once it is not
+                let mut due = if congested { Vec::new() } else { scheduler.deferred() };
+                due.extend(scheduler.due(&entries, chrono::Local::now().naive_local()));
+                for mut entry in due {
+                    let Some(text) = crate::weather::expand(&entry.text, forecast.as_ref()) else {
+                        warn!(target: "bbs", "Skipped scheduled text, no forecast yet: {}", entry.text);
+                        continue;
+                    };
+                    entry.text = text;
+                    if congested && (entry.text.contains(counters::PLACEHOLDER) || entry.targets.contains(&schedule::Target::Broadcast)) {
+                        info!(target: "bbs", "Deferred scheduled text, the mesh is congested: {}", entry.text);
+                        scheduler.defer(entry);
+                        continue;
+                    }
+                    if entry.text.contains(counters::PLACEHOLDER) {
+                        match bbs.digest() {
+                            Ok(digest) => entry.text = entry.text.replace(counters::PLACEHOLDER, &digest),
+                            Err(err) => {
+                                warn!(target: "bbs", "Skipped scheduled text, cannot read the stats: {err}");
+                                continue;
+                            }
+                        }
+                    }
+                    for target in &entry.targets {
+                        match target {
+                            schedule::Target::Channel(ch) => {
+                                if let Err(err) = bbs.post_as_sysop(ch, &entry.text) {
+                                    warn!(target: "bbs", "Scheduled post to {ch} failed: {err}");
+                                }
+                            }
+                            schedule::Target::Broadcast => {
+                                let text = mesh_codec.encode(&entry.text);
+                                if scheduler.budget.try_spend(text.len(), std::time::Instant::now()) {
+                                    for handler in radios.iter() {
+                                        if let Err(err) = handler.send_text_as(text.clone(), Destination::Broadcast, 0, Urgency::Bulk).await {
+                                            warn!(target: "bbs", "Cannot broadcast a scheduled text: {err}");
+                                        }
+                                    }
+                                } else {
+                                    warn!(target: "bbs", "Skipped scheduled broadcast, over airtime budget: {}", entry.text);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            _ = watch_interval.tick() => {
+                match bbs.watched() {
+                    Ok(watched) => alerts.extend(watchdog.check(&watched, Instant::now())),
+                    Err(err) => warn!(target: "bbs", "Cannot read the watched nodes: {err}"),
+                }
+            }
+            _ = prune_interval.tick() => {
+                match bbs.prune() {
+                    Ok(0) => {}
+                    Ok(count) => info!(target: "bbs", "Pruned {count} messages"),
+                    Err(err) => warn!(target: "bbs", "Cannot prune messages: {err}"),
+                }
+            }
+            _ = page_interval.tick(), if config.page_secs > 0 => {
+                pages.next_page();
+                if let Err(err) = update_pages(&mut pages, &bbs, &radios).await {
+                    warn!(target: "screen", "Cannot update the pages: {err}");
+                }
+                show(&mut pages, display_codec);
+            }
+            _ = sysinfo_interval.tick() => {
+                if let Err(err) = update_system(&mut pages, &radios, Path::new(&config.db_path)).await {
+                    warn!(target: "screen", "Cannot read the system info: {err}");
+                }
+                show(&mut pages, display_codec);
+            }
+            _ = button_interval.tick() => {
+                if pages.button_pressed() {
+                    pages.next_page();
+                    if let Err(err) = update_pages(&mut pages, &bbs, &radios).await {
+                        warn!(target: "screen", "Cannot update the pages: {err}");
+                    }
+                    show(&mut pages, display_codec);
+                }
+            }
+            _ = sync_interval.tick(), if !config.federation_peers.is_empty() && !congested => {
+                match bbs.sync_requests() {
+                    Ok(requests) => {
+                        for (peer, request) in requests {
+                            if let Err(err) = radios.route(peer).send_text_as(mesh_codec.encode(&request), Destination::Node(peer), 0, Urgency::Bulk).await {
+                                warn!(target: "bbs", "Cannot ask {} to sync: {err}", format_node_id(peer));
+                            }
+                        }
+                    }
+                    Err(err) => warn!(target: "bbs", "Cannot read the sync cursors: {err}"),
+                }
+            }
+            Some(forecast) = forecast_rx.recv() => {
+                if let Err(err) = bbs.record_forecast(&forecast) {
+                    warn!(target: "bbs", "Cannot keep the forecast: {err}");
+                }
+            }
+            Some(rooms) = rooms_rx.recv() => {
+                if let Err(err) = bbs.record_matrix_rooms(&rooms) {
+                    warn!(target: "bbs", "Cannot keep the Matrix rooms: {err}");
+                }
+            }
+            Some(email) = mail_rx.recv() => {
+                match bbs.email_received(email) {
+                    Ok(true) => {}
+                    Ok(false) => warn!(target: "bbs", "Dropped an email that is not a reply from its recipient"),
+                    Err(err) => warn!(target: "bbs", "Cannot keep an email: {err}"),
+                }
+            }
+            Some(post) = inbound_rx.recv() => {
+                if let Err(err) = bbs.post_bridged(&post.channel, &post.author, &post.text) {
+                    warn!(target: "bbs", "Inbound post to {} failed: {err}", post.channel);
+                }
+            }
+            // Wakes the loop to send the replies as they are due
+            _ = pace_interval.tick(), if !pacer.is_empty() => {}
+            result = &mut shutdown => {
+                result?;
+                info!(target: "bbs", "Shutting down");
+                break;
+            }
+        }
+        while let Some(reply) = pacer.next_due(Instant::now(), congested) {
+            let sent = radios
+                .get(reply.radio)
+                .send_text_as(reply.text, Destination::Node(reply.node), reply.channel, reply.urgency)
+                .await;
+            match sent {
+                Ok(()) => reply_failures = 0,
+                // Nothing sent to the radio goes out anymore
+                Err(err) if err.is::<ServiceFinished>() => bail!("Cannot reply to {}: {err}", format_node_id(reply.node)),
+                // A full queue or an unknown node only loses this reply
+                Err(err) => {
+                    reply_failures += 1;
+                    warn!(target: "bbs", "Cannot reply to {} ({reply_failures} in a row): {err}", format_node_id(reply.node));
+                }
+            }
+        }
+        while let Some(post) = bbs.next_post() {
+            // Nobody listens when the bridges are disabled
+            let _ = posts_tx.send(post);
+        }
+        // Waypoints go to the mesh of every radio, once it is not congested
+        while !congested && let Some(waypoint) = bbs.next_waypoint() {
+            let packet = waypoint_packet(waypoint);
+            for handler in radios.iter() {
+                if let Err(err) = handler.send_waypoint(packet.clone()).await {
+                    warn!(target: "bbs", "Cannot share waypoint {}: {err}", packet.name);
+                }
+            }
+        }
+        while let Some(email) = bbs.next_email() {
+            let _ = email_tx.send(email);
+        }
+        for alert in alerts {
+            let text = match alert_text(&bbs, &alert) {
+                Ok(text) => text,
+                Err(err) => {
+                    warn!(target: "bbs", "Cannot word an alert: {err}");
+                    continue;
+                }
+            };
+            info(&mut pages, display_codec, 1, &text);
+            if !config.sysop_node.is_empty()
+                && let Err(err) = sysop_radio(&radios, &config.sysop_node)
+                    .await
+                    .send_text_as(
+                        mesh_codec.encode(&text),
+                        config.sysop_node.as_str(),
+                        0,
+                        Urgency::Notification,
+                    )
+                    .await
+            {
+                warn!(target: "bbs", "Cannot alert {}: {err}", config.sysop_node);
+            }
+            let _ = alerts_tx.send(text);
         }
     }
 
+    // Disconnects from the radios, then closes the database
+    radios.finish().await;
+    if let Err(err) = bbs.flush_stats() {
+        warn!(target: "bbs", "Cannot write the stats: {err}");
+    }
+    drop(bbs);
+    info(&mut pages, display_codec, 0, "Stopped");
+    if let Err(err) = pages.sleep() {
+        warn!(target: "screen", "Cannot put the display to sleep: {err}");
+    }
     Ok(())
 }
@@ -0,0 +1,281 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::bbs::mistake::{Mistake, mistake};
+use crate::bbs::service::Sender;
+use crate::bbs::storage::Storage;
+
+const DICE_MAX: u32 = 10;
+const DICE_MAX_SIDES: u32 = 1000;
+const DICE_USAGE: &str = "Usage: dice [NdM]";
+
+/// What a command gets to answer, besides its arguments
+pub struct Context<'a> {
+    pub sender: &'a Sender,
+    // Timestamp of the text, in ms
+    pub now: u64,
+    // Since the board started
+    pub uptime: Duration,
+    // For plugins that keep data, under keys of their own
+    pub storage: &'a Storage,
+}
+
+/// A command answered outside the core commands of the board. The core
+/// commands are not plugins, they stay in the parser of the board and win,
+/// so a plugin can not take their names.
+pub trait BbsCommand: Send + Sync {
+    fn name(&self) -> &str;
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+    /// Usage shown by help, e.g. `dice [NdM]`
+    fn help(&self) -> String;
+    /// Usage shown by `h <command>`, with examples and limits
+    fn detail(&self) -> String {
+        self.help()
+    }
+    fn handle(&self, ctx: &Context, args: &[&str]) -> Result<Vec<String>>;
+}
+
+/// When and how the text was heard, and the uptime of the board
+struct Ping;
+
+impl BbsCommand for Ping {
+    fn name(&self) -> &str {
+        "ping"
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &["echo"]
+    }
+
+    fn help(&self) -> String {
+        "ping".into()
+    }
+
+    fn detail(&self) -> String {
+        "ping: when and how the board heard you, SNR, RSSI and hops, to check your link".into()
+    }
+
+    fn handle(&self, ctx: &Context, _args: &[&str]) -> Result<Vec<String>> {
+        let mut pong = format!("pong at {}", super::service::format_time(ctx.now));
+        if let Some(signal) = ctx.sender.signal {
+            pong += &format!(", {signal}");
+        }
+        let uptime = ctx.uptime.as_millis() as u64;
+        pong += &format!(", up {}", super::service::format_age(uptime));
+        Ok(vec![pong])
+    }
+}
+
+/// Rolls `N` dice of `M` sides, `1d6` by default
+struct Dice;
+
+impl Dice {
+    fn parse(arg: Option<&str>) -> Result<(u32, u32)> {
+        let Some(arg) = arg else {
+            return Ok((1, 6));
+        };
+        let (count, sides) = arg
+            .to_lowercase()
+            .split_once('d')
+            .map(|(count, sides)| (count.to_string(), sides.to_string()))
+            .ok_or_else(|| Mistake(DICE_USAGE.into()))?;
+        let usage = |_| Mistake(DICE_USAGE.into());
+        let count = match count.as_str() {
+            "" => 1,
+            count => count.parse().map_err(usage)?,
+        };
+        let sides = sides.parse().map_err(usage)?;
+        if !(1..=DICE_MAX).contains(&count) || !(2..=DICE_MAX_SIDES).contains(&sides) {
+            mistake!("Up to {DICE_MAX} dice of 2 to {DICE_MAX_SIDES} sides");
+        }
+        Ok((count, sides))
+    }
+}
+
+impl BbsCommand for Dice {
+    fn name(&self) -> &str {
+        "dice"
+    }
+
+    fn help(&self) -> String {
+        "dice [NdM]".into()
+    }
+
+    fn detail(&self) -> String {
+        format!(
+            "dice [NdM]: rolls N dice of M sides, 1d6 by default, e.g. dice 2d20. \
+             Up to {DICE_MAX} dice of 2 to {DICE_MAX_SIDES} sides"
+        )
+    }
+
+    fn handle(&self, _ctx: &Context, args: &[&str]) -> Result<Vec<String>> {
+        let (count, sides) = Dice::parse(args.first().copied())?;
+        let rolls: Vec<u32> = (0..count).map(|_| rand::random_range(1..=sides)).collect();
+        let total: u32 = rolls.iter().sum();
+        let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+        Ok(vec![format!(
+            "{count}d{sides}: {} = {total}",
+            rolls.join("+")
+        )])
+    }
+}
+
+/// A fixed reply set in the config
+struct Canned {
+    name: String,
+    text: String,
+}
+
+impl BbsCommand for Canned {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn help(&self) -> String {
+        self.name.clone()
+    }
+
+    fn handle(&self, _ctx: &Context, _args: &[&str]) -> Result<Vec<String>> {
+        Ok(vec![self.text.clone()])
+    }
+}
+
+fn builtin(name: &str) -> Option<Arc<dyn BbsCommand>> {
+    match name {
+        "ping" => Some(Arc::new(Ping)),
+        "dice" => Some(Arc::new(Dice)),
+        _ => None,
+    }
+}
+
+/// The plugin commands enabled, looked up by name or alias
+#[derive(Clone, Default)]
+pub struct Registry {
+    commands: Vec<Arc<dyn BbsCommand>>,
+}
+
+impl Registry {
+    /// The built-in commands named, see [parse_builtins], and the canned
+    /// replies
+    pub fn new(builtins: &[String], canned: &[(String, String)]) -> Self {
+        let mut registry = Self::default();
+        for command in builtins.iter().filter_map(|name| builtin(name)) {
+            registry.register(command);
+        }
+        for (name, text) in canned {
+            registry.register(Arc::new(Canned {
+                name: name.clone(),
+                text: text.clone(),
+            }));
+        }
+        registry
+    }
+
+    /// Adds the command, replacing any other of the same name
+    pub fn register(&mut self, command: Arc<dyn BbsCommand>) {
+        self.commands
+            .retain(|registered| registered.name() != command.name());
+        self.commands.push(command);
+    }
+
+    pub fn find(&self, word: &str) -> Option<&dyn BbsCommand> {
+        self.commands
+            .iter()
+            .find(|command| command.name() == word || command.aliases().contains(&word))
+            .map(|command| command.as_ref())
+    }
+
+    /// Usage of the commands, ` | ` separated like the help of the board.
+    /// None if there are none.
+    pub fn help(&self) -> Option<String> {
+        let help: Vec<String> = self.commands.iter().map(|command| command.help()).collect();
+        (!help.is_empty()).then(|| help.join(" | "))
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.commands.iter().map(|command| command.name()))
+            .finish()
+    }
+}
+
+/// Comma separated names of built-in commands, e.g. `ping,dice`
+pub fn parse_builtins(s: &str) -> Result<Vec<String>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match builtin(name) {
+            Some(_) => Ok(name.to_string()),
+            None => bail!("Unknown built-in command: {name}"),
+        })
+        .collect()
+}
+
+/// Semicolon separated `<name>=<reply>`, the reply may have commas, e.g.
+/// `info=MeshBoard at the library;rules=Be kind, no ads`
+pub fn parse_canned(s: &str) -> Result<Vec<(String, String)>> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, text) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected <name>=<reply>: {entry}"))?;
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                bail!("Invalid command name: {name}");
+            }
+            Ok((name.to_string(), text.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registry() -> Result<()> {
+        let canned = parse_canned("info=MeshBoard at the library; rules = Be kind, no ads;")?;
+        let registry = Registry::new(&parse_builtins("ping, dice")?, &canned);
+        assert!(parse_builtins("ping,weather").is_err());
+        assert!(parse_canned("rules").is_err());
+        assert_eq!(
+            registry.help().as_deref(),
+            Some("ping | dice [NdM] | info | rules")
+        );
+        assert_eq!(
+            registry.find("echo").map(|command| command.name()),
+            Some("ping")
+        );
+        assert!(registry.find("weather").is_none());
+        assert_eq!(registry.find("info").unwrap().detail(), "info");
+        assert!(Registry::default().help().is_none());
+
+        let ctx = Context {
+            sender: &Sender::default(),
+            now: 0,
+            uptime: Duration::ZERO,
+            storage: &Storage::memory(),
+        };
+        let rules = registry.find("rules").unwrap();
+        assert_eq!(rules.handle(&ctx, &[])?, vec!["Be kind, no ads"]);
+
+        assert_eq!(Dice::parse(None)?, (1, 6));
+        assert_eq!(Dice::parse(Some("3D20"))?, (3, 20));
+        assert_eq!(Dice::parse(Some("d4"))?, (1, 4));
+        assert!(Dice::parse(Some("11d6")).is_err());
+        assert!(Dice::parse(Some("2d1")).is_err());
+        let err = Dice::parse(Some("xd6")).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&Mistake(DICE_USAGE.into())));
+        let roll = Dice.handle(&ctx, &["2d6"])?.remove(0);
+        let total: u32 = roll.rsplit(' ').next().unwrap().parse()?;
+        assert!(roll.starts_with("2d6: ") && (2..=12).contains(&total));
+        Ok(())
+    }
+}
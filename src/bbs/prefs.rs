@@ -0,0 +1,293 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, bail};
+
+use crate::bbs::mistake::mistake;
+use crate::bbs::storage::{ChannelId, Storage, UserId};
+
+/// A typed user preference stored as text in the preferences table
+pub struct Pref<T> {
+    pub key: &'static str,
+    pub default: fn() -> T,
+}
+
+impl<T: FromStr + ToString> Pref<T> {
+    pub fn get(&self, storage: &Storage, user_id: UserId) -> Result<T> {
+        Ok(storage
+            .get_preference(user_id, self.key)?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(self.default))
+    }
+
+    pub fn set(&self, storage: &Storage, user_id: UserId, value: &T) -> Result<()> {
+        storage.set_preference(user_id, self.key, &value.to_string())
+    }
+
+    pub fn reset(&self, storage: &Storage, user_id: UserId) -> Result<()> {
+        storage.remove_preference(user_id, self.key)
+    }
+}
+
+/// BBS nickname, empty if not registered
+pub const NICK: Pref<String> = Pref {
+    key: "nick",
+    default: String::new,
+};
+
+/// Why the board pushes a text to a user that did not ask for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Push {
+    /// New post in a subscribed channel
+    Channel,
+    /// New post in a subscribed channel that mentions the user
+    Mention,
+    /// New private mail
+    Mail,
+    /// Welcome text of a new or returning user
+    Motd,
+}
+
+/// What the board may push to a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyMode {
+    On,
+    Off,
+    Mentions,
+    /// Only private mail, nothing is pushed until the board has mail
+    MailOnly,
+}
+
+impl NotifyMode {
+    pub fn allows(self, push: Push) -> bool {
+        match self {
+            NotifyMode::On => true,
+            NotifyMode::Mentions => push != Push::Channel,
+            NotifyMode::MailOnly => push == Push::Mail,
+            NotifyMode::Off => false,
+        }
+    }
+}
+
+impl FromStr for NotifyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "on" => NotifyMode::On,
+            "off" => NotifyMode::Off,
+            "mentions" => NotifyMode::Mentions,
+            "mail-only" => NotifyMode::MailOnly,
+            _ => bail!("Expected on, off, mentions or mail-only"),
+        })
+    }
+}
+
+impl fmt::Display for NotifyMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            NotifyMode::On => "on",
+            NotifyMode::Off => "off",
+            NotifyMode::Mentions => "mentions",
+            NotifyMode::MailOnly => "mail-only",
+        })
+    }
+}
+
+/// What the board pushes to the user
+pub const NOTIFY: Pref<NotifyMode> = Pref {
+    key: "notify",
+    default: || NotifyMode::On,
+};
+
+/// Channel ids, stored comma separated
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Favorites(pub Vec<ChannelId>);
+
+impl FromStr for Favorites {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|cid| !cid.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Favorites)
+    }
+}
+
+impl fmt::Display for Favorites {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cids: Vec<String> = self.0.iter().map(ChannelId::to_string).collect();
+        f.write_str(&cids.join(","))
+    }
+}
+
+/// Channels listed first
+pub const FAVORITES: Pref<Favorites> = Pref {
+    key: "favorites",
+    default: Favorites::default,
+};
+
+/// Short replies, without acks or headers
+#[allow(dead_code)]
+pub const TERSE: Pref<bool> = Pref {
+    key: "terse",
+    default: || false,
+};
+
+/// Language of help and replies, empty for the one of the board
+pub const LANGUAGE: Pref<String> = Pref {
+    key: "lang",
+    default: String::new,
+};
+
+/// Offset from UTC in hours, used when showing dates
+#[allow(dead_code)]
+pub const TIMEZONE: Pref<i32> = Pref {
+    key: "tz",
+    default: || 0,
+};
+
+/// When a session of the user last started, in ms. Users from before it was
+/// kept count as just seen, not as new
+pub const LAST_SEEN: Pref<u64> = Pref {
+    key: "last_seen",
+    default: || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as u64)
+    },
+};
+
+/// Channel the user joined last, their session starts in it again after
+/// a restart
+pub const CHANNEL: Pref<ChannelId> = Pref {
+    key: "channel",
+    default: || 0,
+};
+
+/// Node that gets the mail notifications of the user, 0 if none yet
+pub const MAIL_NODE: Pref<u32> = Pref {
+    key: "mail_node",
+    default: || 0,
+};
+
+// Free-form profile fields, stored as preferences under the prefix
+const PROFILE_PREFIX: &str = "profile.";
+pub(crate) const PROFILE_MAX_FIELDS: usize = 8;
+const PROFILE_FIELD_MAX_LEN: usize = 16;
+pub(crate) const PROFILE_VALUE_MAX_LEN: usize = 100;
+
+/// Profile fields of the user, (field, value) sorted by field
+pub fn profile(storage: &Storage, user_id: UserId) -> Result<Vec<(String, String)>> {
+    Ok(storage
+        .get_preferences(user_id)?
+        .into_iter()
+        .filter_map(|preference| {
+            let field = preference.uid_key.1.strip_prefix(PROFILE_PREFIX)?;
+            Some((field.to_string(), preference.value))
+        })
+        .collect())
+}
+
+/// Sets a profile field, an empty value removes it. Fields are lowercase
+/// words, and their number and length are limited.
+pub fn set_profile_field(
+    storage: &Storage,
+    user_id: UserId,
+    field: &str,
+    value: &str,
+) -> Result<()> {
+    let field = field.to_lowercase();
+    if field.is_empty()
+        || field.chars().count() > PROFILE_FIELD_MAX_LEN
+        || !field.chars().all(|c| c.is_alphanumeric() || c == '_')
+    {
+        mistake!("Field names are words up to {PROFILE_FIELD_MAX_LEN} chars");
+    }
+    let key = format!("{PROFILE_PREFIX}{field}");
+    if value.is_empty() {
+        return storage.remove_preference(user_id, &key);
+    }
+    if value.chars().count() > PROFILE_VALUE_MAX_LEN {
+        mistake!("Up to {PROFILE_VALUE_MAX_LEN} chars per field");
+    }
+    let fields = profile(storage, user_id)?;
+    if fields.len() >= PROFILE_MAX_FIELDS && !fields.iter().any(|(name, _)| *name == field) {
+        mistake!("Up to {PROFILE_MAX_FIELDS} fields, unset one first");
+    }
+    storage.set_preference(user_id, &key, value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_typed_prefs() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        assert!(!TERSE.get(&s, 1)?);
+        TERSE.set(&s, 1, &true)?;
+        assert!(TERSE.get(&s, 1)?);
+        TERSE.reset(&s, 1)?;
+        assert!(!TERSE.get(&s, 1)?);
+
+        TIMEZONE.set(&s, 1, &-3)?;
+        assert_eq!(TIMEZONE.get(&s, 1)?, -3);
+
+        // Unparseable values fall back to the default
+        s.set_preference(1, TIMEZONE.key, "CET")?;
+        assert_eq!(TIMEZONE.get(&s, 1)?, 0);
+
+        NOTIFY.set(&s, 1, &NotifyMode::MailOnly)?;
+        assert_eq!(
+            s.get_preference(1, NOTIFY.key)?.as_deref(),
+            Some("mail-only")
+        );
+        assert_eq!(NOTIFY.get(&s, 1)?, NotifyMode::MailOnly);
+        assert!(!NotifyMode::Mentions.allows(Push::Channel));
+        assert!(NotifyMode::Mentions.allows(Push::Mention));
+        assert!(NotifyMode::MailOnly.allows(Push::Mail));
+        assert!(!NotifyMode::MailOnly.allows(Push::Mention));
+        assert!(!NotifyMode::Off.allows(Push::Mail));
+
+        assert_eq!(FAVORITES.get(&s, 1)?, Favorites(vec![]));
+        FAVORITES.set(&s, 1, &Favorites(vec![3, 1]))?;
+        assert_eq!(s.get_preference(1, FAVORITES.key)?.as_deref(), Some("3,1"));
+        assert_eq!(FAVORITES.get(&s, 1)?, Favorites(vec![3, 1]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        NICK.set(&s, 1, &"ann".to_string())?;
+        set_profile_field(&s, 1, "Location", "Barcelona")?;
+        set_profile_field(&s, 1, "bio", "Hiker")?;
+        set_profile_field(&s, 2, "bio", "Other user")?;
+        assert_eq!(
+            profile(&s, 1)?,
+            vec![
+                ("bio".to_string(), "Hiker".to_string()),
+                ("location".to_string(), "Barcelona".to_string())
+            ]
+        );
+        set_profile_field(&s, 1, "bio", "")?;
+        assert_eq!(profile(&s, 1)?.len(), 1);
+
+        assert!(set_profile_field(&s, 1, "my.field", "x").is_err());
+        assert!(set_profile_field(&s, 1, "bio", &"x".repeat(101)).is_err());
+        for n in 1..PROFILE_MAX_FIELDS {
+            set_profile_field(&s, 1, &format!("f{n}"), "x")?;
+        }
+        assert!(set_profile_field(&s, 1, "extra", "x").is_err());
+        set_profile_field(&s, 1, "location", "Girona")?;
+        Ok(())
+    }
+}
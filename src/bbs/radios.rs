@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Result, bail};
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::mesh::service::{Handler, Options, Service, Status, Transport};
+
+/// Index of a radio in [Radios], the order they were given in
+pub type RadioId = usize;
+
+const ROUTES_PATH: &str = "./meshboard.routes";
+
+/// The radios the board runs on. The first one is the primary, which the
+/// bridges follow and which sends what has no better radio.
+pub struct Radios {
+    handlers: Vec<Handler>,
+    routes: Routes,
+}
+
+/// Radio each node was last heard on, optionally persisted to disk so
+/// notifications find nodes that are not heard again after a restart. They
+/// are kept by radio name, the order of the radios may change.
+#[derive(Default)]
+pub struct Routes {
+    radios: HashMap<u32, RadioId>,
+    names: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl Routes {
+    /// Routes through radios that are no longer given are dropped
+    pub fn open(path: &Path, names: Vec<String>) -> Result<Self> {
+        let mut routes = Self {
+            names,
+            ..Default::default()
+        };
+        if path.exists() {
+            for line in fs::read_to_string(path)?.lines() {
+                let Some((node, name)) = line.split_once(' ') else {
+                    continue;
+                };
+                if let Ok(node) = u32::from_str_radix(node, 16)
+                    && let Some(radio) = routes.names.iter().position(|radio| radio == name)
+                {
+                    routes.radios.insert(node, radio);
+                }
+            }
+        }
+        routes.path = Some(path.to_path_buf());
+        Ok(routes)
+    }
+
+    /// Saved when the node moves to another radio
+    pub fn heard(&mut self, node: u32, radio: RadioId) -> Result<()> {
+        if self.radios.insert(node, radio) == Some(radio) {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Radio to reach the node through, the primary for unknown nodes
+    pub fn radio(&self, node: u32) -> RadioId {
+        self.radios.get(&node).copied().unwrap_or(0)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = self
+            .radios
+            .iter()
+            .filter_map(|(node, radio)| Some(format!("{node:08x} {}\n", self.names.get(*radio)?)))
+            .collect::<String>();
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// Devices listed in `BLE_DEVICE`, comma separated, the primary first
+pub fn ble_devices(config: &Config) -> Result<Vec<String>> {
+    parse_devices(&config.ble_device)
+}
+
+fn parse_devices(devices: &str) -> Result<Vec<String>> {
+    let devices: Vec<_> = devices
+        .split(',')
+        .map(str::trim)
+        .filter(|device| !device.is_empty())
+        .map(str::to_string)
+        .collect();
+    if devices.is_empty() {
+        bail!("No BLE device given, set BLE_DEVICE or use --device");
+    }
+    Ok(devices)
+}
+
+impl Radios {
+    /// Connects to every radio, and the status events of all of them as
+    /// (radio, event), None once the radio is gone
+    pub async fn connect(
+        transports: &[Transport],
+        options: Options,
+    ) -> Result<(Self, UnboundedReceiver<(RadioId, Option<Status>)>)> {
+        let (status_tx, status_rx) = unbounded_channel();
+        let mut handlers = Vec::new();
+        for (radio, transport) in transports.iter().enumerate() {
+            info!(target: "bbs", "Radio {radio}: {transport:?}");
+            let handler = Service::connect(transport, options.clone()).await?;
+            let mut statuses = handler.subscribe();
+            let status_tx = status_tx.clone();
+            tokio::spawn(async move {
+                while let Some(status) = statuses.recv().await {
+                    if status_tx.send((radio, Some(status))).is_err() {
+                        return;
+                    }
+                }
+                let _ = status_tx.send((radio, None));
+            });
+            handlers.push(handler);
+        }
+        // Replays start from no routes, and leave the ones of the radios be
+        let names = transports
+            .iter()
+            .filter_map(|transport| match transport {
+                Transport::Ble(device) => Some(device.clone()),
+                Transport::Replay(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let routes = match names.len() == transports.len() {
+            true => Routes::open(Path::new(ROUTES_PATH), names).unwrap_or_else(|err| {
+                warn!(target: "bbs", "Cannot load the routes: {err}");
+                Routes::default()
+            }),
+            false => Routes::default(),
+        };
+        Ok((Self { handlers, routes }, status_rx))
+    }
+
+    pub fn primary(&self) -> &Handler {
+        &self.handlers[0]
+    }
+
+    pub fn get(&self, radio: RadioId) -> &Handler {
+        &self.handlers[radio]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Handler> {
+        self.handlers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Handler> {
+        self.handlers.iter_mut()
+    }
+
+    /// Records that a packet from `node` came in through `radio`
+    pub fn heard(&mut self, node: u32, radio: RadioId) {
+        if let Err(err) = self.routes.heard(node, radio) {
+            warn!(target: "bbs", "Cannot save the routes: {err}");
+        }
+    }
+
+    /// Radio that last heard the node, to reply through it
+    pub fn route(&self, node: u32) -> &Handler {
+        self.get(self.routes.radio(node))
+    }
+
+    /// Whether a radio is close to its duty cycle, texts that can wait
+    /// should
+    pub async fn is_congested(&self) -> bool {
+        for handler in &self.handlers {
+            if handler.state.write().await.is_congested(Instant::now()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Disconnects from every radio
+    pub async fn finish(self) {
+        for handler in self.handlers {
+            handler.finish().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_routes() -> Result<()> {
+        let mut routes = Routes::default();
+        assert_eq!(routes.radio(7), 0);
+        routes.heard(7, 1)?;
+        routes.heard(8, 2)?;
+        routes.heard(7, 2)?;
+        assert_eq!((routes.radio(7), routes.radio(8)), (2, 2));
+
+        let path = std::env::temp_dir().join(format!("meshboard-routes-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut routes = Routes::open(&path, vec!["radio-868".into(), "radio-433".into()])?;
+        routes.heard(7, 1)?;
+        routes.heard(8, 0)?;
+        // The radios swapped and one is gone
+        let routes = Routes::open(&path, vec!["radio-433".into()])?;
+        assert_eq!((routes.radio(7), routes.radio(8)), (0, 0));
+        let routes = Routes::open(&path, vec!["radio-433".into(), "radio-868".into()])?;
+        assert_eq!((routes.radio(7), routes.radio(8)), (0, 1));
+        fs::remove_file(&path)?;
+
+        assert_eq!(
+            parse_devices("radio-868, radio-433,")?,
+            vec!["radio-868", "radio-433"]
+        );
+        assert!(parse_devices(" , ").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use mini_moka::sync::Cache;
+use std::time::{Duration, Instant};
+
+use crate::bbs::storage::UserPkHash;
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    // Throttled commands since the last allowed one
+    strikes: u32,
+    muted_until: Option<Instant>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Throttled {
+    /// Out of tokens, one will be available after the duration
+    RetryAfter(Duration),
+    /// Hit the limit too many times in a row, muted for the duration
+    Muted(Duration),
+    /// Still muted, the command should be silently dropped
+    Silenced,
+}
+
+/// Where a user stands with the limiter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allowance {
+    /// Commands they can send right away
+    pub tokens: u32,
+    /// Time left muted, None when not muted
+    pub muted: Option<Duration>,
+}
+
+/// Per-user token bucket: `burst` commands at once, refilled at one command
+/// every `refill` interval. Users throttled `mute_after` times in a row are
+/// muted for `mute_duration`, a `mute_after` of 0 never mutes.
+pub struct RateLimiter {
+    burst: f64,
+    refill: Duration,
+    mute_after: u32,
+    mute_duration: Duration,
+    buckets: Cache<UserPkHash, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, refill: Duration, mute_after: u32, mute_duration: Duration) -> Self {
+        Self {
+            burst: burst as f64,
+            refill,
+            mute_after,
+            mute_duration,
+            buckets: Cache::builder()
+                .max_capacity(1024)
+                .time_to_idle((refill * burst.max(1)).max(mute_duration))
+                .build(),
+        }
+    }
+
+    fn refilled(&self, user: &UserPkHash, now: Instant) -> Bucket {
+        let Some(mut bucket) = self.buckets.get(user) else {
+            return Bucket {
+                tokens: self.burst,
+                last: now,
+                strikes: 0,
+                muted_until: None,
+            };
+        };
+        let elapsed = now.saturating_duration_since(bucket.last);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() / self.refill.as_secs_f64()).min(self.burst);
+        bucket.last = now;
+        bucket
+    }
+
+    fn retry_after(&self, bucket: &Bucket) -> Option<Duration> {
+        if bucket.tokens >= 1.0 {
+            return None;
+        }
+        Some(self.refill.mul_f64(1.0 - bucket.tokens))
+    }
+
+    /// Takes a token, or returns why the command has to be dropped
+    pub fn check(&self, user: &UserPkHash) -> Result<(), Throttled> {
+        self.check_at(user, Instant::now())
+    }
+
+    /// The allowance of the user, without taking a token
+    pub fn state(&self, user: &UserPkHash, now: Instant) -> Allowance {
+        let bucket = self.refilled(user, now);
+        Allowance {
+            tokens: bucket.tokens as u32,
+            muted: bucket
+                .muted_until
+                .filter(|until| *until > now)
+                .map(|until| until - now),
+        }
+    }
+
+    pub fn check_at(&self, user: &UserPkHash, now: Instant) -> Result<(), Throttled> {
+        let mut bucket = self.refilled(user, now);
+        let result = if bucket.muted_until.is_some_and(|until| until > now) {
+            Err(Throttled::Silenced)
+        } else if let Some(retry_after) = self.retry_after(&bucket) {
+            bucket.strikes += 1;
+            if self.mute_after > 0 && bucket.strikes >= self.mute_after {
+                bucket.strikes = 0;
+                bucket.muted_until = Some(now + self.mute_duration);
+                Err(Throttled::Muted(self.mute_duration))
+            } else {
+                Err(Throttled::RetryAfter(retry_after))
+            }
+        } else {
+            bucket.tokens -= 1.0;
+            bucket.strikes = 0;
+            bucket.muted_until = None;
+            Ok(())
+        };
+        self.buckets.insert(user.clone(), bucket);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10), 0, Duration::ZERO);
+        let user = UserPkHash([1u8; 32]);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at(&user, now), Ok(()));
+        assert_eq!(limiter.check_at(&user, now), Ok(()));
+        assert_eq!(
+            limiter.check_at(&user, now),
+            Err(Throttled::RetryAfter(Duration::from_secs(10)))
+        );
+        assert_eq!(
+            limiter.check_at(&user, now + Duration::from_secs(4)),
+            Err(Throttled::RetryAfter(Duration::from_secs(6)))
+        );
+        assert_eq!(
+            limiter.check_at(&user, now + Duration::from_secs(10)),
+            Ok(())
+        );
+
+        let other = UserPkHash([2u8; 32]);
+        assert_eq!(
+            limiter.state(&other, now),
+            Allowance {
+                tokens: 2,
+                muted: None,
+            }
+        );
+        assert_eq!(limiter.check_at(&other, now), Ok(()));
+        assert_eq!(limiter.state(&other, now).tokens, 1);
+    }
+
+    #[test]
+    fn test_auto_mute() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(10), 2, Duration::from_secs(60));
+        let user = UserPkHash([1u8; 32]);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at(&user, now), Ok(()));
+        assert!(matches!(
+            limiter.check_at(&user, now),
+            Err(Throttled::RetryAfter(_))
+        ));
+        assert_eq!(
+            limiter.check_at(&user, now),
+            Err(Throttled::Muted(Duration::from_secs(60)))
+        );
+        assert_eq!(
+            limiter.check_at(&user, now + Duration::from_secs(30)),
+            Err(Throttled::Silenced)
+        );
+        // Asking takes no token
+        for _ in 0..2 {
+            assert_eq!(
+                limiter.state(&user, now + Duration::from_secs(40)),
+                Allowance {
+                    tokens: 1,
+                    muted: Some(Duration::from_secs(20)),
+                }
+            );
+        }
+        assert_eq!(
+            limiter.check_at(&user, now + Duration::from_secs(61)),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use reedline::{DefaultPrompt, DefaultPromptSegment, FileBackedHistory, Reedline, Signal};
+use sha2::{Digest, Sha256};
+
+use super::service::{self, BBS, Sender};
+use super::storage::{Storage, UserPkHash};
+use crate::config::Config;
+use crate::mesh::service::{NameResolver, format_node_id};
+
+const HISTORY_PATH: &str = "./meshboard-bbs.history";
+const HISTORY_SIZE: usize = 1000;
+
+/// Made up user of the console, the same short name is always the same user
+fn sender(short_name: &str) -> Sender {
+    let pk_hash: [u8; 32] = Sha256::digest(short_name.as_bytes()).into();
+    Sender {
+        node: u32::from_le_bytes(pk_hash[..4].try_into().unwrap()),
+        pk_hash,
+        short_name: short_name.to_string(),
+        position: None,
+        signal: None,
+        // The console is as private as an encrypted direct message
+        encrypted: true,
+        received: None,
+        packet: None,
+    }
+}
+
+/// Runs the BBS commands typed at the console as if sent by `short_name`,
+/// without a radio. `/as <short_name>` switches user, `/stats` shows the
+/// stats of the board, and with `admin` every console user is an admin.
+/// Notifications the commands raise are printed.
+pub async fn run_repl(config: Config, short_name: &str, admin: bool) -> Result<()> {
+    let storage = Storage::with_backend(config.storage, Path::new(&config.db_path))?;
+    let options = service::Options {
+        // Nobody types fast enough to flood the board
+        rate_limit_burst: 1000,
+        mute_after: 0,
+        ..config.bbs_options()
+    };
+    let mut bbs = BBS::new(storage, options);
+    bbs.init().await?;
+    let mut user = sender(short_name);
+    if admin {
+        bbs.add_admin(UserPkHash(user.pk_hash));
+    }
+    // Console users seen so far, to name who the notifications go to
+    let mut names = NameResolver::default();
+    names.radio_names(user.node, &user.short_name, "");
+
+    let history = FileBackedHistory::with_file(HISTORY_SIZE, PathBuf::from(HISTORY_PATH))?;
+    let mut editor = Reedline::create().with_history(Box::new(history));
+    println!("BBS on {} storage, Ctrl+D exits", config.storage);
+    loop {
+        let prompt = DefaultPrompt::new(
+            DefaultPromptSegment::Basic(user.short_name.clone()),
+            DefaultPromptSegment::Empty,
+        );
+        let line = match tokio::task::block_in_place(|| editor.read_line(&prompt))? {
+            Signal::Success(line) => line,
+            Signal::CtrlC => continue,
+            Signal::CtrlD => break,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(short_name) = line.strip_prefix("/as ") {
+            user = sender(short_name.trim());
+            if admin {
+                bbs.add_admin(UserPkHash(user.pk_hash));
+            }
+            names.radio_names(user.node, &user.short_name, "");
+            println!("Now {} {}", user.short_name, format_node_id(user.node));
+            continue;
+        }
+        if line == "/stats" {
+            println!("{}", bbs.stats_report()?);
+            continue;
+        }
+        match bbs.handle(&user, line).await {
+            Ok(replies) => replies.iter().for_each(|reply| println!("< {reply}")),
+            Err(err) => println!("Error: {err}"),
+        }
+        names.nickname(user.node, &bbs.nickname(&UserPkHash(user.pk_hash))?);
+        while let Some(notification) = bbs.next_notification() {
+            println!(
+                "{} < {}",
+                names.display_name(notification.to),
+                notification.text
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many messages a channel keeps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Policy {
+    /// Older messages are removed, zero keeps them forever
+    pub max_age: Duration,
+    /// Only the newest ones are kept, 0 keeps any number
+    pub max_count: usize,
+}
+
+impl Policy {
+    pub fn keeps_all(&self) -> bool {
+        self.max_age.is_zero() && self.max_count == 0
+    }
+}
+
+/// `<days>:<max messages>`, 0 is unlimited, e.g. `30:500`
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((days, max_count)) = s.trim().split_once(':') else {
+            bail!("Expected <days>:<max messages>");
+        };
+        Ok(Policy {
+            max_age: DAY * days.trim().parse::<u32>()?,
+            max_count: max_count.trim().parse()?,
+        })
+    }
+}
+
+/// Retention of the channels, a policy for all and overrides per channel
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Retention {
+    pub default: Policy,
+    pub channels: HashMap<String, Policy>,
+}
+
+impl Retention {
+    pub fn policy(&self, channel: &str) -> Policy {
+        self.channels.get(channel).copied().unwrap_or(self.default)
+    }
+}
+
+/// Overrides per channel, comma separated `<channel>=<days>:<max messages>`,
+/// e.g. `news=7:100,checkins=1:0`
+pub fn parse_channels(s: &str) -> Result<HashMap<String, Policy>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (channel, policy) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected <channel>=<days>:<max messages>: {entry}"))?;
+            Ok((channel.trim().to_string(), policy.parse()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_policies() -> Result<()> {
+        let retention = Retention {
+            default: "30:0".parse()?,
+            channels: parse_channels("news=7:100, checkins=0:0,")?,
+        };
+        assert_eq!(
+            retention.policy("general"),
+            Policy {
+                max_age: DAY * 30,
+                max_count: 0
+            }
+        );
+        assert_eq!(retention.policy("news").max_count, 100);
+        assert!(retention.policy("checkins").keeps_all());
+
+        assert!("30".parse::<Policy>().is_err());
+        assert!(parse_channels("news:7:100").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+
+const HOUR: Duration = Duration::from_secs(3600);
+// Entries kept back while the mesh is congested, the oldest go first
+const MAX_DEFERRED: usize = 16;
+
+/// Where a scheduled text goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Channel(String),
+    Broadcast,
+}
+
+/// A recurring text, one line of the schedule file:
+/// `<daily|mon..sun> <HH:MM> <targets> <text>`, where targets is a comma
+/// separated list of channel names and `broadcast`, e.g.
+/// `sun 09:00 general,broadcast Welcome to MeshBoard!`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// None runs every day
+    pub day: Option<Weekday>,
+    pub at: NaiveTime,
+    pub targets: Vec<Target>,
+    pub text: String,
+}
+
+impl FromStr for Entry {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut parts = line.trim().splitn(4, char::is_whitespace);
+        let (Some(day), Some(at), Some(targets), Some(text)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Expected <day> <HH:MM> <targets> <text>");
+        };
+        let day = match day {
+            "daily" => None,
+            day => Some(
+                day.parse::<Weekday>()
+                    .map_err(|_| anyhow!("Invalid day {day}"))?,
+            ),
+        };
+        let at = NaiveTime::parse_from_str(at, "%H:%M")?;
+        let targets = targets
+            .split(',')
+            .filter(|target| !target.is_empty())
+            .map(|target| match target {
+                "broadcast" => Target::Broadcast,
+                ch => Target::Channel(ch.to_string()),
+            })
+            .collect();
+        Ok(Self {
+            day,
+            at,
+            targets,
+            text: text.trim().to_string(),
+        })
+    }
+}
+
+impl Entry {
+    /// Whether the entry has to run at some point in (from, to]
+    fn is_due(&self, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+        let mut date = from.date();
+        // A week covers every entry, no need to look further after a long gap
+        for _ in 0..8 {
+            if date > to.date() {
+                break;
+            }
+            let when = date.and_time(self.at);
+            if when > from && when <= to && self.day.is_none_or(|day| day == date.weekday()) {
+                return true;
+            }
+            let Some(next) = date.succ_opt() else {
+                break;
+            };
+            date = next;
+        }
+        false
+    }
+}
+
+/// Reads the schedule file, a missing file is an empty schedule. Blank lines
+/// and lines starting with # are ignored.
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    std::fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(n, line)| {
+            line.parse()
+                .map_err(|err| anyhow!("{}:{}: {}", path.display(), n + 1, err))
+        })
+        .collect()
+}
+
+/// Bytes that scheduled broadcasts may put on air per rolling hour
+pub struct AirtimeBudget {
+    // 0 is unlimited
+    bytes_per_hour: usize,
+    spent: VecDeque<(Instant, usize)>,
+}
+
+impl AirtimeBudget {
+    pub fn new(bytes_per_hour: usize) -> Self {
+        Self {
+            bytes_per_hour,
+            spent: VecDeque::new(),
+        }
+    }
+
+    /// Accounts `bytes` if they fit in the budget
+    pub fn try_spend(&mut self, bytes: usize, now: Instant) -> bool {
+        if self.bytes_per_hour == 0 {
+            return true;
+        }
+        while let Some((ts, _)) = self.spent.front()
+            && now.saturating_duration_since(*ts) >= HOUR
+        {
+            self.spent.pop_front();
+        }
+        let spent: usize = self.spent.iter().map(|(_, bytes)| bytes).sum();
+        if spent + bytes > self.bytes_per_hour {
+            return false;
+        }
+        self.spent.push_back((now, bytes));
+        true
+    }
+}
+
+/// Tells which schedule entries came due, entries can change between calls
+pub struct Scheduler {
+    last_check: NaiveDateTime,
+    pub budget: AirtimeBudget,
+    deferred: VecDeque<Entry>,
+}
+
+impl Scheduler {
+    pub fn new(budget: AirtimeBudget, now: NaiveDateTime) -> Self {
+        Self {
+            last_check: now,
+            budget,
+            deferred: VecDeque::new(),
+        }
+    }
+
+    /// Entries that came due since the previous call
+    pub fn due(&mut self, entries: &[Entry], now: NaiveDateTime) -> Vec<Entry> {
+        let from = std::mem::replace(&mut self.last_check, now);
+        entries
+            .iter()
+            .filter(|entry| entry.is_due(from, now))
+            .cloned()
+            .collect()
+    }
+
+    /// Keeps back an entry that came due, to be sent later
+    pub fn defer(&mut self, entry: Entry) {
+        if self.deferred.len() >= MAX_DEFERRED {
+            self.deferred.pop_front();
+        }
+        self.deferred.push_back(entry);
+    }
+
+    /// The entries kept back, oldest first
+    pub fn deferred(&mut self) -> Vec<Entry> {
+        self.deferred.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        // 2025-06-01 is a Sunday
+        NaiveDate::from_ymd_opt(2025, 6, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_schedule() -> Result<()> {
+        let motd: Entry = "sun 09:00 general,broadcast Welcome to MeshBoard!".parse()?;
+        assert_eq!(motd.day, Some(Weekday::Sun));
+        assert_eq!(
+            motd.targets,
+            vec![Target::Channel("general".into()), Target::Broadcast]
+        );
+        assert_eq!(motd.text, "Welcome to MeshBoard!");
+        assert!("sun 9am general hi".parse::<Entry>().is_err());
+        assert!("sun 09:00 general".parse::<Entry>().is_err());
+
+        let daily: Entry = "daily 12:30 news Noon".parse()?;
+        let entries = [motd, daily];
+        let mut scheduler = Scheduler::new(AirtimeBudget::new(0), at(1, 8, 0));
+        assert!(scheduler.due(&entries, at(1, 8, 59)).is_empty());
+        assert_eq!(scheduler.due(&entries, at(1, 9, 0)).len(), 1);
+        assert!(scheduler.due(&entries, at(1, 9, 1)).is_empty());
+        assert_eq!(scheduler.due(&entries, at(1, 12, 30))[0].text, "Noon");
+        // Monday, only the daily one
+        assert_eq!(scheduler.due(&entries, at(2, 23, 0)).len(), 1);
+
+        for _ in 0..=MAX_DEFERRED {
+            scheduler.defer(entries[0].clone());
+        }
+        scheduler.defer(entries[1].clone());
+        let deferred = scheduler.deferred();
+        assert_eq!(deferred.len(), MAX_DEFERRED);
+        assert_eq!(deferred.last(), Some(&entries[1]));
+        assert!(scheduler.deferred().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_airtime_budget() {
+        let mut budget = AirtimeBudget::new(100);
+        let now = Instant::now();
+        assert!(budget.try_spend(60, now));
+        assert!(!budget.try_spend(60, now));
+        assert!(budget.try_spend(40, now));
+        assert!(budget.try_spend(60, now + HOUR));
+    }
+}
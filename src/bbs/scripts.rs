@@ -0,0 +1,212 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Result, anyhow};
+use rhai::{AST, Array, Dynamic, Engine, Map, Scope};
+use tracing::{debug, warn};
+
+use crate::bbs::plugins::{BbsCommand, Context};
+use crate::mesh::service::format_node_id;
+
+const EXTENSION: &str = "rhai";
+// Limits of a run, so a script can not hang or flood the board
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_STRING_SIZE: usize = 1000;
+const MAX_ITEMS: usize = 100;
+const MAX_REPLIES: usize = 5;
+
+/// A command defined by a rhai script, named after its file. A first line
+/// comment is its usage in the help, e.g. `// roll [sides]`.
+///
+/// Scripts get the words after the command in `args`, and may call
+/// `reply(text)`, `get_user()` for the `short_name` and `node` of the sender,
+/// and `store(key, value)` / `load(key)` for strings kept across runs. A
+/// script that does not reply answers with what it evaluates to.
+pub struct ScriptCommand {
+    name: String,
+    help: String,
+    ast: AST,
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_ITEMS);
+    engine.set_max_map_size(MAX_ITEMS);
+    engine.on_print(|text| debug!(target: "bbs", "script: {text}"));
+    engine
+}
+
+impl ScriptCommand {
+    pub fn new(name: &str, script: &str) -> Result<Self> {
+        let ast = engine()
+            .compile(script)
+            .map_err(|err| anyhow!("{name}: {err}"))?;
+        let help = script
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("//"))
+            .map(|usage| usage.trim().to_string())
+            .filter(|usage| !usage.is_empty())
+            .unwrap_or_else(|| name.to_string());
+        Ok(Self {
+            name: name.to_string(),
+            help,
+            ast,
+        })
+    }
+
+    // Setting keeping the strings of the script, as a JSON object
+    fn key(&self) -> String {
+        format!("script.{}", self.name)
+    }
+}
+
+impl BbsCommand for ScriptCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn help(&self) -> String {
+        self.help.clone()
+    }
+
+    fn handle(&self, ctx: &Context, args: &[&str]) -> Result<Vec<String>> {
+        let stored: BTreeMap<String, String> = match ctx.storage.get_setting(&self.key())? {
+            Some(json) => serde_json::from_str(&json)?,
+            None => BTreeMap::new(),
+        };
+        let stored = Arc::new(Mutex::new((stored, false)));
+        let replies = Arc::new(Mutex::new(Vec::new()));
+
+        let mut engine = engine();
+        let to = replies.clone();
+        engine.register_fn("reply", move |text: &str| {
+            to.lock().unwrap().push(text.to_string());
+        });
+        let (short_name, node) = (ctx.sender.short_name.clone(), ctx.sender.node);
+        engine.register_fn("get_user", move || {
+            let mut user = Map::new();
+            user.insert("short_name".into(), short_name.clone().into());
+            user.insert("node".into(), format_node_id(node).into());
+            user
+        });
+        let kept = stored.clone();
+        engine.register_fn("store", move |key: &str, value: &str| {
+            let mut kept = kept.lock().unwrap();
+            let (values, changed) = &mut *kept;
+            if values.len() < MAX_ITEMS || values.contains_key(key) {
+                values.insert(key.to_string(), value.to_string());
+                *changed = true;
+            }
+        });
+        let kept = stored.clone();
+        engine.register_fn("load", move |key: &str| -> Dynamic {
+            match kept.lock().unwrap().0.get(key) {
+                Some(value) => value.clone().into(),
+                None => Dynamic::UNIT,
+            }
+        });
+
+        let mut scope = Scope::new();
+        let args: Array = args.iter().map(|arg| arg.to_string().into()).collect();
+        scope.push("args", args);
+        let result = engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+            .map_err(|err| {
+                warn!(target: "bbs", "Script {} failed: {err}", self.name);
+                anyhow!("Command failed")
+            })?;
+
+        let stored = stored.lock().unwrap();
+        let (values, changed) = &*stored;
+        if *changed {
+            ctx.storage
+                .set_setting(&self.key(), &serde_json::to_string(values)?)?;
+        }
+        let mut replies = std::mem::take(&mut *replies.lock().unwrap());
+        if replies.is_empty() && !result.is_unit() {
+            replies.push(result.to_string());
+        }
+        replies.truncate(MAX_REPLIES);
+        Ok(replies)
+    }
+}
+
+/// Commands of the `*.rhai` scripts in the directory, none if it does not
+/// exist. Scripts that do not compile are left out with a warning.
+pub fn load_dir(dir: &Path) -> Vec<Arc<dyn BbsCommand>> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut commands: Vec<Arc<dyn BbsCommand>> = Vec::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path
+            .extension()
+            .is_none_or(|extension| extension != EXTENSION)
+        {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let command = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|script| ScriptCommand::new(name, &script));
+        match command {
+            Ok(command) => commands.push(Arc::new(command)),
+            Err(err) => warn!(target: "bbs", "Skipped script {}: {err}", path.display()),
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bbs::service::Sender;
+    use crate::bbs::storage::Storage;
+    use std::time::Duration;
+
+    #[test]
+    fn test_script() -> Result<()> {
+        let storage = Storage::memory();
+        let sender = Sender {
+            node: 2,
+            short_name: "ann".into(),
+            ..Default::default()
+        };
+        let ctx = Context {
+            sender: &sender,
+            now: 0,
+            uptime: Duration::ZERO,
+            storage: &storage,
+        };
+        let script = r#"// count [name]
+            let user = get_user();
+            let name = if args.len() > 0 { args[0] } else { user.short_name };
+            let count = load(name);
+            let count = if count == () { 1 } else { parse_int(count) + 1 };
+            store(name, count.to_string());
+            reply(`${name} counted ${count} times`);
+        "#;
+        let count = ScriptCommand::new("count", script)?;
+        assert_eq!(count.help(), "count [name]");
+        assert_eq!(count.handle(&ctx, &[])?, vec!["ann counted 1 times"]);
+        assert_eq!(count.handle(&ctx, &[])?, vec!["ann counted 2 times"]);
+        assert_eq!(count.handle(&ctx, &["bob"])?, vec!["bob counted 1 times"]);
+
+        let echo = ScriptCommand::new("echo", "args.len()")?;
+        assert_eq!(echo.help(), "echo");
+        assert_eq!(echo.handle(&ctx, &["a", "b"])?, vec!["2"]);
+        let forever = ScriptCommand::new("forever", "loop {}")?;
+        assert!(forever.handle(&ctx, &[]).is_err());
+        assert!(ScriptCommand::new("bad", "let").is_err());
+        Ok(())
+    }
+}
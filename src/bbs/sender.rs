@@ -0,0 +1,211 @@
+use std::{
+    collections::{HashMap, VecDeque, hash_map::Entry},
+    time::{Duration, Instant},
+};
+
+use crate::bbs::federation;
+use crate::bbs::radios::RadioId;
+use crate::mesh::types::Urgency;
+
+/// Told to a node whose commands are not answered while a radio is near
+/// its duty cycle
+pub const CONGESTED: &str = "The mesh is busy, commands are not answered for a few minutes";
+// A node is told it once in this long
+const CONGESTED_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// A reply waiting to go out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub node: u32,
+    // Radio and Meshtastic channel the command came in on
+    pub radio: RadioId,
+    pub channel: u32,
+    pub text: String,
+    pub urgency: Urgency,
+}
+
+/// How soon the reply goes, the frames of [federation] are for a board
+/// and wait behind the replies people are waiting for
+pub fn urgency(text: &str) -> Urgency {
+    if text.starts_with(federation::PREFIX) {
+        Urgency::Bulk
+    } else {
+        Urgency::Reply
+    }
+}
+
+/// Spaces the replies to each node by `delay`, so a long answer does not
+/// fill the TX queue of the radio at once. Replies to other nodes do not
+/// wait for each other.
+pub struct Pacer {
+    delay: Duration,
+    queue: VecDeque<(Instant, Outgoing)>,
+    // When the last reply to each node is due, for the nodes answered in
+    // the last `delay`
+    last: HashMap<u32, Instant>,
+    // When each node was last told the mesh is congested
+    told: HashMap<u32, Instant>,
+}
+
+impl Pacer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            queue: VecDeque::new(),
+            last: HashMap::new(),
+            told: HashMap::new(),
+        }
+    }
+
+    /// What goes back to the node while the mesh is congested: replies
+    /// people wait for are dropped, [CONGESTED] is told once a window
+    /// instead, and replies that are not urgent wait in the queue
+    pub fn congested(&mut self, node: u32, replies: Vec<String>, now: Instant) -> Vec<String> {
+        self.told.retain(|_, told| *told + CONGESTED_WINDOW > now);
+        let mut replies: Vec<String> = replies
+            .into_iter()
+            .filter(|reply| urgency(reply) != Urgency::Reply)
+            .collect();
+        if let Entry::Vacant(told) = self.told.entry(node) {
+            told.insert(now);
+            replies.insert(0, CONGESTED.to_string());
+        }
+        replies
+    }
+
+    /// Queues the reply, due `delay` after the one before to the same node
+    pub fn push(&mut self, reply: Outgoing, now: Instant) {
+        let delay = self.delay;
+        self.last.retain(|_, last| *last + delay > now);
+        let due = match self.last.get(&reply.node) {
+            Some(last) => *last + delay,
+            None => now,
+        };
+        self.last.insert(reply.node, due);
+        self.queue.push_back((due, reply));
+    }
+
+    /// The next reply due by now, if any. While `congested` only the
+    /// replies people wait for go.
+    pub fn next_due(&mut self, now: Instant, congested: bool) -> Option<Outgoing> {
+        let position = self.queue.iter().position(|(due, reply)| {
+            *due <= now && (!congested || reply.urgency == Urgency::Reply)
+        })?;
+        let (_, reply) = self.queue.remove(position)?;
+        Some(reply)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Joins consecutive replies, a line each, while they fit in `max_len`
+/// bytes. Longer replies go on their own, to be split in packets, and so do
+/// the frames of [federation], a peer reads a text as one frame.
+pub fn coalesce(replies: &[String], max_len: usize) -> Vec<String> {
+    let is_frame = |text: &str| text.starts_with(federation::PREFIX);
+    let mut coalesced: Vec<String> = Vec::new();
+    for reply in replies {
+        match coalesced.last_mut() {
+            Some(last)
+                if last.len() + 1 + reply.len() <= max_len
+                    && !is_frame(last)
+                    && !is_frame(reply) =>
+            {
+                last.push('\n');
+                last.push_str(reply);
+            }
+            _ => coalesced.push(reply.clone()),
+        }
+    }
+    coalesced
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reply(node: u32, text: &str) -> Outgoing {
+        Outgoing {
+            node,
+            radio: 0,
+            channel: 0,
+            text: text.into(),
+            urgency: Urgency::Reply,
+        }
+    }
+
+    #[test]
+    fn test_pacer() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut pacer = Pacer::new(Duration::from_secs(2));
+        pacer.push(reply(1, "a"), start);
+        pacer.push(reply(1, "b"), start);
+        pacer.push(reply(2, "c"), start);
+        assert_eq!(pacer.next_due(start, false), Some(reply(1, "a")));
+        assert_eq!(pacer.next_due(start, false), Some(reply(2, "c")));
+        assert_eq!(pacer.next_due(secs(1), false), None);
+        assert_eq!(pacer.next_due(secs(2), false), Some(reply(1, "b")));
+        assert!(pacer.is_empty());
+
+        // A reply long after the last one goes at once
+        pacer.push(reply(1, "d"), secs(10));
+        assert_eq!(pacer.next_due(secs(10), false), Some(reply(1, "d")));
+    }
+
+    #[test]
+    fn test_congested() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(Duration::from_secs(2));
+        let frame = "~fed more".to_string();
+        assert_eq!(
+            pacer.congested(1, vec!["Ack".into(), frame.clone()], start),
+            vec![CONGESTED.to_string(), frame.clone()]
+        );
+        // Told once a window
+        assert!(pacer.congested(1, vec!["Ack".into()], start).is_empty());
+        assert_eq!(
+            pacer.congested(2, vec!["Ack".into()], start),
+            vec![CONGESTED]
+        );
+        let later = start + CONGESTED_WINDOW;
+        assert_eq!(
+            pacer.congested(1, vec!["Ack".into()], later),
+            vec![CONGESTED]
+        );
+
+        // Frames for peers wait until it is not congested
+        pacer.push(
+            Outgoing {
+                urgency: Urgency::Bulk,
+                ..reply(1, &frame)
+            },
+            start,
+        );
+        pacer.push(reply(2, CONGESTED), start);
+        assert_eq!(pacer.next_due(start, true), Some(reply(2, CONGESTED)));
+        assert_eq!(pacer.next_due(start, true), None);
+        assert_eq!(pacer.next_due(start, false).unwrap().text, frame);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let replies: Vec<String> = ["Ack", "general,news", &"x".repeat(20), "Bye"]
+            .iter()
+            .map(|reply| reply.to_string())
+            .collect();
+        assert_eq!(
+            coalesce(&replies, 20),
+            vec!["Ack\ngeneral,news", &"x".repeat(20), "Bye"]
+        );
+        assert_eq!(coalesce(&replies[..2], 200), vec!["Ack\ngeneral,news"]);
+        assert!(coalesce(&[], 200).is_empty());
+        let frames = ["~fed post general 1 0 3fa2c01b 1/1 ann: hi", "~fed more"];
+        let replies: Vec<String> = frames.iter().map(|frame| frame.to_string()).collect();
+        assert_eq!(coalesce(&replies, 200), frames);
+        assert_eq!(urgency(frames[0]), Urgency::Bulk);
+        assert_eq!(urgency("Bye"), Urgency::Reply);
+    }
+}
@@ -1,64 +1,1007 @@
 use mini_moka::sync::Cache;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::{Duration, Instant};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Result, bail};
+use chrono::{DateTime, Local};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tracing::warn;
 
+use crate::bbs::counters;
+use crate::bbs::delivery::{BUCKETS, Deliveries, Delivery, RoundTrips};
+use crate::bbs::federation::{self, FederationOptions, Frame};
+use crate::bbs::files;
+use crate::bbs::i18n::{self, Lang};
+use crate::bbs::mailbox::{self, EmailOptions, IncomingEmail, OutgoingEmail};
+use crate::bbs::mistake::{Mistake, mistake};
+use crate::bbs::plugins::{self, Registry};
+use crate::bbs::prefs;
+use crate::bbs::ratelimit::{RateLimiter, Throttled};
+use crate::bbs::retention::Retention;
+use crate::bbs::schedule;
+use crate::bbs::snapshot;
+use crate::bbs::storage::Ban;
+use crate::bbs::storage::Channel;
+use crate::bbs::storage::ChannelAcl;
+use crate::bbs::storage::ChannelId;
 use crate::bbs::storage::ChannelMessage;
+use crate::bbs::storage::CheckIn;
+use crate::bbs::storage::DirectMessage;
+use crate::bbs::storage::Mail;
+use crate::bbs::storage::Neighbor;
+use crate::bbs::storage::Node;
+use crate::bbs::storage::NodeBan;
+use crate::bbs::storage::Poll;
+use crate::bbs::storage::PositionSample;
+use crate::bbs::storage::RawPacket;
+use crate::bbs::storage::Sighting;
+use crate::bbs::storage::Stats;
 use crate::bbs::storage::Storage;
+use crate::bbs::storage::Subscription;
+use crate::bbs::storage::SyncedPost;
+use crate::bbs::storage::TelemetrySample;
 use crate::bbs::storage::User;
+use crate::bbs::storage::UserId;
 use crate::bbs::storage::UserPkHash;
+use crate::bbs::storage::Vote;
+use crate::bbs::storage::Watch;
+use crate::bbs::storage::Waypoint;
+use crate::bbs::usage;
+use crate::matrix::{self, Rooms};
+use crate::mesh::service::{BROADCAST_ADDR, Metrics, Names, Signal, format_node_id, parse_node_id};
+use crate::weather::Forecast;
 
-const HELP: &str = "h(elp) | c(hannels)  | j(oin) ch | p(ost) msg  | l(list)";
+pub(crate) const NICK_MAX_LEN: usize = 12;
+const LIKE: &str = "👍";
+// Chars of an emoji with its modifiers, e.g. skin tone or gender
+const EMOJI_MAX_LEN: usize = 8;
+const PAGE_SIZE: usize = 5;
+const CHECKINS_CHANNEL: &str = "checkins";
+pub(crate) const CHECKINS_MAX_AGE: u64 = 24 * 60 * 60 * 1000;
+pub(crate) const WHOHERE_RADIUS_KM: f64 = 5.0;
+const WHO_MAX: usize = 5;
+pub(crate) const SEARCH_MAX: usize = 5;
+const TELEMETRY_MAX_AGE: u64 = 24 * 60 * 60 * 1000;
+// Links a node did not tell again for this long are gone, nodes send their
+// NeighborInfo every few hours
+const NEIGHBORS_MAX_AGE: u64 = 24 * 60 * 60 * 1000;
+// Author of the messages posted by the BBS itself or through bridges
+const SYSOP_UID: UserId = UserId::MAX;
+const SYSOP_NAME: &str = "sysop";
+// Posts kept for bridges, the oldest are dropped if nobody takes them
+const MAX_PENDING_POSTS: usize = 64;
+const MOTD_KEY: &str = "motd";
+pub(crate) const POLL_MAX_OPTIONS: usize = 8;
+// Mail listed, newest first
+pub(crate) const MAIL_MAX: usize = 10;
+const WAYPOINTS_MAX: usize = 10;
+// Longest waypoint name the radios take, in bytes
+const WAYPOINT_NAME_MAX: usize = 30;
+// Waypoints are broadcast on every radio, a user shares one at most this often
+const WAYPOINT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Commands remembered by packet id, a text heard again through another
+// radio or after a reconnect comes well within this
+const HANDLED_TTL: Duration = Duration::from_secs(10 * 60);
+// Radio clocks further off the one of the board do not date posts
+const MAX_CLOCK_SKEW: u64 = 5 * 60 * 1000;
+/// Shortcuts of the commands, and the command each stands for
+pub(crate) const SHORTCUTS: &[(&str, &str)] = &[
+    ("h", "help"),
+    ("c", "channels"),
+    ("j", "join"),
+    ("p", "post"),
+    ("r", "reply"),
+    ("l", "list"),
+    ("s", "search"),
+];
+
+/// The command a word names, the word itself unless it is a shortcut
+pub(crate) fn command_name(word: &str) -> &str {
+    SHORTCUTS
+        .iter()
+        .find(|(shortcut, _)| *shortcut == word)
+        .map_or(word, |(_, name)| name)
+}
 
 pub enum Command {
-    Help,
+    /// The commands, or the usage of the one given
+    Help {
+        command: Option<String>,
+    },
     Channels,
-    Join { ch: String },
-    Post { msg: String },
-    List,
+    Join {
+        ch: String,
+        password: Option<String>,
+    },
+    Post {
+        msg: String,
+    },
+    Reply {
+        id: u32,
+        msg: String,
+    },
+    React {
+        id: u32,
+        emoji: String,
+    },
+    List {
+        page: Option<usize>,
+    },
+    Next,
+    Search {
+        keyword: String,
+        // Every channel the user can read, not only the current one
+        all: bool,
+    },
+    Subscribe {
+        ch: String,
+    },
+    Unsubscribe {
+        ch: String,
+    },
+    CheckIn {
+        note: String,
+    },
+    WhoHere {
+        at: Option<(f64, f64)>,
+        radius_km: Option<f64>,
+    },
+    Nick {
+        name: String,
+    },
+    WhoAmI,
+    Status,
+    /// Profile field of the user, cleared when empty
+    Set {
+        field: String,
+        value: String,
+    },
+    Profile {
+        user: Option<String>,
+    },
+    PollNew {
+        question: String,
+        options: Vec<String>,
+    },
+    Vote {
+        id: u32,
+        // Numbered from 1, as listed
+        option: usize,
+    },
+    PollResults {
+        id: u32,
+    },
+    Email {
+        to: String,
+        subject: String,
+        body: String,
+    },
+    Mail,
+    Read {
+        id: u32,
+    },
+    Files,
+    /// Next part of the document, or the one given
+    Get {
+        id: u32,
+        part: Option<usize>,
+    },
+    Who,
+    Where {
+        node: String,
+    },
+    /// Nodes the node hears, as it told in its NeighborInfo
+    Neighbors {
+        node: String,
+    },
+    Waypoints,
+    /// Shares the waypoint on the mesh
+    WaypointAdd {
+        name: String,
+        at: (f64, f64),
+    },
+    Fav {
+        ch: String,
+    },
+    Unfav {
+        ch: String,
+    },
+    Notify {
+        mode: Option<prefs::NotifyMode>,
+    },
+    Lang {
+        lang: Option<Lang>,
+    },
+    MkChan {
+        ch: String,
+    },
+    RmChan {
+        ch: String,
+    },
+    Ban {
+        user: String,
+    },
+    Unban {
+        user: String,
+    },
+    BanList,
+    Purge {
+        ch: String,
+    },
+    Prune,
+    Stats,
+    /// Round trips of the texts to each node, to spot the flaky links
+    DeliveryStats,
+    Fleet,
+    Watch {
+        node: Option<String>,
+    },
+    AnnounceAdd {
+        entry: String,
+    },
+    AnnounceDel {
+        id: u32,
+    },
+    AnnounceList,
+    Telemetry {
+        node: String,
+    },
+    Snapshot,
+    Motd,
+    MotdSet {
+        text: String,
+    },
+    MotdReset,
+    DmLog {
+        page: Option<usize>,
+    },
+    Unwatch {
+        node: String,
+    },
+    Acl {
+        ch: String,
+        action: Option<AclAction>,
+    },
+    /// Text sent to the whole mesh, not only to the BBS users
+    Broadcast {
+        text: String,
+    },
+    Sessions,
+    /// Ends the session of a user, or of everyone with `all`
+    SessionReset {
+        user: String,
+    },
+}
+
+/// Changes to who may use a channel
+pub enum AclAction {
+    /// Open to everyone again
+    Public,
+    /// Only the members, the join password is dropped
+    Private,
+    /// Members, and whoever joins with the password
+    Password(String),
+    Allow(String),
+    Deny(String),
+}
+
+impl Command {
+    // Whether others get to see what the command does, new users are
+    // verified before they may
+    fn publishes(&self) -> bool {
+        matches!(
+            self,
+            Command::Post { .. }
+                | Command::Reply { .. }
+                | Command::React { .. }
+                | Command::PollNew { .. }
+                | Command::Vote { .. }
+                | Command::Email { .. }
+                | Command::WaypointAdd { .. }
+                | Command::CheckIn { .. }
+                | Command::Set { .. }
+        )
+    }
+
+    fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Command::MkChan { .. }
+                | Command::RmChan { .. }
+                | Command::Ban { .. }
+                | Command::Unban { .. }
+                | Command::BanList
+                | Command::Purge { .. }
+                | Command::Prune
+                | Command::Stats
+                | Command::DeliveryStats
+                | Command::Fleet
+                | Command::Watch { .. }
+                | Command::Unwatch { .. }
+                | Command::AnnounceAdd { .. }
+                | Command::AnnounceDel { .. }
+                | Command::AnnounceList
+                | Command::Telemetry { .. }
+                | Command::Snapshot
+                | Command::Motd
+                | Command::MotdSet { .. }
+                | Command::MotdReset
+                | Command::DmLog { .. }
+                | Command::Acl { .. }
+                | Command::Broadcast { .. }
+                | Command::Sessions
+                | Command::SessionReset { .. }
+        )
+    }
 }
+// Whether the error is a failure of the board, anything but a mistake in
+// the command
+fn is_failure(err: &anyhow::Error) -> bool {
+    !err.chain().any(|cause| cause.is::<Mistake>())
+}
+
 impl Command {
+    /// A core command given wrong arguments is a [Mistake], answered with
+    /// its usage unless it tells what is wrong. Anything else that does not
+    /// parse is no command.
     pub fn parse(command: &str) -> Result<Self> {
+        Self::parse_args(command).map_err(|err| {
+            match command.split_whitespace().next().and_then(usage::find) {
+                Some(usage) if is_failure(&err) => Mistake(usage.text).into(),
+                _ => err,
+            }
+        })
+    }
+
+    fn parse_args(command: &str) -> Result<Self> {
         let mut parts = command.split_whitespace();
-        match parts.next() {
-            Some("h") | Some("help") => Ok(Command::Help),
-            Some("c") | Some("channels") => Ok(Command::Channels),
-            Some("j") | Some("join") => Ok(Command::Join {
+        match parts.next().map(command_name) {
+            Some("help") => Ok(Command::Help {
+                command: parts.next().map(str::to_string),
+            }),
+            Some("channels") => Ok(Command::Channels),
+            Some("join") => Ok(Command::Join {
                 ch: parts
                     .next()
                     .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
                     .to_string(),
+                password: parts.next().map(str::to_string),
             }),
-            Some("p") | Some("post") => Ok(Command::Post {
+            Some("post") => Ok(Command::Post {
                 msg: parts.collect::<Vec<_>>().join(" "),
             }),
-            Some("l") | Some("list") => Ok(Command::List),
+            Some("reply") => Ok(Command::Reply {
+                id: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing message number"))?
+                    .trim_start_matches('#')
+                    .parse()?,
+                msg: parts.collect::<Vec<_>>().join(" "),
+            }),
+            Some(word @ ("like" | "react")) => {
+                let id = parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing message number"))?
+                    .trim_start_matches('#')
+                    .parse()?;
+                let emoji = if word == "like" {
+                    LIKE
+                } else {
+                    parts
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Missing emoji"))?
+                };
+                if !is_emoji(emoji) {
+                    mistake!("Not an emoji");
+                }
+                Ok(Command::React {
+                    id,
+                    emoji: emoji.to_string(),
+                })
+            }
+            Some("list") => Ok(Command::List {
+                page: parts.next().map(|page| page.parse()).transpose()?,
+            }),
+            Some("next") => Ok(Command::Next),
+            Some("search") => {
+                let mut words: Vec<_> = parts.collect();
+                let all = words.len() > 1 && words[0] == "all";
+                if all {
+                    words.remove(0);
+                }
+                if words.is_empty() {
+                    bail!("Missing keyword");
+                }
+                Ok(Command::Search {
+                    keyword: words.join(" "),
+                    all,
+                })
+            }
+            Some("sub") => Ok(Command::Subscribe {
+                ch: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string(),
+            }),
+            Some("unsub") => Ok(Command::Unsubscribe {
+                ch: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string(),
+            }),
+            Some("checkin") => Ok(Command::CheckIn {
+                note: parts.collect::<Vec<_>>().join(" "),
+            }),
+            Some("whohere") => {
+                let args = parts
+                    .map(|arg| arg.parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                match args.as_slice() {
+                    [] => Ok(Command::WhoHere {
+                        at: None,
+                        radius_km: None,
+                    }),
+                    [radius_km] => Ok(Command::WhoHere {
+                        at: None,
+                        radius_km: Some(*radius_km),
+                    }),
+                    [lat, lon] => Ok(Command::WhoHere {
+                        at: Some((*lat, *lon)),
+                        radius_km: None,
+                    }),
+                    [lat, lon, radius_km] => Ok(Command::WhoHere {
+                        at: Some((*lat, *lon)),
+                        radius_km: Some(*radius_km),
+                    }),
+                    _ => bail!("Usage: whohere [lat lon] [km]"),
+                }
+            }
+            Some("nick") => Ok(Command::Nick {
+                name: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing nickname"))?
+                    .to_string(),
+            }),
+            Some("whoami") => Ok(Command::WhoAmI),
+            Some("set") => Ok(Command::Set {
+                field: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing field"))?
+                    .to_string(),
+                value: parts.collect::<Vec<_>>().join(" "),
+            }),
+            Some("profile") => Ok(Command::Profile {
+                user: parts.next().map(str::to_string),
+            }),
+            Some("poll") => match parts.next() {
+                Some("new") => {
+                    let (question, options) = parse_poll(&parts.collect::<Vec<_>>().join(" "))?;
+                    Ok(Command::PollNew { question, options })
+                }
+                Some("results") => Ok(Command::PollResults {
+                    id: parts
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Missing poll number"))?
+                        .trim_start_matches('#')
+                        .parse()?,
+                }),
+                _ => bail!("Usage: poll new \"question\" opt1 opt2 | poll results poll#"),
+            },
+            Some("email") => {
+                let (to, subject, body) =
+                    mailbox::parse_email(&parts.collect::<Vec<_>>().join(" "))?;
+                Ok(Command::Email { to, subject, body })
+            }
+            Some("mail") => Ok(Command::Mail),
+            Some("read") => Ok(Command::Read {
+                id: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing mail number"))?
+                    .trim_start_matches('#')
+                    .parse()?,
+            }),
+            Some("files") => Ok(Command::Files),
+            Some("get") => Ok(Command::Get {
+                id: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing file number"))?
+                    .trim_start_matches('#')
+                    .parse()?,
+                part: parts.next().map(str::parse).transpose()?,
+            }),
+            Some("vote") => Ok(Command::Vote {
+                id: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing poll number"))?
+                    .trim_start_matches('#')
+                    .parse()?,
+                option: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing option number"))?
+                    .parse()?,
+            }),
+            Some("status") => Ok(Command::Status),
+            Some("who") => Ok(Command::Who),
+            Some("where") => Ok(Command::Where {
+                node: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing node"))?
+                    .to_string(),
+            }),
+            Some("neighbors") => Ok(Command::Neighbors {
+                node: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing node"))?
+                    .to_string(),
+            }),
+            Some("wp") => match parts.next() {
+                Some("list") | None => Ok(Command::Waypoints),
+                Some("add") => {
+                    let args: Vec<_> = parts.collect();
+                    let [name @ .., lat, lon] = args.as_slice() else {
+                        bail!("Usage: wp add name lat lon");
+                    };
+                    if name.is_empty() {
+                        bail!("Usage: wp add name lat lon");
+                    }
+                    Ok(Command::WaypointAdd {
+                        name: name.join(" "),
+                        at: (lat.parse()?, lon.parse()?),
+                    })
+                }
+                _ => bail!("Usage: wp list | wp add name lat lon"),
+            },
+            Some("fav") => Ok(Command::Fav {
+                ch: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string(),
+            }),
+            Some("unfav") => Ok(Command::Unfav {
+                ch: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string(),
+            }),
+            Some("notify") => Ok(Command::Notify {
+                mode: parts.next().map(str::parse).transpose()?,
+            }),
+            Some("lang") => Ok(Command::Lang {
+                lang: parts.next().map(str::parse).transpose()?,
+            }),
+            Some("mkchan") => Ok(Command::MkChan {
+                ch: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string(),
+            }),
+            Some("rmchan") => Ok(Command::RmChan {
+                ch: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string(),
+            }),
+            Some("ban") => Ok(Command::Ban {
+                user: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing user name"))?
+                    .to_string(),
+            }),
+            Some("unban") => Ok(Command::Unban {
+                user: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing user name"))?
+                    .to_string(),
+            }),
+            Some("banlist") => Ok(Command::BanList),
+            Some("sessions") => match parts.next() {
+                Some("reset") => Ok(Command::SessionReset {
+                    user: parts
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Missing user name"))?
+                        .to_string(),
+                }),
+                _ => Ok(Command::Sessions),
+            },
+            Some("purge") => Ok(Command::Purge {
+                ch: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string(),
+            }),
+            Some("prune") => Ok(Command::Prune),
+            Some("stats") => match parts.next() {
+                None => Ok(Command::Stats),
+                Some("delivery") => Ok(Command::DeliveryStats),
+                _ => bail!("Usage: stats [delivery]"),
+            },
+            Some("fleet") => Ok(Command::Fleet),
+            Some("watch") => Ok(Command::Watch {
+                node: parts.next().map(str::to_string),
+            }),
+            Some("announce") => match parts.next() {
+                Some("add") => Ok(Command::AnnounceAdd {
+                    entry: parts.collect::<Vec<_>>().join(" "),
+                }),
+                Some("del") => Ok(Command::AnnounceDel {
+                    id: parts
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Missing announcement id"))?
+                        .parse()?,
+                }),
+                _ => Ok(Command::AnnounceList),
+            },
+            Some("telemetry") => Ok(Command::Telemetry {
+                node: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing node"))?
+                    .to_string(),
+            }),
+            Some("snapshot") => Ok(Command::Snapshot),
+            Some("motd") => match parts.next() {
+                Some("set") => Ok(Command::MotdSet {
+                    text: parts.collect::<Vec<_>>().join(" "),
+                }),
+                Some("reset") => Ok(Command::MotdReset),
+                _ => Ok(Command::Motd),
+            },
+            Some("dmlog") => Ok(Command::DmLog {
+                page: parts.next().map(|page| page.parse()).transpose()?,
+            }),
+            Some("unwatch") => Ok(Command::Unwatch {
+                node: parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing node"))?
+                    .to_string(),
+            }),
+            Some("acl") => {
+                let ch = parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing channel name"))?
+                    .to_string();
+                let mut arg = || {
+                    parts
+                        .next()
+                        .map(str::to_string)
+                        .ok_or_else(|| anyhow::anyhow!("Missing argument"))
+                };
+                let action = match arg().ok().as_deref() {
+                    None => None,
+                    Some("public") => Some(AclAction::Public),
+                    Some("private") => Some(AclAction::Private),
+                    Some("password") => Some(AclAction::Password(arg()?)),
+                    Some("allow") => Some(AclAction::Allow(arg()?)),
+                    Some("deny") => Some(AclAction::Deny(arg()?)),
+                    Some(_) => {
+                        bail!("Usage: acl ch [public|private|password pw|allow user|deny user]")
+                    }
+                };
+                Ok(Command::Acl { ch, action })
+            }
+            Some("broadcast") => {
+                let text = parts.collect::<Vec<_>>().join(" ");
+                if text.is_empty() {
+                    bail!("Missing text");
+                }
+                Ok(Command::Broadcast { text })
+            }
             _ => bail!("Invalid command"),
         }
     }
 }
 
+/// `"question" opt1 opt2...`, the quotes can be left out of a one word
+/// question
+fn parse_poll(s: &str) -> Result<(String, Vec<String>)> {
+    let (question, options) = match s.strip_prefix('"') {
+        Some(quoted) => quoted
+            .split_once('"')
+            .ok_or_else(|| anyhow::anyhow!("Missing closing quote"))?,
+        None => s.split_once(' ').unwrap_or((s, "")),
+    };
+    let options: Vec<String> = options.split_whitespace().map(str::to_string).collect();
+    if question.trim().is_empty() || !(2..=POLL_MAX_OPTIONS).contains(&options.len()) {
+        mistake!("A question and 2 to {POLL_MAX_OPTIONS} options");
+    }
+    Ok((question.trim().to_string(), options))
+}
+
+/// Who sent a command, as seen by the radio
+#[derive(Debug, Clone, Default)]
+pub struct Sender {
+    pub node: u32,
+    pub pk_hash: [u8; 32],
+    pub short_name: String,
+    // Last known (latitude, longitude) of the node
+    pub position: Option<(f64, f64)>,
+    // How the command was heard, None if not over the air
+    pub signal: Option<Signal>,
+    // Whether the command came as a PKC encrypted direct message
+    pub encrypted: bool,
+    /// When the radio received the command, in ms since the Unix epoch,
+    /// None for now
+    pub received: Option<u64>,
+    /// Id of the mesh packet of the command, the same text heard twice
+    /// carries the same one. None for commands not from the mesh
+    pub packet: Option<u32>,
+}
+
+impl Sender {
+    // When the radio received the command, if its clock is close to the one
+    // of the board, and never after `now`
+    fn received_at(&self, now: u64) -> u64 {
+        match self.received {
+            Some(received) if received.abs_diff(now) <= MAX_CLOCK_SKEW => received.min(now),
+            _ => now,
+        }
+    }
+}
+
+// A single emoji, loosely: a few chars, none of them a letter, digit or ASCII
+fn is_emoji(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars().count() <= EMOJI_MAX_LEN
+        && !s.chars().any(|c| c.is_ascii() || c.is_alphanumeric())
+}
+
+fn password_hash(password: &str) -> [u8; 32] {
+    Sha256::digest(password.as_bytes()).into()
+}
+
+fn distance_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+    let h = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * 6371.0 * h.sqrt().asin()
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 struct Session {
     created: Instant,
+    // Last command
+    last_seen: Instant,
     user_id: u32,
     current_channel: u32,
+    // Time window and page of the last listing
+    list_window: Option<(u64, u64)>,
+    list_page: usize,
+    // Code a pending user replies with to be verified
+    challenge: Option<String>,
+}
+
+// Four digits, leading zeros included
+fn challenge_code() -> String {
+    format!("{:04}", rand::random_range(0..10000))
+}
+
+/// A message to be pushed to a node that did not ask for it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Notification {
+    pub to: u32,
+    pub text: String,
+}
+
+/// A message posted to a channel, relayed to or from bridges
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Post {
+    pub channel: String,
+    pub author: String,
+    pub text: String,
+}
+
+/// "2.5.x" for firmware "2.5.6.abcdef", "?" if unknown
+fn firmware_series(firmware: Option<&str>) -> String {
+    let Some(firmware) = firmware else {
+        return "?".into();
+    };
+    let mut parts = firmware.split('.');
+    match (parts.next(), parts.next()) {
+        (Some(major), Some(minor)) => format!("{major}.{minor}.x"),
+        _ => firmware.to_string(),
+    }
+}
+
+/// "05-31" for a timestamp in ms, in local time
+fn format_date(ts: u64) -> String {
+    DateTime::from_timestamp_millis(ts as i64)
+        .map(|date| date.with_timezone(&Local).format("%m-%d").to_string())
+        .unwrap_or_else(|| "?".into())
+}
+
+/// The local date, days of the counters start at local midnight
+fn today() -> chrono::NaiveDate {
+    Local::now().date_naive()
+}
+
+/// "14:02:11" for a timestamp in ms, in local time
+pub(super) fn format_time(ts: u64) -> String {
+    DateTime::from_timestamp_millis(ts as i64)
+        .map(|date| date.with_timezone(&Local).format("%H:%M:%S").to_string())
+        .unwrap_or_else(|| "?".into())
+}
+
+/// "45s", "12m", "3h" or "2d" for an age in ms
+pub(crate) fn format_age(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// How the channels are listed, favorites always go first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOrder {
+    Name,
+    /// Most recent post first
+    Activity,
+}
+
+impl FromStr for ChannelOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "name" => ChannelOrder::Name,
+            "activity" => ChannelOrder::Activity,
+            _ => bail!("Expected name or activity"),
+        })
+    }
+}
+
+/// Tunables of the BBS
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Users allowed to run admin commands
+    pub admins: Vec<UserPkHash>,
+    /// Commands a user can send at once
+    pub rate_limit_burst: u32,
+    /// Time to earn one more command
+    pub rate_limit_refill: Duration,
+    /// Throttled commands in a row before muting the user, 0 never mutes
+    pub mute_after: u32,
+    /// How long commands of a muted user are ignored
+    pub mute_duration: Duration,
+    pub channel_order: ChannelOrder,
+    /// Where the snapshot command writes the tarballs
+    pub snapshot_dir: PathBuf,
+    /// Config files included in the snapshots
+    pub snapshot_files: Vec<PathBuf>,
+    /// How long direct messages are kept in the log, zero keeps them forever
+    pub dm_log_max_age: Duration,
+    /// How many messages the channels keep, see [BBS::prune]
+    pub retention: Retention,
+    /// Welcome text for new users, until an admin sets another with the motd
+    /// command. {name}, {channels} and {unread} are replaced, empty disables
+    /// the welcome.
+    pub motd: String,
+    /// Users not seen for this long are welcomed again, zero only welcomes
+    /// new users
+    pub motd_after: Duration,
+    /// Longer texts are refused, 0 is unlimited
+    pub max_message_len: usize,
+    /// Only take commands sent as PKC encrypted direct messages, others get
+    /// told how to send them
+    pub require_pki: bool,
+    /// New users reply with a code sent to them before they can post
+    pub verify_new_users: bool,
+    /// How long sessions last after the last command of the user, also
+    /// across restarts
+    pub session_ttl: Duration,
+    /// Language of the help and replies, users pick another with `lang`
+    pub language: Lang,
+    /// Texts matching it are dropped without a reply
+    pub deny_pattern: Option<Regex>,
+    /// Commands besides the core ones, see [plugins]
+    pub plugins: Registry,
+    /// How long polls take votes
+    pub poll_duration: Duration,
+    /// Emails from the mesh, None when the gateway is off
+    pub email: Option<EmailOptions>,
+    /// Boards the channels sync with, None when there are none
+    pub federation: Option<FederationOptions>,
+    /// How long the packets of the texts sent to the board are kept, see
+    /// [BBS::keep_packet]. None does not keep them, zero keeps them forever.
+    pub raw_packets: Option<Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            admins: Vec::new(),
+            rate_limit_burst: 5,
+            rate_limit_refill: Duration::from_secs(30),
+            mute_after: 5,
+            mute_duration: Duration::from_secs(600),
+            channel_order: ChannelOrder::Name,
+            snapshot_dir: PathBuf::from("./snapshots"),
+            snapshot_files: Vec::new(),
+            dm_log_max_age: Duration::from_secs(30 * 24 * 60 * 60),
+            retention: Retention::default(),
+            motd: String::new(),
+            motd_after: Duration::ZERO,
+            max_message_len: 0,
+            require_pki: false,
+            verify_new_users: false,
+            session_ttl: Duration::from_secs(3600),
+            language: Lang::En,
+            deny_pattern: None,
+            plugins: Registry::default(),
+            poll_duration: Duration::from_secs(24 * 60 * 60),
+            email: None,
+            federation: None,
+            raw_packets: None,
+        }
+    }
 }
 
 pub struct BBS {
     storage: Storage,
+    options: Options,
     sessions: Cache<UserPkHash, Session>,
+    notifications: VecDeque<Notification>,
+    posts: VecDeque<Post>,
+    // Waypoints added by users, to share on the mesh
+    waypoints: VecDeque<Waypoint>,
+    // Users that shared a waypoint lately
+    waypoint_sharers: Cache<UserId, ()>,
+    emails: VecDeque<OutgoingEmail>,
+    limiter: RateLimiter,
+    // Last user seen from each node
+    nodes: Cache<u32, UserPkHash>,
+    // Commands already handled, by node and packet id
+    handled: Cache<(u32, u32), ()>,
+    deliveries: Deliveries,
+    round_trips: RoundTrips,
+    // Posts of the peer boards coming in parts
+    assembler: federation::Assembler,
+    // The tally of today, written now and then
+    counter: counters::Counter,
+    started: Instant,
 }
 
 impl BBS {
-    pub fn new(storage: Storage) -> Self {
+    pub fn new(storage: Storage, options: Options) -> Self {
+        let limiter = RateLimiter::new(
+            options.rate_limit_burst,
+            options.rate_limit_refill,
+            options.mute_after,
+            options.mute_duration,
+        );
+        let session_ttl = options.session_ttl;
         Self {
             storage,
+            options,
             sessions: Cache::builder()
                 .max_capacity(1024)
-                .time_to_live(Duration::from_secs(3600))
+                .time_to_live(session_ttl)
+                .build(),
+            notifications: VecDeque::new(),
+            posts: VecDeque::new(),
+            waypoints: VecDeque::new(),
+            waypoint_sharers: Cache::builder()
+                .max_capacity(1024)
+                .time_to_live(WAYPOINT_INTERVAL)
+                .build(),
+            emails: VecDeque::new(),
+            limiter,
+            nodes: Cache::builder().max_capacity(1024).build(),
+            handled: Cache::builder()
+                .max_capacity(1024)
+                .time_to_live(HANDLED_TTL)
                 .build(),
+            deliveries: Deliveries::default(),
+            round_trips: RoundTrips::default(),
+            assembler: federation::Assembler::default(),
+            counter: counters::Counter::new(Instant::now()),
+            started: Instant::now(),
         }
     }
 
@@ -70,88 +1013,3954 @@ impl BBS {
         Ok(())
     }
 
-    pub async fn handle(
-        &mut self,
-        user_pk_hash: [u8; 32],
-        short_name: &str,
-        command: &str,
-    ) -> Result<Vec<String>> {
-        let user_pk_hash = UserPkHash(user_pk_hash);
-        let mut session = if let Some(session) = self.sessions.get(&user_pk_hash) {
-            session
-        } else {
-            let current_channel = 0;
-            let user_id = if let Ok(user) = self.storage.get_user_by_pkhash(user_pk_hash.clone()) {
-                user.uid
-            } else {
-                self.storage.add_user(User {
-                    uid: 0,
-                    short_name: short_name.to_string(),
-                    pk_hash: user_pk_hash.clone(),
-                    last_ts: 0,
-                })?
+    /// Channels in listing order, with whether they are favorites of the user
+    fn channels_for(&self, uid: UserId) -> Result<Vec<(Channel, bool)>> {
+        let favorites = prefs::FAVORITES.get(&self.storage, uid)?;
+        let mut channels = Vec::new();
+        for channel in self.storage.get_channels()? {
+            let last_ts = match self.options.channel_order {
+                ChannelOrder::Name => 0,
+                ChannelOrder::Activity => self.storage.last_message_ts(channel.cid)?.unwrap_or(0),
             };
+            let favorite = favorites.0.contains(&channel.cid);
+            channels.push((channel, favorite, last_ts));
+        }
+        channels.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then(b.2.cmp(&a.2))
+                .then_with(|| a.0.name.cmp(&b.0.name))
+        });
+        Ok(channels
+            .into_iter()
+            .map(|(channel, favorite, _)| (channel, favorite))
+            .collect())
+    }
 
-            Session {
-                created: Instant::now(),
-                current_channel,
-                user_id,
+    /// Nickname of the user, or its radio short name if none
+    fn display_name(&self, user: &User) -> Result<String> {
+        let nick = prefs::NICK.get(&self.storage, user.uid)?;
+        if nick.is_empty() {
+            Ok(user.short_name.clone())
+        } else {
+            Ok(nick)
+        }
+    }
+
+    /// Finds a user by nickname, radio short name or the node id it was last
+    /// seen from
+    fn find_user(&self, name: &str) -> Result<Option<User>> {
+        if let Some(uid) = self.storage.find_preference(prefs::NICK.key, name)?.first() {
+            return Ok(Some(self.storage.get_user_by_id(*uid)?));
+        }
+        if let Some(user) = self.storage.get_user_by_short_name(name)? {
+            return Ok(Some(user));
+        }
+        match parse_node_id(name).and_then(|node| self.nodes.get(&node)) {
+            Some(pk_hash) => Ok(Some(self.storage.get_user_by_pkhash(pk_hash)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The poll, if its channel is still there and the user may use it
+    fn poll(&self, id: u32, pk_hash: &UserPkHash) -> Result<Poll> {
+        let poll = self.storage.get_poll(id)?;
+        let channels = self.storage.get_channels()?;
+        match poll {
+            Some(poll)
+                if channels.iter().any(|channel| channel.cid == poll.cid)
+                    && self.can_access(poll.cid, pk_hash)? =>
+            {
+                Ok(poll)
+            }
+            _ => mistake!("Poll #{id} not found"),
+        }
+    }
+
+    /// Whether the user may read and post to the channel, admins always can
+    fn can_access(&self, cid: ChannelId, pk_hash: &UserPkHash) -> Result<bool> {
+        if self.options.admins.contains(pk_hash) {
+            return Ok(true);
+        }
+        Ok(match self.storage.get_channel_acl(cid)? {
+            Some(acl) => acl.members.contains(pk_hash),
+            None => true,
+        })
+    }
+
+    fn list_page(
+        &self,
+        session: &Session,
+        window: (u64, u64),
+        page: usize,
+        now: u64,
+    ) -> Result<Vec<String>> {
+        let messages = self.storage.get_messages_page(
+            session.current_channel,
+            window.0,
+            window.1,
+            (page - 1) * PAGE_SIZE,
+            PAGE_SIZE + 1,
+        )?;
+        let more = messages.len() > PAGE_SIZE;
+        let mut ret = Vec::new();
+        for msg in messages.into_iter().take(PAGE_SIZE) {
+            let days = now.saturating_sub(msg.cid_ts.1) / (24 * 60 * 60 * 1000);
+            let reactions: String = self
+                .storage
+                .get_reactions(session.current_channel, msg.id)?
+                .into_iter()
+                .map(|(emoji, count)| format!(" +{count}{emoji}"))
+                .collect();
+            match msg.parent_id {
+                Some(parent_id) => ret.push(format!(
+                    "#{} {}d ↳ re #{}, {}{}",
+                    msg.id, days, parent_id, msg.text, reactions
+                )),
+                None => ret.push(format!("#{} {}d, {}{}", msg.id, days, msg.text, reactions)),
             }
+        }
+        if more {
+            ret.push(format!("more (p{})", page + 1));
+        } else if ret.is_empty() && page > 1 {
+            ret.push("No more messages".into());
+        }
+        Ok(ret)
+    }
+
+    /// Queues a notification, if the user wants this kind of push. Every
+    /// proactive send goes through here.
+    fn push(&mut self, uid: UserId, node: u32, push: prefs::Push, text: String) -> Result<()> {
+        if prefs::NOTIFY.get(&self.storage, uid)?.allows(push) {
+            self.notifications
+                .push_back(Notification { to: node, text });
+        }
+        Ok(())
+    }
+
+    /// Notifies the channel subscribers and queues the post for bridges,
+    /// unless the channel is not public
+    fn published(&mut self, cid: ChannelId, uid: UserId, author: &str, msg: &str) -> Result<()> {
+        let channels = self.storage.get_channels()?;
+        let Some(channel) = channels.iter().find(|ch| ch.cid == cid) else {
+            return Ok(());
         };
+        let msg_lowercase = msg.to_lowercase();
+        for sub in self.storage.get_subscriptions(channel.cid)? {
+            let sub_uid = sub.cid_uid.1;
+            if sub_uid == uid {
+                continue;
+            }
+            let sub_user = match self.storage.get_user_by_id(sub_uid) {
+                Ok(sub_user) => sub_user,
+                Err(err) => {
+                    warn!(target: "bbs", "Not notifying subscriber {sub_uid}: {err}");
+                    continue;
+                }
+            };
+            if !self.can_access(cid, &sub_user.pk_hash)? {
+                continue;
+            }
+            let name = self.display_name(&sub_user)?;
+            let push = if msg_lowercase.contains(&format!("@{}", name.to_lowercase())) {
+                prefs::Push::Mention
+            } else {
+                prefs::Push::Channel
+            };
+            self.push(
+                sub_uid,
+                sub.node,
+                push,
+                format!("#{} {}: {}", channel.name, author, msg),
+            )?;
+        }
+        self.count(|tally| *tally.posts.entry(channel.name.clone()).or_default() += 1);
+        if self.storage.get_channel_acl(cid)?.is_some() {
+            return Ok(());
+        }
+        if self.posts.len() == MAX_PENDING_POSTS {
+            self.posts.pop_front();
+        }
+        self.posts.push_back(Post {
+            channel: channel.name.clone(),
+            author: author.to_string(),
+            text: msg.to_string(),
+        });
+        Ok(())
+    }
 
-        let mut user = self.storage.get_user_by_id(session.user_id)?;
+    /// Posts a message on behalf of someone not on the mesh, e.g. a bridge,
+    /// returns its number
+    pub fn post_as(&mut self, ch: &str, author: &str, msg: &str) -> Result<u32> {
+        let channels = self.storage.get_channels()?;
+        let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+            bail!("Channel {ch} not found");
+        };
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis() as u64;
+        let id = self.storage.add_message(ChannelMessage {
+            cid_ts: (channel.cid, now),
+            uid: SYSOP_UID,
+            text: format!("{author}: {msg}"),
+            id: 0,
+            parent_id: None,
+        })?;
+        self.published(channel.cid, SYSOP_UID, author, msg)?;
+        Ok(id)
+    }
 
-        match Command::parse(command) {
-            Ok(Command::Channels) => {
-                let channels = self.storage.get_channels()?;
-                let list = channels
-                    .iter()
-                    .map(|c| c.name.clone())
-                    .collect::<Vec<String>>()
-                    .join(",");
-                return Ok(vec![list]);
-            }
-            Ok(Command::Join { ch }) => {
-                let channels = self.storage.get_channels()?;
-                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
-                    bail!("Channel not found");
-                };
-                session.current_channel = channel.cid;
-                self.sessions.insert(user_pk_hash, session);
-                return Ok(vec!["Ack".into()]);
-            }
-            Ok(Command::Post { msg }) => {
-                let message = ChannelMessage {
-                    cid_ts: (session.current_channel, now),
-                    uid: session.user_id,
-                    text: format!("{}: {}", user.short_name, msg),
-                };
+    /// Posts a message that came through a bridge. Channels that are not
+    /// public are not bridged, so they take none.
+    pub fn post_bridged(&mut self, ch: &str, author: &str, msg: &str) -> Result<u32> {
+        let channels = self.storage.get_channels()?;
+        if let Some(channel) = channels.iter().find(|channel| channel.name == ch)
+            && self.storage.get_channel_acl(channel.cid)?.is_some()
+        {
+            bail!("Channel {ch} is not public");
+        }
+        self.post_as(ch, author, msg)
+    }
 
-                self.storage.add_message(message)?;
+    /// Posts a message on behalf of the BBS, e.g. scheduled announcements
+    pub fn post_as_sysop(&mut self, ch: &str, msg: &str) -> Result<()> {
+        self.post_as(ch, SYSOP_NAME, msg)?;
+        Ok(())
+    }
 
-                return Ok(vec!["Ack".into()]);
+    /// Updates the fleet inventory, fields unknown to `node` keep their value
+    pub fn node_seen(&self, mut node: Node) -> Result<()> {
+        if let Some(known) = self.storage.get_node(node.num)? {
+            for (field, known) in [
+                (&mut node.short_name, known.short_name),
+                (&mut node.hw_model, known.hw_model),
+                (&mut node.role, known.role),
+            ] {
+                if field.is_empty() {
+                    *field = known;
+                }
             }
+            node.firmware = node.firmware.or(known.firmware);
+        }
+        self.storage.upsert_node(node)
+    }
 
-            Ok(Command::List) => {
-                let messages =
-                    self.storage
-                        .get_messages(session.current_channel, user.last_ts, now)?;
-                let mut ret = vec![format!("{} Messages.", messages.len())];
-                for msg in messages {
-                    let days = (now - msg.cid_ts.1) / (24 * 60 * 60);
-                    ret.push(format!("{}d, {}", days, msg.text));
-                }
-                user.last_ts = now;
-                self.storage.update_user(user.uid, user)?;
-                return Ok(ret);
+    /// Records that a node was heard, names unknown to `sighting` keep their
+    /// value
+    pub fn node_heard(&mut self, mut sighting: Sighting) -> Result<()> {
+        self.count(|tally| tally.packets += 1);
+        if let Some(known) = self.storage.get_sighting(sighting.num)? {
+            if sighting.short_name.is_empty() {
+                sighting.short_name = known.short_name;
             }
-            _ => {
-                return Ok(vec![HELP.into()]);
+            if sighting.long_name.is_empty() {
+                sighting.long_name = known.long_name;
             }
         }
+        self.storage.upsert_sighting(sighting)
+    }
+
+    pub fn record_telemetry(&self, num: u32, ts: u64, metrics: Metrics) -> Result<()> {
+        self.storage.add_telemetry(TelemetrySample {
+            num_ts: (num, ts),
+            metrics,
+        })
+    }
+
+    pub fn record_position(&self, num: u32, ts: u64, (lat, lon): (f64, f64)) -> Result<()> {
+        self.storage.add_position(PositionSample {
+            num_ts: (num, ts),
+            latitude_i: (lat * 1e7) as i32,
+            longitude_i: (lon * 1e7) as i32,
+        })
+    }
+
+    /// Finds a node of the inventory by short name or node id
+    fn find_node(&self, name: &str) -> Result<Option<u32>> {
+        let nodes = self.storage.get_nodes()?;
+        if let Some(node) = nodes.iter().find(|node| node.short_name == name) {
+            return Ok(Some(node.num));
+        }
+        Ok(parse_node_id(name))
+    }
+
+    /// Name to show for the node, see [Names::display]
+    pub fn node_name(&self, num: u32) -> Result<String> {
+        let mut names = Names::default();
+        if let Some(sighting) = self.storage.get_sighting(num)? {
+            names.short_name = sighting.short_name;
+            names.long_name = sighting.long_name;
+        }
+        if let Some(node) = self.storage.get_node(num)?
+            && !node.short_name.is_empty()
+        {
+            names.short_name = node.short_name;
+        }
+        if let Some(pk_hash) = self.nodes.get(&num) {
+            names.nickname = self.nickname(&pk_hash)?;
+        }
+        Ok(names.display(num))
+    }
+
+    /// BBS nickname of the user, empty if none
+    pub fn nickname(&self, pk_hash: &UserPkHash) -> Result<String> {
+        match self.storage.get_user_by_pkhash(pk_hash.clone()) {
+            Ok(user) => prefs::NICK.get(&self.storage, user.uid),
+            Err(_) => Ok(String::new()),
+        }
+    }
+
+    /// Keeps the MeshPacket of a text sent to the board, as protobuf, when
+    /// the options ask for it. `meshboard decode` decodes it again.
+    pub fn keep_packet(&self, id: u32, from: u32, raw: &[u8]) -> Result<()> {
+        if self.options.raw_packets.is_none() || raw.is_empty() {
+            return Ok(());
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        self.storage.add_raw_packet(RawPacket {
+            id,
+            from,
+            ts: now,
+            bytes: raw.to_vec(),
+        })
+    }
+
+    // Keeps every text sent to the board within the rate limit, also the
+    // ones that are not commands, without channel passwords
+    fn log_direct_message(&self, sender: &Sender, text: &str) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let text = match Command::parse(text) {
+            Ok(Command::Join {
+                ch,
+                password: Some(_),
+            }) => format!("join {ch} ***"),
+            Ok(Command::Acl {
+                ch,
+                action: Some(AclAction::Password(_)),
+            }) => format!("acl {ch} password ***"),
+            _ => text.to_string(),
+        };
+        self.storage.add_direct_message(DirectMessage {
+            ts: now,
+            from: sender.node,
+            pk_hash: UserPkHash(sender.pk_hash),
+            text,
+        })
+    }
+
+    /// Removes the messages past the retention of their channel, then
+    /// compacts the database. Returns how many messages were removed.
+    pub fn prune(&mut self) -> Result<usize> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut count = 0;
+        for channel in self.storage.get_channels()? {
+            let policy = self.options.retention.policy(&channel.name);
+            if policy.keeps_all() {
+                continue;
+            }
+            let ts_end = if policy.max_age.is_zero() {
+                0
+            } else {
+                now.saturating_sub(policy.max_age.as_millis() as u64)
+            };
+            count += self
+                .storage
+                .prune_messages(channel.cid, ts_end, policy.max_count)?;
+        }
+        self.storage
+            .prune_synced_posts(now.saturating_sub(federation::KEEP_HASHES.as_millis() as u64))?;
+        self.storage.prune_waypoints(now)?;
+        self.storage
+            .prune_neighbors(now.saturating_sub(NEIGHBORS_MAX_AGE))?;
+        if !self.options.dm_log_max_age.is_zero() {
+            let max_age = self.options.dm_log_max_age.as_millis() as u64;
+            self.storage
+                .prune_direct_messages(now.saturating_sub(max_age))?;
+        }
+        if let Some(max_age) = self.options.raw_packets
+            && !max_age.is_zero()
+        {
+            self.storage
+                .prune_raw_packets(now.saturating_sub(max_age.as_millis() as u64))?;
+        }
+        if count > 0 {
+            self.storage.compact()?;
+        }
+        Ok(count)
+    }
+
+    /// Announcements scheduled with the announce command
+    pub fn announcements(&self) -> Result<Vec<schedule::Entry>> {
+        self.storage
+            .get_announcements()?
+            .into_iter()
+            .map(|announcement| announcement.entry.parse())
+            .collect()
+    }
+
+    /// Keeps the forecast for the weather command and scheduled texts
+    pub fn record_forecast(&self, forecast: &Forecast) -> Result<()> {
+        forecast.store(&self.storage)
+    }
+
+    /// The last forecast fetched, see [crate::weather]
+    pub fn forecast(&self) -> Result<Option<Forecast>> {
+        Forecast::load(&self.storage)
+    }
+
+    /// Rooms the Matrix bridge created for the channels
+    pub fn matrix_rooms(&self) -> Result<Rooms> {
+        matrix::load_rooms(&self.storage)
+    }
+
+    pub fn record_matrix_rooms(&self, rooms: &Rooms) -> Result<()> {
+        matrix::store_rooms(&self.storage, rooms)
+    }
+
+    /// Nodes the sysop asked to watch
+    pub fn watched(&self) -> Result<Vec<u32>> {
+        Ok(self
+            .storage
+            .get_watches()?
+            .into_iter()
+            .map(|watch| watch.node)
+            .collect())
+    }
+
+    /// Lets the user run admin commands, besides the admins of the options
+    pub fn add_admin(&mut self, user: UserPkHash) {
+        if !self.options.admins.contains(&user) {
+            self.options.admins.push(user);
+        }
+    }
+
+    /// Welcome text, as set by the admins or the default of the options
+    fn motd(&self) -> Result<String> {
+        Ok(match self.storage.get_setting(MOTD_KEY)? {
+            Some(motd) => motd,
+            None => self.options.motd.clone(),
+        })
+    }
+
+    // Records that the user was seen, and returns the welcome text if the user
+    // is new or was not seen for a while
+    fn greeting(&self, user: &User, now: u64, new_user: bool) -> Result<Option<String>> {
+        let last_seen = prefs::LAST_SEEN.get(&self.storage, user.uid)?;
+        prefs::LAST_SEEN.set(&self.storage, user.uid, &now)?;
+        let after = self.options.motd_after.as_millis() as u64;
+        let due = new_user || (after > 0 && now.saturating_sub(last_seen) > after);
+        let motd = self.motd()?;
+        if !due || motd.is_empty() {
+            return Ok(None);
+        }
+        let mut channels = Vec::new();
+        let mut unread = 0;
+        for (channel, _) in self.channels_for(user.uid)? {
+            if !self.can_access(channel.cid, &user.pk_hash)? {
+                continue;
+            }
+            unread += self
+                .storage
+                .count_messages(channel.cid, user.last_ts, now)?;
+            channels.push(channel.name);
+        }
+        Ok(Some(
+            motd.replace("{name}", &self.display_name(user)?)
+                .replace("{channels}", &channels.join(","))
+                .replace("{unread}", &unread.to_string()),
+        ))
+    }
+
+    /// Tracks the replies just sent to the node for `command`, each with the
+    /// texts of the packets it goes out in, for the status command. The
+    /// replies to status itself are not tracked, they would hide the batch
+    /// they report on.
+    pub fn replied(&mut self, node: u32, command: &str, replies: Vec<(String, Vec<String>)>) {
+        if matches!(Command::parse(command), Ok(Command::Status)) {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        self.deliveries.replied(node, now, replies);
+    }
+
+    /// A text went out to the node, returns whether it is one of the replies
+    pub fn reply_sent(&mut self, node: u32, id: u32, text: &str) -> bool {
+        self.deliveries.sent(node, id, text)
+    }
+
+    /// The mesh reported on a reply packet
+    pub fn reply_delivery(&mut self, id: u32, delivery: Delivery) -> Result<()> {
+        if !self.deliveries.update(id, delivery.clone()) {
+            return Ok(());
+        }
+        match delivery {
+            Delivery::Delivered => self.count(|tally| tally.acked += 1),
+            Delivery::Failed => self.count(|tally| tally.failed += 1),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// A text to the node was acked after `round_trip` ms, or None ran out
+    /// of retries, for `stats delivery`
+    pub fn delivery_ended(&mut self, node: u32, id: u32, round_trip: Option<u64>) {
+        self.round_trips.record(node, id, round_trip);
+    }
+
+    /// The sessions of the users active in the last hour, most recent first:
+    /// public key hash prefix, name, current channel and idle time
+    pub fn sessions(&self) -> Result<Vec<String>> {
+        let channels = self.storage.get_channels()?;
+        let mut sessions: Vec<(UserPkHash, Session)> = self
+            .sessions
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        sessions.sort_by_key(|(_, session)| std::cmp::Reverse(session.last_seen));
+        let mut lines = Vec::new();
+        for (pk_hash, session) in sessions {
+            let user = self.storage.get_user_by_id(session.user_id)?;
+            let channel = channels
+                .iter()
+                .find(|channel| channel.cid == session.current_channel)
+                .map_or("?", |channel| channel.name.as_str());
+            lines.push(format!(
+                "{} {} #{} idle {}",
+                hex::encode(&pk_hash.0[..4]),
+                self.display_name(&user)?,
+                channel,
+                format_age(session.last_seen.elapsed().as_millis() as u64)
+            ));
+        }
+        Ok(lines)
+    }
+
+    /// Ends the session of the user, their next command starts in the first
+    /// channel with no listing to page. False if they had none.
+    pub fn end_session(&self, pk_hash: &UserPkHash) -> Result<bool> {
+        let Some(session) = self.sessions.get(pk_hash) else {
+            return Ok(false);
+        };
+        prefs::CHANNEL.reset(&self.storage, session.user_id)?;
+        self.sessions.invalidate(pk_hash);
+        Ok(true)
+    }
+
+    // Channel a new session of the user starts in: the one they were in if
+    // their last session started within the session TTL, e.g. before a
+    // restart, and the first one otherwise
+    fn restored_channel(&self, user_id: UserId) -> Result<ChannelId> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let last_seen = prefs::LAST_SEEN.get(&self.storage, user_id)?;
+        if now.saturating_sub(last_seen) > self.options.session_ttl.as_millis() as u64 {
+            return Ok(0);
+        }
+        let cid = prefs::CHANNEL.get(&self.storage, user_id)?;
+        let exists = self
+            .storage
+            .get_channels()?
+            .iter()
+            .any(|channel| channel.cid == cid);
+        Ok(if exists { cid } else { 0 })
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        self.storage.stats()
+    }
+
+    /// Totals of the board, and the tally of today
+    pub fn stats_report(&self) -> Result<String> {
+        let stats = self.storage.stats()?;
+        Ok(format!(
+            "{} users, {} channels, {} messages\nToday: {}",
+            stats.users,
+            stats.channels,
+            stats.messages,
+            self.counter.today(&self.storage, today())?
+        ))
+    }
+
+    /// The tally of yesterday, posts of the channels open to everyone only
+    pub fn digest(&self) -> Result<String> {
+        let mut tally = self.counter.yesterday(&self.storage, today())?;
+        let mut public = Vec::new();
+        for channel in self.storage.get_channels()? {
+            if self.storage.get_channel_acl(channel.cid)?.is_none() {
+                public.push(channel.name);
+            }
+        }
+        tally.posts.retain(|channel, _| public.contains(channel));
+        Ok(format!("Stats of {}: {tally}", tally.date))
+    }
+
+    /// The sync requests to send to each peer board
+    pub fn sync_requests(&self) -> Result<Vec<(u32, String)>> {
+        let Some(federation) = &self.options.federation else {
+            return Ok(Vec::new());
+        };
+        federation
+            .peers
+            .keys()
+            .map(|peer| {
+                let request = federation::sync_request(&self.storage, *peer, &federation.channels)?;
+                Ok((*peer, request))
+            })
+            .collect()
+    }
+
+    /// Answers a frame of the sync protocol from a peer board
+    fn federated(&mut self, peer: u32, frame: Frame) -> Result<Vec<String>> {
+        let Some(federation) = self.options.federation.clone() else {
+            return Ok(Vec::new());
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        match frame {
+            Frame::Sync(cursors) => {
+                let channels = self.storage.get_channels()?;
+                let origin = federation::origin(&self.storage)?;
+                let oldest = now.saturating_sub(federation::BACKFILL.as_millis() as u64);
+                let (mut frames, mut posts) = (Vec::new(), 0);
+                for (name, since) in cursors {
+                    let Some(channel) = channels.iter().find(|channel| channel.name == name) else {
+                        continue;
+                    };
+                    if !federation.channels.contains(&name) {
+                        continue;
+                    }
+                    let messages = self.storage.get_messages_page(
+                        channel.cid,
+                        since.max(oldest) + 1,
+                        u64::MAX,
+                        0,
+                        usize::MAX,
+                    )?;
+                    let mut prev = since;
+                    for message in messages {
+                        let hash = match self.storage.get_synced_post_of(channel.cid, message.id)? {
+                            // Loops back to where it came from
+                            Some(synced) if synced.from == peer => continue,
+                            Some(synced) => synced.hash,
+                            None => {
+                                let hash = federation::hash(
+                                    origin,
+                                    &name,
+                                    message.cid_ts.1,
+                                    &message.text,
+                                );
+                                self.storage.add_synced_post(SyncedPost {
+                                    hash,
+                                    from: 0,
+                                    ts: now,
+                                    message: Some((channel.cid, message.id)),
+                                })?;
+                                hash
+                            }
+                        };
+                        if federation::is_full(posts) {
+                            frames.push(Frame::More.to_string());
+                            return Ok(frames);
+                        }
+                        frames.extend(federation::post_frames(
+                            &name,
+                            message.cid_ts.1,
+                            prev,
+                            hash,
+                            &message.text,
+                            federation.max_payload,
+                        ));
+                        prev = message.cid_ts.1;
+                        posts += 1;
+                    }
+                }
+                Ok(frames)
+            }
+            Frame::Post {
+                channel,
+                ts,
+                prev,
+                hash,
+                part,
+                parts,
+                text,
+            } => {
+                let channels = self.storage.get_channels()?;
+                let Some(cid) = channels
+                    .iter()
+                    .find(|known| known.name == channel)
+                    .map(|known| known.cid)
+                    .filter(|_| federation.channels.contains(&channel))
+                else {
+                    return Ok(Vec::new());
+                };
+                let Some(text) = self.assembler.add(peer, hash, part, parts, &text) else {
+                    return Ok(Vec::new());
+                };
+                // Came already, through this peer or another
+                if self.storage.get_synced_post(hash)?.is_none() {
+                    let (author, msg) = text.split_once(": ").unwrap_or((SYSOP_NAME, &text));
+                    let id = self.post_as(&channel, author, msg)?;
+                    self.storage.add_synced_post(SyncedPost {
+                        hash,
+                        from: peer,
+                        ts: now,
+                        message: Some((cid, id)),
+                    })?;
+                }
+                federation::advance(&self.storage, peer, &channel, prev, ts)?;
+                Ok(Vec::new())
+            }
+            Frame::More => Ok(vec![federation::sync_request(
+                &self.storage,
+                peer,
+                &federation.channels,
+            )?]),
+        }
+    }
+
+    /// Updates the tally of today, a stat that cannot be counted fails
+    /// nothing else
+    fn count(&mut self, f: impl FnOnce(&mut counters::Tally)) {
+        if let Err(err) = self
+            .counter
+            .count(&self.storage, today(), Instant::now(), f)
+        {
+            warn!(target: "bbs", "Cannot count the stats: {err}");
+        }
+    }
+
+    /// Writes the tally of today, before shutting down
+    pub fn flush_stats(&mut self) -> Result<()> {
+        self.counter.flush(&self.storage, Instant::now())
+    }
+
+    /// A text from the mesh could not be answered
+    pub fn text_failed(&mut self) {
+        self.count(|tally| tally.errors += 1)
+    }
+
+    /// Next pending push notification, if any
+    pub fn next_notification(&mut self) -> Option<Notification> {
+        self.notifications.pop_front()
+    }
+
+    /// Next post not yet relayed, if any
+    pub fn next_post(&mut self) -> Option<Post> {
+        self.posts.pop_front()
+    }
+
+    /// Next waypoint to share on the mesh, if any
+    pub fn next_waypoint(&mut self) -> Option<Waypoint> {
+        self.waypoints.pop_front()
+    }
+
+    /// Keeps a waypoint a node shared, unless it changes one locked to
+    /// another node
+    pub fn record_waypoint(&self, waypoint: Waypoint) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        self.storage.add_waypoint(waypoint, now)?;
+        Ok(())
+    }
+
+    /// Keeps the nodes a node hears, with the SNR it hears each at
+    pub fn record_neighbors(&self, node: u32, neighbors: &[(u32, f32)]) -> Result<()> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let neighbors = neighbors
+            .iter()
+            .map(|(neighbor, snr)| Neighbor {
+                node_neighbor: (node, *neighbor),
+                snr: *snr,
+                ts,
+            })
+            .collect();
+        self.storage.set_neighbors(node, neighbors)
+    }
+
+    /// Next email to send through the gateway, if any
+    pub fn next_email(&mut self) -> Option<OutgoingEmail> {
+        self.emails.pop_front()
+    }
+
+    /// Files a reply to an email of a user as their mail, and lets them know.
+    /// False if it is not a reply to one of their emails, from the address it
+    /// went to or one of the allowed ones.
+    pub fn email_received(&mut self, email: IncomingEmail) -> Result<bool> {
+        let Some((uid, token, subject)) = mailbox::untag(&email.subject) else {
+            return Ok(false);
+        };
+        let Ok(user) = self.storage.get_user_by_id(uid) else {
+            return Ok(false);
+        };
+        let Some(to) = mailbox::thread_recipient(&self.storage, user.uid, &token)? else {
+            return Ok(false);
+        };
+        let allowed = self
+            .options
+            .email
+            .as_ref()
+            .is_some_and(|options| options.allows(&email.from));
+        if !email.from.eq_ignore_ascii_case(&to) && !allowed {
+            return Ok(false);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let id = self.storage.add_mail(Mail {
+            id: 0,
+            uid: user.uid,
+            from: email.from.clone(),
+            subject,
+            text: mailbox::reply_text(&email.text),
+            ts: now,
+            read: false,
+        })?;
+        let node = prefs::MAIL_NODE.get(&self.storage, user.uid)?;
+        if node != 0 {
+            self.push(
+                user.uid,
+                node,
+                prefs::Push::Mail,
+                format!("Mail #{id} from {}, send read {id}", email.from),
+            )?;
+        }
+        Ok(true)
+    }
+
+    /// Whether the user or the node of the sender is banned, their texts are
+    /// dropped
+    pub fn is_blocked(&self, sender: &Sender) -> Result<bool> {
+        Ok(self.storage.is_node_banned(sender.node)?
+            || self.storage.is_banned(&UserPkHash(sender.pk_hash))?)
+    }
+
+    /// Replies to the command, in the language of the user. Mistakes in the
+    /// command, e.g. a channel that does not exist, are replied too, Err is
+    /// left for failures of the board itself.
+    pub async fn handle(&mut self, sender: &Sender, command: &str) -> Result<Vec<String>> {
+        // Rebroadcasts and radios sharing the mesh deliver the same packet
+        // more than once, it runs once
+        if let Some(packet) = sender.packet {
+            if self.handled.contains_key(&(sender.node, packet)) {
+                return Ok(vec![]);
+            }
+            self.handled.insert((sender.node, packet), ());
+        }
+        let replies = match self.handle_command(sender, command).await {
+            Ok(replies) => replies,
+            Err(err) if is_failure(&err) => return Err(err),
+            Err(err) => vec![err.to_string()],
+        };
+        let lang = self.language(&UserPkHash(sender.pk_hash))?;
+        Ok(replies
+            .into_iter()
+            .map(|reply| i18n::translate(lang, &reply).map_or(reply, str::to_string))
+            .collect())
+    }
+
+    // Language of the user, the one of the board until they pick one
+    fn language(&self, pk_hash: &UserPkHash) -> Result<Lang> {
+        let Ok(user) = self.storage.get_user_by_pkhash(pk_hash.clone()) else {
+            return Ok(self.options.language);
+        };
+        Ok(prefs::LANGUAGE
+            .get(&self.storage, user.uid)?
+            .parse()
+            .unwrap_or(self.options.language))
+    }
+
+    async fn handle_command(&mut self, sender: &Sender, command: &str) -> Result<Vec<String>> {
+        let user_pk_hash = UserPkHash(sender.pk_hash);
+        if self.is_blocked(sender)? {
+            return Ok(vec![]);
+        }
+        // Peer boards sync in bursts, they are not rate limited. Node ids are
+        // easy to spoof, only frames encrypted with their key are taken.
+        if sender.encrypted
+            && self.options.federation.as_ref().is_some_and(|federation| {
+                federation.peers.get(&sender.node) == Some(&sender.pk_hash)
+            })
+            && let Some(frame) = Frame::parse(command)
+        {
+            return self.federated(sender.node, frame);
+        }
+        self.nodes.insert(sender.node, user_pk_hash.clone());
+        match self.limiter.check(&user_pk_hash) {
+            Ok(()) => {}
+            Err(Throttled::RetryAfter(retry_after)) => {
+                return Ok(vec![format!(
+                    "Slow down, try again in {}s",
+                    retry_after.as_secs().max(1)
+                )]);
+            }
+            Err(Throttled::Muted(duration)) => {
+                return Ok(vec![format!(
+                    "Too many commands, muted for {}m",
+                    duration.as_secs().div_ceil(60)
+                )]);
+            }
+            Err(Throttled::Silenced) => return Ok(vec![]),
+        }
+        // The log is for the sysop, a text is answered even if it fails
+        if let Err(err) = self.log_direct_message(sender, command) {
+            warn!(target: "bbs", "Cannot log a direct message: {err}");
+        }
+        // Denied and oversized texts count against the rate limit too, so
+        // they cannot flood the board with replies
+        if let Some(pattern) = &self.options.deny_pattern
+            && pattern.is_match(command)
+        {
+            return Ok(vec![]);
+        }
+        let max_len = self.options.max_message_len;
+        if max_len > 0 && command.chars().count() > max_len {
+            return Ok(vec![format!("Too long, max {max_len} chars")]);
+        }
+        if self.options.require_pki && !sender.encrypted {
+            return Ok(vec![
+                "This board only takes encrypted DMs. Use firmware 2.5 or newer and wait \
+                 to get its node info, then try again"
+                    .to_string(),
+            ]);
+        }
+        // Whether the command starts a session, and of a user never seen
+        let (mut started, mut new_user) = (false, false);
+        let mut session = if let Some(session) = self.sessions.get(&user_pk_hash) {
+            session
+        } else {
+            started = true;
+            let user_id = if let Ok(user) = self.storage.get_user_by_pkhash(user_pk_hash.clone()) {
+                user.uid
+            } else {
+                new_user = true;
+                self.storage.add_user(User {
+                    uid: 0,
+                    short_name: sender.short_name.clone(),
+                    pk_hash: user_pk_hash.clone(),
+                    last_ts: 0,
+                    pending: self.options.verify_new_users,
+                })?
+            };
+
+            Session {
+                created: Instant::now(),
+                last_seen: Instant::now(),
+                current_channel: self.restored_channel(user_id)?,
+                user_id,
+                list_window: None,
+                list_page: 1,
+                challenge: None,
+            }
+        };
+        // Sessions last the TTL from the last command
+        session.last_seen = Instant::now();
+        self.sessions.insert(user_pk_hash.clone(), session.clone());
+
+        let mut user = self.storage.get_user_by_id(session.user_id)?;
+        self.count(|tally| {
+            tally.users.insert(session.user_id);
+        });
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        if started && let Some(text) = self.greeting(&user, now, new_user)? {
+            self.push(user.uid, sender.node, prefs::Push::Motd, text)?;
+        }
+
+        let is_admin = self.options.admins.contains(&user_pk_hash);
+        let text = i18n::unalias(command);
+        let text = text.as_ref();
+        let command = Command::parse(text);
+        let mut args = text.split_whitespace();
+        let plugin = match command {
+            Ok(_) => None,
+            Err(_) => args.next().and_then(|word| self.options.plugins.find(word)),
+        };
+        if let Ok(command) = &command
+            && command.is_admin()
+            && !is_admin
+        {
+            return Ok(vec!["Not allowed".into()]);
+        }
+        if user.pending && !is_admin {
+            if session.challenge.as_deref() == Some(text.trim()) {
+                user.pending = false;
+                self.storage.update_user(user.uid, user.clone())?;
+                session.challenge = None;
+                self.sessions.insert(user_pk_hash, session);
+                return Ok(vec!["Verified, you can post now".into()]);
+            }
+            if plugin.is_some() || command.as_ref().is_ok_and(Command::publishes) {
+                let code = session.challenge.get_or_insert_with(challenge_code).clone();
+                self.sessions.insert(user_pk_hash, session);
+                return Ok(vec![format!(
+                    "New users are verified first, reply {code} then post again"
+                )]);
+            }
+        }
+        if let Some(plugin) = plugin {
+            let ctx = plugins::Context {
+                sender,
+                now,
+                uptime: self.started.elapsed(),
+                storage: &self.storage,
+            };
+            return plugin.handle(&ctx, &args.collect::<Vec<_>>());
+        }
+
+        match command {
+            Ok(Command::Channels) => {
+                let mut names = Vec::new();
+                for (c, favorite) in self.channels_for(user.uid)? {
+                    // Channels only members can join are not advertised
+                    let private = self
+                        .storage
+                        .get_channel_acl(c.cid)?
+                        .is_some_and(|acl| acl.password.is_none());
+                    if private && !self.can_access(c.cid, &user_pk_hash)? {
+                        continue;
+                    }
+                    if favorite {
+                        names.push(format!("*{}", c.name));
+                    } else {
+                        names.push(c.name);
+                    }
+                }
+                return Ok(vec![names.join(",")]);
+            }
+            Ok(Command::Where { node }) => {
+                let Some(num) = self.find_node(&node)? else {
+                    mistake!("Node not found");
+                };
+                let Some(position) = self.storage.last_position(num)? else {
+                    return Ok(vec![format!("No position for {}", self.node_name(num)?)]);
+                };
+                return Ok(vec![format!(
+                    "{} {:.5},{:.5} {} ago",
+                    self.node_name(num)?,
+                    position.latitude_i as f64 / 1e7,
+                    position.longitude_i as f64 / 1e7,
+                    format_age(now.saturating_sub(position.num_ts.1))
+                )]);
+            }
+            Ok(Command::Neighbors { node }) => {
+                let Some(num) = self.find_node(&node)? else {
+                    mistake!("Node not found");
+                };
+                let mut neighbors = self.storage.get_neighbors(num)?;
+                if neighbors.is_empty() {
+                    return Ok(vec![format!("No neighbors for {}", self.node_name(num)?)]);
+                }
+                neighbors.sort_by(|a, b| b.snr.total_cmp(&a.snr));
+                let mut ret = vec![format!("{} hears", self.node_name(num)?)];
+                for neighbor in neighbors {
+                    ret.push(format!(
+                        "{} {:.1}dB {} ago",
+                        self.node_name(neighbor.node_neighbor.1)?,
+                        neighbor.snr,
+                        format_age(now.saturating_sub(neighbor.ts))
+                    ));
+                }
+                return Ok(ret);
+            }
+            Ok(Command::Fav { ch }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                let mut favorites = prefs::FAVORITES.get(&self.storage, user.uid)?;
+                if !favorites.0.contains(&channel.cid) {
+                    favorites.0.push(channel.cid);
+                    prefs::FAVORITES.set(&self.storage, user.uid, &favorites)?;
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Unfav { ch }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                let mut favorites = prefs::FAVORITES.get(&self.storage, user.uid)?;
+                favorites.0.retain(|cid| *cid != channel.cid);
+                prefs::FAVORITES.set(&self.storage, user.uid, &favorites)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Join { ch, password }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                if !self.can_access(channel.cid, &user_pk_hash)? {
+                    let Some(mut acl) = self.storage.get_channel_acl(channel.cid)? else {
+                        mistake!("Private channel");
+                    };
+                    match (&acl.password, password) {
+                        (None, _) => mistake!("Private channel"),
+                        (Some(_), None) => mistake!("Password required"),
+                        (Some(hash), Some(password)) if *hash != password_hash(&password) => {
+                            mistake!("Wrong password")
+                        }
+                        _ => {}
+                    }
+                    acl.members.push(user_pk_hash.clone());
+                    self.storage.set_channel_acl(acl)?;
+                }
+                session.current_channel = channel.cid;
+                prefs::CHANNEL.set(&self.storage, user.uid, &channel.cid)?;
+                self.sessions.insert(user_pk_hash, session);
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(
+                Command::Post { .. }
+                | Command::Reply { .. }
+                | Command::React { .. }
+                | Command::PollNew { .. }
+                | Command::Vote { .. },
+            ) if !self.can_access(session.current_channel, &user_pk_hash)? => {
+                mistake!("Not a member of the channel");
+            }
+            Ok(Command::Post { msg }) => {
+                let author = self.display_name(&user)?;
+                self.storage.add_message(ChannelMessage {
+                    cid_ts: (session.current_channel, sender.received_at(now)),
+                    uid: session.user_id,
+                    text: format!("{}: {}", author, msg),
+                    id: 0,
+                    parent_id: None,
+                })?;
+                self.published(session.current_channel, session.user_id, &author, &msg)?;
+
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Reply { id, msg }) => {
+                if self
+                    .storage
+                    .get_message(session.current_channel, id)?
+                    .is_none()
+                {
+                    mistake!("Message #{id} not found");
+                }
+                let author = self.display_name(&user)?;
+                self.storage.add_message(ChannelMessage {
+                    cid_ts: (session.current_channel, sender.received_at(now)),
+                    uid: session.user_id,
+                    text: format!("{}: {}", author, msg),
+                    id: 0,
+                    parent_id: Some(id),
+                })?;
+                let msg = format!("↳ re #{id} {msg}");
+                self.published(session.current_channel, session.user_id, &author, &msg)?;
+
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::React { id, emoji }) => {
+                if self
+                    .storage
+                    .get_message(session.current_channel, id)?
+                    .is_none()
+                {
+                    mistake!("Message #{id} not found");
+                }
+                if !self.storage.toggle_reaction(
+                    session.current_channel,
+                    id,
+                    session.user_id,
+                    &emoji,
+                )? {
+                    return Ok(vec!["Reaction removed".into()]);
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Subscribe { ch }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                if !self.can_access(channel.cid, &user_pk_hash)? {
+                    mistake!("Not a member of the channel");
+                }
+                self.storage.add_subscription(Subscription {
+                    cid_uid: (channel.cid, session.user_id),
+                    node: sender.node,
+                })?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Unsubscribe { ch }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                self.storage
+                    .remove_subscription(channel.cid, session.user_id)?;
+                return Ok(vec!["Ack".into()]);
+            }
+
+            Ok(Command::List { .. } | Command::Next)
+                if !self.can_access(session.current_channel, &user_pk_hash)? =>
+            {
+                mistake!("Not a member of the channel");
+            }
+            Ok(Command::List { page: None }) => {
+                let window = (user.last_ts, now);
+                let count =
+                    self.storage
+                        .count_messages(session.current_channel, window.0, window.1)?;
+                let mut ret = vec![format!("{} Messages.", count)];
+                ret.extend(self.list_page(&session, window, 1, now)?);
+
+                session.list_window = Some(window);
+                session.list_page = 1;
+                self.sessions.insert(user_pk_hash, session);
+
+                user.last_ts = now;
+                self.storage.update_user(user.uid, user)?;
+                return Ok(ret);
+            }
+            Ok(Command::List { page: Some(page) }) => {
+                let Some(window) = session.list_window else {
+                    mistake!("Nothing listed yet");
+                };
+                let page = page.max(1);
+                let ret = self.list_page(&session, window, page, now)?;
+
+                session.list_page = page;
+                self.sessions.insert(user_pk_hash, session);
+                return Ok(ret);
+            }
+            Ok(Command::Next) => {
+                let Some(window) = session.list_window else {
+                    mistake!("Nothing listed yet");
+                };
+                let page = session.list_page + 1;
+                let ret = self.list_page(&session, window, page, now)?;
+
+                session.list_page = page;
+                self.sessions.insert(user_pk_hash, session);
+                return Ok(ret);
+            }
+            Ok(Command::Search { keyword, all }) => {
+                let mut hits = Vec::new();
+                for channel in self.storage.get_channels()? {
+                    if !all && channel.cid != session.current_channel {
+                        continue;
+                    }
+                    if !self.can_access(channel.cid, &user_pk_hash)? {
+                        if !all {
+                            mistake!("Not a member of the channel");
+                        }
+                        continue;
+                    }
+                    for msg in self
+                        .storage
+                        .search_messages(channel.cid, &keyword, SEARCH_MAX)?
+                    {
+                        hits.push((channel.name.clone(), msg));
+                    }
+                }
+                hits.sort_by_key(|(_, msg)| std::cmp::Reverse(msg.cid_ts.1));
+                let mut ret: Vec<String> = hits
+                    .into_iter()
+                    .take(SEARCH_MAX)
+                    .map(|(channel, msg)| {
+                        let date = format_date(msg.cid_ts.1);
+                        if all {
+                            format!("{} #{} {}, {}", channel, msg.id, date, msg.text)
+                        } else {
+                            format!("#{} {}, {}", msg.id, date, msg.text)
+                        }
+                    })
+                    .collect();
+                if ret.is_empty() {
+                    ret.push("No messages found".into());
+                }
+                return Ok(ret);
+            }
+            Ok(Command::CheckIn { note }) => {
+                let Some((lat, lon)) = sender.position else {
+                    mistake!("No position known, enable position sharing");
+                };
+                // When the radio heard it, as the position was then
+                let ts = sender.received_at(now);
+                self.storage.add_checkin(CheckIn {
+                    ts_uid: (ts, session.user_id),
+                    latitude_i: (lat * 1e7) as i32,
+                    longitude_i: (lon * 1e7) as i32,
+                    note: note.clone(),
+                })?;
+
+                let channels = self.storage.get_channels()?;
+                let cid = match channels.iter().find(|ch| ch.name == CHECKINS_CHANNEL) {
+                    Some(channel) => channel.cid,
+                    None => self.storage.add_channel(CHECKINS_CHANNEL)?,
+                };
+                self.storage.add_message(ChannelMessage {
+                    cid_ts: (cid, ts),
+                    uid: session.user_id,
+                    text: format!(
+                        "{} @ {:.5},{:.5}: {}",
+                        self.display_name(&user)?,
+                        lat,
+                        lon,
+                        note
+                    ),
+                    id: 0,
+                    parent_id: None,
+                })?;
+
+                return Ok(vec!["Checked in".into()]);
+            }
+            Ok(Command::WhoHere { at, radius_km }) => {
+                let Some(at) = at.or(sender.position) else {
+                    mistake!("No position known, use whohere lat lon");
+                };
+                let radius_km = radius_km.unwrap_or(WHOHERE_RADIUS_KM);
+
+                let mut ret = Vec::new();
+                for checkin in self
+                    .storage
+                    .get_checkins(now.saturating_sub(CHECKINS_MAX_AGE))?
+                    .into_iter()
+                    .rev()
+                {
+                    let pos = (
+                        checkin.latitude_i as f64 / 1e7,
+                        checkin.longitude_i as f64 / 1e7,
+                    );
+                    let distance = distance_km(at, pos);
+                    if distance > radius_km {
+                        continue;
+                    }
+                    let name = self
+                        .storage
+                        .get_user_by_id(checkin.ts_uid.1)
+                        .and_then(|user| self.display_name(&user))
+                        .unwrap_or("?".to_string());
+                    let hours = now.saturating_sub(checkin.ts_uid.0) / (60 * 60 * 1000);
+                    ret.push(format!(
+                        "{}h {} {:.1}km {}",
+                        hours, name, distance, checkin.note
+                    ));
+                }
+                if ret.is_empty() {
+                    ret.push("Nobody here".into());
+                }
+                return Ok(ret);
+            }
+            Ok(Command::Waypoints) => {
+                let mut ret = Vec::new();
+                for waypoint in self
+                    .storage
+                    .get_waypoints(now)?
+                    .into_iter()
+                    .take(WAYPOINTS_MAX)
+                {
+                    let at = (
+                        waypoint.latitude_i as f64 / 1e7,
+                        waypoint.longitude_i as f64 / 1e7,
+                    );
+                    let mut line = format!(
+                        "{} {:.5},{:.5} by {}",
+                        waypoint.name,
+                        at.0,
+                        at.1,
+                        self.node_name(waypoint.from)?
+                    );
+                    if let Some(position) = sender.position {
+                        line.push_str(&format!(" {:.1}km", distance_km(position, at)));
+                    }
+                    ret.push(line);
+                }
+                if ret.is_empty() {
+                    ret.push("No waypoints".into());
+                }
+                return Ok(ret);
+            }
+            Ok(Command::WaypointAdd { name, at }) => {
+                if !(-90.0..=90.0).contains(&at.0) || !(-180.0..=180.0).contains(&at.1) {
+                    mistake!("Latitude is -90 to 90 and longitude -180 to 180");
+                }
+                if name.len() > WAYPOINT_NAME_MAX {
+                    mistake!("Names up to {WAYPOINT_NAME_MAX} bytes");
+                }
+                if self.waypoint_sharers.contains_key(&user.uid) {
+                    mistake!(
+                        "One waypoint every {} minutes",
+                        WAYPOINT_INTERVAL.as_secs() / 60
+                    );
+                }
+                let waypoint = Waypoint {
+                    // Random, as radios pick them
+                    id: rand::random(),
+                    name: name.clone(),
+                    description: format!("by {}", self.display_name(&user)?),
+                    latitude_i: (at.0 * 1e7).round() as i32,
+                    longitude_i: (at.1 * 1e7).round() as i32,
+                    expire: 0,
+                    from: sender.node,
+                    ts: now,
+                    locked_to: 0,
+                };
+                self.storage.add_waypoint(waypoint.clone(), now)?;
+                self.waypoints.push_back(waypoint);
+                self.waypoint_sharers.insert(user.uid, ());
+                return Ok(vec![format!("Waypoint {name} shared")]);
+            }
+            Ok(Command::Who) => {
+                let mut ret = Vec::new();
+                for sighting in self.storage.get_sightings()?.into_iter().take(WHO_MAX) {
+                    let name = if sighting.short_name.is_empty() {
+                        format_node_id(sighting.num)
+                    } else {
+                        sighting.short_name
+                    };
+                    let mut line = format!(
+                        "{} {}",
+                        format_age(now.saturating_sub(sighting.last_heard)),
+                        name
+                    );
+                    if !sighting.long_name.is_empty() {
+                        line.push_str(&format!(" {}", sighting.long_name));
+                    }
+                    if let Some(hops) = sighting.hops {
+                        line.push_str(&format!(", {hops} hops"));
+                    }
+                    line.push_str(&format!(", {:.1}dB", sighting.snr));
+                    // How throttled the user last seen from it is, for the sysop
+                    if is_admin && let Some(pk_hash) = self.nodes.get(&sighting.num) {
+                        let allowance = self.limiter.state(&pk_hash, Instant::now());
+                        line.push_str(&format!(", {} cmds", allowance.tokens));
+                        if let Some(muted) = allowance.muted {
+                            line.push_str(&format!(
+                                ", muted {}",
+                                format_age(muted.as_millis() as u64)
+                            ));
+                        }
+                    }
+                    ret.push(line);
+                }
+                if ret.is_empty() {
+                    ret.push("No nodes heard".into());
+                }
+                return Ok(ret);
+            }
+            Ok(Command::Notify { mode: None }) => {
+                let mode = prefs::NOTIFY.get(&self.storage, user.uid)?;
+                return Ok(vec![format!("notify {}", mode)]);
+            }
+            Ok(Command::Notify { mode: Some(mode) }) => {
+                prefs::NOTIFY.set(&self.storage, user.uid, &mode)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Lang { lang: None }) => {
+                return Ok(vec![format!("lang {}", self.language(&user_pk_hash)?)]);
+            }
+            Ok(Command::Lang { lang: Some(lang) }) => {
+                prefs::LANGUAGE.set(&self.storage, user.uid, &lang.to_string())?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Nick { name }) => {
+                if name.len() > NICK_MAX_LEN
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    mistake!("Nickname must be up to {NICK_MAX_LEN} letters, digits, _ or -");
+                }
+                // Nor the short name of another node, it would pass for it
+                let taken =
+                    self.storage
+                        .find_preference(prefs::NICK.key, &name)?
+                        .iter()
+                        .any(|uid| *uid != user.uid)
+                        || self
+                            .storage
+                            .get_user_by_short_name(&name)?
+                            .is_some_and(|other| other.uid != user.uid)
+                        || self.storage.get_sightings()?.iter().any(|sighting| {
+                            sighting.short_name == name && sighting.num != sender.node
+                        });
+                if taken {
+                    return Ok(vec![format!("Nickname {} is taken", name)]);
+                }
+                prefs::NICK.set(&self.storage, user.uid, &name)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::WhoAmI) => {
+                let nick = prefs::NICK.get(&self.storage, user.uid)?;
+                return Ok(vec![format!(
+                    "uid {}, nick {}, radio {} {}, pk {}",
+                    user.uid,
+                    if nick.is_empty() { "-" } else { &nick },
+                    user.short_name,
+                    format_node_id(sender.node),
+                    hex::encode(&user.pk_hash.0[..4])
+                )]);
+            }
+            Ok(Command::Set { field, value }) => {
+                prefs::set_profile_field(&self.storage, user.uid, &field, &value)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Profile { user: name }) => {
+                let profile_user = match name {
+                    Some(name) => {
+                        let Some(found) = self.find_user(&name)? else {
+                            mistake!("User not found");
+                        };
+                        found
+                    }
+                    None => user.clone(),
+                };
+                let name = self.display_name(&profile_user)?;
+                let fields = prefs::profile(&self.storage, profile_user.uid)?;
+                if fields.is_empty() {
+                    return Ok(vec![format!("No profile for {name}")]);
+                }
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, value)| format!("{field}: {value}"))
+                    .collect();
+                return Ok(vec![format!("{name}, {}", fields.join(", "))]);
+            }
+            Ok(Command::PollNew { question, options }) => {
+                // Dated when the radio heard it, like posts, and open the
+                // whole duration from now
+                let ts = sender.received_at(now);
+                let closes = now + self.options.poll_duration.as_millis() as u64;
+                let id = self.storage.add_poll(Poll {
+                    id: 0,
+                    cid: session.current_channel,
+                    uid: session.user_id,
+                    question: question.clone(),
+                    options: options.clone(),
+                    ts,
+                    closes,
+                })?;
+                let options: Vec<String> = options
+                    .iter()
+                    .enumerate()
+                    .map(|(n, option)| format!("{}) {option}", n + 1))
+                    .collect();
+                let msg = format!(
+                    "Poll #{id}: {question} {}, send vote {id} n",
+                    options.join(" ")
+                );
+                let author = self.display_name(&user)?;
+                self.storage.add_message(ChannelMessage {
+                    cid_ts: (session.current_channel, ts),
+                    uid: session.user_id,
+                    text: format!("{}: {}", author, msg),
+                    id: 0,
+                    parent_id: None,
+                })?;
+                self.published(session.current_channel, session.user_id, &author, &msg)?;
+                return Ok(vec![format!(
+                    "Poll #{id} open for {}",
+                    format_age(closes - now)
+                )]);
+            }
+            Ok(Command::Vote { id, option }) => {
+                let poll = self.poll(id, &user_pk_hash)?;
+                if now >= poll.closes {
+                    return Ok(vec![format!("Poll #{id} is closed")]);
+                }
+                if option == 0 || option > poll.options.len() {
+                    mistake!("Options are 1 to {}", poll.options.len());
+                }
+                let vote = Vote {
+                    poll_uid: (id, user.uid),
+                    option: option as u32 - 1,
+                };
+                if !self.storage.add_vote(vote)? {
+                    return Ok(vec![format!("Already voted in poll #{id}")]);
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::PollResults { id }) => {
+                let poll = self.poll(id, &user_pk_hash)?;
+                let votes = self.storage.get_votes(&poll)?;
+                let results: Vec<String> = poll
+                    .options
+                    .iter()
+                    .zip(votes)
+                    .map(|(option, votes)| format!("{option} {votes}"))
+                    .collect();
+                let state = if now >= poll.closes {
+                    "closed".to_string()
+                } else {
+                    format!("closes in {}", format_age(poll.closes - now))
+                };
+                return Ok(vec![format!(
+                    "#{id} {}: {} ({state})",
+                    poll.question,
+                    results.join(", ")
+                )]);
+            }
+            Ok(Command::Email { to, subject, body }) => {
+                let Some(email) = &self.options.email else {
+                    return Ok(vec!["Email is not enabled".into()]);
+                };
+                if !email.allows(&to) {
+                    mistake!("Not allowed to email {to}");
+                }
+                if !mailbox::take_quota(&self.storage, user.uid, now, email.daily_quota)? {
+                    return Ok(vec![format!("Up to {} emails a day", email.daily_quota)]);
+                }
+                prefs::MAIL_NODE.set(&self.storage, user.uid, &sender.node)?;
+                let token = mailbox::open_thread(&self.storage, user.uid, &to)?;
+                self.emails.push_back(OutgoingEmail {
+                    uid: user.uid,
+                    tag: mailbox::tag(user.uid, &token),
+                    author: self.display_name(&user)?,
+                    to: to.clone(),
+                    subject,
+                    body,
+                });
+                return Ok(vec![format!("Email to {to} queued, replies come as mail")]);
+            }
+            Ok(Command::Mail) => {
+                let mails = self.storage.get_mails(user.uid)?;
+                if mails.is_empty() {
+                    return Ok(vec!["No mail".into()]);
+                }
+                return Ok(mails
+                    .iter()
+                    .rev()
+                    .take(MAIL_MAX)
+                    .map(|mail| {
+                        format!(
+                            "#{}{} {} {} {}",
+                            mail.id,
+                            if mail.read { "" } else { "*" },
+                            format_age(now.saturating_sub(mail.ts)),
+                            mail.from,
+                            mail.subject
+                        )
+                    })
+                    .collect());
+            }
+            Ok(Command::Read { id }) => {
+                let mails = self.storage.get_mails(user.uid)?;
+                let Some(mail) = mails.iter().find(|mail| mail.id == id) else {
+                    mistake!("Mail #{id} not found");
+                };
+                self.storage.mark_mail_read(id)?;
+                return Ok(vec![format!(
+                    "From {}, {}: {}",
+                    mail.from, mail.subject, mail.text
+                )]);
+            }
+            Ok(Command::Files) => {
+                let files = files::list(&self.storage)?;
+                if files.is_empty() {
+                    return Ok(vec!["No files".into()]);
+                }
+                return Ok(files);
+            }
+            Ok(Command::Get { id, part }) => {
+                let Some(document) = self.storage.get_document(id)? else {
+                    mistake!("File #{id} not found");
+                };
+                let (part, parts, text) = files::fetch(&self.storage, user.uid, &document, part)?;
+                return Ok(vec![format!("{} {part}/{parts}: {text}", document.name)]);
+            }
+            Ok(Command::Status) => {
+                let Some(batch) = self.deliveries.batch(sender.node) else {
+                    return Ok(vec!["No replies sent yet".into()]);
+                };
+                let (delivered, pending, failed) = batch.counts();
+                let mut status = format!(
+                    "Last reply {} ago: {}/{} delivered",
+                    format_age(now.saturating_sub(batch.ts)),
+                    delivered,
+                    delivered + pending + failed
+                );
+                if pending > 0 {
+                    status += &format!(", {pending} pending");
+                }
+                if failed > 0 {
+                    status += &format!(", {failed} failed, resending");
+                }
+                // What failed goes again with this reply
+                let mut replies = vec![status];
+                replies.extend(batch.failed());
+                return Ok(replies);
+            }
+            Ok(Command::MkChan { ch }) => {
+                let channels = self.storage.get_channels()?;
+                if channels.iter().any(|_ch| _ch.name == ch) {
+                    mistake!("Channel already exists");
+                }
+                self.storage.add_channel(&ch)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::RmChan { ch }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                self.storage.remove_channel(channel.cid)?;
+                // Users in it go to the first channel left
+                let first = self
+                    .storage
+                    .get_channels()?
+                    .first()
+                    .map_or(0, |channel| channel.cid);
+                let moved: Vec<(UserPkHash, Session)> = self
+                    .sessions
+                    .iter()
+                    .filter(|entry| entry.value().current_channel == channel.cid)
+                    .map(|entry| (entry.key().clone(), entry.value().clone()))
+                    .collect();
+                for (pk_hash, mut session) in moved {
+                    session.current_channel = first;
+                    session.list_window = None;
+                    self.sessions.insert(pk_hash, session);
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Ban { user: name }) => {
+                // Node ids ban the node too, whoever uses it next
+                let node = parse_node_id(&name);
+                if let Some(num) = node {
+                    self.storage.add_node_ban(NodeBan { num, ts: now })?;
+                }
+                match self.find_user(&name)? {
+                    Some(user) => {
+                        self.sessions.invalidate(&user.pk_hash);
+                        self.storage.add_ban(Ban {
+                            pk_hash: user.pk_hash,
+                            ts: now,
+                        })?;
+                    }
+                    None if node.is_none() => mistake!("User not found"),
+                    None => {}
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Unban { user: name }) => {
+                let mut found = false;
+                if let Some(num) = parse_node_id(&name) {
+                    found |= self.storage.remove_node_ban(num)?;
+                }
+                if let Some(user) = self.find_user(&name)? {
+                    found |= self.storage.remove_ban(&user.pk_hash)?;
+                }
+                if !found {
+                    mistake!("Not banned");
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Sessions) => {
+                let sessions = self.sessions()?;
+                if sessions.is_empty() {
+                    return Ok(vec!["No sessions".into()]);
+                }
+                return Ok(sessions);
+            }
+            Ok(Command::SessionReset { user: name }) => {
+                if name == "all" {
+                    let pk_hashes: Vec<UserPkHash> = self
+                        .sessions
+                        .iter()
+                        .map(|entry| entry.key().clone())
+                        .collect();
+                    for pk_hash in pk_hashes {
+                        self.end_session(&pk_hash)?;
+                    }
+                    return Ok(vec!["Ack".into()]);
+                }
+                let Some(user) = self.find_user(&name)? else {
+                    mistake!("User not found");
+                };
+                if !self.end_session(&user.pk_hash)? {
+                    mistake!("No session");
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::BanList) => {
+                let mut bans = Vec::new();
+                for ban in self.storage.get_bans()? {
+                    let name = match self.storage.get_user_by_pkhash(ban.pk_hash.clone()) {
+                        Ok(user) => self.display_name(&user)?,
+                        Err(_) => "?".to_string(),
+                    };
+                    bans.push(format!(
+                        "{} pk {} {} ago",
+                        name,
+                        hex::encode(&ban.pk_hash.0[..4]),
+                        format_age(now.saturating_sub(ban.ts))
+                    ));
+                }
+                for ban in self.storage.get_node_bans()? {
+                    bans.push(format!(
+                        "{} node {} ago",
+                        format_node_id(ban.num),
+                        format_age(now.saturating_sub(ban.ts))
+                    ));
+                }
+                if bans.is_empty() {
+                    return Ok(vec!["No bans".into()]);
+                }
+                return Ok(bans);
+            }
+            Ok(Command::Purge { ch }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                let count = self.storage.purge_messages(channel.cid)?;
+                return Ok(vec![format!("{} messages removed", count)]);
+            }
+            Ok(Command::Motd) => {
+                let motd = self.motd()?;
+                if motd.is_empty() {
+                    return Ok(vec!["No motd".into()]);
+                }
+                return Ok(vec![motd]);
+            }
+            Ok(Command::MotdSet { text }) => {
+                self.storage.set_setting(MOTD_KEY, &text)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::MotdReset) => {
+                self.storage.remove_setting(MOTD_KEY)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Broadcast { text }) => {
+                self.notifications.push_back(Notification {
+                    to: BROADCAST_ADDR,
+                    text,
+                });
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Prune) => {
+                let count = self.prune()?;
+                return Ok(vec![format!("{} messages removed", count)]);
+            }
+            Ok(Command::Stats) => {
+                return Ok(vec![self.stats_report()?]);
+            }
+            Ok(Command::DeliveryStats) => {
+                let mut ret = Vec::new();
+                for (node, link) in self.round_trips.links() {
+                    let mut line = format!(
+                        "{} {}/{} acked",
+                        self.node_name(node)?,
+                        link.acked,
+                        link.total()
+                    );
+                    if let Some(median) = link.median {
+                        line.push_str(&format!(", median {:.1}s,", median as f64 / 1000.0));
+                        for (n, count) in link.histogram.iter().enumerate() {
+                            match BUCKETS.get(n) {
+                                Some(secs) => line.push_str(&format!(" <{secs}s {count}")),
+                                None => line.push_str(&format!(" {}s+ {count}", BUCKETS[n - 1])),
+                            }
+                        }
+                    }
+                    ret.push(line);
+                }
+                if ret.is_empty() {
+                    ret.push("No deliveries yet".into());
+                }
+                return Ok(ret);
+            }
+            Ok(Command::Fleet) => {
+                let mut fleet: Vec<((String, String), usize)> = Vec::new();
+                for node in self.storage.get_nodes()? {
+                    let key = (node.hw_model, firmware_series(node.firmware.as_deref()));
+                    match fleet.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, count)) => *count += 1,
+                        None => fleet.push((key, 1)),
+                    }
+                }
+                fleet.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+                if fleet.is_empty() {
+                    return Ok(vec!["No nodes seen".into()]);
+                }
+                return Ok(fleet
+                    .into_iter()
+                    .map(|((hw_model, series), count)| format!("{count}x {hw_model} on {series}"))
+                    .collect());
+            }
+            Ok(Command::Watch { node: None }) => {
+                let watched: Vec<String> = self
+                    .watched()?
+                    .into_iter()
+                    .map(|num| self.node_name(num))
+                    .collect::<Result<_>>()?;
+                if watched.is_empty() {
+                    return Ok(vec!["No watched nodes".into()]);
+                }
+                return Ok(vec![watched.join(",")]);
+            }
+            Ok(Command::Watch { node: Some(node) }) => {
+                let Some(num) = self.find_node(&node)? else {
+                    mistake!("Node not found");
+                };
+                self.storage.add_watch(Watch { node: num, ts: now })?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::AnnounceAdd { entry }) => {
+                entry
+                    .parse::<schedule::Entry>()
+                    .map_err(|err| Mistake(err.to_string()))?;
+                let id = self.storage.add_announcement(&entry)?;
+                return Ok(vec![format!("Announcement {id} added")]);
+            }
+            Ok(Command::AnnounceDel { id }) => {
+                if !self.storage.remove_announcement(id)? {
+                    mistake!("Announcement not found");
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::AnnounceList) => {
+                let announcements = self.storage.get_announcements()?;
+                if announcements.is_empty() {
+                    return Ok(vec!["No announcements".into()]);
+                }
+                return Ok(announcements
+                    .into_iter()
+                    .map(|announcement| format!("{}: {}", announcement.id, announcement.entry))
+                    .collect());
+            }
+            Ok(Command::Telemetry { node }) => {
+                let Some(num) = self.find_node(&node)? else {
+                    mistake!("Node not found");
+                };
+                let ret: Vec<String> = self
+                    .storage
+                    .get_telemetry(num, now.saturating_sub(TELEMETRY_MAX_AGE))?
+                    .into_iter()
+                    .rev()
+                    .take(PAGE_SIZE)
+                    .map(|sample| {
+                        format!(
+                            "{} {}",
+                            format_age(now.saturating_sub(sample.num_ts.1)),
+                            sample.metrics
+                        )
+                    })
+                    .collect();
+                if ret.is_empty() {
+                    return Ok(vec!["No telemetry".into()]);
+                }
+                return Ok(ret);
+            }
+            Ok(Command::Snapshot) => {
+                std::fs::create_dir_all(&self.options.snapshot_dir)?;
+                let out = self
+                    .options
+                    .snapshot_dir
+                    .join(format!("meshboard-{now}.tar"));
+                snapshot::create(&self.storage, &self.options.snapshot_files, &out)?;
+                let name = out.file_name().unwrap_or_default().to_string_lossy();
+                return Ok(vec![format!("Snapshot {name}")]);
+            }
+            Ok(Command::DmLog { page }) => {
+                let page = page.unwrap_or(1).max(1);
+                let messages = self
+                    .storage
+                    .get_direct_messages((page - 1) * PAGE_SIZE, PAGE_SIZE + 1)?;
+                if messages.is_empty() {
+                    return Ok(vec!["No direct messages".into()]);
+                }
+                let more = messages.len() > PAGE_SIZE;
+                let mut ret = Vec::new();
+                for dm in messages.into_iter().take(PAGE_SIZE) {
+                    let name = match self.storage.get_user_by_pkhash(dm.pk_hash) {
+                        Ok(user) => user.short_name,
+                        Err(_) => self.node_name(dm.from)?,
+                    };
+                    ret.push(format!(
+                        "{} {}: {}",
+                        format_age(now.saturating_sub(dm.ts)),
+                        name,
+                        dm.text
+                    ));
+                }
+                if more {
+                    ret.push(format!("more (dmlog {})", page + 1));
+                }
+                return Ok(ret);
+            }
+            Ok(Command::Unwatch { node }) => {
+                let Some(num) = self.find_node(&node)? else {
+                    mistake!("Node not found");
+                };
+                if !self.storage.remove_watch(num)? {
+                    mistake!("Node not watched");
+                }
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Acl { ch, action }) => {
+                let channels = self.storage.get_channels()?;
+                let Some(channel) = channels.iter().find(|_ch| _ch.name == ch) else {
+                    mistake!("Channel not found");
+                };
+                let acl = self.storage.get_channel_acl(channel.cid)?;
+                let Some(action) = action else {
+                    let Some(acl) = acl else {
+                        return Ok(vec![format!("#{} public", channel.name)]);
+                    };
+                    let mut members = Vec::new();
+                    for pk_hash in acl.members {
+                        members.push(match self.storage.get_user_by_pkhash(pk_hash.clone()) {
+                            Ok(user) => self.display_name(&user)?,
+                            Err(_) => hex::encode(&pk_hash.0[..4]),
+                        });
+                    }
+                    return Ok(vec![format!(
+                        "#{} {}, members: {}",
+                        channel.name,
+                        if acl.password.is_some() {
+                            "password"
+                        } else {
+                            "private"
+                        },
+                        if members.is_empty() {
+                            "-".to_string()
+                        } else {
+                            members.join(",")
+                        }
+                    )]);
+                };
+                let mut acl = acl.unwrap_or(ChannelAcl {
+                    cid: channel.cid,
+                    password: None,
+                    members: Vec::new(),
+                });
+                match action {
+                    AclAction::Public => {
+                        self.storage.remove_channel_acl(channel.cid)?;
+                        return Ok(vec!["Ack".into()]);
+                    }
+                    AclAction::Private => acl.password = None,
+                    AclAction::Password(password) => acl.password = Some(password_hash(&password)),
+                    AclAction::Allow(name) => {
+                        let Some(user) = self.find_user(&name)? else {
+                            mistake!("User not found");
+                        };
+                        if !acl.members.contains(&user.pk_hash) {
+                            acl.members.push(user.pk_hash);
+                        }
+                    }
+                    AclAction::Deny(name) => {
+                        let Some(user) = self.find_user(&name)? else {
+                            mistake!("User not found");
+                        };
+                        acl.members.retain(|member| *member != user.pk_hash);
+                        self.storage.remove_subscription(channel.cid, user.uid)?;
+                    }
+                }
+                self.storage.set_channel_acl(acl)?;
+                return Ok(vec!["Ack".into()]);
+            }
+            Ok(Command::Help {
+                command: Some(word),
+            }) => {
+                let word = i18n::unalias(&word).trim().to_string();
+                if let Some(usage) = usage::find(&word).filter(|usage| is_admin || !usage.admin) {
+                    let max_len = self.options.max_message_len;
+                    if matches!(usage.name, "post" | "reply") && max_len > 0 {
+                        return Ok(vec![format!("{}. Up to {max_len} chars", usage.text)]);
+                    }
+                    return Ok(vec![usage.text]);
+                }
+                if let Some(plugin) = self.options.plugins.find(&word) {
+                    return Ok(vec![plugin.detail()]);
+                }
+                mistake!("No help for {word}, send h for the commands");
+            }
+            // Arguments the command was given wrong, answered with its usage
+            Err(err) if !is_failure(&err) => return Err(err),
+            _ => {
+                let lang = self.language(&user_pk_hash)?;
+                let help = match self.options.plugins.help() {
+                    Some(plugins) => format!("{} | {plugins}", i18n::help(lang)),
+                    None => i18n::help(lang).into(),
+                };
+                if is_admin {
+                    return Ok(vec![help, i18n::admin_help(lang).into()]);
+                }
+                return Ok(vec![help]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bbs::i18n::HELP;
+    use crate::bbs::storage::Document;
+    use std::collections::BTreeMap;
+
+    fn sender(n: u8) -> Sender {
+        Sender {
+            node: n as u32,
+            pk_hash: [n; 32],
+            short_name: format!("user{n}"),
+            position: None,
+            signal: None,
+            encrypted: false,
+            received: None,
+            packet: None,
+        }
+    }
+
+    async fn bbs() -> anyhow::Result<BBS> {
+        let mut bbs = BBS::new(
+            Storage::memory(),
+            Options {
+                admins: vec![UserPkHash([1; 32])],
+                ..Default::default()
+            },
+        );
+        bbs.init().await?;
+        Ok(bbs)
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        futures::executor::block_on(future)
+    }
+
+    #[test]
+    fn test_admin_commands_rejected() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+
+            for command in [
+                "mkchan x",
+                "rmchan news",
+                "ban user1",
+                "purge news",
+                "stats",
+            ] {
+                assert_eq!(bbs.handle(&user, command).await?, vec!["Not allowed"]);
+            }
+            assert_eq!(bbs.handle(&sender(3), "c").await?, vec!["general,news"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_admin_commands() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    admins: vec![UserPkHash([1; 32])],
+                    rate_limit_burst: 100,
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let admin = sender(1);
+            let user = sender(2);
+
+            assert_eq!(bbs.handle(&admin, "mkchan misc").await?, vec!["Ack"]);
+            assert_eq!(bbs.handle(&admin, "rmchan news").await?, vec!["Ack"]);
+            assert_eq!(bbs.handle(&user, "c").await?, vec!["general,misc"]);
+
+            bbs.handle(&user, "j general").await?;
+            bbs.handle(&user, "p hello").await?;
+            assert_eq!(
+                bbs.handle(&admin, "purge general").await?,
+                vec!["1 messages removed"]
+            );
+
+            // Users in a removed channel move to the first one left
+            bbs.handle(&user, "j misc").await?;
+            assert_eq!(bbs.handle(&admin, "rmchan misc").await?, vec!["Ack"]);
+            bbs.handle(&user, "p moved").await?;
+            assert_eq!(bbs.storage.get_messages(1, 0, u64::MAX)?.len(), 1);
+
+            assert_eq!(bbs.handle(&admin, "ban user2").await?, vec!["Ack"]);
+            assert!(bbs.handle(&user, "c").await?.is_empty());
+
+            let other = sender(3);
+            bbs.handle(&other, "c").await?;
+            assert_eq!(bbs.handle(&admin, "ban !00000003").await?, vec!["Ack"]);
+            assert!(bbs.handle(&other, "c").await?.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_broadcast() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let (admin, user) = (sender(1), sender(2));
+
+            assert_eq!(
+                bbs.handle(&user, "broadcast hi all").await?,
+                vec!["Not allowed"]
+            );
+            assert_eq!(bbs.handle(&admin, "broadcast hi all").await?, vec!["Ack"]);
+            let broadcast = std::iter::from_fn(|| bbs.next_notification()).last();
+            assert_eq!(
+                broadcast,
+                Some(Notification {
+                    to: BROADCAST_ADDR,
+                    text: "hi all".into()
+                })
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_bans() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    admins: vec![UserPkHash([1; 32])],
+                    max_message_len: 20,
+                    deny_pattern: Some(Regex::new("(?i)free btc")?),
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let (admin, user) = (sender(1), sender(2));
+
+            assert!(bbs.handle(&user, "p FREE BTC here").await?.is_empty());
+            assert_eq!(
+                bbs.handle(&user, "p this is way too long").await?,
+                vec!["Too long, max 20 chars"]
+            );
+            assert_eq!(bbs.storage.get_messages(0, 0, u64::MAX)?.len(), 0);
+            // Denied texts are rate limited as any other
+            let spammer = sender(4);
+            for _ in 0..5 {
+                assert!(bbs.handle(&spammer, "free btc").await?.is_empty());
+            }
+            assert!(bbs.handle(&spammer, "free btc").await?[0].starts_with("Slow down"));
+
+            bbs.handle(&user, "c").await?;
+            bbs.handle(&admin, "ban !00000002").await?;
+            bbs.handle(&admin, "ban !00000007").await?;
+            assert!(bbs.is_blocked(&user)?);
+            assert!(bbs.is_blocked(&Sender {
+                node: 7,
+                ..sender(3)
+            })?);
+            assert_eq!(
+                bbs.handle(&admin, "banlist").await?,
+                vec![
+                    "user2 pk 02020202 0s ago",
+                    "!00000002 node 0s ago",
+                    "!00000007 node 0s ago"
+                ]
+            );
+            assert_eq!(bbs.handle(&admin, "unban user2").await?, vec!["Ack"]);
+            assert!(bbs.is_blocked(&user)?);
+            bbs.handle(&admin, "unban !00000002").await?;
+            assert!(!bbs.is_blocked(&user)?);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_nick() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user2 = sender(2);
+            let user3 = sender(3);
+
+            assert_eq!(bbs.handle(&user2, "nick pere").await?, vec!["Ack"]);
+            assert_eq!(
+                bbs.handle(&user3, "nick pere").await?,
+                vec!["Nickname pere is taken"]
+            );
+            assert_eq!(
+                bbs.handle(&user2, "whoami").await?,
+                vec!["uid 0, nick pere, radio user2 !00000002, pk 02020202"]
+            );
+            // Nor the short name of another node, its own is fine
+            assert_eq!(
+                bbs.handle(&user3, "nick user2").await?,
+                vec!["Nickname user2 is taken"]
+            );
+            assert_eq!(bbs.handle(&user3, "nick user3").await?, vec!["Ack"]);
+
+            bbs.handle(&user2, "p hola").await?;
+            let messages = bbs.storage.get_messages(0, 0, u64::MAX)?;
+            assert_eq!(messages[0].text, "pere: hola");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_post_as_sysop() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            bbs.handle(&sender(2), "sub general").await?;
+
+            bbs.post_as_sysop("general", "Welcome")?;
+            assert!(bbs.post_as_sysop("nope", "Welcome").is_err());
+
+            let notification = bbs.next_notification().unwrap();
+            assert_eq!(notification.to, 2);
+            assert_eq!(notification.text, "#general sysop: Welcome");
+            let messages = bbs.storage.get_messages(1, 0, u64::MAX)?;
+            assert_eq!(messages[0].uid, SYSOP_UID);
+            assert_eq!(
+                bbs.next_post(),
+                Some(Post {
+                    channel: "general".into(),
+                    author: "sysop".into(),
+                    text: "Welcome".into()
+                })
+            );
+            // Channels that are not public are not bridged
+            bbs.handle(&sender(1), "mkchan ops").await?;
+            bbs.handle(&sender(1), "acl ops private").await?;
+            bbs.post_as_sysop("ops", "Meeting at 9")?;
+            assert_eq!(bbs.next_post(), None);
+            assert!(bbs.post_bridged("ops", "ann@matrix", "hi").is_err());
+            bbs.post_bridged("general", "ann@matrix", "hi")?;
+            assert_eq!(bbs.next_post().unwrap().author, "ann@matrix");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_channel_order() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    admins: vec![UserPkHash([1; 32])],
+                    rate_limit_burst: 100,
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let admin = sender(1);
+            let user = sender(2);
+
+            bbs.handle(&admin, "mkchan misc").await?;
+            assert_eq!(bbs.handle(&user, "c").await?, vec!["general,misc,news"]);
+
+            assert_eq!(bbs.handle(&user, "fav news").await?, vec!["Ack"]);
+            assert_eq!(bbs.handle(&user, "c").await?, vec!["*news,general,misc"]);
+            // Favorites are per user
+            assert_eq!(bbs.handle(&admin, "c").await?, vec!["general,misc,news"]);
+
+            bbs.options.channel_order = ChannelOrder::Activity;
+            bbs.post_as("misc", "bot", "first")?;
+            bbs.post_as("general", "bot", "second")?;
+            assert_eq!(bbs.handle(&user, "c").await?, vec!["*news,general,misc"]);
+            assert_eq!(bbs.handle(&user, "unfav news").await?, vec!["Ack"]);
+            assert_eq!(bbs.handle(&user, "c").await?, vec!["general,misc,news"]);
+            bbs.post_as("misc", "bot", "third")?;
+            assert_eq!(bbs.handle(&user, "c").await?, vec!["misc,general,news"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_dmlog() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    admins: vec![UserPkHash([1; 32])],
+                    rate_limit_burst: 100,
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let admin = sender(1);
+            let user = sender(2);
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            // Past the 30 days retention
+            bbs.storage.add_direct_message(DirectMessage {
+                ts: now - 31 * 24 * 60 * 60 * 1000,
+                from: 2,
+                pk_hash: UserPkHash([2; 32]),
+                text: "old".into(),
+            })?;
+            bbs.prune()?;
+            assert_eq!(bbs.handle(&user, "dmlog").await?, vec!["Not allowed"]);
+            assert_eq!(bbs.handle(&user, "hello bbs").await?, vec![HELP]);
+            assert_eq!(
+                bbs.handle(&admin, "dmlog").await?,
+                vec!["0s user1: dmlog", "0s user2: hello bbs", "0s user2: dmlog"]
+            );
+
+            for n in 0..PAGE_SIZE {
+                bbs.handle(&user, &format!("h{n}")).await?;
+            }
+            let page = bbs.handle(&admin, "dmlog").await?;
+            assert_eq!(page.len(), PAGE_SIZE + 1);
+            assert_eq!(page[PAGE_SIZE], "more (dmlog 2)");
+            assert_eq!(
+                bbs.handle(&admin, "dmlog 2").await?,
+                vec![
+                    "0s user2: h1",
+                    "0s user2: h0",
+                    "0s user1: dmlog",
+                    "0s user2: hello bbs",
+                    "0s user2: dmlog"
+                ]
+            );
+
+            // Channel passwords are not kept
+            bbs.handle(&user, "join ops hunter2").await?;
+            bbs.handle(&user, "acl ops password hunter2").await?;
+            assert_eq!(
+                bbs.handle(&admin, "dmlog").await?[..3],
+                [
+                    "0s user1: dmlog",
+                    "0s user2: acl ops password ***",
+                    "0s user2: join ops ***"
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_telemetry() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let admin = sender(1);
+
+            assert_eq!(
+                bbs.handle(&admin, "telemetry !00000007").await?,
+                vec!["No telemetry"]
+            );
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            bbs.record_telemetry(
+                7,
+                now - 2 * 60 * 60 * 1000,
+                Metrics {
+                    battery_level: Some(81),
+                    voltage: Some(4.05),
+                    channel_utilization: Some(12.34),
+                    air_util_tx: Some(1.5),
+                    ..Default::default()
+                },
+            )?;
+            bbs.record_telemetry(
+                7,
+                now - 10 * 60 * 1000,
+                Metrics {
+                    temperature: Some(21.5),
+                    relative_humidity: Some(40.2),
+                    barometric_pressure: Some(1013.3),
+                    ..Default::default()
+                },
+            )?;
+            // Too old
+            bbs.record_telemetry(7, now - 48 * 60 * 60 * 1000, Metrics::default())?;
+
+            assert_eq!(
+                bbs.handle(&admin, "telemetry 7").await?,
+                vec![
+                    "10m 21.5C 40%RH 1013.3hPa",
+                    "2h bat 81% 4.05V ch 12.3% tx 1.5%"
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_where() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+
+            bbs.node_seen(Node {
+                num: 7,
+                short_name: "SOL".into(),
+                hw_model: String::new(),
+                role: String::new(),
+                firmware: None,
+                last_seen: 0,
+            })?;
+            assert_eq!(
+                bbs.handle(&user, "where SOL").await?,
+                vec!["No position for SOL"]
+            );
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            bbs.record_position(7, now - 60 * 60 * 1000, (41.0, 2.0))?;
+            bbs.record_position(7, now - 5 * 60 * 1000, (41.3851, 2.1734))?;
+            assert_eq!(
+                bbs.handle(&user, "where SOL").await?,
+                vec!["SOL 41.38510,2.17340 5m ago"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "where !00000007").await?,
+                vec!["SOL 41.38510,2.17340 5m ago"]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_fleet() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let admin = sender(1);
+
+            assert_eq!(bbs.handle(&admin, "fleet").await?, vec!["No nodes seen"]);
+
+            let node = |num, hw_model: &str, firmware: Option<&str>| Node {
+                num,
+                short_name: String::new(),
+                hw_model: hw_model.into(),
+                role: String::new(),
+                firmware: firmware.map(String::from),
+                last_seen: 0,
+            };
+            bbs.node_seen(node(1, "HELTEC_V3", Some("2.5.6.abc")))?;
+            bbs.node_seen(node(2, "HELTEC_V3", Some("2.5.4.def")))?;
+            bbs.node_seen(node(3, "RAK4631", Some("2.4.2")))?;
+            // A later nodeinfo does not forget the firmware
+            bbs.node_seen(node(3, "RAK4631", None))?;
+            bbs.node_seen(node(4, "RAK4631", None))?;
+
+            assert_eq!(
+                bbs.handle(&admin, "fleet").await?,
+                vec![
+                    "2x HELTEC_V3 on 2.5.x",
+                    "1x RAK4631 on 2.4.x",
+                    "1x RAK4631 on ?"
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_who() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+
+            assert_eq!(bbs.handle(&user, "who").await?, vec!["No nodes heard"]);
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            let sighting = |num, short_name: &str, last_heard, hops| Sighting {
+                num,
+                short_name: short_name.into(),
+                long_name: String::new(),
+                last_heard,
+                snr: 6.5,
+                rssi: -90,
+                hops,
+            };
+            bbs.node_heard(Sighting {
+                long_name: "Sol Relay".into(),
+                ..sighting(1, "SOL", now - 3 * 3600 * 1000, Some(2))
+            })?;
+            bbs.node_heard(sighting(2, "", now - 5 * 60 * 1000, None))?;
+            // A packet does not forget the names
+            bbs.node_heard(sighting(1, "", now - 30 * 1000, Some(1)))?;
+
+            assert_eq!(
+                bbs.handle(&user, "who").await?,
+                vec!["30s SOL Sol Relay, 1 hops, 6.5dB", "5m !00000002, 6.5dB"]
+            );
+            // Admins see the commands left to the users of the nodes
+            assert_eq!(
+                bbs.handle(&sender(1), "who").await?,
+                vec![
+                    "30s SOL Sol Relay, 1 hops, 6.5dB, 4 cmds",
+                    "5m !00000002, 6.5dB, 3 cmds"
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_watch() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let admin = sender(1);
+
+            bbs.node_seen(Node {
+                num: 0xa4c13b9f,
+                short_name: "SOL".into(),
+                hw_model: String::new(),
+                role: String::new(),
+                firmware: None,
+                last_seen: 0,
+            })?;
+
+            assert_eq!(bbs.handle(&admin, "watch SOL").await?, vec!["Ack"]);
+            assert_eq!(bbs.handle(&admin, "watch !00000007").await?, vec!["Ack"]);
+            assert_eq!(bbs.watched()?, vec![7, 0xa4c13b9f]);
+            assert_eq!(bbs.handle(&admin, "watch").await?, vec!["!00000007,SOL"]);
+            assert_eq!(bbs.handle(&admin, "unwatch 7").await?, vec!["Ack"]);
+            assert_eq!(bbs.watched()?, vec![0xa4c13b9f]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_notify() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let (user2, user3, user4) = (sender(2), sender(3), sender(4));
+            for user in [&user2, &user3, &user4] {
+                bbs.handle(user, "sub general").await?;
+            }
+
+            assert_eq!(bbs.handle(&user3, "notify").await?, vec!["notify on"]);
+            assert_eq!(bbs.handle(&user3, "notify mentions").await?, vec!["Ack"]);
+            assert_eq!(bbs.handle(&user4, "notify off").await?, vec!["Ack"]);
+            assert_eq!(
+                bbs.handle(&user4, "notify maybe").await?,
+                vec![usage::find("notify").unwrap().text]
+            );
+
+            bbs.post_as_sysop("general", "hello")?;
+            bbs.post_as_sysop("general", "hello @User3")?;
+            let mut notifications = Vec::new();
+            while let Some(notification) = bbs.next_notification() {
+                notifications.push((notification.to, notification.text));
+            }
+            assert_eq!(
+                notifications,
+                vec![
+                    (2, "#general sysop: hello".into()),
+                    (2, "#general sysop: hello @User3".into()),
+                    (3, "#general sysop: hello @User3".into()),
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_announce() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let admin = sender(1);
+
+            assert_eq!(
+                bbs.handle(&admin, "announce add sun 09:00 general,broadcast Welcome!")
+                    .await?,
+                vec!["Announcement 1 added"]
+            );
+            assert_eq!(
+                bbs.handle(&admin, "announce add someday news hi").await?,
+                vec!["Expected <day> <HH:MM> <targets> <text>"]
+            );
+            assert_eq!(
+                bbs.handle(&admin, "announce list").await?,
+                vec!["1: sun 09:00 general,broadcast Welcome!"]
+            );
+            assert_eq!(bbs.announcements()?[0].text, "Welcome!");
+            assert_eq!(bbs.handle(&admin, "announce del 1").await?, vec!["Ack"]);
+            assert_eq!(
+                bbs.handle(&admin, "announce").await?,
+                vec!["No announcements"]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_acl() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    admins: vec![UserPkHash([1; 32])],
+                    rate_limit_burst: 100,
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let admin = sender(1);
+            let user2 = sender(2);
+            let user3 = sender(3);
+
+            bbs.handle(&admin, "mkchan ops").await?;
+            assert_eq!(
+                bbs.handle(&admin, "acl ops password s3cret").await?,
+                vec!["Ack"]
+            );
+            assert_eq!(
+                bbs.handle(&user2, "j ops").await?,
+                vec!["Password required"]
+            );
+            assert_eq!(
+                bbs.handle(&user2, "j ops nope").await?,
+                vec!["Wrong password"]
+            );
+            assert_eq!(bbs.handle(&user2, "j ops s3cret").await?, vec!["Ack"]);
+            assert_eq!(bbs.handle(&user2, "p hi").await?, vec!["Ack"]);
+
+            assert_eq!(bbs.handle(&admin, "acl ops deny user2").await?, vec!["Ack"]);
+            assert_eq!(
+                bbs.handle(&user2, "p again").await?,
+                vec!["Not a member of the channel"]
+            );
+            assert_eq!(
+                bbs.handle(&user2, "poll new \"Again?\" yes no").await?,
+                vec!["Not a member of the channel"]
+            );
+            bbs.handle(&admin, "acl ops private").await?;
+            assert_eq!(
+                bbs.handle(&admin, "acl ops").await?,
+                vec!["#ops private, members: -"]
+            );
+
+            assert_eq!(bbs.handle(&user3, "c").await?, vec!["general,news"]);
+            assert_eq!(
+                bbs.handle(&user3, "j ops s3cret").await?,
+                vec!["Private channel"]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_list_pages() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    rate_limit_burst: 100,
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let reader = sender(2);
+            let page = |bbs: &BBS| {
+                bbs.sessions
+                    .get(&UserPkHash([2; 32]))
+                    .map(|session| session.list_page)
+            };
+            assert_eq!(
+                bbs.handle(&reader, "next").await?,
+                vec!["Nothing listed yet"]
+            );
+            for n in 1..=12 {
+                bbs.handle(&sender(3), &format!("p m{n}")).await?;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+
+            let first = bbs.handle(&reader, "l").await?;
+            assert_eq!(first[0], "12 Messages.");
+            assert_eq!(first[1], "#1 0d, user3: m1");
+            assert_eq!(first.last().unwrap(), "more (p2)");
+            assert_eq!(first.len(), PAGE_SIZE + 2);
+            assert_eq!(page(&bbs), Some(1));
+
+            let second = bbs.handle(&reader, "l 2").await?;
+            assert_eq!(second[0], "#6 0d, user3: m6");
+            assert_eq!(second.last().unwrap(), "more (p3)");
+            assert_eq!(page(&bbs), Some(2));
+            // The last page has no hint
+            assert_eq!(
+                bbs.handle(&reader, "next").await?,
+                vec!["#11 0d, user3: m11", "#12 0d, user3: m12"]
+            );
+            assert_eq!(page(&bbs), Some(3));
+            assert_eq!(bbs.handle(&reader, "next").await?, vec!["No more messages"]);
+            assert_eq!(page(&bbs), Some(4));
+            // Pages stay in the window listed, newer posts wait for the next l
+            bbs.handle(&sender(3), "p m13").await?;
+            assert_eq!(bbs.handle(&reader, "l 3").await?.len(), 2);
+            assert_eq!(bbs.handle(&reader, "l 0").await?, first[1..]);
+            assert_eq!(page(&bbs), Some(1));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_reply() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user2 = sender(2);
+            let user3 = sender(3);
+
+            bbs.handle(&user2, "sub news").await?;
+            bbs.handle(&user2, "p hello").await?;
+            assert_eq!(bbs.handle(&user3, "r 1 hi there").await?, vec!["Ack"]);
+            assert_eq!(
+                bbs.handle(&user3, "r #9 anyone?").await?,
+                vec!["Message #9 not found"]
+            );
+            assert_eq!(
+                bbs.next_notification().map(|n| n.text),
+                Some("#news user3: ↳ re #1 hi there".to_string())
+            );
+
+            // Listings end at the current millisecond
+            std::thread::sleep(Duration::from_millis(2));
+            assert_eq!(
+                bbs.handle(&user3, "l").await?,
+                vec![
+                    "2 Messages.",
+                    "#1 0d, user2: hello",
+                    "#2 0d ↳ re #1, user3: hi there"
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_reactions() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let (user2, user3, user4) = (sender(2), sender(3), sender(4));
+
+            bbs.handle(&user2, "p hello").await?;
+            assert_eq!(bbs.handle(&user3, "like 1").await?, vec!["Ack"]);
+            assert_eq!(
+                bbs.handle(&user3, "like #1").await?,
+                vec!["Reaction removed"]
+            );
+            bbs.handle(&user3, "like 1").await?;
+            bbs.handle(&user4, "react 1 🎉").await?;
+            bbs.handle(&user2, "like 1").await?;
+            assert_eq!(
+                bbs.handle(&user4, "react 1 wow").await?,
+                vec!["Not an emoji"]
+            );
+            assert_eq!(
+                bbs.handle(&user4, "like 9").await?,
+                vec!["Message #9 not found"]
+            );
+
+            std::thread::sleep(Duration::from_millis(2));
+            assert_eq!(
+                bbs.handle(&user4, "l").await?,
+                vec!["1 Messages.", "#1 0d, user2: hello +2👍 +1🎉"]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_search() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user2 = sender(2);
+            let user3 = sender(3);
+
+            bbs.handle(&user2, "p Repeater on the hill is down").await?;
+            bbs.handle(&user2, "j general").await?;
+            std::thread::sleep(Duration::from_millis(2));
+            bbs.handle(&user2, "p who fixes the repeater?").await?;
+            let date = format_date(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64,
+            );
+            assert_eq!(
+                bbs.handle(&user3, "s REPEATER").await?,
+                vec![format!("#1 {date}, user2: Repeater on the hill is down")]
+            );
+            assert_eq!(
+                bbs.handle(&user3, "search all repeater").await?,
+                vec![
+                    format!("general #1 {date}, user2: who fixes the repeater?"),
+                    format!("news #1 {date}, user2: Repeater on the hill is down"),
+                ]
+            );
+            assert_eq!(
+                bbs.handle(&user3, "s antenna").await?,
+                vec!["No messages found"]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_prune() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    admins: vec![UserPkHash([1; 32])],
+                    retention: Retention {
+                        channels: crate::bbs::retention::parse_channels("news=0:1")?,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let user = sender(2);
+
+            for msg in ["p one", "p two", "j general", "p three"] {
+                bbs.handle(&user, msg).await?;
+            }
+            assert_eq!(bbs.handle(&user, "prune").await?, vec!["Not allowed"]);
+            assert_eq!(
+                bbs.handle(&sender(1), "prune").await?,
+                vec!["1 messages removed"]
+            );
+            assert_eq!(bbs.storage.get_messages(0, 0, u64::MAX)?.len(), 1);
+            assert_eq!(bbs.storage.get_messages(1, 0, u64::MAX)?.len(), 1);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_motd() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    admins: vec![UserPkHash([1; 32])],
+                    motd: "Hi {name}, {unread} unread in {channels}".into(),
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let (admin, user) = (sender(1), sender(2));
+
+            bbs.handle(&admin, "p hello").await?;
+            let greeting = bbs.next_notification().unwrap();
+            assert_eq!(
+                (greeting.to, greeting.text.as_str()),
+                (1, "Hi user1, 0 unread in general,news")
+            );
+            bbs.handle(&admin, "p again").await?;
+            assert!(bbs.next_notification().is_none());
+
+            std::thread::sleep(Duration::from_millis(2));
+            bbs.handle(&user, "h").await?;
+            assert_eq!(
+                bbs.next_notification().unwrap().text,
+                "Hi user2, 2 unread in general,news"
+            );
+
+            assert_eq!(
+                bbs.handle(&user, "motd set Hey").await?,
+                vec!["Not allowed"]
+            );
+            bbs.handle(&admin, "motd set Hey {name}").await?;
+            assert_eq!(bbs.handle(&admin, "motd").await?, vec!["Hey {name}"]);
+            bbs.handle(&sender(3), "h").await?;
+            assert_eq!(bbs.next_notification().unwrap().text, "Hey user3");
+            bbs.handle(&admin, "motd reset").await?;
+            assert_eq!(bbs.motd()?, "Hi {name}, {unread} unread in {channels}");
+
+            // Back after motd_after, once per session
+            bbs.options.motd_after = Duration::from_secs(60);
+            bbs.sessions.invalidate_all();
+            let uid = bbs.storage.get_user_by_pkhash(UserPkHash([2; 32]))?.uid;
+            prefs::LAST_SEEN.set(&bbs.storage, uid, &1000)?;
+            bbs.handle(&user, "c").await?;
+            assert!(bbs.next_notification().is_some());
+            bbs.handle(&user, "c").await?;
+            assert!(bbs.next_notification().is_none());
+            // Users from before last_seen was kept are not new
+            bbs.sessions.invalidate_all();
+            prefs::LAST_SEEN.reset(&bbs.storage, uid)?;
+            bbs.handle(&user, "c").await?;
+            assert!(bbs.next_notification().is_none());
+            // Nor greeted if they turned notifications off
+            bbs.handle(&sender(4), "notify off").await?;
+            bbs.sessions.invalidate_all();
+            let uid = bbs.storage.get_user_by_pkhash(UserPkHash([4; 32]))?.uid;
+            prefs::LAST_SEEN.set(&bbs.storage, uid, &1000)?;
+            while bbs.next_notification().is_some() {}
+            bbs.handle(&sender(4), "c").await?;
+            assert!(bbs.next_notification().is_none());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_profile() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let (ann, bob) = (sender(2), sender(3));
+
+            bbs.handle(&ann, "nick ann").await?;
+            assert_eq!(
+                bbs.handle(&ann, "profile").await?,
+                vec!["No profile for ann"]
+            );
+            assert_eq!(
+                bbs.handle(&ann, "set location Barcelona").await?,
+                vec!["Ack"]
+            );
+            bbs.handle(&ann, "set bio Hiker, radio nerd").await?;
+            assert_eq!(
+                bbs.handle(&bob, "profile ann").await?,
+                vec!["ann, bio: Hiker, radio nerd, location: Barcelona"]
+            );
+            bbs.handle(&ann, "set bio").await?;
+            assert_eq!(
+                bbs.handle(&bob, "profile user2").await?,
+                vec!["ann, location: Barcelona"]
+            );
+            assert_eq!(
+                bbs.handle(&bob, "profile nobody").await?,
+                vec!["User not found"]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_polls() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let (ann, bob) = (sender(2), sender(3));
+
+            bbs.handle(&ann, "j general").await?;
+            assert_eq!(
+                bbs.handle(&ann, "poll new \"Meetup day?\" sat sun").await?,
+                vec!["Poll #1 open for 1d"]
+            );
+            assert!(
+                bbs.handle(&ann, "l")
+                    .await?
+                    .iter()
+                    .any(|line| line.ends_with("Poll #1: Meetup day? 1) sat 2) sun, send vote 1 n"))
+            );
+            assert_eq!(bbs.handle(&ann, "vote 1 2").await?, vec!["Ack"]);
+            assert_eq!(
+                bbs.handle(&ann, "vote 1 1").await?,
+                vec!["Already voted in poll #1"]
+            );
+            bbs.handle(&bob, "vote #1 2").await?;
+            assert_eq!(
+                bbs.handle(&bob, "vote 1 3").await?,
+                vec!["Options are 1 to 2"]
+            );
+            assert_eq!(
+                bbs.handle(&bob, "vote 2 1").await?,
+                vec!["Poll #2 not found"]
+            );
+            assert_eq!(
+                bbs.handle(&bob, "poll results 1").await?,
+                vec!["#1 Meetup day?: sat 0, sun 2 (closes in 23h)"]
+            );
+
+            // Past the rate limit of ann and bob, dated as the radio heard it
+            let received = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64
+                - 60 * 1000;
+            let carol = Sender {
+                received: Some(received),
+                ..sender(4)
+            };
+            bbs.options.poll_duration = Duration::ZERO;
+            bbs.handle(&carol, "poll new now? yes no").await?;
+            assert_eq!(bbs.storage.get_poll(2)?.map(|poll| poll.ts), Some(received));
+            assert_eq!(
+                bbs.handle(&carol, "vote 2 1").await?,
+                vec!["Poll #2 is closed"]
+            );
+            assert_eq!(
+                bbs.handle(&carol, "poll results 2").await?,
+                vec!["#2 now?: yes 0, no 0 (closed)"]
+            );
+            assert!(parse_poll("\"Open question sat sun").is_err());
+            assert!(parse_poll("\"Day?\" sat").is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_federation() -> anyhow::Result<()> {
+        block_on(async {
+            let board = |peer: &Sender| {
+                BBS::new(
+                    Storage::memory(),
+                    Options {
+                        federation: Some(FederationOptions {
+                            peers: BTreeMap::from([(peer.node, peer.pk_hash)]),
+                            channels: vec!["general".into()],
+                            max_payload: 200,
+                        }),
+                        ..Default::default()
+                    },
+                )
+            };
+            let (a, b) = (
+                Sender {
+                    encrypted: true,
+                    ..sender(10)
+                },
+                Sender {
+                    encrypted: true,
+                    ..sender(11)
+                },
+            );
+            let (mut board_a, mut board_b) = (board(&b), board(&a));
+            board_a.init().await?;
+            board_b.init().await?;
+            let texts = |bbs: &BBS| -> anyhow::Result<Vec<String>> {
+                let general = bbs.storage.get_channels()?;
+                let cid = general.iter().find(|ch| ch.name == "general").unwrap().cid;
+                Ok(bbs
+                    .storage
+                    .get_messages_page(cid, 0, u64::MAX, 0, 100)?
+                    .into_iter()
+                    .map(|message| message.text)
+                    .collect())
+            };
+
+            board_a.handle(&sender(2), "j general").await?;
+            board_a.handle(&sender(2), "p bridge is out").await?;
+            board_a.post_as("news", "ann", "not synced")?;
+            let (peer, request) = board_b.sync_requests()?.remove(0);
+            assert_eq!((peer, request.as_str()), (a.node, "~fed sync general=0"));
+            let frames = board_a.handle(&b, &request).await?;
+            assert_eq!(frames.len(), 1);
+            for frame in &frames {
+                assert!(board_b.handle(&a, frame).await?.is_empty());
+            }
+            assert_eq!(texts(&board_b)?, vec!["user2: bridge is out"]);
+            // Sent again, or back to where it came from, it is dropped
+            assert!(board_b.handle(&a, &frames[0]).await?.is_empty());
+            let (_, request) = board_a.sync_requests()?.remove(0);
+            assert!(board_b.handle(&a, &request).await?.is_empty());
+            assert_eq!(texts(&board_b)?.len(), 1);
+
+            for n in 0..6 {
+                board_a.post_as("general", "ann", &format!("post {n}"))?;
+            }
+            let (_, request) = board_b.sync_requests()?.remove(0);
+            assert_ne!(request, "~fed sync general=0");
+            // As they go out, joined while they fit a packet
+            let frames = crate::bbs::sender::coalesce(&board_a.handle(&b, &request).await?, 200);
+            assert_eq!(frames.last().unwrap(), "~fed more");
+            let mut replies = Vec::new();
+            for frame in &frames {
+                replies.extend(board_b.handle(&a, frame).await?);
+            }
+            let frames = board_a.handle(&b, &replies[0]).await?;
+            assert_eq!(frames.len(), 1);
+            board_b.handle(&a, &frames[0]).await?;
+            assert_eq!(texts(&board_b)?.len(), 7);
+            assert_eq!(texts(&board_b)?.last().unwrap(), "ann: post 5");
+
+            // The same text posted again is a new post, and a post lost on
+            // the way is asked for again
+            board_a.post_as("general", "ann", "ok")?;
+            board_a.post_as("general", "ann", "ok")?;
+            let (_, request) = board_b.sync_requests()?.remove(0);
+            let frames = board_a.handle(&b, &request).await?;
+            assert_eq!(frames.len(), 2);
+            board_b.handle(&a, &frames[1]).await?;
+            assert_eq!(board_b.sync_requests()?.remove(0).1, request);
+            board_b.handle(&a, &frames[0]).await?;
+            let (_, request) = board_b.sync_requests()?.remove(0);
+            assert_eq!(board_a.handle(&b, &request).await?, vec![frames[1].clone()]);
+            board_b.handle(&a, &frames[1]).await?;
+            assert_eq!(texts(&board_b)?[7..], ["ann: ok", "ann: ok"]);
+            let (_, request) = board_b.sync_requests()?.remove(0);
+            assert!(board_a.handle(&b, &request).await?.is_empty());
+
+            // Only peers sync, with their key over encrypted messages
+            assert!(!board_b.handle(&sender(3), &request).await?[0].starts_with("~fed"));
+            let spoofed = Sender {
+                pk_hash: [3; 32],
+                ..a.clone()
+            };
+            assert!(!board_b.handle(&spoofed, &request).await?[0].starts_with("~fed"));
+            let plain = Sender {
+                encrypted: false,
+                ..a.clone()
+            };
+            assert!(!board_b.handle(&plain, &request).await?[0].starts_with("~fed"));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_stats() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let yesterday = today().pred_opt().unwrap();
+            counters::count(&bbs.storage, yesterday, |tally| {
+                tally.posts.insert("general".into(), 4);
+                tally.posts.insert("ops".into(), 2);
+            })?;
+            let admin = sender(1);
+            bbs.handle(&admin, "mkchan ops").await?;
+            bbs.handle(&admin, "acl ops private").await?;
+            bbs.handle(&admin, "j ops").await?;
+            bbs.handle(&admin, "p meeting at 9").await?;
+            bbs.handle(&sender(2), "j general").await?;
+            bbs.handle(&sender(2), "p hi").await?;
+            bbs.post_as("general", "ann", "hello")?;
+            assert_eq!(
+                bbs.handle(&admin, "stats").await?,
+                vec![
+                    "2 users, 3 channels, 3 messages\n\
+                    Today: 3 posts, 2 users, 0 packets, no acks\n\
+                    general 2, ops 1"
+                ]
+            );
+            assert_eq!(
+                bbs.digest()?,
+                format!("Stats of {yesterday}: 4 posts, 0 users, 0 packets, no acks\ngeneral 4")
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_email() -> anyhow::Result<()> {
+        block_on(async {
+            let user = sender(2);
+            let mut disabled = bbs().await?;
+            assert_eq!(
+                disabled
+                    .handle(&user, "email ann@example.com Trail | hi")
+                    .await?,
+                vec!["Email is not enabled"]
+            );
+
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    email: Some(EmailOptions {
+                        allow: vec!["@example.com".into()],
+                        daily_quota: 1,
+                    }),
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            assert_eq!(
+                bbs.handle(&user, "email ann@example.com Trail | Bridge is out")
+                    .await?,
+                vec!["Email to ann@example.com queued, replies come as mail"]
+            );
+            let email = bbs.next_email().unwrap();
+            assert_eq!(
+                (
+                    email.to.as_str(),
+                    email.subject.as_str(),
+                    email.body.as_str()
+                ),
+                ("ann@example.com", "Trail", "Bridge is out")
+            );
+            assert_eq!(
+                bbs.handle(&user, "email ann@example.com Again | hi")
+                    .await?,
+                vec!["Up to 1 emails a day"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "email bob@other.org Hi | hi").await?,
+                vec!["Not allowed to email bob@other.org"]
+            );
+
+            let reply = IncomingEmail {
+                from: "ann@example.com".into(),
+                subject: format!("Re: Trail {}", email.tag),
+                text: "Thanks!\n> Bridge is out".into(),
+            };
+            // Forged: untagged, a guessed tag, or from someone else
+            assert!(!bbs.email_received(IncomingEmail {
+                subject: "Offer".into(),
+                ..reply.clone()
+            })?);
+            assert!(!bbs.email_received(IncomingEmail {
+                subject: format!("Re: Trail {}", mailbox::tag(email.uid, "0")),
+                ..reply.clone()
+            })?);
+            assert!(!bbs.email_received(IncomingEmail {
+                from: "eve@other.org".into(),
+                ..reply.clone()
+            })?);
+            assert!(bbs.email_received(reply)?);
+            let notification = bbs.next_notification().unwrap();
+            assert_eq!(
+                (notification.to, notification.text.as_str()),
+                (2, "Mail #1 from ann@example.com, send read 1")
+            );
+            assert_eq!(
+                bbs.handle(&user, "mail").await?,
+                vec!["#1* 0s ann@example.com Re: Trail"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "read 1").await?,
+                vec!["From ann@example.com, Re: Trail: Thanks!"]
+            );
+            assert_eq!(bbs.handle(&sender(3), "mail").await?, vec!["No mail"]);
+            assert_eq!(
+                bbs.handle(&sender(3), "read 1").await?,
+                vec!["Mail #1 not found"]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_files() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+            assert_eq!(bbs.handle(&user, "files").await?, vec!["No files"]);
+            bbs.storage.add_document(Document {
+                id: 0,
+                name: "rules.txt".into(),
+                text: "Be kind. ".repeat(100),
+                ts: 0,
+            })?;
+            assert_eq!(
+                bbs.handle(&user, "files").await?,
+                vec!["#1 rules.txt 1KB 2 parts"]
+            );
+            let part = bbs.handle(&user, "get 1").await?;
+            assert!(part[0].starts_with("rules.txt 1/2: Be kind."));
+            let part = bbs.handle(&user, "get 1").await?;
+            assert!(part[0].starts_with("rules.txt 2/2: "));
+            let part = bbs.handle(&user, "get #1 2").await?;
+            assert!(part[0].starts_with("rules.txt 2/2: "));
+            assert_eq!(
+                bbs.handle(&sender(3), "get 2").await?,
+                vec!["File #2 not found"]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_plugins() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    plugins: Registry::new(
+                        &["ping".into()],
+                        &[("info".into(), "MeshBoard at the library".into())],
+                    ),
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            assert_eq!(
+                bbs.handle(&sender(2), "info").await?,
+                vec!["MeshBoard at the library"]
+            );
+            assert_eq!(
+                bbs.handle(&sender(2), "dice").await?,
+                vec![format!("{HELP} | ping | info")]
+            );
+            let pong = bbs.handle(&sender(2), "ping").await?;
+            assert!(pong[0].starts_with("pong at "));
+            assert!(pong[0].ends_with(", up 0s"));
+            let user = Sender {
+                signal: Some(Signal {
+                    snr: 7.5,
+                    rssi: -90,
+                    hops: Some(2),
+                }),
+                ..sender(3)
+            };
+            let pong = bbs.handle(&user, "echo").await?;
+            assert!(pong[0].ends_with(", SNR 7.5, RSSI -90, 2 hops, up 0s"));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_status() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+
+            assert_eq!(
+                bbs.handle(&user, "status").await?,
+                vec!["No replies sent yet"]
+            );
+            bbs.replied(
+                2,
+                "l",
+                vec![
+                    ("one".into(), vec!["one".into()]),
+                    ("two".into(), vec!["two".into()]),
+                ],
+            );
+            bbs.reply_sent(2, 10, "one");
+            bbs.reply_sent(2, 11, "two");
+            bbs.reply_delivery(10, Delivery::Delivered)?;
+            assert_eq!(
+                bbs.handle(&user, "status").await?,
+                vec!["Last reply 0s ago: 1/2 delivered, 1 pending"]
+            );
+            bbs.reply_delivery(11, Delivery::Failed)?;
+            assert_eq!(
+                bbs.handle(&user, "status").await?,
+                vec![
+                    "Last reply 0s ago: 1/2 delivered, 1 failed, resending",
+                    "two"
+                ]
+            );
+            // The status replies leave the batch they report on
+            bbs.replied(2, "status", vec![("two".into(), vec!["two".into()])]);
+            assert_eq!(bbs.deliveries.batch(2).unwrap().replies, vec!["one", "two"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_keep_packet() -> anyhow::Result<()> {
+        block_on(async {
+            let off = bbs().await?;
+            off.keep_packet(1, 2, b"raw")?;
+            assert_eq!(off.storage.get_raw_packet(1)?, None);
+
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    raw_packets: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            bbs.keep_packet(1, 2, b"raw")?;
+            bbs.keep_packet(2, 2, b"")?;
+            let packet = bbs.storage.get_raw_packet(1)?.unwrap();
+            assert_eq!((packet.from, packet.bytes), (2, b"raw".to_vec()));
+            assert_eq!(bbs.storage.get_raw_packet(2)?, None);
+            bbs.prune()?;
+            assert!(bbs.storage.get_raw_packet(1)?.is_some());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_require_pki() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    require_pki: true,
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let refused = bbs.handle(&sender(2), "l").await?;
+            assert!(refused[0].starts_with("This board only takes encrypted DMs"));
+            let user = Sender {
+                encrypted: true,
+                ..sender(2)
+            };
+            assert_ne!(bbs.handle(&user, "l").await?, refused);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_verify_new_users() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    verify_new_users: true,
+                    rate_limit_burst: 100,
+                    plugins: Registry::new(&["ping".into()], &[]),
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let user = sender(2);
+            assert!(!bbs.handle(&user, "c").await?.is_empty());
+            let challenge = bbs.handle(&user, "p hello").await?;
+            assert!(challenge[0].starts_with("New users are verified first, reply "));
+            let code = challenge[0].split_whitespace().nth(6).unwrap().to_string();
+            assert_eq!(bbs.handle(&user, "p hello").await?, challenge);
+            assert_eq!(bbs.storage.get_messages(0, 0, u64::MAX)?.len(), 0);
+            // Nor anything else others get to see
+            for command in [
+                "poll new \"Day?\" sat sun",
+                "like 1",
+                "checkin here",
+                "ping",
+            ] {
+                assert_eq!(bbs.handle(&user, command).await?, challenge);
+            }
+
+            assert_eq!(
+                bbs.handle(&user, &code).await?,
+                vec!["Verified, you can post now"]
+            );
+            assert!(!bbs.storage.get_user_by_pkhash(UserPkHash([2; 32]))?.pending);
+            bbs.handle(&user, "p hello").await?;
+            assert_eq!(bbs.storage.get_messages(0, 0, u64::MAX)?.len(), 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_sessions() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let admin = sender(1);
+            bbs.handle(&sender(2), "j general").await?;
+            bbs.handle(&sender(3), "c").await?;
+            let sessions = bbs.handle(&admin, "sessions").await?;
+            assert_eq!(sessions.len(), 3);
+            assert!(sessions[0].starts_with("01010101 user1 #"));
+            assert!(sessions.contains(&"02020202 user2 #general idle 0s".to_string()));
+
+            assert_eq!(
+                bbs.handle(&admin, "sessions reset user2").await?,
+                vec!["Ack"]
+            );
+            assert_eq!(bbs.handle(&admin, "sessions").await?.len(), 2);
+            assert_eq!(
+                bbs.handle(&admin, "sessions reset user2").await?,
+                vec!["No session"]
+            );
+            assert_eq!(
+                bbs.handle(&sender(2), "sessions").await?,
+                vec!["Not allowed"]
+            );
+            bbs.handle(&admin, "sessions reset all").await?;
+            assert_eq!(bbs.sessions()?.len(), 0);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_restored_sessions() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+            let general = bbs
+                .storage
+                .get_channels()?
+                .into_iter()
+                .find(|channel| channel.name == "general")
+                .unwrap()
+                .cid;
+            bbs.handle(&user, "j general").await?;
+            // As after a restart
+            bbs.sessions.invalidate_all();
+            bbs.handle(&user, "p still here").await?;
+            assert_eq!(bbs.storage.get_messages(general, 0, u64::MAX)?.len(), 1);
+
+            bbs.sessions.invalidate_all();
+            let uid = bbs.storage.get_user_by_pkhash(UserPkHash([2; 32]))?.uid;
+            prefs::LAST_SEEN.set(&bbs.storage, uid, &1000)?;
+            bbs.handle(&user, "p too late").await?;
+            assert_eq!(bbs.storage.get_messages(general, 0, u64::MAX)?.len(), 1);
+
+            bbs.handle(&user, "j general").await?;
+            bbs.handle(&sender(1), "sessions reset user2").await?;
+            bbs.handle(&user, "p back at the start").await?;
+            assert_eq!(bbs.storage.get_messages(general, 0, u64::MAX)?.len(), 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_lang() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+            assert_eq!(bbs.handle(&user, "lang").await?, vec!["lang en"]);
+            assert_eq!(bbs.handle(&user, "lang es").await?, vec!["Hecho"]);
+            assert_eq!(bbs.handle(&user, "idioma").await?, vec!["lang es"]);
+            assert_eq!(bbs.handle(&user, "ayuda").await?[0], i18n::help(Lang::Es));
+            assert_eq!(bbs.handle(&user, "canales").await?, vec!["general,news"]);
+
+            // Others keep the language of the board
+            let other = sender(3);
+            assert_eq!(bbs.handle(&other, "lang").await?, vec!["lang en"]);
+            bbs.options.language = Lang::Es;
+            assert_eq!(bbs.handle(&other, "lang").await?, vec!["lang es"]);
+            assert_eq!(
+                bbs.handle(&other, "unir nowhere").await?,
+                vec!["Canal no encontrado"]
+            );
+            assert_eq!(bbs.handle(&other, "lang en").await?, vec!["Ack"]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_help_detail() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            bbs.options.max_message_len = 200;
+            let user = sender(2);
+            let post = bbs.handle(&user, "h p").await?;
+            assert!(post[0].starts_with("p <text>: posts to your current channel"));
+            assert!(post[0].ends_with("Up to 200 chars"));
+            assert_eq!(bbs.handle(&user, "help publicar").await?, post);
+            assert_eq!(
+                bbs.handle(&user, "h ban").await?,
+                vec!["No help for ban, send h for the commands"]
+            );
+            assert!(bbs.handle(&sender(1), "h ban").await?[0].starts_with("ban <user>"));
+            assert_eq!(bbs.handle(&user, "h").await?, vec![HELP]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_malformed_commands() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = BBS::new(
+                Storage::memory(),
+                Options {
+                    rate_limit_burst: 100,
+                    ..Default::default()
+                },
+            );
+            bbs.init().await?;
+            let user = sender(2);
+            // Commands given wrong arguments get their usage, words that
+            // are no command the help
+            let usage = |word| vec![usage::find(word).unwrap().text];
+            assert_eq!(bbs.handle(&user, "j").await?, usage("join"));
+            assert_eq!(bbs.handle(&user, "r x hi").await?, usage("reply"));
+            assert_eq!(bbs.handle(&user, "vote 1").await?, usage("vote"));
+            assert_eq!(bbs.handle(&user, "whohere x").await?, usage("whohere"));
+            assert_eq!(bbs.handle(&user, "wp add x").await?, usage("wp"));
+            assert_eq!(bbs.handle(&user, "email bob").await?, usage("email"));
+            assert_eq!(bbs.handle(&user, "lang xx").await?, usage("lang"));
+            assert_eq!(bbs.handle(&user, "ban").await?, usage("ban"));
+            assert_eq!(bbs.handle(&user, "xyzzy").await?, vec![HELP]);
+            // Unless the mistake tells what is wrong
+            let user = sender(4);
+            assert_eq!(bbs.handle(&user, "react 1 x").await?, vec!["Not an emoji"]);
+            assert_eq!(
+                bbs.handle(&user, "email bob subject | text").await?,
+                vec!["Invalid address bob"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "poll new q yes").await?,
+                vec!["A question and 2 to 8 options"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "j nowhere").await?,
+                vec!["Channel not found"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "announce add x").await?,
+                vec!["Not allowed"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "read 7").await?,
+                vec!["Mail #7 not found"]
+            );
+            assert!(is_failure(&std::io::Error::other("disk full").into()));
+            assert!(is_failure(&anyhow::anyhow!("Storage is not empty")));
+            assert!(!is_failure(&Mistake("Channel not found".into()).into()));
+            assert_eq!(
+                bbs.handle(&sender(3), "set this_field_name_is_too_long x")
+                    .await?,
+                vec!["Field names are words up to 16 chars"]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_received_date() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            // A minute ago, as the radio received it
+            let received = now - 60 * 1000;
+            let user = Sender {
+                received: Some(received),
+                ..sender(2)
+            };
+            bbs.handle(&user, "p Old news").await?;
+            assert_eq!(
+                bbs.handle(&sender(3), "s news").await?,
+                vec![format!("#1 {}, user2: Old news", format_date(received))]
+            );
+
+            // A radio clock off by a day dates it now, and the posts dated
+            // before the newest one are numbered after it
+            let ahead = Sender {
+                received: Some(now + 24 * 60 * 60 * 1000),
+                ..sender(2)
+            };
+            bbs.handle(&ahead, "p Tomorrow").await?;
+            let before = Sender {
+                received: Some(now - 2 * 60 * 1000),
+                ..sender(2)
+            };
+            bbs.handle(&before, "p Earlier").await?;
+            bbs.handle(&sender(2), "p Latest").await?;
+            let messages = bbs.storage.get_messages(0, 0, u64::MAX)?;
+            let numbers: Vec<_> = messages
+                .iter()
+                .map(|message| (message.id, message.text.as_str()))
+                .collect();
+            assert_eq!(
+                numbers,
+                vec![
+                    (3, "user2: Earlier"),
+                    (1, "user2: Old news"),
+                    (2, "user2: Tomorrow"),
+                    (4, "user2: Latest")
+                ]
+            );
+            assert!(messages[2].cid_ts.1 <= now + MAX_CLOCK_SKEW);
+            // Listings end at the current millisecond
+            std::thread::sleep(Duration::from_millis(2));
+            assert_eq!(bbs.handle(&sender(3), "l").await?.len(), 5);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_whohere() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            let received = now - 4 * 60 * 1000;
+            let here = Sender {
+                position: Some((41.38, 2.17)),
+                received: Some(received),
+                ..sender(2)
+            };
+            assert_eq!(
+                bbs.handle(&here, "checkin at the bar").await?,
+                vec!["Checked in"]
+            );
+            assert_eq!(bbs.storage.get_checkins(0)?[0].ts_uid.0, received);
+            let far = Sender {
+                position: Some((41.5, 2.17)),
+                ..sender(3)
+            };
+            bbs.handle(&far, "checkin on the hill").await?;
+            // Hours ago, and a clock a bit ahead is not in the future
+            for (n, ts, note) in [
+                (4, now - 3 * 60 * 60 * 1000, "lunch"),
+                (5, now + 60 * 1000, "ahead"),
+            ] {
+                bbs.handle(&sender(n), "c").await?;
+                let uid = bbs.storage.get_user_by_pkhash(UserPkHash([n; 32]))?.uid;
+                bbs.storage.add_checkin(CheckIn {
+                    ts_uid: (ts, uid),
+                    latitude_i: 413_900_000,
+                    longitude_i: 21_700_000,
+                    note: note.into(),
+                })?;
+            }
+
+            let near = Sender {
+                position: Some((41.38, 2.18)),
+                ..sender(6)
+            };
+            assert_eq!(
+                bbs.handle(&near, "whohere").await?,
+                vec![
+                    "0h user5 1.4km ahead",
+                    "0h user2 0.8km at the bar",
+                    "3h user4 1.4km lunch",
+                ]
+            );
+            assert_eq!(bbs.handle(&near, "whohere 20").await?.len(), 4);
+            assert_eq!(
+                bbs.handle(&near, "whohere 10 10").await?,
+                vec!["Nobody here"]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_duplicate_packet() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = Sender {
+                packet: Some(42),
+                ..sender(2)
+            };
+            assert_eq!(bbs.handle(&user, "p Bridge is out").await?, vec!["Ack"]);
+            assert!(bbs.handle(&user, "p Bridge is out").await?.is_empty());
+            // Packet ids are per node
+            let other = Sender {
+                packet: Some(42),
+                ..sender(3)
+            };
+            assert_eq!(bbs.handle(&other, "p Bridge is out").await?, vec!["Ack"]);
+            let listed = bbs.handle(&sender(3), "l").await?;
+            assert_eq!(
+                listed.iter().filter(|line| line.contains("Bridge")).count(),
+                2
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_waypoints() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+            assert_eq!(bbs.handle(&user, "wp").await?, vec!["No waypoints"]);
+            assert_eq!(
+                bbs.handle(&user, "wp add Base camp 41.38 2.17").await?,
+                vec!["Waypoint Base camp shared"]
+            );
+            let shared = bbs.next_waypoint().unwrap();
+            assert_eq!(
+                (shared.name.as_str(), shared.latitude_i, shared.from),
+                ("Base camp", 413_800_000, 2)
+            );
+            assert!(bbs.next_waypoint().is_none());
+            let near = Sender {
+                position: Some((41.38, 2.18)),
+                ..sender(3)
+            };
+            assert_eq!(
+                bbs.handle(&near, "wp list").await?,
+                vec!["Base camp 41.38000,2.17000 by !00000002 0.8km"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "wp add x 91 2").await?,
+                vec!["Latitude is -90 to 90 and longitude -180 to 180"]
+            );
+            assert_eq!(
+                bbs.handle(&user, "wp add Tent 41.38 2.17").await?,
+                vec!["One waypoint every 10 minutes"]
+            );
+            assert!(bbs.next_waypoint().is_none());
+            // The radios take 30 bytes, fewer chars when they are not ASCII
+            assert_eq!(
+                bbs.handle(&near, &format!("wp add {} 41.38 2.17", "é".repeat(16)))
+                    .await?,
+                vec!["Names up to 30 bytes"]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_neighbors() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let user = sender(2);
+            bbs.record_neighbors(5, &[(6, 3.0), (7, 9.5)])?;
+            assert_eq!(
+                bbs.handle(&user, "neighbors !00000005").await?,
+                vec![
+                    "!00000005 hears",
+                    "!00000007 9.5dB 0s ago",
+                    "!00000006 3.0dB 0s ago"
+                ]
+            );
+            assert_eq!(
+                bbs.handle(&user, "neighbors !00000006").await?,
+                vec!["No neighbors for !00000006"]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_delivery_stats() -> anyhow::Result<()> {
+        block_on(async {
+            let mut bbs = bbs().await?;
+            let admin = sender(1);
+            assert_eq!(
+                bbs.handle(&admin, "stats delivery").await?,
+                vec!["No deliveries yet"]
+            );
+            bbs.delivery_ended(2, 10, Some(4_000));
+            bbs.delivery_ended(2, 11, Some(12_000));
+            bbs.delivery_ended(2, 12, Some(65_000));
+            bbs.delivery_ended(3, 13, None);
+            assert_eq!(
+                bbs.handle(&admin, "stats delivery").await?,
+                vec![
+                    "!00000003 0/1 acked",
+                    "!00000002 3/3 acked, median 12.0s, <5s 1 <15s 1 <30s 0 <60s 0 60s+ 1"
+                ]
+            );
+            assert_eq!(
+                bbs.handle(&sender(2), "stats delivery").await?,
+                vec!["Not allowed"]
+            );
+            Ok(())
+        })
     }
 }
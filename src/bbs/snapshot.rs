@@ -0,0 +1,112 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::bbs::storage::Storage;
+
+// Archive entry with the records of the board, as JSON
+const RECORDS_ENTRY: &str = "meshboard.json";
+// Archive folder with the config files
+const FILES_DIR: &str = "files";
+
+/// Writes a tarball with every record of `storage` and the `files` that
+/// exist, e.g. `.env` and the schedule. Safe while the board is running, the
+/// records are read in a single transaction.
+pub fn create(storage: &Storage, files: &[PathBuf], out: &Path) -> Result<()> {
+    let records = serde_json::to_vec(&storage.snapshot()?)?;
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let mut archive = tar::Builder::new(File::create(out)?);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(records.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    archive.append_data(&mut header, RECORDS_ENTRY, records.as_slice())?;
+    for file in files.iter().filter(|file| file.exists()) {
+        let name = file
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid file {}", file.display()))?;
+        archive.append_path_with_name(file, Path::new(FILES_DIR).join(name))?;
+    }
+    archive.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// Loads the records of a tarball made by [create] into an empty `storage`,
+/// and writes its config files into `files_dir`. Existing files are only
+/// replaced with `force`. Returns the files written.
+pub fn restore(
+    storage: &Storage,
+    archive: &Path,
+    files_dir: &Path,
+    force: bool,
+) -> Result<Vec<PathBuf>> {
+    let mut records = None;
+    let mut files = Vec::new();
+    for entry in tar::Archive::new(File::open(archive)?).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        if path == Path::new(RECORDS_ENTRY) {
+            records = Some(serde_json::from_slice(&content)?);
+        } else if let Ok(name) = path.strip_prefix(FILES_DIR)
+            && let Some(name) = name.file_name()
+        {
+            files.push((files_dir.join(name), content));
+        }
+    }
+    let Some(records) = records else {
+        bail!("{} has no {RECORDS_ENTRY}", archive.display());
+    };
+    if !force && let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+        bail!("{} exists, use --force to replace it", path.display());
+    }
+
+    storage.restore(records)?;
+    let mut written = Vec::new();
+    for (path, content) in files {
+        std::fs::write(&path, content)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_archive() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("meshboard-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let schedule = dir.join("meshboard.schedule");
+        std::fs::write(&schedule, "daily 09:00 news Morning")?;
+        let out = dir.join("meshboard.tar");
+
+        let storage = Storage::memory();
+        storage.add_channel("news")?;
+        create(&storage, &[schedule.clone(), dir.join("missing.env")], &out)?;
+
+        let restored = Storage::memory();
+        assert!(restore(&restored, &out, &dir, false).is_err());
+        assert_eq!(
+            restore(&restored, &out, &dir, true)?,
+            vec![schedule.clone()]
+        );
+        assert_eq!(restored.snapshot()?, storage.snapshot()?);
+        assert_eq!(
+            std::fs::read_to_string(&schedule)?,
+            "daily 09:00 news Morning"
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
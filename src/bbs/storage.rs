@@ -3,25 +3,67 @@ use native_db::Builder;
 use native_db::Database;
 use native_db::Key;
 use native_db::Models;
+use native_db::ToInput;
 use native_db::ToKey;
 use native_db::native_db;
+use native_db::transaction::{RTransaction, RwTransaction};
 use native_model::Model;
 use native_model::native_model;
 use serde::Deserialize;
 use serde::Serialize;
+use tracing::{debug, info};
 
+use crate::mesh::service::Metrics;
+
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 static MODELS: OnceLock<Models> = OnceLock::new();
+/// Version of the models, bumped with every change that needs a migration,
+/// see [migrate_to]
+const SCHEMA_VERSION: u32 = 5;
+/// Waypoints kept, the oldest received go first
+pub const WAYPOINTS_KEPT: usize = 256;
 
 fn models() -> &'static Models {
     MODELS.get_or_init(|| {
         let mut models = Models::new();
 
+        models.define::<UserV1>().unwrap();
         models.define::<User>().unwrap();
         models.define::<Channel>().unwrap();
+        models.define::<ChannelMessageV1>().unwrap();
         models.define::<ChannelMessage>().unwrap();
+        models.define::<Subscription>().unwrap();
+        models.define::<Preference>().unwrap();
+        models.define::<CheckIn>().unwrap();
+        models.define::<Ban>().unwrap();
+        models.define::<Node>().unwrap();
+        models.define::<Watch>().unwrap();
+        models.define::<Announcement>().unwrap();
+        models.define::<Sighting>().unwrap();
+        models.define::<TelemetrySample>().unwrap();
+        models.define::<PositionSample>().unwrap();
+        models.define::<DirectMessage>().unwrap();
+        models.define::<ChannelAcl>().unwrap();
+        models.define::<SchemaVersion>().unwrap();
+        models.define::<Setting>().unwrap();
+        models.define::<Reaction>().unwrap();
+        models.define::<NodeBan>().unwrap();
+        models.define::<Poll>().unwrap();
+        models.define::<Vote>().unwrap();
+        models.define::<Document>().unwrap();
+        models.define::<Mail>().unwrap();
+        models.define::<SyncedPostV1>().unwrap();
+        models.define::<SyncedPost>().unwrap();
+        models.define::<RawPacket>().unwrap();
+        models.define::<WaypointV1>().unwrap();
+        models.define::<Waypoint>().unwrap();
+        models.define::<Neighbor>().unwrap();
         models
     })
 }
@@ -41,9 +83,22 @@ impl ToKey for UserPkHash {
         vec!["pk_hash".to_string()]
     }
 }
+/// [User] before new users were verified, migrated on open
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Eq)]
 #[native_model(id = 1, version = 1)]
 #[native_db]
+pub struct UserV1 {
+    #[primary_key]
+    pub uid: UserId,
+    #[secondary_key(unique)]
+    pub pk_hash: UserPkHash,
+    pub short_name: String,
+    pub last_ts: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, Eq)]
+#[native_model(id = 1, version = 2, from = UserV1)]
+#[native_db]
 pub struct User {
     // User Id
     #[primary_key]
@@ -55,6 +110,33 @@ pub struct User {
     pub short_name: String,
     // Last Seen Timestamp
     pub last_ts: u64,
+    // New user that has not answered the challenge yet, see
+    // [crate::bbs::service::Options::verify_new_users]
+    #[serde(default)]
+    pub pending: bool,
+}
+
+impl From<UserV1> for User {
+    fn from(user: UserV1) -> Self {
+        Self {
+            uid: user.uid,
+            pk_hash: user.pk_hash,
+            short_name: user.short_name,
+            last_ts: user.last_ts,
+            pending: false,
+        }
+    }
+}
+
+impl From<User> for UserV1 {
+    fn from(user: User) -> Self {
+        Self {
+            uid: user.uid,
+            pk_hash: user.pk_hash,
+            short_name: user.short_name,
+            last_ts: user.last_ts,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
@@ -66,14 +148,642 @@ pub struct Channel {
     pub name: String,
 }
 
+/// [ChannelMessage] before messages were numbered, migrated on open
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
 #[native_model(id = 3, version = 1)]
 #[native_db]
+pub struct ChannelMessageV1 {
+    #[primary_key]
+    pub cid_ts: (ChannelId, u64),
+    pub uid: UserId,
+    pub text: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 3, version = 2, from = ChannelMessageV1)]
+#[native_db]
 pub struct ChannelMessage {
     #[primary_key]
     pub cid_ts: (ChannelId, u64),
     pub uid: UserId,
     pub text: String,
+    // Number of the message in its channel, from 1, set by add_message
+    #[serde(default)]
+    pub id: u32,
+    // Number of the message this one replies to
+    #[serde(default)]
+    pub parent_id: Option<u32>,
+}
+
+impl From<ChannelMessageV1> for ChannelMessage {
+    fn from(message: ChannelMessageV1) -> Self {
+        Self {
+            cid_ts: message.cid_ts,
+            uid: message.uid,
+            text: message.text,
+            id: 0,
+            parent_id: None,
+        }
+    }
+}
+
+impl From<ChannelMessage> for ChannelMessageV1 {
+    fn from(message: ChannelMessage) -> Self {
+        Self {
+            cid_ts: message.cid_ts,
+            uid: message.uid,
+            text: message.text,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 4, version = 1)]
+#[native_db]
+pub struct Subscription {
+    #[primary_key]
+    pub cid_uid: (ChannelId, UserId),
+    // Node to notify
+    pub node: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 5, version = 1)]
+#[native_db]
+pub struct Preference {
+    #[primary_key]
+    pub uid_key: (UserId, String),
+    pub value: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 6, version = 1)]
+#[native_db]
+pub struct CheckIn {
+    #[primary_key]
+    pub ts_uid: (u64, UserId),
+    // Degrees * 1e7, as sent by the radio
+    pub latitude_i: i32,
+    pub longitude_i: i32,
+    pub note: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 7, version = 1)]
+#[native_db]
+pub struct Ban {
+    #[primary_key]
+    pub pk_hash: UserPkHash,
+    // Ban Timestamp
+    pub ts: u64,
+}
+
+/// A banned node, whoever sends from it
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 19, version = 1)]
+#[native_db]
+pub struct NodeBan {
+    // Node number
+    #[primary_key]
+    pub num: u32,
+    // Ban Timestamp
+    pub ts: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 8, version = 1)]
+#[native_db]
+pub struct Node {
+    // Node number
+    #[primary_key]
+    pub num: u32,
+    pub short_name: String,
+    // Hardware model, e.g. HELTEC_V3
+    pub hw_model: String,
+    // Device role, e.g. ROUTER
+    pub role: String,
+    // Only known for nodes that reported their metadata
+    pub firmware: Option<String>,
+    // Last Seen Timestamp
+    pub last_seen: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 9, version = 1)]
+#[native_db]
+pub struct Watch {
+    // Watched node number
+    #[primary_key]
+    pub node: u32,
+    // Watch Timestamp
+    pub ts: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 10, version = 1)]
+#[native_db]
+pub struct Announcement {
+    #[primary_key]
+    pub id: u32,
+    // Schedule line, see bbs::schedule::Entry
+    pub entry: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[native_model(id = 11, version = 1)]
+#[native_db]
+pub struct Sighting {
+    // Node number
+    #[primary_key]
+    pub num: u32,
+    pub short_name: String,
+    pub long_name: String,
+    // Last Heard Timestamp
+    pub last_heard: u64,
+    pub snr: f32,
+    pub rssi: i32,
+    // Hops away, unknown for old firmwares
+    pub hops: Option<u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[native_model(id = 12, version = 1)]
+#[native_db]
+pub struct TelemetrySample {
+    // Node number and Timestamp
+    #[primary_key]
+    pub num_ts: (u32, u64),
+    pub metrics: Metrics,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 13, version = 1)]
+#[native_db]
+pub struct PositionSample {
+    // Node number and Timestamp
+    #[primary_key]
+    pub num_ts: (u32, u64),
+    // Degrees * 1e7, as sent by the radio
+    pub latitude_i: i32,
+    pub longitude_i: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 14, version = 1)]
+#[native_db]
+pub struct DirectMessage {
+    // Received Timestamp
+    #[primary_key]
+    pub ts: u64,
+    // Sender node number
+    pub from: u32,
+    pub pk_hash: UserPkHash,
+    pub text: String,
+}
+
+/// Who may read and post to a channel, channels without one are public
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 15, version = 1)]
+#[native_db]
+pub struct ChannelAcl {
+    #[primary_key]
+    pub cid: ChannelId,
+    // Sha256 of the join password, None only lets the members in
+    pub password: Option<[u8; 32]>,
+    pub members: Vec<UserPkHash>,
+}
+
+/// A schema version the database was migrated to, the last one is current
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 16, version = 1)]
+#[native_db]
+pub struct SchemaVersion {
+    #[primary_key]
+    pub version: u32,
+    // Migration Timestamp
+    pub ts: u64,
+}
+
+/// A board wide setting changed at runtime, e.g. the motd
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 17, version = 1)]
+#[native_db]
+pub struct Setting {
+    #[primary_key]
+    pub key: String,
+    pub value: String,
+}
+
+/// Reaction of a user to a channel message, one per user and message
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 18, version = 1)]
+#[native_db]
+pub struct Reaction {
+    // Channel, message number and user
+    #[primary_key]
+    pub cid_id_uid: (ChannelId, u32, UserId),
+    pub emoji: String,
+}
+
+/// A question asked in a channel, votes are taken until it closes
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 20, version = 1)]
+#[native_db]
+pub struct Poll {
+    #[primary_key]
+    pub id: u32,
+    pub cid: ChannelId,
+    pub uid: UserId,
+    pub question: String,
+    pub options: Vec<String>,
+    // Creation and closing Timestamps
+    pub ts: u64,
+    pub closes: u64,
+}
+
+/// Vote of a user in a poll, one per user and poll
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 21, version = 1)]
+#[native_db]
+pub struct Vote {
+    // Poll and user
+    #[primary_key]
+    pub poll_uid: (u32, UserId),
+    // Index in the options of the poll
+    pub option: u32,
+}
+
+/// A text document of the files area
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 22, version = 1)]
+#[native_db]
+pub struct Document {
+    #[primary_key]
+    pub id: u32,
+    pub name: String,
+    pub text: String,
+    // Upload Timestamp
+    pub ts: u64,
+}
+
+/// Private mail of a user, e.g. the reply to an email
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 23, version = 1)]
+#[native_db]
+pub struct Mail {
+    #[primary_key]
+    pub id: u32,
+    // Recipient
+    pub uid: UserId,
+    pub from: String,
+    pub subject: String,
+    pub text: String,
+    // Received Timestamp
+    pub ts: u64,
+    pub read: bool,
+}
+
+/// [SyncedPost] before it pointed to the message, migrated on open
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 24, version = 1)]
+#[native_db]
+pub struct SyncedPostV1 {
+    #[primary_key]
+    pub hash: u32,
+    pub from: u32,
+    pub ts: u64,
+}
+
+/// A post synced with the peer boards, see [crate::bbs::federation]
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 24, version = 2, from = SyncedPostV1)]
+#[native_db]
+pub struct SyncedPost {
+    #[primary_key]
+    pub hash: u32,
+    // Peer board it came from, 0 for the posts of this board
+    pub from: u32,
+    // Synced Timestamp
+    pub ts: u64,
+    // Channel and number of the message of the post on this board, None for
+    // the posts synced before it was kept
+    #[secondary_key(unique, optional)]
+    #[serde(default)]
+    pub message: Option<(ChannelId, u32)>,
+}
+
+impl From<SyncedPostV1> for SyncedPost {
+    fn from(post: SyncedPostV1) -> Self {
+        Self {
+            hash: post.hash,
+            from: post.from,
+            ts: post.ts,
+            message: None,
+        }
+    }
+}
+
+impl From<SyncedPost> for SyncedPostV1 {
+    fn from(post: SyncedPost) -> Self {
+        Self {
+            hash: post.hash,
+            from: post.from,
+            ts: post.ts,
+        }
+    }
+}
+
+/// The MeshPacket of a text sent to the board, as it came from the radio
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 25, version = 1)]
+#[native_db]
+pub struct RawPacket {
+    // Packet id
+    #[primary_key]
+    pub id: u32,
+    pub from: u32,
+    // Received Timestamp
+    pub ts: u64,
+    // Protobuf encoded
+    pub bytes: Vec<u8>,
+}
+
+/// [Waypoint] before it could be locked, migrated on open
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 26, version = 1)]
+#[native_db]
+pub struct WaypointV1 {
+    #[primary_key]
+    pub id: u32,
+    pub name: String,
+    pub description: String,
+    pub latitude_i: i32,
+    pub longitude_i: i32,
+    pub expire: u32,
+    pub from: u32,
+    pub ts: u64,
+}
+
+/// A waypoint shared on the mesh, by a node or with `wp add`
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[native_model(id = 26, version = 2, from = WaypointV1)]
+#[native_db]
+pub struct Waypoint {
+    // Waypoint id, a waypoint with the same id replaces it
+    #[primary_key]
+    pub id: u32,
+    pub name: String,
+    pub description: String,
+    // Degrees * 1e7, as sent by the radio
+    pub latitude_i: i32,
+    pub longitude_i: i32,
+    // Unix time in seconds it expires at, 0 never
+    pub expire: u32,
+    // Node that shared it
+    pub from: u32,
+    // Received Timestamp
+    pub ts: u64,
+    // Only node that may change or delete it, 0 any
+    #[serde(default)]
+    pub locked_to: u32,
+}
+
+impl From<WaypointV1> for Waypoint {
+    fn from(waypoint: WaypointV1) -> Self {
+        Self {
+            id: waypoint.id,
+            name: waypoint.name,
+            description: waypoint.description,
+            latitude_i: waypoint.latitude_i,
+            longitude_i: waypoint.longitude_i,
+            expire: waypoint.expire,
+            from: waypoint.from,
+            ts: waypoint.ts,
+            locked_to: 0,
+        }
+    }
+}
+
+impl From<Waypoint> for WaypointV1 {
+    fn from(waypoint: Waypoint) -> Self {
+        Self {
+            id: waypoint.id,
+            name: waypoint.name,
+            description: waypoint.description,
+            latitude_i: waypoint.latitude_i,
+            longitude_i: waypoint.longitude_i,
+            expire: waypoint.expire,
+            from: waypoint.from,
+            ts: waypoint.ts,
+        }
+    }
+}
+
+/// A node a node hears, from the NeighborInfo packets it sends
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[native_model(id = 27, version = 1)]
+#[native_db]
+pub struct Neighbor {
+    // Node number and the number of the node it hears
+    #[primary_key]
+    pub node_neighbor: (u32, u32),
+    pub snr: f32,
+    // Received Timestamp
+    pub ts: u64,
+}
+
+/// Every record of the board, see [Storage::snapshot]. Records missing in
+/// older snapshots are left empty.
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct Snapshot {
+    pub users: Vec<User>,
+    pub channels: Vec<Channel>,
+    pub messages: Vec<ChannelMessage>,
+    pub subscriptions: Vec<Subscription>,
+    pub preferences: Vec<Preference>,
+    pub checkins: Vec<CheckIn>,
+    pub bans: Vec<Ban>,
+    pub nodes: Vec<Node>,
+    pub watches: Vec<Watch>,
+    pub announcements: Vec<Announcement>,
+    pub sightings: Vec<Sighting>,
+    pub telemetry: Vec<TelemetrySample>,
+    pub positions: Vec<PositionSample>,
+    pub direct_messages: Vec<DirectMessage>,
+    pub channel_acls: Vec<ChannelAcl>,
+    pub settings: Vec<Setting>,
+    pub reactions: Vec<Reaction>,
+    pub node_bans: Vec<NodeBan>,
+    pub polls: Vec<Poll>,
+    pub votes: Vec<Vote>,
+    pub documents: Vec<Document>,
+    pub mails: Vec<Mail>,
+    pub synced_posts: Vec<SyncedPost>,
+    pub raw_packets: Vec<RawPacket>,
+    pub waypoints: Vec<Waypoint>,
+    pub neighbors: Vec<Neighbor>,
+}
+
+fn scan_all<T: ToInput>(r: &RTransaction) -> Result<Vec<T>> {
+    Ok(r.scan().primary()?.all()?.collect::<Result<_, _>>()?)
+}
+
+fn insert_all<T: ToInput>(rw: &RwTransaction, records: Vec<T>) -> Result<()> {
+    for record in records {
+        rw.insert(record)?;
+    }
+    Ok(())
+}
+
+// Removes the reactions to the messages of the channel numbered in `ids`
+fn remove_reactions(
+    rw: &RwTransaction,
+    channel_id: ChannelId,
+    ids: RangeInclusive<u32>,
+) -> Result<()> {
+    let reactions: Vec<Reaction> = rw
+        .scan()
+        .primary()?
+        .range((channel_id, *ids.start(), 0)..=(channel_id, *ids.end(), UserId::MAX))?
+        .collect::<Result<_, _>>()?;
+    for reaction in reactions {
+        rw.remove(reaction)?;
+    }
+    Ok(())
+}
+
+// Numbers the messages that have none, the ones from before messages were
+// numbered, in the order they were posted
+fn number_messages(rw: &RwTransaction) -> Result<usize> {
+    let messages: Vec<ChannelMessage> = rw.scan().primary()?.all()?.collect::<Result<_, _>>()?;
+    let mut last = (ChannelId::MAX, 0);
+    let mut count = 0;
+    for mut message in messages {
+        let cid = message.cid_ts.0;
+        if last.0 != cid {
+            last = (cid, 0);
+        }
+        if message.id == 0 {
+            message.id = last.1 + 1;
+            rw.upsert(message.clone())?;
+            count += 1;
+        }
+        last.1 = message.id;
+    }
+    Ok(count)
+}
+
+// Removes the messages of the channel and their reactions, returns how many
+// messages were removed
+fn purge_messages(rw: &RwTransaction, channel_id: ChannelId) -> Result<usize> {
+    let messages: Vec<ChannelMessage> = rw
+        .scan()
+        .primary()?
+        .range((channel_id, 0)..=(channel_id, u64::MAX))?
+        .collect::<Result<_, _>>()?;
+    let count = messages.len();
+    for message in messages {
+        rw.remove(message)?;
+    }
+    // Numbers start over, reactions would end up on the new messages
+    remove_reactions(rw, channel_id, 0..=u32::MAX)?;
+    Ok(count)
+}
+
+// Brings the models from `version - 1` to `version`. Databases from before
+// the schema was versioned go through every step, so steps must do nothing
+// when there is nothing to migrate.
+fn migrate_to(rw: &RwTransaction, version: u32) -> Result<()> {
+    match version {
+        // Messages are numbered, and replies point to their parent
+        2 => {
+            rw.migrate::<ChannelMessage>()?;
+            let count = number_messages(rw)?;
+            info!(target: "storage", "Numbered {count} messages");
+        }
+        // Users may be pending verification, the ones there are not
+        3 => rw.migrate::<User>()?,
+        // Synced posts point to their message
+        4 => rw.migrate::<SyncedPost>()?,
+        // Waypoints may be locked to a node
+        5 => rw.migrate::<Waypoint>()?,
+        _ => anyhow::bail!("No migration to schema version {version}"),
+    }
+    Ok(())
+}
+
+// Runs the migrations the database is missing, new databases are just
+// stamped with the current version
+fn migrate(db: &Database) -> Result<()> {
+    let rw = db.rw_transaction()?;
+    let last: Option<SchemaVersion> = rw.scan().primary()?.all()?.last().transpose()?;
+    let fresh = rw.len().primary::<UserV1>()?
+        + rw.len().primary::<Channel>()?
+        + rw.len().primary::<ChannelMessageV1>()?
+        == 0;
+    let current = match last {
+        Some(last) => last.version,
+        None if fresh => 0,
+        None => 1,
+    };
+    if current > SCHEMA_VERSION {
+        anyhow::bail!(
+            "Database schema v{current} is newer than v{SCHEMA_VERSION}, upgrade meshboard"
+        );
+    }
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    if current == 0 {
+        rw.insert(SchemaVersion {
+            version: SCHEMA_VERSION,
+            ts,
+        })?;
+        rw.commit()?;
+        return Ok(());
+    }
+    for version in current + 1..=SCHEMA_VERSION {
+        info!(target: "storage", "Migrating the database to schema v{version}");
+        migrate_to(&rw, version)?;
+        rw.insert(SchemaVersion { version, ts })?;
+    }
+    rw.commit()?;
+    Ok(())
+}
+
+pub struct Stats {
+    pub users: u64,
+    pub channels: u64,
+    pub messages: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    NativeDb,
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "native_db" => Ok(Backend::NativeDb),
+            "memory" => Ok(Backend::Memory),
+            "sqlite" => anyhow::bail!("sqlite backend is not supported yet"),
+            _ => anyhow::bail!("Unknown storage backend '{s}', use native_db or memory"),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::NativeDb => write!(f, "native_db"),
+            Backend::Memory => write!(f, "memory"),
+        }
+    }
 }
 
 pub struct Storage {
@@ -81,20 +791,45 @@ pub struct Storage {
 }
 
 impl Storage {
-    #[cfg(test)]
+    pub fn with_backend(backend: Backend, path: &Path) -> Result<Self> {
+        match backend {
+            Backend::NativeDb => Self::open(path),
+            Backend::Memory => Ok(Self::memory()),
+        }
+    }
     pub fn memory() -> Self {
         let db = Builder::new().create_in_memory(models()).unwrap();
+        migrate(&db).unwrap();
+        debug!(target: "storage", "In memory database");
         Self { db }
     }
+    /// Opens the database file, migrating it to the current schema
     pub fn open(path: &Path) -> Result<Self> {
         let db = Builder::new().create(models(), path)?;
+        migrate(&db)?;
+        debug!(target: "storage", path = %path.display(), "Opened database");
         Ok(Self { db })
     }
+
+    /// Schema version of the database
+    pub fn schema_version(&self) -> Result<u32> {
+        let r = self.db.r_transaction()?;
+        let last: Option<SchemaVersion> = r.scan().primary()?.all()?.last().transpose()?;
+        Ok(last.map_or(1, |last| last.version))
+    }
     pub fn add_channel(&self, name: &str) -> Result<u32> {
         let rw = self.db.rw_transaction()?;
-        let cid = rw.len().primary::<Channel>()? as u32;
+        // Channels can be removed, so take the next to the highest id
+        let cid = rw
+            .scan()
+            .primary::<Channel>()?
+            .all()?
+            .last()
+            .transpose()?
+            .map(|ch| ch.cid + 1)
+            .unwrap_or(0);
         let channel = Channel {
-            cid: cid,
+            cid,
             name: name.into(),
         };
 
@@ -113,21 +848,216 @@ impl Storage {
         Ok(channels)
     }
 
-    pub fn add_message(&self, message: ChannelMessage) -> Result<u32> {
+    pub fn remove_channel(&self, channel_id: ChannelId) -> Result<()> {
         let rw = self.db.rw_transaction()?;
-        rw.insert(message)?;
+        purge_messages(&rw, channel_id)?;
+        let subscriptions: Vec<Subscription> = rw
+            .scan()
+            .primary()?
+            .range((channel_id, 0)..=(channel_id, UserId::MAX))?
+            .collect::<Result<_, _>>()?;
+        for subscription in subscriptions {
+            rw.remove(subscription)?;
+        }
+        let acl: Option<ChannelAcl> = rw.get().primary(channel_id)?;
+        if let Some(acl) = acl {
+            rw.remove(acl)?;
+        }
+        let channel: Option<Channel> = rw.get().primary(channel_id)?;
+        if let Some(channel) = channel {
+            rw.remove(channel)?;
+        }
         rw.commit()?;
-        Ok(0)
+        Ok(())
     }
 
-    pub fn get_messages(
-        &self,
-        channel_id: u32,
-        ts_start: u64,
-        ts_end: u64,
-    ) -> Result<Vec<ChannelMessage>> {
-        let r = self.db.r_transaction()?;
-        let mut messages: Vec<ChannelMessage> = Vec::new();
+    /// Removes all messages of a channel, returns how many were removed
+    pub fn purge_messages(&self, channel_id: ChannelId) -> Result<usize> {
+        let rw = self.db.rw_transaction()?;
+        let count = purge_messages(&rw, channel_id)?;
+        rw.commit()?;
+        Ok(count)
+    }
+
+    /// Stores the message numbered next in its channel, returns the number
+    pub fn add_message(&self, mut message: ChannelMessage) -> Result<u32> {
+        let rw = self.db.rw_transaction()?;
+        // Messages are keyed by timestamp, move same millisecond ones forward
+        while rw
+            .get()
+            .primary::<ChannelMessage>(message.cid_ts)?
+            .is_some()
+        {
+            message.cid_ts.1 += 1;
+        }
+        // Numbered after the highest number, posts may come dated before the
+        // newest one
+        let channel_id = message.cid_ts.0;
+        let mut last = 0;
+        for other in rw
+            .scan()
+            .primary::<ChannelMessage>()?
+            .range((channel_id, 0)..=(channel_id, u64::MAX))?
+        {
+            last = last.max(other?.id);
+        }
+        message.id = last + 1;
+        let id = message.id;
+        rw.insert(message)?;
+        rw.commit()?;
+        Ok(id)
+    }
+
+    /// Newest messages of the channel containing `keyword`, ignoring case, up
+    /// to `limit`. The channel is scanned from the newest message, one at a
+    /// time, until enough are found.
+    pub fn search_messages(
+        &self,
+        channel_id: ChannelId,
+        keyword: &str,
+        limit: usize,
+    ) -> Result<Vec<ChannelMessage>> {
+        let keyword = keyword.to_lowercase();
+        let r = self.db.r_transaction()?;
+        let mut hits = Vec::new();
+        for msg in r
+            .scan()
+            .primary::<ChannelMessage>()?
+            .range((channel_id, 0)..=(channel_id, u64::MAX))?
+            .rev()
+        {
+            if hits.len() == limit {
+                break;
+            }
+            let msg = msg?;
+            if msg.text.to_lowercase().contains(&keyword) {
+                hits.push(msg);
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Removes the messages of the channel posted before `ts_end`, and the
+    /// oldest ones past the newest `keep`, 0 keeps any number. Returns how
+    /// many were removed.
+    pub fn prune_messages(&self, channel_id: ChannelId, ts_end: u64, keep: usize) -> Result<usize> {
+        let rw = self.db.rw_transaction()?;
+        let mut removed = Vec::new();
+        for (n, msg) in rw
+            .scan()
+            .primary::<ChannelMessage>()?
+            .range((channel_id, 0)..=(channel_id, u64::MAX))?
+            .rev()
+            .enumerate()
+        {
+            let msg = msg?;
+            if msg.cid_ts.1 < ts_end || (keep > 0 && n >= keep) {
+                removed.push(msg);
+            }
+        }
+        let count = removed.len();
+        for msg in removed {
+            remove_reactions(&rw, channel_id, msg.id..=msg.id)?;
+            rw.remove(msg)?;
+        }
+        rw.commit()?;
+        Ok(count)
+    }
+
+    /// Gives back to the filesystem the space of the removed records
+    pub fn compact(&mut self) -> Result<()> {
+        self.db.compact()?;
+        Ok(())
+    }
+
+    /// Message of the channel by its number
+    pub fn get_message(&self, channel_id: ChannelId, id: u32) -> Result<Option<ChannelMessage>> {
+        let r = self.db.r_transaction()?;
+        // Replies are mostly to recent messages, start from the newest
+        for msg in r
+            .scan()
+            .primary::<ChannelMessage>()?
+            .range((channel_id, 0)..=(channel_id, u64::MAX))?
+            .rev()
+        {
+            let msg = msg?;
+            if msg.id == id {
+                return Ok(Some(msg));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sets the reaction of the user to the message, the same one again
+    /// removes it. Returns whether the reaction is set.
+    pub fn toggle_reaction(
+        &self,
+        channel_id: ChannelId,
+        id: u32,
+        user_id: UserId,
+        emoji: &str,
+    ) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let reaction: Option<Reaction> = rw.get().primary((channel_id, id, user_id))?;
+        let set = match reaction {
+            Some(reaction) if reaction.emoji == emoji => {
+                rw.remove(reaction)?;
+                false
+            }
+            _ => {
+                rw.upsert(Reaction {
+                    cid_id_uid: (channel_id, id, user_id),
+                    emoji: emoji.to_string(),
+                })?;
+                true
+            }
+        };
+        rw.commit()?;
+        Ok(set)
+    }
+
+    /// Reactions to the message as (emoji, count), the most given first
+    pub fn get_reactions(&self, channel_id: ChannelId, id: u32) -> Result<Vec<(String, usize)>> {
+        let r = self.db.r_transaction()?;
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for reaction in r
+            .scan()
+            .primary::<Reaction>()?
+            .range((channel_id, id, 0)..=(channel_id, id, UserId::MAX))?
+        {
+            let reaction = reaction?;
+            match counts
+                .iter_mut()
+                .find(|(emoji, _)| *emoji == reaction.emoji)
+            {
+                Some((_, count)) => *count += 1,
+                None => counts.push((reaction.emoji, 1)),
+            }
+        }
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(counts)
+    }
+
+    /// Timestamp of the newest message of the channel
+    pub fn last_message_ts(&self, channel_id: ChannelId) -> Result<Option<u64>> {
+        let r = self.db.r_transaction()?;
+        let last: Option<ChannelMessage> = r
+            .scan()
+            .primary()?
+            .range((channel_id, 0)..=(channel_id, u64::MAX))?
+            .next_back()
+            .transpose()?;
+        Ok(last.map(|msg| msg.cid_ts.1))
+    }
+
+    pub fn get_messages(
+        &self,
+        channel_id: u32,
+        ts_start: u64,
+        ts_end: u64,
+    ) -> Result<Vec<ChannelMessage>> {
+        let r = self.db.r_transaction()?;
+        let mut messages: Vec<ChannelMessage> = Vec::new();
         for msg in r
             .scan()
             .primary()?
@@ -139,6 +1069,39 @@ impl Storage {
         Ok(messages)
     }
 
+    pub fn get_messages_page(
+        &self,
+        channel_id: u32,
+        ts_start: u64,
+        ts_end: u64,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<ChannelMessage>> {
+        let r = self.db.r_transaction()?;
+        let mut messages: Vec<ChannelMessage> = Vec::new();
+        for msg in r
+            .scan()
+            .primary()?
+            .range((channel_id, ts_start)..(channel_id, ts_end))?
+            .skip(offset)
+            .take(limit)
+        {
+            messages.push(msg?);
+        }
+
+        Ok(messages)
+    }
+
+    pub fn count_messages(&self, channel_id: u32, ts_start: u64, ts_end: u64) -> Result<usize> {
+        let r = self.db.r_transaction()?;
+        let count = r
+            .scan()
+            .primary::<ChannelMessage>()?
+            .range((channel_id, ts_start)..(channel_id, ts_end))?
+            .count();
+        Ok(count)
+    }
+
     pub fn add_user(&self, mut user: User) -> Result<UserId> {
         let rw = self.db.rw_transaction()?;
         let user_id = rw.len().primary::<User>()? as u32;
@@ -165,6 +1128,826 @@ impl Storage {
         Ok(user)
     }
 
+    pub fn add_subscription(&self, subscription: Subscription) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(subscription)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_subscription(&self, channel_id: ChannelId, user_id: UserId) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let subscription: Option<Subscription> = rw.get().primary((channel_id, user_id))?;
+        let Some(subscription) = subscription else {
+            return Ok(false);
+        };
+        rw.remove(subscription)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_subscriptions(&self, channel_id: ChannelId) -> Result<Vec<Subscription>> {
+        let r = self.db.r_transaction()?;
+        let mut subscriptions: Vec<Subscription> = Vec::new();
+        for sub in r
+            .scan()
+            .primary()?
+            .range((channel_id, 0)..=(channel_id, UserId::MAX))?
+        {
+            subscriptions.push(sub?);
+        }
+
+        Ok(subscriptions)
+    }
+
+    pub fn set_preference(&self, user_id: UserId, key: &str, value: &str) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(Preference {
+            uid_key: (user_id, key.to_string()),
+            value: value.to_string(),
+        })?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn get_preference(&self, user_id: UserId, key: &str) -> Result<Option<String>> {
+        let r = self.db.r_transaction()?;
+        let preference: Option<Preference> = r.get().primary((user_id, key.to_string()))?;
+        Ok(preference.map(|p| p.value))
+    }
+
+    pub fn remove_preference(&self, user_id: UserId, key: &str) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        let preference: Option<Preference> = rw.get().primary((user_id, key.to_string()))?;
+        if let Some(preference) = preference {
+            rw.remove(preference)?;
+        }
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn get_preferences(&self, user_id: UserId) -> Result<Vec<Preference>> {
+        let r = self.db.r_transaction()?;
+        let mut preferences: Vec<Preference> = Vec::new();
+        for preference in r
+            .scan()
+            .primary()?
+            .range((user_id, String::new())..(user_id + 1, String::new()))?
+        {
+            preferences.push(preference?);
+        }
+
+        Ok(preferences)
+    }
+
+    /// Users that have the preference set to the value
+    pub fn find_preference(&self, key: &str, value: &str) -> Result<Vec<UserId>> {
+        let r = self.db.r_transaction()?;
+        let mut user_ids = Vec::new();
+        for preference in r.scan().primary::<Preference>()?.all()? {
+            let preference = preference?;
+            if preference.uid_key.1 == key && preference.value == value {
+                user_ids.push(preference.uid_key.0);
+            }
+        }
+        Ok(user_ids)
+    }
+
+    pub fn add_checkin(&self, checkin: CheckIn) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(checkin)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn get_checkins(&self, ts_start: u64) -> Result<Vec<CheckIn>> {
+        let r = self.db.r_transaction()?;
+        let mut checkins: Vec<CheckIn> = Vec::new();
+        for checkin in r.scan().primary()?.range((ts_start, UserId::MIN)..)? {
+            checkins.push(checkin?);
+        }
+
+        Ok(checkins)
+    }
+
+    pub fn get_user_by_short_name(&self, short_name: &str) -> Result<Option<User>> {
+        let r = self.db.r_transaction()?;
+        for user in r.scan().primary::<User>()?.all()? {
+            let user = user?;
+            if user.short_name == short_name {
+                return Ok(Some(user));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn add_ban(&self, ban: Ban) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(ban)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn is_banned(&self, pk_hash: &UserPkHash) -> Result<bool> {
+        let r = self.db.r_transaction()?;
+        let ban: Option<Ban> = r.get().primary(pk_hash.clone())?;
+        Ok(ban.is_some())
+    }
+
+    pub fn remove_ban(&self, pk_hash: &UserPkHash) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let ban: Option<Ban> = rw.get().primary(pk_hash.clone())?;
+        let Some(ban) = ban else {
+            return Ok(false);
+        };
+        rw.remove(ban)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_bans(&self) -> Result<Vec<Ban>> {
+        let r = self.db.r_transaction()?;
+        scan_all(&r)
+    }
+
+    pub fn add_node_ban(&self, ban: NodeBan) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(ban)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn is_node_banned(&self, num: u32) -> Result<bool> {
+        let r = self.db.r_transaction()?;
+        let ban: Option<NodeBan> = r.get().primary(num)?;
+        Ok(ban.is_some())
+    }
+
+    pub fn remove_node_ban(&self, num: u32) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let ban: Option<NodeBan> = rw.get().primary(num)?;
+        let Some(ban) = ban else {
+            return Ok(false);
+        };
+        rw.remove(ban)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_node_bans(&self) -> Result<Vec<NodeBan>> {
+        let r = self.db.r_transaction()?;
+        scan_all(&r)
+    }
+
+    pub fn get_node(&self, num: u32) -> Result<Option<Node>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary(num)?)
+    }
+
+    pub fn upsert_node(&self, node: Node) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(node)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn get_nodes(&self) -> Result<Vec<Node>> {
+        let r = self.db.r_transaction()?;
+        let mut nodes = Vec::new();
+        for node in r.scan().primary()?.all()? {
+            nodes.push(node?);
+        }
+        Ok(nodes)
+    }
+
+    pub fn add_watch(&self, watch: Watch) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(watch)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_watch(&self, node: u32) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let watch: Option<Watch> = rw.get().primary(node)?;
+        let Some(watch) = watch else {
+            return Ok(false);
+        };
+        rw.remove(watch)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_watches(&self) -> Result<Vec<Watch>> {
+        let r = self.db.r_transaction()?;
+        let mut watches = Vec::new();
+        for watch in r.scan().primary()?.all()? {
+            watches.push(watch?);
+        }
+        Ok(watches)
+    }
+
+    pub fn add_announcement(&self, entry: &str) -> Result<u32> {
+        let rw = self.db.rw_transaction()?;
+        // Announcements can be removed, so take the next to the highest id
+        let id = rw
+            .scan()
+            .primary::<Announcement>()?
+            .all()?
+            .last()
+            .transpose()?
+            .map(|announcement| announcement.id + 1)
+            .unwrap_or(1);
+        rw.insert(Announcement {
+            id,
+            entry: entry.into(),
+        })?;
+        rw.commit()?;
+        Ok(id)
+    }
+
+    pub fn remove_announcement(&self, id: u32) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let announcement: Option<Announcement> = rw.get().primary(id)?;
+        let Some(announcement) = announcement else {
+            return Ok(false);
+        };
+        rw.remove(announcement)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_announcements(&self) -> Result<Vec<Announcement>> {
+        let r = self.db.r_transaction()?;
+        let mut announcements = Vec::new();
+        for announcement in r.scan().primary()?.all()? {
+            announcements.push(announcement?);
+        }
+        Ok(announcements)
+    }
+
+    /// Stores the poll under the next id, which is returned
+    pub fn add_poll(&self, mut poll: Poll) -> Result<u32> {
+        let rw = self.db.rw_transaction()?;
+        poll.id = rw
+            .scan()
+            .primary::<Poll>()?
+            .all()?
+            .last()
+            .transpose()?
+            .map(|last| last.id + 1)
+            .unwrap_or(1);
+        let id = poll.id;
+        rw.insert(poll)?;
+        rw.commit()?;
+        Ok(id)
+    }
+
+    pub fn get_poll(&self, id: u32) -> Result<Option<Poll>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary(id)?)
+    }
+
+    /// Records the vote, false if the user already voted in the poll
+    pub fn add_vote(&self, vote: Vote) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let voted: Option<Vote> = rw.get().primary(vote.poll_uid)?;
+        if voted.is_some() {
+            return Ok(false);
+        }
+        rw.insert(vote)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    /// Votes of each option of the poll, in the order of the options
+    pub fn get_votes(&self, poll: &Poll) -> Result<Vec<usize>> {
+        let r = self.db.r_transaction()?;
+        let mut counts = vec![0; poll.options.len()];
+        for vote in r
+            .scan()
+            .primary::<Vote>()?
+            .range((poll.id, 0)..=(poll.id, UserId::MAX))?
+        {
+            if let Some(count) = counts.get_mut(vote?.option as usize) {
+                *count += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Stores the document under the next id, which is returned
+    pub fn add_document(&self, mut document: Document) -> Result<u32> {
+        let rw = self.db.rw_transaction()?;
+        document.id = rw
+            .scan()
+            .primary::<Document>()?
+            .all()?
+            .last()
+            .transpose()?
+            .map(|last| last.id + 1)
+            .unwrap_or(1);
+        let id = document.id;
+        rw.insert(document)?;
+        rw.commit()?;
+        Ok(id)
+    }
+
+    pub fn get_document(&self, id: u32) -> Result<Option<Document>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary(id)?)
+    }
+
+    pub fn get_documents(&self) -> Result<Vec<Document>> {
+        let r = self.db.r_transaction()?;
+        scan_all(&r)
+    }
+
+    pub fn remove_document(&self, id: u32) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let document: Option<Document> = rw.get().primary(id)?;
+        let Some(document) = document else {
+            return Ok(false);
+        };
+        rw.remove(document)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn add_mail(&self, mut mail: Mail) -> Result<u32> {
+        let rw = self.db.rw_transaction()?;
+        mail.id = rw
+            .scan()
+            .primary::<Mail>()?
+            .all()?
+            .last()
+            .transpose()?
+            .map(|last| last.id + 1)
+            .unwrap_or(1);
+        let id = mail.id;
+        rw.insert(mail)?;
+        rw.commit()?;
+        Ok(id)
+    }
+
+    /// Mail of the user, oldest first
+    pub fn get_mails(&self, uid: UserId) -> Result<Vec<Mail>> {
+        let r = self.db.r_transaction()?;
+        let mails: Vec<Mail> = scan_all(&r)?;
+        Ok(mails.into_iter().filter(|mail| mail.uid == uid).collect())
+    }
+
+    pub fn mark_mail_read(&self, id: u32) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        let mail: Option<Mail> = rw.get().primary(id)?;
+        if let Some(mail) = mail {
+            let read = Mail {
+                read: true,
+                ..mail.clone()
+            };
+            rw.update(mail, read)?;
+        }
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn get_synced_post(&self, hash: u32) -> Result<Option<SyncedPost>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary(hash)?)
+    }
+
+    /// The synced post of the message number `id` of the channel
+    pub fn get_synced_post_of(&self, cid: ChannelId, id: u32) -> Result<Option<SyncedPost>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().secondary(SyncedPostKey::message, Some((cid, id)))?)
+    }
+
+    /// Records the post, unless it was synced already
+    pub fn add_synced_post(&self, post: SyncedPost) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        if rw.get().primary::<SyncedPost>(post.hash)?.is_none() {
+            rw.insert(post)?;
+        }
+        rw.commit()?;
+        Ok(())
+    }
+
+    /// Removes the posts synced before `ts_end`, returns how many
+    pub fn prune_synced_posts(&self, ts_end: u64) -> Result<usize> {
+        let rw = self.db.rw_transaction()?;
+        let posts: Vec<SyncedPost> = rw
+            .scan()
+            .primary()?
+            .all()?
+            .filter(|post: &Result<SyncedPost, _>| post.as_ref().is_ok_and(|post| post.ts < ts_end))
+            .collect::<Result<_, _>>()?;
+        let count = posts.len();
+        for post in posts {
+            rw.remove(post)?;
+        }
+        rw.commit()?;
+        Ok(count)
+    }
+
+    /// Keeps the packet, replacing the one with the same id
+    pub fn add_raw_packet(&self, packet: RawPacket) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(packet)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn get_raw_packet(&self, id: u32) -> Result<Option<RawPacket>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary(id)?)
+    }
+
+    /// Removes the packets received before `ts_end`, returns how many
+    pub fn prune_raw_packets(&self, ts_end: u64) -> Result<usize> {
+        let rw = self.db.rw_transaction()?;
+        let packets: Vec<RawPacket> = rw
+            .scan()
+            .primary()?
+            .all()?
+            .filter(|packet: &Result<RawPacket, _>| {
+                packet.as_ref().is_ok_and(|packet| packet.ts < ts_end)
+            })
+            .collect::<Result<_, _>>()?;
+        let count = packets.len();
+        for packet in packets {
+            rw.remove(packet)?;
+        }
+        rw.commit()?;
+        Ok(count)
+    }
+
+    pub fn get_sighting(&self, num: u32) -> Result<Option<Sighting>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary(num)?)
+    }
+
+    pub fn upsert_sighting(&self, sighting: Sighting) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(sighting)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    /// Most recently heard first
+    pub fn get_sightings(&self) -> Result<Vec<Sighting>> {
+        let r = self.db.r_transaction()?;
+        let mut sightings = Vec::new();
+        for sighting in r.scan().primary()?.all()? {
+            sightings.push(sighting?);
+        }
+        sightings.sort_by_key(|sighting: &Sighting| std::cmp::Reverse(sighting.last_heard));
+        Ok(sightings)
+    }
+
+    pub fn add_telemetry(&self, sample: TelemetrySample) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(sample)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    /// Samples of the node since `ts_start`, oldest first
+    pub fn get_telemetry(&self, num: u32, ts_start: u64) -> Result<Vec<TelemetrySample>> {
+        let r = self.db.r_transaction()?;
+        let mut samples = Vec::new();
+        for sample in r
+            .scan()
+            .primary()?
+            .range((num, ts_start)..=(num, u64::MAX))?
+        {
+            samples.push(sample?);
+        }
+        Ok(samples)
+    }
+
+    /// All records, read in a single transaction so writes done meanwhile
+    /// are either fully in or out
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let r = self.db.r_transaction()?;
+        Ok(Snapshot {
+            users: scan_all(&r)?,
+            channels: scan_all(&r)?,
+            messages: scan_all(&r)?,
+            subscriptions: scan_all(&r)?,
+            preferences: scan_all(&r)?,
+            checkins: scan_all(&r)?,
+            bans: scan_all(&r)?,
+            nodes: scan_all(&r)?,
+            watches: scan_all(&r)?,
+            announcements: scan_all(&r)?,
+            sightings: scan_all(&r)?,
+            telemetry: scan_all(&r)?,
+            positions: scan_all(&r)?,
+            direct_messages: scan_all(&r)?,
+            channel_acls: scan_all(&r)?,
+            settings: scan_all(&r)?,
+            reactions: scan_all(&r)?,
+            node_bans: scan_all(&r)?,
+            polls: scan_all(&r)?,
+            votes: scan_all(&r)?,
+            documents: scan_all(&r)?,
+            mails: scan_all(&r)?,
+            synced_posts: scan_all(&r)?,
+            raw_packets: scan_all(&r)?,
+            waypoints: scan_all(&r)?,
+            neighbors: scan_all(&r)?,
+        })
+    }
+
+    /// Loads a snapshot into an empty storage
+    pub fn restore(&self, snapshot: Snapshot) -> Result<()> {
+        let stats = self.stats()?;
+        if stats.users + stats.channels + stats.messages > 0 {
+            anyhow::bail!("Storage is not empty");
+        }
+        let rw = self.db.rw_transaction()?;
+        insert_all(&rw, snapshot.users)?;
+        insert_all(&rw, snapshot.channels)?;
+        insert_all(&rw, snapshot.messages)?;
+        insert_all(&rw, snapshot.subscriptions)?;
+        insert_all(&rw, snapshot.preferences)?;
+        insert_all(&rw, snapshot.checkins)?;
+        insert_all(&rw, snapshot.bans)?;
+        insert_all(&rw, snapshot.nodes)?;
+        insert_all(&rw, snapshot.watches)?;
+        insert_all(&rw, snapshot.announcements)?;
+        insert_all(&rw, snapshot.sightings)?;
+        insert_all(&rw, snapshot.telemetry)?;
+        insert_all(&rw, snapshot.positions)?;
+        insert_all(&rw, snapshot.direct_messages)?;
+        insert_all(&rw, snapshot.channel_acls)?;
+        insert_all(&rw, snapshot.settings)?;
+        insert_all(&rw, snapshot.reactions)?;
+        insert_all(&rw, snapshot.node_bans)?;
+        insert_all(&rw, snapshot.polls)?;
+        insert_all(&rw, snapshot.votes)?;
+        insert_all(&rw, snapshot.documents)?;
+        insert_all(&rw, snapshot.mails)?;
+        insert_all(&rw, snapshot.synced_posts)?;
+        insert_all(&rw, snapshot.raw_packets)?;
+        insert_all(&rw, snapshot.waypoints)?;
+        insert_all(&rw, snapshot.neighbors)?;
+        number_messages(&rw)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn add_position(&self, sample: PositionSample) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(sample)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    /// Positions of the node since `ts_start`, oldest first
+    pub fn get_positions(&self, num: u32, ts_start: u64) -> Result<Vec<PositionSample>> {
+        let r = self.db.r_transaction()?;
+        let mut samples = Vec::new();
+        for sample in r
+            .scan()
+            .primary()?
+            .range((num, ts_start)..=(num, u64::MAX))?
+        {
+            samples.push(sample?);
+        }
+        Ok(samples)
+    }
+
+    /// Keeps the waypoint, replacing the one with the same id. An expired
+    /// one removes it, that is how nodes delete waypoints. A waypoint locked
+    /// to a node is only changed by that node, returns false when it was
+    /// not. Past [WAYPOINTS_KEPT] the oldest received are removed.
+    pub fn add_waypoint(&self, waypoint: Waypoint, now: u64) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let kept: Option<Waypoint> = rw.get().primary(waypoint.id)?;
+        if let Some(kept) = &kept
+            && kept.locked_to != 0
+            && kept.locked_to != waypoint.from
+        {
+            return Ok(false);
+        }
+        if waypoint.expire != 0 && waypoint.expire as u64 * 1000 <= now {
+            if let Some(kept) = kept {
+                rw.remove(kept)?;
+            }
+        } else {
+            rw.upsert(waypoint)?;
+            let count = rw.len().primary::<Waypoint>()? as usize;
+            if count > WAYPOINTS_KEPT {
+                let mut waypoints: Vec<Waypoint> =
+                    rw.scan().primary()?.all()?.collect::<Result<_, _>>()?;
+                waypoints.sort_by_key(|waypoint| waypoint.ts);
+                for waypoint in waypoints.into_iter().take(count - WAYPOINTS_KEPT) {
+                    rw.remove(waypoint)?;
+                }
+            }
+        }
+        rw.commit()?;
+        Ok(true)
+    }
+
+    /// Removes the waypoints expired by `now`
+    pub fn prune_waypoints(&self, now: u64) -> Result<usize> {
+        let rw = self.db.rw_transaction()?;
+        let waypoints: Vec<Waypoint> = rw
+            .scan()
+            .primary()?
+            .all()?
+            .filter(|waypoint: &Result<Waypoint, _>| {
+                waypoint.as_ref().is_ok_and(|waypoint| {
+                    waypoint.expire != 0 && waypoint.expire as u64 * 1000 <= now
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let count = waypoints.len();
+        for waypoint in waypoints {
+            rw.remove(waypoint)?;
+        }
+        rw.commit()?;
+        Ok(count)
+    }
+
+    /// The waypoints not expired by `now`, newest first
+    pub fn get_waypoints(&self, now: u64) -> Result<Vec<Waypoint>> {
+        let r = self.db.r_transaction()?;
+        let mut waypoints: Vec<Waypoint> = r
+            .scan()
+            .primary()?
+            .all()?
+            .filter(|waypoint: &Result<Waypoint, _>| {
+                waypoint.as_ref().is_ok_and(|waypoint| {
+                    waypoint.expire == 0 || waypoint.expire as u64 * 1000 > now
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        waypoints.sort_by_key(|waypoint| std::cmp::Reverse(waypoint.ts));
+        Ok(waypoints)
+    }
+
+    /// Replaces the neighbors of the node, each NeighborInfo lists all of
+    /// them
+    pub fn set_neighbors(&self, node: u32, neighbors: Vec<Neighbor>) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        let kept: Vec<Neighbor> = rw
+            .scan()
+            .primary()?
+            .range((node, 0)..=(node, u32::MAX))?
+            .collect::<Result<_, _>>()?;
+        for neighbor in kept {
+            rw.remove(neighbor)?;
+        }
+        for neighbor in neighbors {
+            rw.insert(neighbor)?;
+        }
+        rw.commit()?;
+        Ok(())
+    }
+
+    pub fn get_neighbors(&self, node: u32) -> Result<Vec<Neighbor>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.scan()
+            .primary()?
+            .range((node, 0)..=(node, u32::MAX))?
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Every link of the mesh the nodes told about
+    pub fn get_neighbor_links(&self) -> Result<Vec<Neighbor>> {
+        let r = self.db.r_transaction()?;
+        scan_all(&r)
+    }
+
+    /// Removes the links told before `ts_end`
+    pub fn prune_neighbors(&self, ts_end: u64) -> Result<usize> {
+        let rw = self.db.rw_transaction()?;
+        let neighbors: Vec<Neighbor> = rw
+            .scan()
+            .primary()?
+            .all()?
+            .filter(|neighbor: &Result<Neighbor, _>| {
+                neighbor.as_ref().is_ok_and(|neighbor| neighbor.ts < ts_end)
+            })
+            .collect::<Result<_, _>>()?;
+        let count = neighbors.len();
+        for neighbor in neighbors {
+            rw.remove(neighbor)?;
+        }
+        rw.commit()?;
+        Ok(count)
+    }
+
+    pub fn last_position(&self, num: u32) -> Result<Option<PositionSample>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.scan()
+            .primary()?
+            .range((num, 0)..=(num, u64::MAX))?
+            .next_back()
+            .transpose()?)
+    }
+
+    pub fn add_direct_message(&self, mut message: DirectMessage) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        // Keyed by timestamp, move same millisecond ones forward
+        while rw.get().primary::<DirectMessage>(message.ts)?.is_some() {
+            message.ts += 1;
+        }
+        rw.insert(message)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    /// Direct messages, most recent first
+    pub fn get_direct_messages(&self, offset: usize, limit: usize) -> Result<Vec<DirectMessage>> {
+        let r = self.db.r_transaction()?;
+        let mut messages = Vec::new();
+        for message in r.scan().primary()?.all()?.rev().skip(offset).take(limit) {
+            messages.push(message?);
+        }
+        Ok(messages)
+    }
+
+    /// Removes the direct messages received before `ts_end`, returns how many
+    pub fn prune_direct_messages(&self, ts_end: u64) -> Result<usize> {
+        let rw = self.db.rw_transaction()?;
+        let messages: Vec<DirectMessage> = rw
+            .scan()
+            .primary()?
+            .range(..ts_end)?
+            .collect::<Result<_, _>>()?;
+        let count = messages.len();
+        for message in messages {
+            rw.remove(message)?;
+        }
+        rw.commit()?;
+        Ok(count)
+    }
+
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let r = self.db.r_transaction()?;
+        let setting: Option<Setting> = r.get().primary(key.to_string())?;
+        Ok(setting.map(|setting| setting.value))
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(Setting {
+            key: key.to_string(),
+            value: value.to_string(),
+        })?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    /// Removes the setting, returns whether it was set
+    pub fn remove_setting(&self, key: &str) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let setting: Option<Setting> = rw.get().primary(key.to_string())?;
+        let Some(setting) = setting else {
+            return Ok(false);
+        };
+        rw.remove(setting)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_channel_acl(&self, channel_id: ChannelId) -> Result<Option<ChannelAcl>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary(channel_id)?)
+    }
+
+    pub fn set_channel_acl(&self, acl: ChannelAcl) -> Result<()> {
+        let rw = self.db.rw_transaction()?;
+        rw.upsert(acl)?;
+        rw.commit()?;
+        Ok(())
+    }
+
+    /// Makes the channel public again, returns whether it was not
+    pub fn remove_channel_acl(&self, channel_id: ChannelId) -> Result<bool> {
+        let rw = self.db.rw_transaction()?;
+        let acl: Option<ChannelAcl> = rw.get().primary(channel_id)?;
+        let Some(acl) = acl else {
+            return Ok(false);
+        };
+        rw.remove(acl)?;
+        rw.commit()?;
+        Ok(true)
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        let r = self.db.r_transaction()?;
+        Ok(Stats {
+            users: r.len().primary::<User>()?,
+            channels: r.len().primary::<Channel>()?,
+            messages: r.len().primary::<ChannelMessage>()?,
+        })
+    }
+
     pub fn get_user_by_pkhash(&self, pk_hash: UserPkHash) -> Result<User> {
         let r = self.db.r_transaction()?;
         let user: User = r
@@ -195,6 +1978,27 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_remove_channel() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let cid0 = s.add_channel("talk")?;
+        let cid1 = s.add_channel("news")?;
+        s.add_message(ChannelMessage {
+            cid_ts: (cid0, 1),
+            uid: 0,
+            text: "hi".into(),
+            id: 0,
+            parent_id: None,
+        })?;
+        s.remove_channel(cid0)?;
+        let cid2 = s.add_channel("misc")?;
+        assert_ne!(cid2, cid1);
+        assert_eq!(s.get_channels()?.len(), 2);
+        assert_eq!(s.stats()?.messages, 0);
+
+        Ok(())
+    }
+
     #[test]
     fn test_users() -> anyhow::Result<()> {
         let s = Storage::memory();
@@ -205,6 +2009,7 @@ mod test {
             short_name: "user0".to_string(),
             pk_hash: UserPkHash([7u8; 32]),
             last_ts: 0,
+            pending: false,
         };
         user0.uid = s.add_user(user0.clone())?;
         assert_eq!(user0, s.get_user_by_id(user0.uid)?);
@@ -214,6 +2019,7 @@ mod test {
             short_name: "user1".to_string(),
             pk_hash: UserPkHash([8u8; 32]),
             last_ts: 99,
+            pending: true,
         };
         user1.uid = s.add_user(user1.clone())?;
         assert_eq!(user1, s.get_user_by_id(user1.uid)?);
@@ -225,6 +2031,27 @@ mod test {
         s.update_user(user0.uid, user0.clone())?;
         assert_eq!(user0, s.get_user_by_id(user0.uid)?);
 
+        assert_eq!(s.get_user_by_short_name("user1")?, Some(user1.clone()));
+        assert_eq!(s.get_user_by_short_name("user2")?, None);
+
+        assert!(!s.is_banned(&user1.pk_hash)?);
+        s.add_ban(Ban {
+            pk_hash: user1.pk_hash.clone(),
+            ts: 0,
+        })?;
+        assert!(s.is_banned(&user1.pk_hash)?);
+        assert_eq!(s.get_bans()?.len(), 1);
+        assert!(s.remove_ban(&user1.pk_hash)?);
+        assert!(!s.remove_ban(&user1.pk_hash)?);
+        assert!(!s.is_banned(&user1.pk_hash)?);
+
+        s.add_node_ban(NodeBan { num: 7, ts: 0 })?;
+        assert!(s.is_node_banned(7)?);
+        assert!(!s.is_node_banned(8)?);
+        assert_eq!(s.get_node_bans()?, vec![NodeBan { num: 7, ts: 0 }]);
+        assert!(s.remove_node_ban(7)?);
+        assert!(!s.is_node_banned(7)?);
+
         Ok(())
     }
 
@@ -232,21 +2059,23 @@ mod test {
     fn test_messages() -> anyhow::Result<()> {
         let s = Storage::memory();
 
-        let mkmsg = |cid, ts| ChannelMessage {
+        let mkmsg = |cid, ts, id| ChannelMessage {
             cid_ts: (cid, ts),
             uid: 1,
             text: format!("{cid}{ts}"),
+            id,
+            parent_id: None,
         };
 
-        let msg1 = mkmsg(0, 1);
-        s.add_message(msg1.clone())?;
-        let msg2 = mkmsg(0, 2);
-        s.add_message(msg2.clone())?;
-        let msg3 = mkmsg(0, 3);
+        let msg1 = mkmsg(0, 1, 1);
+        assert_eq!(s.add_message(msg1.clone())?, 1);
+        let msg2 = mkmsg(0, 2, 2);
+        assert_eq!(s.add_message(msg2.clone())?, 2);
+        let msg3 = mkmsg(0, 3, 3);
         s.add_message(msg3.clone())?;
-        let msg4 = mkmsg(1, 4);
-        s.add_message(msg4.clone())?;
-        let msg5 = mkmsg(1, 5);
+        let msg4 = mkmsg(1, 4, 1);
+        assert_eq!(s.add_message(msg4.clone())?, 1);
+        let msg5 = mkmsg(1, 5, 2);
         s.add_message(msg5.clone())?;
 
         assert_eq!(
@@ -258,6 +2087,669 @@ mod test {
 
         assert_eq!(s.get_messages(1, 4, 6)?, vec![msg4.clone(), msg5.clone()]);
 
+        assert_eq!(s.count_messages(0, 1, 4)?, 3);
+        assert_eq!(s.get_messages_page(0, 1, 4, 1, 1)?, vec![msg2.clone()]);
+        assert_eq!(s.get_messages_page(0, 1, 4, 2, 5)?, vec![msg3.clone()]);
+
+        assert_eq!(s.last_message_ts(0)?, Some(3));
+        assert_eq!(s.last_message_ts(1)?, Some(5));
+        assert_eq!(s.last_message_ts(2)?, None);
+
+        assert_eq!(s.get_message(0, 2)?, Some(msg2.clone()));
+        assert_eq!(s.get_message(1, 2)?, Some(msg5));
+        assert_eq!(s.get_message(1, 3)?, None);
+
+        assert_eq!(s.search_messages(0, "0", 2)?, vec![msg3, msg2.clone()]);
+        assert_eq!(s.search_messages(0, "02", 5)?, vec![msg2]);
+        assert!(s.search_messages(1, "02", 5)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_messages() -> anyhow::Result<()> {
+        let mut s = Storage::memory();
+        for (cid, ts) in [(0, 1), (0, 2), (0, 3), (0, 4), (1, 1)] {
+            s.add_message(ChannelMessage {
+                cid_ts: (cid, ts),
+                uid: 1,
+                text: format!("{cid}{ts}"),
+                id: 0,
+                parent_id: None,
+            })?;
+        }
+
+        assert_eq!(s.prune_messages(0, 2, 0)?, 1);
+        assert_eq!(s.prune_messages(0, 0, 2)?, 1);
+        assert_eq!(s.prune_messages(0, 0, 2)?, 0);
+        let ts: Vec<_> = s
+            .get_messages(0, 0, u64::MAX)?
+            .into_iter()
+            .map(|msg| msg.cid_ts.1)
+            .collect();
+        assert_eq!(ts, vec![3, 4]);
+        assert_eq!(s.get_messages(1, 0, u64::MAX)?.len(), 1);
+        s.compact()?;
+
+        Ok(())
+    }
+
+    // Database as written before the schema was versioned
+    fn v1_fixture(path: &Path) -> anyhow::Result<()> {
+        let mut models = Models::new();
+        models.define::<UserV1>()?;
+        models.define::<Channel>()?;
+        models.define::<ChannelMessageV1>()?;
+        let db = Builder::new().create(&models, path)?;
+        let rw = db.rw_transaction()?;
+        rw.insert(UserV1 {
+            uid: 0,
+            short_name: "ann".into(),
+            pk_hash: UserPkHash([1; 32]),
+            last_ts: 0,
+        })?;
+        for (cid, name) in [(0, "news"), (1, "general")] {
+            rw.insert(Channel {
+                cid,
+                name: name.into(),
+            })?;
+        }
+        for (cid, ts) in [(0, 10), (0, 20), (1, 15)] {
+            rw.insert(ChannelMessageV1 {
+                cid_ts: (cid, ts),
+                uid: 0,
+                text: format!("{cid}{ts}"),
+            })?;
+        }
+        rw.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_migration() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("meshboard-v1-{}.db", std::process::id()));
+        v1_fixture(&path)?;
+
+        let s = Storage::open(&path)?;
+        assert_eq!(s.schema_version()?, SCHEMA_VERSION);
+        assert_eq!(s.get_user_by_id(0)?.short_name, "ann");
+        assert!(!s.get_user_by_id(0)?.pending);
+        assert_eq!(s.get_user_by_pkhash(UserPkHash([1; 32]))?.uid, 0);
+        assert_eq!(s.get_channels()?.len(), 2);
+        let ids: Vec<_> = s
+            .get_messages(0, 0, u64::MAX)?
+            .into_iter()
+            .chain(s.get_messages(1, 0, u64::MAX)?)
+            .map(|msg| (msg.cid_ts, msg.id))
+            .collect();
+        assert_eq!(ids, vec![((0, 10), 1), ((0, 20), 2), ((1, 15), 1)]);
+        assert_eq!(s.add_message(s.get_message(0, 1)?.unwrap())?, 3);
+        drop(s);
+
+        // Nothing left to migrate
+        let s = Storage::open(&path)?;
+        assert_eq!(s.snapshot()?.messages.len(), 4);
+        let rw = s.db.rw_transaction()?;
+        rw.insert(SchemaVersion { version: 99, ts: 0 })?;
+        rw.commit()?;
+        drop(s);
+        assert!(Storage::open(&path).is_err());
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(Storage::memory().schema_version()?, SCHEMA_VERSION);
+        Ok(())
+    }
+
+    #[test]
+    fn test_preferences() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        s.set_preference(1, "lang", "ca")?;
+        s.set_preference(1, "terse", "true")?;
+        s.set_preference(2, "lang", "es")?;
+        assert_eq!(s.get_preference(1, "lang")?, Some("ca".to_string()));
+        assert_eq!(s.get_preference(1, "tz")?, None);
+
+        s.set_preference(1, "lang", "en")?;
+        assert_eq!(s.get_preference(1, "lang")?, Some("en".to_string()));
+        assert_eq!(s.find_preference("lang", "es")?, vec![2]);
+        assert_eq!(s.get_preferences(1)?.len(), 2);
+
+        s.remove_preference(1, "terse")?;
+        assert_eq!(s.get_preference(1, "terse")?, None);
+        assert_eq!(s.get_preference(2, "lang")?, Some("es".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkins() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let mkcheckin = |ts, uid| CheckIn {
+            ts_uid: (ts, uid),
+            latitude_i: 413_870_000,
+            longitude_i: 21_700_000,
+            note: format!("{ts}{uid}"),
+        };
+
+        s.add_checkin(mkcheckin(10, 1))?;
+        s.add_checkin(mkcheckin(20, 2))?;
+        s.add_checkin(mkcheckin(30, 1))?;
+
+        assert_eq!(s.get_checkins(0)?.len(), 3);
+        assert_eq!(
+            s.get_checkins(20)?,
+            vec![mkcheckin(20, 2), mkcheckin(30, 1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_subscriptions() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let mksub = |cid, uid| Subscription {
+            cid_uid: (cid, uid),
+            node: 100 + uid,
+        };
+
+        s.add_subscription(mksub(0, 1))?;
+        s.add_subscription(mksub(0, 2))?;
+        s.add_subscription(mksub(1, 1))?;
+
+        assert_eq!(s.get_subscriptions(0)?, vec![mksub(0, 1), mksub(0, 2)]);
+        assert_eq!(s.get_subscriptions(1)?, vec![mksub(1, 1)]);
+
+        assert!(s.remove_subscription(0, 1)?);
+        assert!(!s.remove_subscription(0, 1)?);
+        assert_eq!(s.get_subscriptions(0)?, vec![mksub(0, 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nodes() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let mknode = |num, firmware: Option<&str>| Node {
+            num,
+            short_name: format!("n{num}"),
+            hw_model: "HELTEC_V3".into(),
+            role: "CLIENT".into(),
+            firmware: firmware.map(String::from),
+            last_seen: 10,
+        };
+
+        assert_eq!(s.get_node(1)?, None);
+        s.upsert_node(mknode(1, None))?;
+        s.upsert_node(mknode(2, None))?;
+        s.upsert_node(mknode(1, Some("2.5.6")))?;
+
+        assert_eq!(s.get_node(1)?, Some(mknode(1, Some("2.5.6"))));
+        assert_eq!(
+            s.get_nodes()?,
+            vec![mknode(1, Some("2.5.6")), mknode(2, None)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_watches() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        s.add_watch(Watch { node: 2, ts: 10 })?;
+        s.add_watch(Watch { node: 1, ts: 20 })?;
+        assert_eq!(
+            s.get_watches()?,
+            vec![Watch { node: 1, ts: 20 }, Watch { node: 2, ts: 10 }]
+        );
+
+        assert!(s.remove_watch(1)?);
+        assert!(!s.remove_watch(1)?);
+        assert_eq!(s.get_watches()?, vec![Watch { node: 2, ts: 10 }]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_announcements() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        assert_eq!(s.add_announcement("daily 09:00 news a")?, 1);
+        assert_eq!(s.add_announcement("daily 10:00 news b")?, 2);
+        assert!(s.remove_announcement(2)?);
+        assert!(!s.remove_announcement(2)?);
+        assert_eq!(s.add_announcement("daily 11:00 news c")?, 2);
+
+        let entries: Vec<_> = s
+            .get_announcements()?
+            .into_iter()
+            .map(|announcement| announcement.entry)
+            .collect();
+        assert_eq!(entries, vec!["daily 09:00 news a", "daily 11:00 news c"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        assert_eq!(s.get_setting("motd")?, None);
+        s.set_setting("motd", "hi")?;
+        s.set_setting("motd", "hello")?;
+        assert_eq!(s.get_setting("motd")?.as_deref(), Some("hello"));
+        assert!(s.remove_setting("motd")?);
+        assert!(!s.remove_setting("motd")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reactions() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        for ts in [1, 2] {
+            s.add_message(ChannelMessage {
+                cid_ts: (0, ts),
+                uid: 1,
+                text: format!("{ts}"),
+                id: 0,
+                parent_id: None,
+            })?;
+        }
+
+        assert!(s.toggle_reaction(0, 1, 1, "👍")?);
+        assert!(!s.toggle_reaction(0, 1, 1, "👍")?);
+        s.toggle_reaction(0, 1, 1, "❤️")?;
+        s.toggle_reaction(0, 1, 1, "👍")?;
+        s.toggle_reaction(0, 1, 2, "👍")?;
+        s.toggle_reaction(0, 1, 3, "❤️")?;
+        s.toggle_reaction(0, 2, 1, "👍")?;
+        assert_eq!(
+            s.get_reactions(0, 1)?,
+            vec![("👍".to_string(), 2), ("❤️".to_string(), 1)]
+        );
+
+        assert_eq!(s.prune_messages(0, 2, 0)?, 1);
+        assert!(s.get_reactions(0, 1)?.is_empty());
+        assert_eq!(s.get_reactions(0, 2)?.len(), 1);
+        s.purge_messages(0)?;
+        assert!(s.get_reactions(0, 2)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_polls() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let poll = Poll {
+            id: 0,
+            cid: 0,
+            uid: 1,
+            question: "Meetup day?".into(),
+            options: vec!["sat".into(), "sun".into()],
+            ts: 10,
+            closes: 20,
+        };
+        assert_eq!(s.add_poll(poll.clone())?, 1);
+        assert_eq!(s.add_poll(poll.clone())?, 2);
+        assert_eq!(
+            s.get_poll(1)?.map(|poll| poll.question),
+            Some(poll.question)
+        );
+        assert_eq!(s.get_poll(3)?, None);
+
+        let vote = |poll, uid, option| Vote {
+            poll_uid: (poll, uid),
+            option,
+        };
+        assert!(s.add_vote(vote(1, 1, 1))?);
+        assert!(!s.add_vote(vote(1, 1, 0))?);
+        assert!(s.add_vote(vote(1, 2, 1))?);
+        assert!(s.add_vote(vote(2, 1, 0))?);
+        let first = s.get_poll(1)?.unwrap();
+        assert_eq!(s.get_votes(&first)?, vec![0, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_acls() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let cid = s.add_channel("ops")?;
+        assert_eq!(s.get_channel_acl(cid)?, None);
+        let acl = ChannelAcl {
+            cid,
+            password: None,
+            members: vec![UserPkHash([1; 32])],
+        };
+        s.set_channel_acl(acl.clone())?;
+        assert_eq!(s.get_channel_acl(cid)?, Some(acl.clone()));
+        assert!(s.remove_channel_acl(cid)?);
+        assert!(!s.remove_channel_acl(cid)?);
+
+        s.set_channel_acl(acl)?;
+        s.remove_channel(cid)?;
+        assert_eq!(s.get_channel_acl(cid)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let cid = s.add_channel("news")?;
+        let uid = s.add_user(User {
+            uid: 0,
+            short_name: "user0".into(),
+            pk_hash: UserPkHash([7u8; 32]),
+            last_ts: 0,
+            pending: false,
+        })?;
+        s.add_message(ChannelMessage {
+            cid_ts: (cid, 1),
+            uid,
+            text: "hi".into(),
+            id: 0,
+            parent_id: None,
+        })?;
+        s.set_preference(uid, "nick", "pere")?;
+        s.add_watch(Watch { node: 7, ts: 10 })?;
+
+        let snapshot = s.snapshot()?;
+        assert_eq!(snapshot.messages.len(), 1);
+
+        let restored = Storage::memory();
+        restored.restore(s.snapshot()?)?;
+        assert_eq!(restored.snapshot()?, snapshot);
+        assert!(restored.restore(snapshot).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_telemetry() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let mksample = |num, ts, battery_level| TelemetrySample {
+            num_ts: (num, ts),
+            metrics: Metrics {
+                battery_level: Some(battery_level),
+                voltage: Some(4.1),
+                ..Default::default()
+            },
+        };
+
+        s.add_telemetry(mksample(1, 10, 90))?;
+        s.add_telemetry(mksample(1, 20, 80))?;
+        s.add_telemetry(mksample(2, 15, 50))?;
+
+        assert_eq!(
+            s.get_telemetry(1, 0)?,
+            vec![mksample(1, 10, 90), mksample(1, 20, 80)]
+        );
+        assert_eq!(s.get_telemetry(1, 15)?, vec![mksample(1, 20, 80)]);
+        assert_eq!(s.get_telemetry(3, 0)?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_positions() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let mkposition = |num, ts| PositionSample {
+            num_ts: (num, ts),
+            latitude_i: 413851000 + ts as i32,
+            longitude_i: 21734000,
+        };
+
+        assert_eq!(s.last_position(1)?, None);
+        s.add_position(mkposition(1, 10))?;
+        s.add_position(mkposition(1, 20))?;
+        s.add_position(mkposition(2, 30))?;
+
+        assert_eq!(s.last_position(1)?, Some(mkposition(1, 20)));
+        assert_eq!(
+            s.get_positions(1, 0)?,
+            vec![mkposition(1, 10), mkposition(1, 20)]
+        );
+        assert_eq!(s.get_positions(1, 15)?, vec![mkposition(1, 20)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sightings() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let mksighting = |num, last_heard| Sighting {
+            num,
+            short_name: format!("n{num}"),
+            long_name: format!("Node {num}"),
+            last_heard,
+            snr: 6.5,
+            rssi: -90,
+            hops: Some(1),
+        };
+
+        assert_eq!(s.get_sighting(1)?, None);
+        s.upsert_sighting(mksighting(1, 10))?;
+        s.upsert_sighting(mksighting(2, 20))?;
+        s.upsert_sighting(mksighting(3, 15))?;
+        s.upsert_sighting(mksighting(1, 30))?;
+
+        assert_eq!(s.get_sighting(1)?, Some(mksighting(1, 30)));
+        assert_eq!(
+            s.get_sightings()?,
+            vec![mksighting(1, 30), mksighting(2, 20), mksighting(3, 15)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_direct_messages() -> anyhow::Result<()> {
+        let s = Storage::memory();
+
+        let mkdm = |ts, text: &str| DirectMessage {
+            ts,
+            from: 7,
+            pk_hash: UserPkHash([7u8; 32]),
+            text: text.into(),
+        };
+        s.add_direct_message(mkdm(10, "hi"))?;
+        s.add_direct_message(mkdm(10, "hello"))?;
+        s.add_direct_message(mkdm(30, "bye"))?;
+
+        let texts = |messages: Vec<DirectMessage>| {
+            messages
+                .into_iter()
+                .map(|dm| (dm.ts, dm.text))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(s.get_direct_messages(0, 10)?),
+            vec![(30, "bye".into()), (11, "hello".into()), (10, "hi".into())]
+        );
+        assert_eq!(
+            texts(s.get_direct_messages(1, 1)?),
+            vec![(11, "hello".into())]
+        );
+
+        assert_eq!(s.prune_direct_messages(30)?, 2);
+        assert_eq!(
+            texts(s.get_direct_messages(0, 10)?),
+            vec![(30, "bye".into())]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mail() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let mail = |uid, subject: &str| Mail {
+            id: 0,
+            uid,
+            from: "ann@example.com".into(),
+            subject: subject.into(),
+            text: "hi".into(),
+            ts: 0,
+            read: false,
+        };
+        assert_eq!(s.add_mail(mail(1, "one"))?, 1);
+        assert_eq!(s.add_mail(mail(2, "other"))?, 2);
+        assert_eq!(s.add_mail(mail(1, "two"))?, 3);
+        s.mark_mail_read(1)?;
+        let mails = s.get_mails(1)?;
+        assert_eq!(
+            mails
+                .iter()
+                .map(|mail| (mail.id, mail.read))
+                .collect::<Vec<_>>(),
+            vec![(1, true), (3, false)]
+        );
+        assert_eq!(s.snapshot()?.mails.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_synced_posts() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let post = |hash, from, ts| SyncedPost {
+            hash,
+            from,
+            ts,
+            message: Some((1, hash)),
+        };
+        s.add_synced_post(post(1, 7, 100))?;
+        s.add_synced_post(post(1, 0, 300))?;
+        s.add_synced_post(post(2, 0, 200))?;
+        assert_eq!(s.get_synced_post(1)?, Some(post(1, 7, 100)));
+        assert_eq!(s.get_synced_post(3)?, None);
+        assert_eq!(s.get_synced_post_of(1, 2)?, Some(post(2, 0, 200)));
+        assert_eq!(s.get_synced_post_of(2, 2)?, None);
+        assert_eq!(s.prune_synced_posts(150)?, 1);
+        assert_eq!(s.snapshot()?.synced_posts, vec![post(2, 0, 200)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_packets() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let packet = |id, ts, bytes: &[u8]| RawPacket {
+            id,
+            from: 7,
+            ts,
+            bytes: bytes.to_vec(),
+        };
+        s.add_raw_packet(packet(1, 100, b"a"))?;
+        s.add_raw_packet(packet(1, 100, b"b"))?;
+        s.add_raw_packet(packet(2, 200, b"c"))?;
+        assert_eq!(s.get_raw_packet(1)?, Some(packet(1, 100, b"b")));
+        assert_eq!(s.get_raw_packet(3)?, None);
+        assert_eq!(s.prune_raw_packets(150)?, 1);
+        assert_eq!(s.snapshot()?.raw_packets, vec![packet(2, 200, b"c")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_waypoints() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let waypoint = |id, name: &str, expire, ts| Waypoint {
+            id,
+            name: name.into(),
+            description: String::new(),
+            latitude_i: 413_800_000,
+            longitude_i: 21_700_000,
+            expire,
+            from: 7,
+            ts,
+            locked_to: 0,
+        };
+        s.add_waypoint(waypoint(1, "camp", 0, 100), 100)?;
+        s.add_waypoint(waypoint(2, "water", 0, 200), 200)?;
+        s.add_waypoint(waypoint(3, "stage", 10, 300), 300)?;
+        s.add_waypoint(waypoint(2, "well", 20, 400), 400)?;
+        assert_eq!(
+            s.get_waypoints(500)?,
+            vec![
+                waypoint(2, "well", 20, 400),
+                waypoint(3, "stage", 10, 300),
+                waypoint(1, "camp", 0, 100)
+            ]
+        );
+        assert_eq!(s.get_waypoints(20_000)?, vec![waypoint(1, "camp", 0, 100)]);
+        // Expired on arrival, the node deleted it
+        s.add_waypoint(waypoint(1, "camp", 1, 600), 2_000)?;
+        assert_eq!(
+            s.snapshot()?.waypoints,
+            vec![waypoint(2, "well", 20, 400), waypoint(3, "stage", 10, 300)]
+        );
+        assert_eq!(s.prune_waypoints(15_000)?, 1);
+        assert_eq!(s.get_waypoints(0)?, vec![waypoint(2, "well", 20, 400)]);
+
+        // Only the node it is locked to changes it
+        let locked = Waypoint {
+            locked_to: 7,
+            ..waypoint(4, "gate", 0, 700)
+        };
+        assert!(s.add_waypoint(locked.clone(), 700)?);
+        let moved = Waypoint {
+            from: 8,
+            ..waypoint(4, "elsewhere", 0, 800)
+        };
+        assert!(!s.add_waypoint(moved, 800)?);
+        assert!(!s.add_waypoint(
+            Waypoint {
+                from: 8,
+                ..waypoint(4, "gate", 1, 800)
+            },
+            2_000
+        )?);
+        assert_eq!(s.get_waypoints(0)?[0], locked);
+        assert!(s.add_waypoint(waypoint(4, "gate", 1, 900), 2_000)?);
+        assert_eq!(s.get_waypoints(0)?.len(), 1);
+
+        // The oldest go past the cap
+        for id in 0..WAYPOINTS_KEPT as u32 {
+            s.add_waypoint(waypoint(100 + id, "pin", 0, 1_000 + id as u64), 0)?;
+        }
+        let waypoints = s.get_waypoints(0)?;
+        assert_eq!(waypoints.len(), WAYPOINTS_KEPT);
+        assert!(!waypoints.iter().any(|waypoint| waypoint.id == 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_neighbors() -> anyhow::Result<()> {
+        let s = Storage::memory();
+        let neighbor = |node, neighbor, snr, ts| Neighbor {
+            node_neighbor: (node, neighbor),
+            snr,
+            ts,
+        };
+        s.set_neighbors(1, vec![neighbor(1, 2, 6.5, 100), neighbor(1, 3, -2.0, 100)])?;
+        s.set_neighbors(2, vec![neighbor(2, 1, 7.0, 150)])?;
+        assert_eq!(
+            s.get_neighbors(1)?,
+            vec![neighbor(1, 2, 6.5, 100), neighbor(1, 3, -2.0, 100)]
+        );
+        // Node 1 lost node 3
+        s.set_neighbors(1, vec![neighbor(1, 2, 5.0, 200)])?;
+        assert_eq!(s.get_neighbors(1)?, vec![neighbor(1, 2, 5.0, 200)]);
+        assert_eq!(
+            s.get_neighbor_links()?,
+            vec![neighbor(1, 2, 5.0, 200), neighbor(2, 1, 7.0, 150)]
+        );
+        assert_eq!(s.snapshot()?.neighbors.len(), 2);
+        assert_eq!(s.prune_neighbors(180)?, 1);
+        assert_eq!(s.get_neighbor_links()?, vec![neighbor(1, 2, 5.0, 200)]);
         Ok(())
     }
 }
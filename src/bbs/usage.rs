@@ -0,0 +1,304 @@
+use crate::bbs::mailbox::MAX_BODY;
+use crate::bbs::prefs::{PROFILE_MAX_FIELDS, PROFILE_VALUE_MAX_LEN};
+use crate::bbs::service::{
+    CHECKINS_MAX_AGE, MAIL_MAX, NICK_MAX_LEN, POLL_MAX_OPTIONS, SEARCH_MAX, WHOHERE_RADIUS_KM,
+    command_name,
+};
+
+/// Detailed help of a core command, shown by `h <command>`
+pub struct Usage {
+    pub name: &'static str,
+    /// Commands that share the usage, e.g. `unfav` the one of `fav`
+    pub also: &'static [&'static str],
+    /// Only shown to admins
+    pub admin: bool,
+    pub text: String,
+}
+
+fn user(name: &'static str, also: &'static [&'static str], text: impl Into<String>) -> Usage {
+    Usage {
+        name,
+        also,
+        admin: false,
+        text: text.into(),
+    }
+}
+
+fn admin(name: &'static str, also: &'static [&'static str], text: impl Into<String>) -> Usage {
+    Usage {
+        name,
+        also,
+        admin: true,
+        text: text.into(),
+    }
+}
+
+/// The core commands, by name, with the limits the board applies
+pub fn usages() -> Vec<Usage> {
+    let checkin_hours = CHECKINS_MAX_AGE / (60 * 60 * 1000);
+    vec![
+        user(
+            "help",
+            &[],
+            "h [command]: the commands, or how to use one, e.g. h post",
+        ),
+        user("channels", &[], "c: the channels you can join"),
+        user(
+            "join",
+            &[],
+            "j <channel> [password]: moves you to the channel, e.g. j general. \
+             Password channels only ask the first time",
+        ),
+        user(
+            "post",
+            &[],
+            "p <text>: posts to your current channel, e.g. p Bridge is out. \
+             @name mentions notify the user",
+        ),
+        user(
+            "reply",
+            &[],
+            "r <n> <text>: replies to post #n of the channel, e.g. r 12 see you there",
+        ),
+        user(
+            "like",
+            &[],
+            "like <n>: reacts with a thumbs up to post #n, again to take it back",
+        ),
+        user(
+            "react",
+            &[],
+            "react <n> <emoji>: reacts to post #n, e.g. react 12 🎉, again to take it back",
+        ),
+        user(
+            "list",
+            &[],
+            "l [page]: the last posts of your channel, newest first, e.g. l 2 for older ones",
+        ),
+        user("next", &[], "next: the page after the last one listed"),
+        user(
+            "search",
+            &[],
+            format!(
+                "s [all] <words>: up to {SEARCH_MAX} posts of your channel with the words, or \
+                 of every channel with all, e.g. s all bridge"
+            ),
+        ),
+        user(
+            "sub",
+            &[],
+            "sub <channel>: pushes you the new posts of the channel",
+        ),
+        user("unsub", &[], "unsub <channel>: stops pushing them"),
+        user(
+            "checkin",
+            &[],
+            format!(
+                "checkin [note]: tells you are around, with your last position, for \
+                 {checkin_hours}h"
+            ),
+        ),
+        user(
+            "whohere",
+            &[],
+            format!(
+                "whohere [lat lon] [km]: check-ins of the last {checkin_hours}h within \
+                 {WHOHERE_RADIUS_KM}km of you, or of the place, e.g. whohere 41.38 2.17 10"
+            ),
+        ),
+        user(
+            "nick",
+            &[],
+            format!(
+                "nick <name>: a unique name up to {NICK_MAX_LEN} chars shown instead of your \
+                 short name, e.g. nick ann"
+            ),
+        ),
+        user("whoami", &[], "whoami: your name, node and channel"),
+        user(
+            "status",
+            &[],
+            "status: how many packets of your last reply were delivered",
+        ),
+        user(
+            "set",
+            &[],
+            format!(
+                "set <field> [text]: a field of your profile, cleared without text, e.g. \
+                 set location Barcelona. Up to {PROFILE_MAX_FIELDS} fields of \
+                 {PROFILE_VALUE_MAX_LEN} chars"
+            ),
+        ),
+        user(
+            "profile",
+            &[],
+            "profile [user]: your profile, or the one of a user by name or node",
+        ),
+        user(
+            "poll",
+            &[],
+            format!(
+                "poll new \"<question>\" <option>...: asks the channel, 2 to \
+                 {POLL_MAX_OPTIONS} one word options, e.g. poll new \"Hike?\" sat sun. \
+                 poll results <poll#> shows the votes"
+            ),
+        ),
+        user(
+            "vote",
+            &[],
+            "vote <poll#> <n>: votes the nth option, once per poll, e.g. vote 3 1",
+        ),
+        user(
+            "email",
+            &[],
+            format!(
+                "email <to> <subject> | <text>: emails up to {MAX_BODY} chars, e.g. \
+                 email ann@example.com Trail | Bridge is out"
+            ),
+        ),
+        user(
+            "mail",
+            &[],
+            format!("mail: your last {MAIL_MAX} private mails, newest first"),
+        ),
+        user("read", &[], "read <mail#>: the whole mail, e.g. read 4"),
+        user("files", &[], "files: the documents you can get"),
+        user(
+            "get",
+            &[],
+            "get <file#> [part]: the next part of the document, or the one given",
+        ),
+        user(
+            "notify",
+            &[],
+            "notify [on|off|mentions|mail-only]: what the board pushes to you",
+        ),
+        user("who", &[], "who: the users heard lately"),
+        user(
+            "where",
+            &[],
+            "where <node>: the last position of the node, e.g. where !a1b2c3d4",
+        ),
+        user(
+            "neighbors",
+            &[],
+            "neighbors <node>: the nodes the node hears and their SNR, e.g. neighbors !a1b2c3d4",
+        ),
+        user(
+            "wp",
+            &[],
+            "wp [list] | wp add <name> <lat> <lon>: the waypoints shared on the mesh, or \
+             shares one, e.g. wp add Base camp 41.38 2.17",
+        ),
+        user(
+            "fav",
+            &["unfav"],
+            "fav <channel>: lists the channel first, unfav <channel> undoes it",
+        ),
+        user(
+            "lang",
+            &[],
+            "lang [en|es]: the language of the help and replies",
+        ),
+        admin("mkchan", &[], "mkchan <channel>: creates the channel"),
+        admin(
+            "rmchan",
+            &[],
+            "rmchan <channel>: removes the channel and its posts",
+        ),
+        admin(
+            "ban",
+            &["unban"],
+            "ban <user>: drops the commands of the user, unban <user> undoes it",
+        ),
+        admin("banlist", &[], "banlist: the banned users"),
+        admin(
+            "purge",
+            &[],
+            "purge <channel>: removes the posts of the channel",
+        ),
+        admin("prune", &[], "prune: removes the posts past retention now"),
+        admin(
+            "stats",
+            &[],
+            "stats [delivery]: the totals of the board and what went on today, or the \
+             round trips of the texts to each node",
+        ),
+        admin(
+            "fleet",
+            &[],
+            "fleet: the nodes heard, with their hardware and firmware",
+        ),
+        admin(
+            "watch",
+            &["unwatch"],
+            "watch [node]: alerts the sysop node when the node goes silent or its battery \
+             runs low, the watched ones without node. unwatch <node> stops it",
+        ),
+        admin(
+            "announce",
+            &[],
+            "announce add <daily|mon..sun> <HH:MM> <targets> <text> | del <n> | list: \
+             scheduled texts, targets are channels and broadcast separated by commas, \
+             e.g. announce add sun 09:00 general,broadcast Net at 10",
+        ),
+        admin(
+            "telemetry",
+            &[],
+            "telemetry <node>: the last metrics of the node",
+        ),
+        admin("snapshot", &[], "snapshot: saves a backup of the board"),
+        admin(
+            "motd",
+            &[],
+            "motd [set <text>|reset]: the welcome text, {name} {channels} {unread} are replaced",
+        ),
+        admin(
+            "dmlog",
+            &[],
+            "dmlog [page]: the last direct messages the board got",
+        ),
+        admin(
+            "acl",
+            &[],
+            "acl <channel> [public|private|password <pw>|allow <user>|deny <user>]: who \
+             may use the channel",
+        ),
+        admin(
+            "broadcast",
+            &[],
+            "broadcast <text>: sends the text to the whole mesh",
+        ),
+        admin(
+            "sessions",
+            &[],
+            "sessions [reset <user>|all]: the active sessions, reset ends them",
+        ),
+    ]
+}
+
+/// The usage of the command, by name, shortcut or a command sharing it
+pub fn find(word: &str) -> Option<Usage> {
+    let name = command_name(word);
+    usages()
+        .into_iter()
+        .find(|usage| usage.name == name || usage.also.contains(&name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_usages() {
+        assert_eq!(find("p").map(|usage| usage.name), Some("post"));
+        assert!(find("ban").is_some_and(|usage| usage.admin));
+        assert_eq!(find("unfav").map(|usage| usage.name), Some("fav"));
+        assert!(find("weather").is_none());
+        assert!(find("mail").is_some_and(|usage| usage.text.contains(&MAIL_MAX.to_string())));
+        // Every shortcut of the parser has its usage
+        for (_, name) in crate::bbs::service::SHORTCUTS {
+            assert!(find(name).is_some(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alert {
+    /// Not heard for the duration
+    Silent { node: u32, since: Duration },
+    /// Heard again after a silent alert
+    Back { node: u32 },
+    /// Battery level, in percent, below the threshold
+    LowBattery { node: u32, level: u32 },
+}
+
+/// Raises alerts for watched nodes that go silent or run low on battery, once
+/// per incident
+pub struct Watchdog {
+    silence: Duration,
+    battery_min: u32,
+    started: Instant,
+    last_heard: HashMap<u32, Instant>,
+    silent: HashSet<u32>,
+    low_battery: HashSet<u32>,
+}
+
+impl Watchdog {
+    pub fn new(silence: Duration, battery_min: u32, now: Instant) -> Self {
+        Self {
+            silence,
+            battery_min,
+            started: now,
+            last_heard: HashMap::new(),
+            silent: HashSet::new(),
+            low_battery: HashSet::new(),
+        }
+    }
+
+    /// Any packet from the node
+    pub fn heard(&mut self, node: u32, now: Instant) -> Option<Alert> {
+        self.last_heard.insert(node, now);
+        self.silent.remove(&node).then_some(Alert::Back { node })
+    }
+
+    /// Battery telemetry from a watched node
+    pub fn battery(&mut self, node: u32, level: u32) -> Option<Alert> {
+        if level >= self.battery_min {
+            self.low_battery.remove(&node);
+            return None;
+        }
+        self.low_battery
+            .insert(node)
+            .then_some(Alert::LowBattery { node, level })
+    }
+
+    /// Watched nodes that went silent since the last check
+    pub fn check(&mut self, watched: &[u32], now: Instant) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for node in watched {
+            // Nodes never heard are counted from startup
+            let last_heard = self.last_heard.get(node).unwrap_or(&self.started);
+            let since = now.saturating_duration_since(*last_heard);
+            if since >= self.silence && self.silent.insert(*node) {
+                alerts.push(Alert::Silent { node: *node, since });
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_watchdog() {
+        let now = Instant::now();
+        let minute = Duration::from_secs(60);
+        let mut watchdog = Watchdog::new(10 * minute, 20, now);
+
+        assert_eq!(watchdog.heard(1, now), None);
+        assert!(watchdog.check(&[1, 2], now + 5 * minute).is_empty());
+        assert_eq!(
+            watchdog.check(&[1, 2], now + 10 * minute),
+            vec![
+                Alert::Silent {
+                    node: 1,
+                    since: 10 * minute
+                },
+                Alert::Silent {
+                    node: 2,
+                    since: 10 * minute
+                }
+            ]
+        );
+        // Once per incident
+        assert!(watchdog.check(&[1, 2], now + 20 * minute).is_empty());
+        assert_eq!(
+            watchdog.heard(1, now + 21 * minute),
+            Some(Alert::Back { node: 1 })
+        );
+
+        assert_eq!(
+            watchdog.battery(1, 15),
+            Some(Alert::LowBattery { node: 1, level: 15 })
+        );
+        assert_eq!(watchdog.battery(1, 10), None);
+        assert_eq!(watchdog.battery(1, 80), None);
+        assert!(watchdog.battery(1, 15).is_some());
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Result, bail};
+
+pub trait TextCodec: Send + Sync {
+    /// Applied to text leaving meshboard
+    fn encode(&self, text: &str) -> String;
+    /// Applied to text entering meshboard
+    fn decode(&self, text: &str) -> String {
+        text.to_string()
+    }
+}
+
+pub fn by_name(name: &str) -> Result<Box<dyn TextCodec>> {
+    match name {
+        "utf8" => Ok(Box::new(Utf8Codec {})),
+        "ascii" => Ok(Box::new(AsciiCodec {})),
+        "gsm7" => Ok(Box::new(Gsm7Codec {})),
+        _ => bail!("Unknown codec '{name}', use utf8, ascii or gsm7"),
+    }
+}
+
+fn transliterate(c: char) -> Option<&'static str> {
+    let s = match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' => "a",
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' => "A",
+        'ç' | 'ć' | 'č' => "c",
+        'Ç' | 'Ć' | 'Č' => "C",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => "e",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ę' | 'Ě' => "E",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => "i",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ī' => "I",
+        'ñ' | 'ń' | 'ň' => "n",
+        'Ñ' | 'Ń' | 'Ň' => "N",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' => "o",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' => "O",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' => "u",
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ū' | 'Ů' => "U",
+        'ý' | 'ÿ' => "y",
+        'Ý' | 'Ÿ' => "Y",
+        'ł' => "l",
+        'Ł' => "L",
+        'š' | 'ś' => "s",
+        'Š' | 'Ś' => "S",
+        'ž' | 'ź' | 'ż' => "z",
+        'Ž' | 'Ź' | 'Ż' => "Z",
+        'ß' => "ss",
+        'æ' => "ae",
+        'Æ' => "AE",
+        'œ' => "oe",
+        'Œ' => "OE",
+        '·' => ".",
+        '‘' | '’' | '´' | '`' => "'",
+        '“' | '”' | '«' | '»' => "\"",
+        '–' | '—' => "-",
+        '…' => "...",
+        '€' => "EUR",
+        _ => return None,
+    };
+    Some(s)
+}
+
+/// Leaves text untouched
+pub struct Utf8Codec {}
+impl TextCodec for Utf8Codec {
+    fn encode(&self, text: &str) -> String {
+        text.to_string()
+    }
+}
+
+/// Strips diacritics and replaces anything else outside ASCII with '?',
+/// useful for displays with ASCII-only fonts
+pub struct AsciiCodec {}
+impl TextCodec for AsciiCodec {
+    fn encode(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_ascii() {
+                out.push(c);
+            } else if let Some(s) = transliterate(c) {
+                out.push_str(s);
+            } else {
+                out.push('?');
+            }
+        }
+        out
+    }
+    fn decode(&self, text: &str) -> String {
+        self.encode(text)
+    }
+}
+
+const GSM7_BASIC: &str = "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+    ¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà^{}\\[~]|€";
+
+/// Restricts text to the GSM 03.38 alphabet, transliterating when possible,
+/// so that every character takes a single byte or septet
+pub struct Gsm7Codec {}
+impl TextCodec for Gsm7Codec {
+    fn encode(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if GSM7_BASIC.contains(c) {
+                out.push(c);
+            } else if let Some(s) = transliterate(c) {
+                out.push_str(s);
+            } else {
+                out.push('?');
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codecs() -> anyhow::Result<()> {
+        let text = "Canció d’en Pere 👋";
+        assert_eq!(by_name("utf8")?.encode(text), text);
+        assert_eq!(by_name("ascii")?.encode(text), "Cancio d'en Pere ?");
+        assert_eq!(by_name("gsm7")?.encode("àçé Ł"), "àcé L");
+        assert!(by_name("ebcdic").is_err());
+        Ok(())
+    }
+}
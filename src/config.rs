@@ -0,0 +1,561 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::bbs::{
+    self, i18n::Lang, retention::Retention, service::ChannelOrder, storage::Backend,
+    storage::UserPkHash,
+};
+use crate::logging::LogFormat;
+use crate::mesh;
+use crate::screen::{EpdOptions, Panel};
+
+/// Settings file loaded at start, values in the environment win
+pub const ENV_PATH: &str = ".env";
+
+/// Runtime settings, read from the environment (or the .env file)
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// BLE device names of the radios, comma separated, the primary first
+    pub ble_device: String,
+    /// Log lines as text or JSON
+    pub log_format: LogFormat,
+    /// Log levels, per target, e.g. `info,meshloop=debug`
+    pub log_filter: String,
+    /// Text codec applied to messages exchanged over the mesh
+    pub mesh_codec: String,
+    /// Text codec applied to text drawn on the display
+    pub display_codec: String,
+    /// Max bytes per mesh text, longer replies are chunked
+    pub max_payload: usize,
+    /// Delay between consecutive outgoing mesh texts
+    pub send_delay: Duration,
+    /// Delay between consecutive replies to the same node
+    pub reply_delay: Duration,
+    /// Percent of the hour the radio may transmit, 0 for the one of its region
+    pub duty_cycle_percent: f64,
+    /// Retransmissions of unacked direct texts
+    pub max_retries: u32,
+    /// Time to wait for an ack before retransmitting
+    pub ack_timeout: Duration,
+    /// Public key hashes of the BBS admins
+    pub admins: Vec<[u8; 32]>,
+    /// Commands a user can send at once
+    pub rate_limit_burst: u32,
+    /// Time for a user to earn one more command
+    pub rate_limit_refill: Duration,
+    /// Throttled commands in a row before muting a user, 0 never mutes
+    pub mute_after: u32,
+    /// How long a muted user is ignored
+    pub mute_duration: Duration,
+    /// Storage backend of the BBS
+    pub storage: Backend,
+    /// Database file, for file backed storages
+    pub db_path: String,
+    /// How the channels are listed, by name or by activity
+    pub channel_order: ChannelOrder,
+    /// File with the recurring broadcasts, see [crate::bbs::schedule::Entry]
+    pub schedule_path: String,
+    /// Bytes per hour scheduled broadcasts may use, 0 is unlimited
+    pub broadcast_budget: usize,
+    /// Where the snapshot admin command writes the tarballs
+    pub snapshot_dir: String,
+    /// Days direct messages are kept in the log, 0 keeps them forever
+    pub dm_log_days: u64,
+    /// Keep the MeshPacket of the texts sent to the board, for `decode`
+    pub raw_packets: bool,
+    /// Days the packets are kept, 0 keeps them forever
+    pub raw_packet_days: u64,
+    /// How many messages the channels keep, see [crate::bbs::retention]
+    pub retention: Retention,
+    /// Welcome text for new users, empty disables it
+    pub motd: String,
+    /// Days before a returning user is welcomed again, 0 only welcomes new users
+    pub motd_after_days: u64,
+    /// Longest text the BBS accepts, in chars, 0 is unlimited
+    pub max_message_len: usize,
+    /// Only take commands sent as PKC encrypted direct messages
+    pub require_pki: bool,
+    /// New users reply with a code before they can post
+    pub verify_new_users: bool,
+    /// Sessions last this long after the last command, also across restarts
+    pub session_ttl: Duration,
+    /// Language of the help and replies until users pick theirs
+    pub language: Lang,
+    /// Texts matching it are dropped, e.g. `(?i)free btc|t\.me/`
+    pub deny_pattern: Option<Regex>,
+    /// Built-in plugin commands enabled, e.g. `ping,dice`
+    pub plugins: Vec<String>,
+    /// Fixed replies to commands, (name, reply)
+    pub canned: Vec<(String, String)>,
+    /// Where the `*.rhai` command scripts are, with the scripts feature
+    pub commands_dir: String,
+    /// Hours polls take votes
+    pub poll_hours: u64,
+    /// Seconds each screen page is shown before the next, 0 only changes
+    /// page with the button
+    pub page_secs: u64,
+    /// BCM number of the GPIO with the page button, 0 is none
+    pub page_button_gpio: u64,
+    /// E-paper panel model
+    pub epd_panel: Panel,
+    /// SPI device of the e-paper
+    pub epd_spi: String,
+    /// Sysfs number of the first GPIO
+    pub epd_gpio_base: u64,
+    /// BCM numbers of the e-paper pins
+    pub epd_cs_pin: u64,
+    pub epd_busy_pin: u64,
+    pub epd_dc_pin: u64,
+    pub epd_rst_pin: u64,
+    /// Where to capture the packets received, empty disables the capture
+    pub capture_dir: String,
+    /// MB of packets per capture file, 0 is unlimited
+    pub capture_max_file_mb: u64,
+    /// MB on disk of all the capture files, 0 is unlimited
+    pub capture_max_total_mb: u64,
+    /// Gzip the capture files
+    pub capture_compress: bool,
+    /// Seconds without packets from the radio before reconnecting, 0 never
+    pub radio_idle_secs: u64,
+    /// Seconds without packets from the radio before probing it, 0 never
+    pub radio_keepalive_secs: u64,
+    /// Minutes without hearing a node before direct texts to it are held
+    /// until it is back, 0 never holds them
+    pub offline_after_mins: u64,
+    /// MQTT broker to relay the BBS to, empty disables the bridge
+    pub mqtt_host: String,
+    pub mqtt_port: u16,
+    pub mqtt_user: String,
+    pub mqtt_password: String,
+    /// Prefix of the MQTT topics
+    pub mqtt_topic: String,
+    /// Accept posts published to `<topic>/in/<channel>`
+    pub mqtt_inbound: bool,
+    /// Relay the BBS to a Telegram chat, see [crate::telegram]
+    pub telegram: bool,
+    pub telegram_token: String,
+    pub telegram_chat_id: i64,
+    /// Channel where the Telegram replies are posted
+    pub telegram_channel: String,
+    /// Callsign gating the BBS to APRS-IS, with the aprs feature, empty
+    /// disables it. See [crate::aprs]
+    pub aprs_callsign: String,
+    /// APRS-IS passcode of the callsign, -1 is none
+    pub aprs_passcode: i32,
+    /// APRS-IS server, `host:port`
+    pub aprs_server: String,
+    /// Path of our packets, e.g. `TCPIP*`
+    pub aprs_path: String,
+    /// Addressee of the posts, e.g. the group bulletin `BLN1MESH`
+    pub aprs_to: String,
+    /// Channels whose posts go to APRS-IS
+    pub aprs_channels: Vec<String>,
+    /// Position beaconed for the BBS, the one of the radio when None
+    pub aprs_position: Option<(f64, f64)>,
+    /// Minutes between beacons, 0 disables them
+    pub aprs_beacon_mins: u64,
+    /// Report the positions heard on the mesh, of the nodes that let their
+    /// packets go to MQTT
+    pub aprs_nodes: bool,
+    /// Where the `weather` command forecasts for, (lat, lon), None disables it
+    pub weather_location: Option<(f64, f64)>,
+    /// Days in the forecast
+    pub weather_days: u32,
+    /// Hours between forecast updates
+    pub weather_hours: u64,
+    /// Relay the channels to Matrix rooms, see [crate::matrix]
+    pub matrix: bool,
+    pub matrix_homeserver: String,
+    pub matrix_token: String,
+    /// Rooms of the channels, besides the ones the bridge created
+    pub matrix_rooms: crate::matrix::Rooms,
+    /// Users invited to the rooms the bridge creates
+    pub matrix_invite: Vec<String>,
+    /// Let users email from the mesh, with the email feature. See
+    /// [crate::email]
+    pub email: bool,
+    pub email_smtp_host: String,
+    pub email_smtp_port: u16,
+    pub email_imap_host: String,
+    pub email_imap_port: u16,
+    pub email_user: String,
+    pub email_password: String,
+    /// Address the emails are sent from, and replied to
+    pub email_from: String,
+    /// Addresses, or domains as `@example.com`, users may email
+    pub email_allow: Vec<String>,
+    /// Emails per user and day
+    pub email_daily_quota: u32,
+    /// Seconds between checks for replies
+    pub email_poll_secs: u64,
+    /// Node ids of the boards the channels sync with, with the hash of their
+    /// public key, see [bbs::federation]
+    pub federation_peers: BTreeMap<u32, [u8; 32]>,
+    /// Channels synced with the peers
+    pub federation_channels: Vec<String>,
+    /// Minutes between sync requests to the peers
+    pub federation_sync_mins: u64,
+    /// Channel where the stats of the day before are posted at midnight,
+    /// empty disables it
+    pub digest_channel: String,
+    /// Node that gets the watch alerts over the mesh, by short name or id
+    pub sysop_node: String,
+    /// Alert when a watched node is not heard for this long
+    pub watch_silence: Duration,
+    /// Alert when a watched node reports a lower battery level, in percent
+    pub watch_battery: u32,
+}
+
+fn var_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse()
+            .map_err(|err| anyhow!("Invalid {name}={value}: {err}")),
+        _ => Ok(default),
+    }
+}
+
+/// Sets the variable in the settings file, replacing its line if it has one.
+/// A file that can not be read is left alone.
+pub fn save_var(path: &Path, name: &str, value: &str) -> Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(anyhow!("Cannot read {}: {err}", path.display())),
+    };
+    fs::write(path, with_var(&content, name, value))?;
+    Ok(())
+}
+
+// Name of the variable the line sets, exported or not
+fn var_name(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once('=')?;
+    let key = key.trim();
+    Some(key.strip_prefix("export ").map_or(key, str::trim_start))
+}
+
+fn with_var(content: &str, name: &str, value: &str) -> String {
+    let line = format!("{name}={value}");
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    match lines
+        .iter_mut()
+        .find(|existing| var_name(existing) == Some(name))
+    {
+        Some(existing) if existing.trim_start().starts_with("export ") => {
+            *existing = format!("export {line}")
+        }
+        Some(existing) => *existing = line,
+        None => lines.push(line),
+    }
+    lines.join("\n") + "\n"
+}
+
+fn pk_hashes(name: &str) -> Result<Vec<[u8; 32]>> {
+    let value = env::var(name).unwrap_or_default();
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            hex::decode(s)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| anyhow!("Invalid {name}, expected hex pk hashes: {s}"))
+        })
+        .collect()
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            ble_device: var_or("BLE_DEVICE", String::new())?,
+            log_format: var_or("LOG_FORMAT", LogFormat::Text)?,
+            log_filter: var_or("LOG_FILTER", "info".to_string())?,
+            mesh_codec: var_or("MESH_CODEC", "utf8".to_string())?,
+            display_codec: var_or("DISPLAY_CODEC", "ascii".to_string())?,
+            max_payload: var_or("MAX_PAYLOAD", 200)?,
+            send_delay: Duration::from_millis(var_or("SEND_DELAY_MS", 1000)?),
+            reply_delay: Duration::from_millis(var_or("REPLY_DELAY_MS", 2000)?),
+            duty_cycle_percent: var_or("DUTY_CYCLE_PERCENT", 0.0)?,
+            max_retries: var_or("MAX_RETRIES", 3)?,
+            ack_timeout: Duration::from_secs(var_or("ACK_TIMEOUT_SECS", 30)?),
+            admins: pk_hashes("ADMINS")?,
+            rate_limit_burst: var_or("RATE_LIMIT_BURST", 5)?,
+            rate_limit_refill: Duration::from_secs(var_or("RATE_LIMIT_REFILL_SECS", 30)?),
+            mute_after: var_or("MUTE_AFTER", 5)?,
+            mute_duration: Duration::from_secs(var_or("MUTE_SECS", 600)?),
+            storage: var_or("STORAGE", Backend::NativeDb)?,
+            db_path: var_or("DB_PATH", "./meshboard.db".to_string())?,
+            channel_order: var_or("CHANNEL_ORDER", ChannelOrder::Name)?,
+            schedule_path: var_or("SCHEDULE_PATH", "./meshboard.schedule".to_string())?,
+            broadcast_budget: var_or("BROADCAST_BUDGET_BYTES", 1000)?,
+            snapshot_dir: var_or("SNAPSHOT_DIR", "./snapshots".to_string())?,
+            dm_log_days: var_or("DM_LOG_DAYS", 30)?,
+            raw_packets: var_or("RAW_PACKETS", false)?,
+            raw_packet_days: var_or("RAW_PACKET_DAYS", 30)?,
+            retention: Retention {
+                default: var_or("RETENTION", Default::default())?,
+                channels: bbs::retention::parse_channels(
+                    &env::var("RETENTION_CHANNELS").unwrap_or_default(),
+                )?,
+            },
+            // Set but empty disables the welcome
+            motd: env::var("MOTD").unwrap_or_else(|_| {
+                "Welcome {name}! Channels: {channels}, {unread} unread. Send h for help".to_string()
+            }),
+            motd_after_days: var_or("MOTD_AFTER_DAYS", 30)?,
+            max_message_len: var_or("MAX_MESSAGE_LEN", 0)?,
+            require_pki: var_or("REQUIRE_PKI", false)?,
+            verify_new_users: var_or("VERIFY_NEW_USERS", false)?,
+            session_ttl: Duration::from_secs(60 * var_or("SESSION_TTL_MINS", 60)?),
+            language: var_or("LANGUAGE", Lang::En)?,
+            deny_pattern: match env::var("DENY_PATTERN").unwrap_or_default() {
+                pattern if pattern.is_empty() => None,
+                pattern => Some(
+                    Regex::new(&pattern)
+                        .map_err(|err| anyhow!("Invalid DENY_PATTERN={pattern}: {err}"))?,
+                ),
+            },
+            plugins: bbs::plugins::parse_builtins(
+                &env::var("PLUGINS").unwrap_or_else(|_| "ping,dice".to_string()),
+            )?,
+            canned: bbs::plugins::parse_canned(&env::var("CANNED").unwrap_or_default())?,
+            commands_dir: var_or("COMMANDS_DIR", "./commands".to_string())?,
+            poll_hours: var_or("POLL_HOURS", 24)?,
+            page_secs: var_or("PAGE_SECS", 30)?,
+            page_button_gpio: var_or("PAGE_BUTTON_GPIO", 0)?,
+            epd_panel: var_or("EPD_PANEL", Panel::Epd2in13V2)?,
+            epd_spi: var_or("EPD_SPI", "/dev/spidev0.0".to_string())?,
+            epd_gpio_base: var_or("EPD_GPIO_BASE", 512)?,
+            epd_cs_pin: var_or("EPD_CS_PIN", 26)?,
+            epd_busy_pin: var_or("EPD_BUSY_PIN", 24)?,
+            epd_dc_pin: var_or("EPD_DC_PIN", 25)?,
+            epd_rst_pin: var_or("EPD_RST_PIN", 17)?,
+            capture_dir: var_or("CAPTURE_DIR", String::new())?,
+            capture_max_file_mb: var_or("CAPTURE_MAX_FILE_MB", 16)?,
+            capture_max_total_mb: var_or("CAPTURE_MAX_TOTAL_MB", 512)?,
+            capture_compress: var_or("CAPTURE_COMPRESS", true)?,
+            radio_idle_secs: var_or("RADIO_IDLE_SECS", 300)?,
+            radio_keepalive_secs: var_or("RADIO_KEEPALIVE_SECS", 60)?,
+            offline_after_mins: var_or("OFFLINE_AFTER_MINS", 120)?,
+            mqtt_host: var_or("MQTT_HOST", String::new())?,
+            mqtt_port: var_or("MQTT_PORT", 1883)?,
+            mqtt_user: var_or("MQTT_USER", String::new())?,
+            mqtt_password: var_or("MQTT_PASSWORD", String::new())?,
+            mqtt_topic: var_or("MQTT_TOPIC", "meshboard".to_string())?,
+            mqtt_inbound: var_or("MQTT_INBOUND", false)?,
+            telegram: var_or("TELEGRAM", false)?,
+            telegram_token: var_or("TELEGRAM_TOKEN", String::new())?,
+            telegram_chat_id: var_or("TELEGRAM_CHAT_ID", 0)?,
+            telegram_channel: var_or("TELEGRAM_CHANNEL", "general".to_string())?,
+            aprs_callsign: var_or("APRS_CALLSIGN", String::new())?,
+            aprs_passcode: var_or("APRS_PASSCODE", -1)?,
+            aprs_server: var_or("APRS_SERVER", "rotate.aprs2.net:14580".to_string())?,
+            aprs_path: var_or("APRS_PATH", "TCPIP*".to_string())?,
+            aprs_to: var_or("APRS_TO", "BLN1MESH".to_string())?,
+            aprs_channels: env::var("APRS_CHANNELS")
+                .unwrap_or_else(|_| "general".to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|channel| !channel.is_empty())
+                .map(str::to_string)
+                .collect(),
+            aprs_position: match env::var("APRS_POSITION").unwrap_or_default() {
+                position if position.is_empty() => None,
+                position => Some(crate::weather::parse_location(&position)?),
+            },
+            aprs_beacon_mins: var_or("APRS_BEACON_MINS", 30)?,
+            aprs_nodes: var_or("APRS_NODES", false)?,
+            weather_location: match env::var("WEATHER_LOCATION").unwrap_or_default() {
+                location if location.is_empty() => None,
+                location => Some(crate::weather::parse_location(&location)?),
+            },
+            weather_days: var_or("WEATHER_DAYS", 3)?,
+            weather_hours: var_or("WEATHER_HOURS", 3)?,
+            matrix: var_or("MATRIX", false)?,
+            matrix_homeserver: var_or("MATRIX_HOMESERVER", "https://matrix.org".to_string())?,
+            matrix_token: var_or("MATRIX_TOKEN", String::new())?,
+            matrix_rooms: crate::matrix::parse_rooms(
+                &env::var("MATRIX_ROOMS").unwrap_or_default(),
+            )?,
+            matrix_invite: env::var("MATRIX_INVITE")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|user| !user.is_empty())
+                .map(str::to_string)
+                .collect(),
+            email: var_or("EMAIL", false)?,
+            email_smtp_host: var_or("EMAIL_SMTP_HOST", String::new())?,
+            email_smtp_port: var_or("EMAIL_SMTP_PORT", 465)?,
+            email_imap_host: var_or("EMAIL_IMAP_HOST", String::new())?,
+            email_imap_port: var_or("EMAIL_IMAP_PORT", 993)?,
+            email_user: var_or("EMAIL_USER", String::new())?,
+            email_password: var_or("EMAIL_PASSWORD", String::new())?,
+            email_from: var_or("EMAIL_FROM", String::new())?,
+            email_allow: env::var("EMAIL_ALLOW")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|allowed| !allowed.is_empty())
+                .map(str::to_string)
+                .collect(),
+            email_daily_quota: var_or("EMAIL_DAILY_QUOTA", 5)?,
+            email_poll_secs: var_or("EMAIL_POLL_SECS", 120)?,
+            federation_peers: env::var("FEDERATION_PEERS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|peer| !peer.is_empty())
+                .map(|peer| {
+                    let invalid = || {
+                        anyhow!("Invalid FEDERATION_PEERS, expected <node id>=<public key>: {peer}")
+                    };
+                    let (node, key) = peer.split_once('=').ok_or_else(invalid)?;
+                    let node = mesh::service::parse_node_id(node.trim()).ok_or_else(invalid)?;
+                    let key = BASE64_STANDARD.decode(key.trim()).map_err(|_| invalid())?;
+                    Ok((node, Sha256::digest(&key).into()))
+                })
+                .collect::<Result<_>>()?,
+            federation_channels: env::var("FEDERATION_CHANNELS")
+                .unwrap_or_else(|_| "general".to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|channel| !channel.is_empty())
+                .map(str::to_string)
+                .collect(),
+            federation_sync_mins: var_or("FEDERATION_SYNC_MINS", 30)?,
+            digest_channel: var_or("DIGEST_CHANNEL", String::new())?,
+            sysop_node: var_or("SYSOP_NODE", String::new())?,
+            watch_silence: Duration::from_secs(60 * var_or("WATCH_SILENCE_MINS", 60)?),
+            watch_battery: var_or("WATCH_BATTERY_PCT", 20)?,
+        })
+    }
+
+    /// Config files included in the snapshots
+    pub fn snapshot_files(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(".env"), PathBuf::from(&self.schedule_path)]
+    }
+
+    pub fn mesh_options(&self) -> mesh::service::Options {
+        mesh::service::Options {
+            max_payload: self.max_payload,
+            send_delay: self.send_delay,
+            max_retries: self.max_retries,
+            ack_timeout: self.ack_timeout,
+            capture: (!self.capture_dir.is_empty()).then(|| mesh::service::CaptureOptions {
+                dir: PathBuf::from(&self.capture_dir),
+                max_file_bytes: self.capture_max_file_mb * 1024 * 1024,
+                max_total_bytes: self.capture_max_total_mb * 1024 * 1024,
+                compress: self.capture_compress,
+            }),
+            idle_timeout: Duration::from_secs(self.radio_idle_secs),
+            keepalive: Duration::from_secs(self.radio_keepalive_secs),
+            offline_after: Duration::from_secs(self.offline_after_mins * 60),
+            duty_cycle: (self.duty_cycle_percent > 0.0).then_some(self.duty_cycle_percent),
+        }
+    }
+
+    pub fn epd_options(&self) -> EpdOptions {
+        EpdOptions {
+            panel: self.epd_panel,
+            spi: self.epd_spi.clone(),
+            gpio_base: self.epd_gpio_base,
+            cs: self.epd_cs_pin,
+            busy: self.epd_busy_pin,
+            dc: self.epd_dc_pin,
+            rst: self.epd_rst_pin,
+            button: (self.page_button_gpio > 0).then_some(self.page_button_gpio),
+        }
+    }
+
+    pub fn bbs_options(&self) -> bbs::service::Options {
+        let mut plugins = bbs::plugins::Registry::new(&self.plugins, &self.canned);
+        if self.weather_location.is_some() {
+            plugins.register(Arc::new(crate::weather::Weather));
+        }
+        #[cfg(feature = "scripts")]
+        for command in bbs::scripts::load_dir(Path::new(&self.commands_dir)) {
+            plugins.register(command);
+        }
+        bbs::service::Options {
+            admins: self.admins.iter().cloned().map(UserPkHash).collect(),
+            rate_limit_burst: self.rate_limit_burst,
+            rate_limit_refill: self.rate_limit_refill,
+            mute_after: self.mute_after,
+            mute_duration: self.mute_duration,
+            channel_order: self.channel_order,
+            snapshot_dir: PathBuf::from(&self.snapshot_dir),
+            snapshot_files: self.snapshot_files(),
+            dm_log_max_age: Duration::from_secs(self.dm_log_days * 24 * 60 * 60),
+            retention: self.retention.clone(),
+            motd: self.motd.clone(),
+            motd_after: Duration::from_secs(self.motd_after_days * 24 * 60 * 60),
+            max_message_len: self.max_message_len,
+            require_pki: self.require_pki,
+            verify_new_users: self.verify_new_users,
+            session_ttl: self.session_ttl,
+            language: self.language,
+            deny_pattern: self.deny_pattern.clone(),
+            plugins,
+            poll_duration: Duration::from_secs(self.poll_hours * 60 * 60),
+            federation: (!self.federation_peers.is_empty()).then(|| {
+                bbs::federation::FederationOptions {
+                    peers: self.federation_peers.clone(),
+                    channels: self.federation_channels.clone(),
+                    max_payload: self.max_payload,
+                }
+            }),
+            email: (cfg!(feature = "email") && self.email).then(|| bbs::mailbox::EmailOptions {
+                allow: self.email_allow.clone(),
+                daily_quota: self.email_daily_quota,
+            }),
+            raw_packets: self
+                .raw_packets
+                .then(|| Duration::from_secs(self.raw_packet_days * 24 * 60 * 60)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_with_var() {
+        assert_eq!(with_var("", "BLE_DEVICE", "radio"), "BLE_DEVICE=radio\n");
+        assert_eq!(
+            with_var("# Radio\nBLE_DEVICE=\nMAX_RETRIES=3", "BLE_DEVICE", "radio"),
+            "# Radio\nBLE_DEVICE=radio\nMAX_RETRIES=3\n"
+        );
+        assert_eq!(
+            with_var("MAX_RETRIES=3\n", "BLE_DEVICE", "radio"),
+            "MAX_RETRIES=3\nBLE_DEVICE=radio\n"
+        );
+        assert_eq!(
+            with_var("export BLE_DEVICE=old\n", "BLE_DEVICE", "radio"),
+            "export BLE_DEVICE=radio\n"
+        );
+        assert_eq!(
+            with_var("EXPORT_DIR=x\n", "DIR", "radio"),
+            "EXPORT_DIR=x\nDIR=radio\n"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use lettre::{
+    Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::Mailbox,
+    transport::smtp::authentication::Credentials,
+};
+use mail_parser::MessageParser;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+use tokio_rustls::{
+    TlsConnector,
+    client::TlsStream,
+    rustls::{ClientConfig, RootCertStore, crypto::ring, pki_types::ServerName},
+};
+use tracing::{info, warn};
+
+use crate::bbs::mailbox::{self, IncomingEmail, OutgoingEmail};
+use crate::config::Config;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+// Most of an email fetched, replies are read from its start
+const MAX_EMAIL_SIZE: usize = 64 * 1024;
+
+/// `"text"`, escaped as an IMAP quoted string
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Size of the literal that follows the line, for lines ending in `{n}`
+fn literal_size(line: &str) -> Option<usize> {
+    let (_, size) = line.strip_suffix('}')?.rsplit_once('{')?;
+    size.parse().ok()
+}
+
+/// The sender, subject and text of a raw email, None if it has no sender
+/// or no text
+fn parse(raw: &[u8]) -> Option<IncomingEmail> {
+    let message = MessageParser::default().parse(raw)?;
+    let from = message.from()?.first()?.address()?.to_string();
+    Some(IncomingEmail {
+        from,
+        subject: message.subject().unwrap_or_default().to_string(),
+        text: message.body_text(0)?.to_string(),
+    })
+}
+
+/// Just enough IMAP over TLS to read the unseen emails of the inbox
+struct Imap {
+    stream: BufReader<TlsStream<TcpStream>>,
+    tag: u32,
+}
+
+impl Imap {
+    async fn connect(host: &str, port: u16) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let tls = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let tcp = TcpStream::connect((host, port)).await?;
+        let stream = TlsConnector::from(Arc::new(tls))
+            .connect(ServerName::try_from(host.to_string())?, tcp)
+            .await?;
+        let mut imap = Self {
+            stream: BufReader::new(stream),
+            tag: 0,
+        };
+        let greeting = imap.read_line().await?;
+        if !greeting.starts_with("* OK") {
+            bail!("Unexpected IMAP greeting: {greeting}");
+        }
+        Ok(imap)
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        if self.stream.read_until(b'\n', &mut line).await? == 0 {
+            bail!("Disconnected");
+        }
+        Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+    }
+
+    /// Runs the command, returning its untagged responses with the
+    /// literals of each
+    async fn command(&mut self, command: &str) -> Result<Vec<(String, Vec<Vec<u8>>)>> {
+        self.tag += 1;
+        let tag = format!("a{} ", self.tag);
+        self.stream
+            .get_mut()
+            .write_all(format!("{tag}{command}\r\n").as_bytes())
+            .await?;
+        // Not the whole command, it may have the password
+        let name = command.split_whitespace().next().unwrap_or_default();
+        let mut responses = Vec::new();
+        loop {
+            let mut line = self.read_line().await?;
+            if let Some(status) = line.strip_prefix(&tag) {
+                if !status.starts_with("OK") {
+                    bail!("IMAP {name} failed: {status}");
+                }
+                return Ok(responses);
+            }
+            let mut literals = Vec::new();
+            while let Some(size) = literal_size(&line) {
+                if size > MAX_EMAIL_SIZE {
+                    bail!("IMAP {name} sent {size} bytes");
+                }
+                let mut literal = vec![0; size];
+                self.stream.read_exact(&mut literal).await?;
+                literals.push(literal);
+                line.push_str(&self.read_line().await?);
+            }
+            responses.push((line, literals));
+        }
+    }
+}
+
+/// Reads the unseen emails of the inbox, marking them as seen
+async fn fetch_unseen(config: &Config) -> Result<Vec<IncomingEmail>> {
+    let mut imap = Imap::connect(&config.email_imap_host, config.email_imap_port).await?;
+    imap.command(&format!(
+        "LOGIN {} {}",
+        quote(&config.email_user),
+        quote(&config.email_password)
+    ))
+    .await?;
+    imap.command("SELECT INBOX").await?;
+    let uids: Vec<u32> = imap
+        .command("UID SEARCH UNSEEN")
+        .await?
+        .iter()
+        .filter_map(|(line, _)| line.strip_prefix("* SEARCH"))
+        .flat_map(|uids| uids.split_whitespace().filter_map(|uid| uid.parse().ok()))
+        .collect();
+    let mut emails = Vec::new();
+    for uid in uids {
+        let raw = imap
+            .command(&format!("UID FETCH {uid} BODY.PEEK[]<0.{MAX_EMAIL_SIZE}>"))
+            .await?
+            .into_iter()
+            .find_map(|(_, literals)| literals.into_iter().next());
+        match raw.as_deref().and_then(parse) {
+            Some(email) => emails.push(email),
+            None => warn!("Skipped email {uid}, it has no sender or text"),
+        }
+        imap.command(&format!("UID STORE {uid} +FLAGS (\\Seen)"))
+            .await?;
+    }
+    // The emails are read already
+    let _ = imap.command("LOGOUT").await;
+    Ok(emails)
+}
+
+async fn send(
+    smtp: &AsyncSmtpTransport<Tokio1Executor>,
+    from: &Address,
+    email: &OutgoingEmail,
+) -> Result<()> {
+    let message = Message::builder()
+        .from(Mailbox::new(
+            Some(format!("{} via MeshBoard", email.author)),
+            from.clone(),
+        ))
+        .reply_to(Mailbox::new(None, from.clone()))
+        .to(email.to.parse()?)
+        .subject(format!("{} {}", email.subject, email.tag))
+        .body(format!(
+            "{}\n\n-- \nSent by {} from the mesh, reply to answer",
+            email.body, email.author
+        ))?;
+    smtp.send(message).await?;
+    Ok(())
+}
+
+/// Sends the emails of the users through `EMAIL_SMTP_HOST` as `EMAIL_FROM`,
+/// and checks `EMAIL_IMAP_HOST` every `EMAIL_POLL_SECS` for the replies.
+/// Runs until the BBS is gone.
+pub async fn run_gateway(
+    config: Config,
+    mut email_rx: UnboundedReceiver<OutgoingEmail>,
+    mail_tx: UnboundedSender<IncomingEmail>,
+) -> Result<()> {
+    if config.email_smtp_host.is_empty() || config.email_imap_host.is_empty() {
+        bail!("The email gateway needs EMAIL_SMTP_HOST and EMAIL_IMAP_HOST");
+    }
+    let from: Address = config
+        .email_from
+        .parse()
+        .map_err(|err| anyhow!("Invalid EMAIL_FROM={}: {err}", config.email_from))?;
+    // Port 465 is TLS from the start, others upgrade with STARTTLS
+    let smtp = match config.email_smtp_port {
+        465 => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.email_smtp_host)?,
+        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.email_smtp_host)?,
+    }
+    .port(config.email_smtp_port)
+    .credentials(Credentials::new(
+        config.email_user.clone(),
+        config.email_password.clone(),
+    ))
+    .timeout(Some(TIMEOUT))
+    .build();
+    info!("Email gateway as {from}");
+
+    let mut poll_interval =
+        tokio::time::interval(Duration::from_secs(config.email_poll_secs.max(10)));
+    loop {
+        tokio::select! {
+            email = email_rx.recv() => {
+                let Some(email) = email else {
+                    return Ok(());
+                };
+                match send(&smtp, &from, &email).await {
+                    Ok(()) => info!("Emailed {} for {}", email.to, email.author),
+                    Err(err) => warn!("Cannot email {} for {}: {err}", email.to, email.author),
+                }
+            }
+            _ = poll_interval.tick() => {
+                let emails = match tokio::time::timeout(TIMEOUT * 2, fetch_unseen(&config)).await {
+                    Ok(Ok(emails)) => emails,
+                    Ok(Err(err)) => {
+                        warn!("Cannot check the email: {err}");
+                        continue;
+                    }
+                    Err(_) => {
+                        warn!("Cannot check the email: timed out");
+                        continue;
+                    }
+                };
+                for email in emails {
+                    if mail_tx.send(email).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_email() {
+        assert_eq!(quote(r#"pa"ss\word"#), r#""pa\"ss\\word""#);
+        assert_eq!(
+            literal_size("* 1 FETCH (UID 7 BODY[]<0> {1234}"),
+            Some(1234)
+        );
+        assert_eq!(literal_size("* 1 FETCH (UID 7 FLAGS ())"), None);
+
+        let raw = b"From: Ann <ann@example.com>\r\n\
+            To: bbs@example.org\r\n\
+            Subject: Re: Trail [mb12]\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            \r\n\
+            Thanks!\r\n\
+            > Bridge is out\r\n";
+        assert_eq!(
+            parse(raw),
+            Some(IncomingEmail {
+                from: "ann@example.com".into(),
+                subject: "Re: Trail [mb12]".into(),
+                text: "Thanks!\r\n> Bridge is out\r\n".into(),
+            })
+        );
+        assert_eq!(parse(b"Subject: hi\r\n\r\nhi\r\n"), None);
+    }
+}
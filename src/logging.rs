@@ -0,0 +1,45 @@
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use tracing_subscriber::EnvFilter;
+
+/// How the log lines are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with the fields of the event and its spans,
+    /// for journald or Loki
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => bail!("unknown log format {other}, expected text or json"),
+        }
+    }
+}
+
+/// Sets up the logger. `filter` takes `RUST_LOG` style directives, e.g.
+/// `info,meshloop=debug,storage=warn`, and `RUST_LOG` wins when set. The
+/// board logs under the targets `meshloop`, `bbs`, `storage` and `screen`.
+pub fn init(format: LogFormat, filter: &str) -> Result<()> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(env) if !env.is_empty() => env,
+        _ => filter.to_string(),
+    };
+    let filter =
+        EnvFilter::try_new(&filter).map_err(|err| anyhow!("Invalid log filter {filter}: {err}"))?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+    .map_err(|err| anyhow!(err))
+}
@@ -1,16 +1,38 @@
 //! This example connects via Bluetooth LE to the radio and prints out all received packets.
 #[allow(unused)]
 use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
+use crate::bbs::storage::{Backend, Storage};
+use crate::config::Config;
+use crate::mesh::service::{Service, Transport, parse_node_id};
 use crate::screen::NoScreen;
+use crate::screen::term::TermScreen;
 
+#[cfg(feature = "aprs")]
+mod aprs;
 mod bbs;
+mod codec;
+mod config;
+#[cfg(feature = "email")]
+mod email;
+mod logging;
+mod matrix;
 mod mesh;
+mod mqtt;
 mod screen;
+mod selftest;
+mod send;
+mod sysinfo;
+mod telegram;
 mod tool;
+mod tui;
+mod watch;
+mod weather;
 
 include!(concat!(env!("OUT_DIR"), "/build_info.rs"));
 
@@ -22,44 +44,376 @@ struct Cli {
     command: Commands,
 }
 
+/// Where the board shows what it is doing
+#[derive(Clone, Copy, ValueEnum)]
+enum DisplayKind {
+    /// The e-paper HAT, see EPD_PANEL
+    Epd,
+    None,
+    /// Drawn in the terminal, as the e-paper would show it
+    Term,
+}
+
+#[derive(Args, Default)]
+struct StartArgs {
+    /// Storage backend: native_db or memory
+    #[arg(long)]
+    storage: Option<Backend>,
+    /// Database file, DB_PATH by default
+    #[arg(long)]
+    db: Option<String>,
+    /// BLE device names, comma separated, BLE_DEVICE by default
+    #[arg(long)]
+    device: Option<String>,
+    /// Log levels, LOG_FILTER by default, e.g. `info,meshloop=debug`
+    #[arg(long)]
+    log_level: Option<String>,
+    /// Relay posts and direct messages to the TELEGRAM_CHAT_ID chat
+    #[arg(long)]
+    telegram: bool,
+}
+
+impl StartArgs {
+    fn apply(&self, config: &mut Config) {
+        if let Some(storage) = self.storage {
+            config.storage = storage;
+        }
+        if let Some(db) = &self.db {
+            config.db_path = db.clone();
+        }
+        if let Some(device) = &self.device {
+            config.ble_device = device.clone();
+        }
+        if let Some(log_level) = &self.log_level {
+            config.log_filter = log_level.clone();
+        }
+        config.telegram |= self.telegram;
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
-    /// Display test
-    Start,
-    /// Display test
-    StartNoDisplay,
+    /// Run the BBS
+    Start {
+        #[command(flatten)]
+        args: StartArgs,
+        #[arg(long, value_enum, default_value = "epd")]
+        display: DisplayKind,
+    },
+    /// Run the BBS without a display, same as `start --display none`
+    StartNoDisplay(StartArgs),
     /// Run REPL utility
-    MeshTool,
+    MeshTool {
+        /// Allow the `admin` commands, which reboot and reconfigure radios
+        #[arg(long)]
+        enable_admin: bool,
+    },
+    /// Check the radio and the BBS end to end, exits with 1 on failure
+    SelfTest {
+        /// Short name of a known node to message
+        peer: String,
+        /// Seconds to wait for the ack or echo
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Send a text and exit: 0 once sent (or acked), 1 if it failed, 2 on timeout
+    Send {
+        /// Short name, nickname or !hex id of the node, or broadcast
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        text: String,
+        /// Wait for the node to ack it, broadcasts are not acked
+        #[arg(long)]
+        wait_ack: bool,
+        /// Seconds to wait for it to go out, and for the ack
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Write a tarball with the BBS records and config files, with the board stopped
+    Snapshot {
+        /// Tarball to write
+        out: String,
+    },
+    /// Manage the text documents users list with `files`, with the board stopped
+    Files {
+        #[command(subcommand)]
+        action: FilesAction,
+    },
+    /// Write the mesh graph the nodes told with NeighborInfo, with the board stopped
+    Graph {
+        /// File to write, JSON if it ends in .json and Graphviz DOT otherwise
+        #[arg(long, default_value = "mesh.dot")]
+        out: String,
+    },
+    /// Decode a packet kept with RAW_PACKETS, with the board stopped
+    Decode {
+        /// Packet id, decimal or !hex
+        id: String,
+        /// Print it as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Load a snapshot tarball into an empty database, config files go to the current dir
+    Restore {
+        /// Tarball made by snapshot
+        archive: String,
+        /// Replace existing config files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Feed a packet capture or scenario through the mesh handler, printing the texts decoded
+    Replay {
+        /// Capture file, e.g. captures/packets-20250601-000.cap.gz, or scenario file
+        file: String,
+        /// Print every packet too
+        #[arg(long)]
+        all: bool,
+    },
+    /// List the BLE devices around, to pick BLE_DEVICE
+    Scan {
+        /// Seconds to scan for
+        #[arg(long, default_value_t = 5)]
+        secs: u64,
+        /// Only devices whose name starts with it
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Print them as JSON
+        #[arg(long)]
+        json: bool,
+        /// Write the only device found as BLE_DEVICE to the .env file
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print the node database of the radio and exit
+    Nodes {
+        /// Print them as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the node, firmware, region and channels of the radio and exit
+    Info {
+        /// Print it as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the packets the radio hears, without the BBS or a display
+    Watch {
+        /// Only these ports, comma separated, e.g. text_message,position,encrypted
+        #[arg(long, value_delimiter = ',')]
+        ports: Vec<String>,
+        /// Print the packets as JSON lines
+        #[arg(long)]
+        json: bool,
+        /// Forward the texts to the TELEGRAM_CHAT_ID chat
+        #[arg(long)]
+        telegram: bool,
+        /// Capture the packets to this dir, CAPTURE_DIR by default
+        #[arg(long)]
+        capture: Option<String>,
+    },
+    /// Full screen view of the radio: nodes, live texts and a line to send them
+    Tui {
+        /// BLE device name or auto, BLE_DEVICE by default
+        device: Option<String>,
+    },
+    /// Type BBS commands at the console, without a radio
+    #[cfg(feature = "repl")]
+    BbsRepl {
+        /// Storage backend: native_db or memory
+        #[arg(long)]
+        storage: Option<Backend>,
+        /// Database file
+        #[arg(long)]
+        db: Option<String>,
+        /// Short name of the user typing, `/as <short_name>` switches it
+        #[arg(long = "as", default_value = "local")]
+        short_name: String,
+        /// Let the console users run admin commands
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Run the BBS on a scenario or capture file instead of a radio, with an in-memory database
+    Simulate {
+        /// Scenario file, see scenarios/, or capture file
+        file: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FilesAction {
+    /// Add a text file, up to 16KB
+    Add {
+        path: String,
+        /// Name shown to users, the file name by default
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Remove a document by number
+    Rm { id: u32 },
+    /// List the documents
+    List,
+}
+
+async fn run_bbs_display(config: Config, display: DisplayKind) -> Result<()> {
+    match display {
+        DisplayKind::Epd => run_bbs_epd(config).await,
+        DisplayKind::None => bbs::run_bbs(config, NoScreen {}).await,
+        DisplayKind::Term => bbs::run_bbs(config, TermScreen::default()).await,
+    }
 }
 
 #[cfg(target_os = "linux")]
-async fn run_bbs_display() -> Result<()> {
-    let display = crate::screen::epd::EpdScreen::new()?;
-    bbs::run_bbs(display).await?;
-    Ok(())
+async fn run_bbs_epd(config: Config) -> Result<()> {
+    let display = crate::screen::epd::open(&config.epd_options())?;
+    bbs::run_bbs(config, display).await
 }
 
 #[cfg(not(target_os = "linux"))]
-async fn run_bbs_display() -> Result<()> {
-    use crate::screen::NoScreen;
-
-    bbs::run_bbs(NoScreen {}).await?;
-    Ok(())
+async fn run_bbs_epd(config: Config) -> Result<()> {
+    tracing::warn!(target: "screen", "The e-paper needs Linux, running without a display");
+    bbs::run_bbs(config, NoScreen {}).await
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
 
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp_secs()
-        .init();
-
     let cli = Cli::parse();
+    let mut config = Config::from_env()?;
+    // Before the logger is set up, as they may change the log levels
+    if let Commands::Start { args, .. } | Commands::StartNoDisplay(args) = &cli.command {
+        args.apply(&mut config);
+    }
+    // Log lines would garble the TUI, unless RUST_LOG asks for them
+    let log_filter = match cli.command {
+        Commands::Tui { .. } => "off",
+        _ => &config.log_filter,
+    };
+    logging::init(config.log_format, log_filter)?;
     match cli.command {
-        Commands::Start => run_bbs_display().await?,
-        Commands::StartNoDisplay => bbs::run_bbs(NoScreen {}).await?,
-        Commands::MeshTool => tool::run_tool().await?,
+        Commands::Start { display, .. } => run_bbs_display(config, display).await?,
+        Commands::StartNoDisplay(_) => bbs::run_bbs(config, NoScreen {}).await?,
+        Commands::MeshTool { enable_admin } => tool::run_tool(enable_admin).await?,
+        Commands::Watch {
+            ports,
+            json,
+            telegram,
+            capture,
+        } => {
+            if let Some(capture) = capture {
+                config.capture_dir = capture;
+            }
+            watch::run_watch(config, ports, json, telegram).await?
+        }
+        Commands::Nodes { json } => tool::run_nodes(&config, json).await?,
+        Commands::Info { json } => tool::run_info(&config, json).await?,
+        Commands::Scan {
+            secs,
+            prefix,
+            json,
+            save,
+        } => tool::run_scan(secs, &prefix, json, save).await?,
+        Commands::Tui { device } => {
+            let device = match device {
+                Some(device) if device == "auto" => tool::ble_device_auto().await?,
+                Some(device) => device,
+                None => bbs::radios::ble_devices(&config)?.remove(0),
+            };
+            let handler = Service::from_ble(&device, config.mesh_options()).await?;
+            tui::run_tui(handler).await?
+        }
+        Commands::SelfTest { peer, timeout } => {
+            if !selftest::run_selftest(config, &peer, Duration::from_secs(timeout)).await? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Send {
+            to,
+            text,
+            wait_ack,
+            timeout,
+        } => {
+            let code =
+                send::run_send(config, &to, &text, wait_ack, Duration::from_secs(timeout)).await?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Snapshot { out } => {
+            let storage = Storage::with_backend(config.storage, Path::new(&config.db_path))?;
+            bbs::snapshot::create(&storage, &config.snapshot_files(), Path::new(&out))?;
+        }
+        Commands::Files { action } => {
+            let storage = Storage::with_backend(config.storage, Path::new(&config.db_path))?;
+            match action {
+                FilesAction::Add { path, name } => {
+                    let id = bbs::files::add(&storage, Path::new(&path), name.as_deref())?;
+                    println!("Added #{id}");
+                }
+                FilesAction::Rm { id } => {
+                    if !storage.remove_document(id)? {
+                        anyhow::bail!("File #{id} not found");
+                    }
+                }
+                FilesAction::List => {
+                    for line in bbs::files::list(&storage)? {
+                        println!("{line}");
+                    }
+                }
+            }
+        }
+        Commands::Graph { out } => {
+            let storage = Storage::with_backend(config.storage, Path::new(&config.db_path))?;
+            let graph = bbs::graph::export(&storage, Path::new(&out))?;
+            println!(
+                "Wrote {} nodes and {} links to {out}",
+                graph.nodes.len(),
+                graph.links.len()
+            );
+        }
+        Commands::Decode { id, json } => {
+            let Some(id) = parse_node_id(&id) else {
+                anyhow::bail!("Invalid packet id {id}");
+            };
+            watch::run_decode(&config, id, json)?
+        }
+        Commands::Replay { file, all } => {
+            tool::run_replay(Path::new(&file), config.mesh_options(), all).await?
+        }
+        Commands::Simulate { file } => {
+            config.storage = Backend::Memory;
+            config.mqtt_host.clear();
+            config.telegram = false;
+            config.capture_dir.clear();
+            config.send_delay = Duration::ZERO;
+            config.reply_delay = Duration::ZERO;
+            let transport = Transport::Replay(PathBuf::from(file));
+            bbs::run_bbs_on(config, NoScreen {}, vec![transport]).await?
+        }
+        #[cfg(feature = "repl")]
+        Commands::BbsRepl {
+            storage,
+            db,
+            short_name,
+            admin,
+        } => {
+            StartArgs {
+                storage,
+                db,
+                ..Default::default()
+            }
+            .apply(&mut config);
+            bbs::repl::run_repl(config, &short_name, admin).await?
+        }
+        Commands::Restore { archive, force } => {
+            let storage = Storage::with_backend(config.storage, Path::new(&config.db_path))?;
+            let files =
+                bbs::snapshot::restore(&storage, Path::new(&archive), Path::new("."), force)?;
+            for file in files {
+                println!("Restored {}", file.display());
+            }
+        }
     }
 
     Ok(())
@@ -0,0 +1,431 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow, bail};
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use tokio::sync::{broadcast, mpsc::UnboundedSender};
+use tracing::{info, warn};
+
+use crate::bbs::service::Post;
+use crate::bbs::storage::Storage;
+use crate::config::Config;
+
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+const SEND_INTERVAL: Duration = Duration::from_secs(1);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Texts waiting to be sent, the oldest are dropped when Matrix is unreachable
+const MAX_QUEUED: usize = 100;
+// Posts that came from Matrix, remembered so they are not sent back
+const MAX_RELAYED: usize = 32;
+// Setting keeping the rooms, as JSON
+const ROOMS_SETTING: &str = "matrix.rooms";
+// Appended to the authors of the posts from Matrix, so they cannot pass for
+// users of the mesh
+const AUTHOR_SUFFIX: &str = "@matrix";
+
+/// Matrix room id of each channel
+pub type Rooms = BTreeMap<String, String>;
+
+/// The rooms of the channels, kept across restarts
+pub fn load_rooms(storage: &Storage) -> Result<Rooms> {
+    match storage.get_setting(ROOMS_SETTING)? {
+        Some(json) => Ok(serde_json::from_str(&json)?),
+        None => Ok(Rooms::new()),
+    }
+}
+
+pub fn store_rooms(storage: &Storage, rooms: &Rooms) -> Result<()> {
+    storage.set_setting(ROOMS_SETTING, &serde_json::to_string(rooms)?)
+}
+
+/// Comma separated `<channel>=<room id>`, e.g. `general=!abc:matrix.org`
+pub fn parse_rooms(s: &str) -> Result<Rooms> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (channel, room) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected <channel>=<room id>: {entry}"))?;
+            let (channel, room) = (channel.trim(), room.trim());
+            if !room.starts_with('!') {
+                bail!("Invalid room id {room}, expected !id:server");
+            }
+            Ok((channel.to_string(), room.to_string()))
+        })
+        .collect()
+}
+
+/// The homeserver asked to wait before the next request
+#[derive(Debug)]
+struct RateLimited(Duration);
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rate limited for {:?}", self.0)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+#[derive(Deserialize, Default)]
+struct Error {
+    errcode: Option<String>,
+    error: Option<String>,
+    retry_after_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Sync {
+    next_batch: String,
+    #[serde(default)]
+    rooms: SyncRooms,
+}
+
+#[derive(Deserialize, Default)]
+struct SyncRooms {
+    #[serde(default)]
+    join: HashMap<String, JoinedRoom>,
+}
+
+#[derive(Deserialize)]
+struct JoinedRoom {
+    #[serde(default)]
+    timeline: Timeline,
+}
+
+#[derive(Deserialize, Default)]
+struct Timeline {
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    sender: String,
+    #[serde(default)]
+    content: serde_json::Value,
+}
+
+/// Minimal Matrix client-server API client, logged in with an access token
+#[derive(Clone)]
+pub struct MatrixClient {
+    client: reqwest::Client,
+    homeserver: String,
+    token: String,
+}
+
+impl MatrixClient {
+    pub fn new(homeserver: &str, token: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            homeserver: homeserver.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<serde_json::Value>,
+    ) -> Result<T> {
+        let mut request = self
+            .client
+            .request(
+                method,
+                format!("{}/_matrix/client/v3{path}", self.homeserver),
+            )
+            .bearer_auth(&self.token)
+            .query(query)
+            .timeout(SYNC_TIMEOUT + Duration::from_secs(10));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+        let error: Error = response.json().await.unwrap_or_default();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let wait = Duration::from_millis(error.retry_after_ms.unwrap_or(5000));
+            return Err(RateLimited(wait).into());
+        }
+        bail!(
+            "Matrix {path} failed: {} {}",
+            error.errcode.unwrap_or_else(|| status.to_string()),
+            error.error.unwrap_or_default()
+        );
+    }
+
+    /// Our user id, e.g. `@meshboard:matrix.org`
+    pub async fn whoami(&self) -> Result<String> {
+        let response: serde_json::Value =
+            self.call(Method::GET, "/account/whoami", &[], None).await?;
+        response["user_id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Matrix whoami returned no user_id"))
+    }
+
+    pub async fn send_text(&self, room: &str, text: &str, txn_id: &str) -> Result<()> {
+        let _: serde_json::Value = self
+            .call(
+                Method::PUT,
+                &format!("/rooms/{room}/send/m.room.message/{txn_id}"),
+                &[],
+                Some(json!({ "msgtype": "m.text", "body": text })),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Creates a private room, inviting the users. Returns its id.
+    pub async fn create_room(&self, name: &str, invite: &[String]) -> Result<String> {
+        let response: serde_json::Value = self
+            .call(
+                Method::POST,
+                "/createRoom",
+                &[],
+                Some(json!({ "name": name, "preset": "private_chat", "invite": invite })),
+            )
+            .await?;
+        response["room_id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Matrix createRoom returned no room_id"))
+    }
+
+    /// Long polls the events after `since`
+    async fn sync(&self, since: Option<&str>) -> Result<Sync> {
+        let mut query = vec![("timeout", SYNC_TIMEOUT.as_millis().to_string())];
+        if let Some(since) = since {
+            query.push(("since", since.to_string()));
+        }
+        self.call(Method::GET, "/sync", &query, None).await
+    }
+}
+
+/// Rooms of the channels, and the posts relayed from Matrix
+#[derive(Default)]
+struct Shared {
+    rooms: Rooms,
+    relayed: VecDeque<Post>,
+}
+
+/// Texts written by others in the rooms of the channels, as posts named
+/// after the localpart of the sender with [AUTHOR_SUFFIX]
+fn inbound_posts(sync: &Sync, rooms: &Rooms, me: &str) -> Vec<Post> {
+    let mut posts = Vec::new();
+    for (room, joined) in &sync.rooms.join {
+        let Some((channel, _)) = rooms.iter().find(|(_, id)| *id == room) else {
+            continue;
+        };
+        for event in &joined.timeline.events {
+            if event.kind != "m.room.message"
+                || event.sender == me
+                || event.content["msgtype"] != "m.text"
+            {
+                continue;
+            }
+            let Some(text) = event.content["body"].as_str() else {
+                continue;
+            };
+            let author = event
+                .sender
+                .trim_start_matches('@')
+                .split(':')
+                .next()
+                .unwrap_or_default();
+            posts.push(Post {
+                channel: channel.clone(),
+                author: format!("{author}{AUTHOR_SUFFIX}"),
+                text: text.to_string(),
+            });
+        }
+    }
+    posts
+}
+
+/// Posts the texts written in the rooms to their channels. The first sync
+/// only skips the history.
+async fn poll_rooms(
+    matrix: MatrixClient,
+    me: String,
+    shared: Arc<Mutex<Shared>>,
+    inbound_tx: UnboundedSender<Post>,
+) {
+    let mut since: Option<String> = None;
+    loop {
+        let sync = match matrix.sync(since.as_deref()).await {
+            Ok(sync) => sync,
+            Err(err) => {
+                let wait = match err.downcast_ref::<RateLimited>() {
+                    Some(RateLimited(wait)) => *wait,
+                    None => {
+                        warn!("Matrix sync failed: {err}");
+                        RETRY_DELAY
+                    }
+                };
+                tokio::time::sleep(wait).await;
+                continue;
+            }
+        };
+        if since.replace(sync.next_batch.clone()).is_none() {
+            continue;
+        }
+        let posts = {
+            let mut shared = shared.lock().unwrap();
+            let posts = inbound_posts(&sync, &shared.rooms, &me);
+            for post in &posts {
+                if shared.relayed.len() == MAX_RELAYED {
+                    shared.relayed.pop_front();
+                }
+                shared.relayed.push_back(post.clone());
+            }
+            posts
+        };
+        for post in posts {
+            if inbound_tx.send(post).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Relays the BBS posts to a Matrix room per channel, creating the rooms
+/// missing and sending them to `rooms_tx` to be kept, and posts the texts
+/// written in the rooms to their channels. Runs until the BBS is gone.
+pub async fn run_bridge(
+    config: Config,
+    rooms: Rooms,
+    mut posts_rx: broadcast::Receiver<Post>,
+    inbound_tx: UnboundedSender<Post>,
+    rooms_tx: UnboundedSender<Rooms>,
+) -> Result<()> {
+    if config.matrix_token.is_empty() {
+        bail!("Matrix needs MATRIX_TOKEN");
+    }
+    let matrix = MatrixClient::new(&config.matrix_homeserver, &config.matrix_token);
+    let me = matrix.whoami().await?;
+    info!("Matrix bridge as {me}, {} rooms", rooms.len());
+    let shared = Arc::new(Mutex::new(Shared {
+        rooms,
+        ..Default::default()
+    }));
+    let poller = tokio::spawn(poll_rooms(matrix.clone(), me, shared.clone(), inbound_tx));
+
+    let session = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let mut sent = 0;
+    let mut queue = VecDeque::new();
+    let mut send_interval = tokio::time::interval(SEND_INTERVAL);
+    loop {
+        tokio::select! {
+            post = posts_rx.recv() => match post {
+                Ok(post) => {
+                    let mut shared = shared.lock().unwrap();
+                    if let Some(n) = shared.relayed.iter().position(|relayed| *relayed == post) {
+                        shared.relayed.remove(n);
+                        continue;
+                    }
+                    if queue.len() == MAX_QUEUED {
+                        queue.pop_front();
+                    }
+                    queue.push_back(post);
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Matrix bridge behind, {skipped} posts not relayed");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = send_interval.tick() => {
+                let Some(post) = queue.front() else {
+                    continue;
+                };
+                let room = shared.lock().unwrap().rooms.get(&post.channel).cloned();
+                let room = match room {
+                    Some(room) => Ok(room),
+                    None => matrix
+                        .create_room(&format!("#{}", post.channel), &config.matrix_invite)
+                        .await
+                        .inspect(|room| {
+                            info!("Matrix room {room} created for {}", post.channel);
+                            let mut shared = shared.lock().unwrap();
+                            shared.rooms.insert(post.channel.clone(), room.clone());
+                            let _ = rooms_tx.send(shared.rooms.clone());
+                        }),
+                };
+                let text = format!("{}: {}", post.author, post.text);
+                let result = match room {
+                    Ok(room) => matrix.send_text(&room, &text, &format!("mb{session}.{sent}")).await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(()) => {
+                        sent += 1;
+                        queue.pop_front();
+                    }
+                    Err(err) => match err.downcast_ref::<RateLimited>() {
+                        Some(RateLimited(wait)) => tokio::time::sleep(*wait).await,
+                        None => warn!("Matrix send failed: {err}"),
+                    },
+                }
+            }
+        }
+    }
+    poller.abort();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inbound_posts() -> Result<()> {
+        let rooms = parse_rooms("general=!abc:matrix.org, news = !def:matrix.org")?;
+        assert_eq!(rooms.len(), 2);
+        assert!(parse_rooms("general=#general:matrix.org").is_err());
+        assert!(parse_rooms("general").is_err());
+
+        let sync: Sync = serde_json::from_str(
+            r#"{"next_batch":"s2","rooms":{"join":{
+                "!abc:matrix.org":{"timeline":{"events":[
+                    {"type":"m.room.message","sender":"@ann:matrix.org","content":{"msgtype":"m.text","body":"hi mesh"}},
+                    {"type":"m.room.message","sender":"@meshboard:matrix.org","content":{"msgtype":"m.text","body":"bob: hi"}},
+                    {"type":"m.room.message","sender":"@ann:matrix.org","content":{"msgtype":"m.image","body":"cat.png"}},
+                    {"type":"m.room.member","sender":"@carl:matrix.org","content":{"membership":"join"}}
+                ]}},
+                "!other:matrix.org":{"timeline":{"events":[
+                    {"type":"m.room.message","sender":"@ann:matrix.org","content":{"msgtype":"m.text","body":"elsewhere"}}
+                ]}}
+            }}}"#,
+        )?;
+        assert_eq!(
+            inbound_posts(&sync, &rooms, "@meshboard:matrix.org"),
+            vec![Post {
+                channel: "general".into(),
+                author: "ann@matrix".into(),
+                text: "hi mesh".into(),
+            }]
+        );
+        let empty: Sync = serde_json::from_str(r#"{"next_batch":"s1"}"#)?;
+        assert!(inbound_posts(&empty, &rooms, "@meshboard:matrix.org").is_empty());
+
+        let storage = Storage::memory();
+        assert!(load_rooms(&storage)?.is_empty());
+        store_rooms(&storage, &rooms)?;
+        assert_eq!(load_rooms(&storage)?, rooms);
+        Ok(())
+    }
+}
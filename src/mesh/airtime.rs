@@ -0,0 +1,186 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+/// Bytes a text takes on air besides its own, the mesh header and the
+/// protobuf around it
+pub const PACKET_OVERHEAD: usize = 32;
+// Meshtastic sends 16 preamble symbols
+const PREAMBLE_SYMBOLS: f64 = 16.0;
+/// Percent of the budget used above which the mesh is congested
+pub const NEAR_CAP: f64 = 80.0;
+
+/// LoRa settings the airtime of a packet depends on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modem {
+    pub spread_factor: u32,
+    pub bandwidth_hz: u32,
+    /// Denominator of the coding rate, 5 for 4/5 to 8 for 4/8
+    pub coding_rate: u32,
+}
+
+impl Modem {
+    pub const LONG_FAST: Modem = Modem::new(11, 250_000, 5);
+
+    const fn new(spread_factor: u32, bandwidth_hz: u32, coding_rate: u32) -> Self {
+        Self {
+            spread_factor,
+            bandwidth_hz,
+            coding_rate,
+        }
+    }
+
+    /// Custom LoRa settings, None unless spread factor 7 to 12, a bandwidth
+    /// and coding rate 4/5 to 4/8, the airtime of others makes no sense
+    pub fn custom(spread_factor: u32, bandwidth_hz: u32, coding_rate: u32) -> Option<Self> {
+        ((7..=12).contains(&spread_factor) && bandwidth_hz > 0 && (5..=8).contains(&coding_rate))
+            .then(|| Modem::new(spread_factor, bandwidth_hz, coding_rate))
+    }
+
+    /// The modem preset by its Meshtastic name, e.g. `LONG_FAST`
+    pub fn preset(name: &str) -> Option<Self> {
+        Some(match name {
+            "SHORT_TURBO" => Modem::new(7, 500_000, 5),
+            "SHORT_FAST" => Modem::new(7, 250_000, 5),
+            "SHORT_SLOW" => Modem::new(8, 250_000, 5),
+            "MEDIUM_FAST" => Modem::new(9, 250_000, 5),
+            "MEDIUM_SLOW" => Modem::new(10, 250_000, 5),
+            "LONG_FAST" => Modem::LONG_FAST,
+            "LONG_MODERATE" => Modem::new(11, 125_000, 8),
+            "LONG_SLOW" => Modem::new(12, 125_000, 8),
+            "VERY_LONG_SLOW" => Modem::new(12, 62_500, 8),
+            _ => return None,
+        })
+    }
+
+    /// Time on air of a packet of `bytes`, with the formula of the Semtech
+    /// datasheets: explicit header and CRC on
+    pub fn airtime(&self, bytes: usize) -> Duration {
+        let sf = self.spread_factor as f64;
+        let symbol = 2f64.powf(sf) / self.bandwidth_hz as f64;
+        // Low data rate optimization, for symbols over 16ms
+        let low_rate = if symbol > 0.016 { 1.0 } else { 0.0 };
+        let bits = 8.0 * bytes as f64 - 4.0 * sf + 28.0 + 16.0;
+        let blocks = (bits / (4.0 * (sf - 2.0 * low_rate))).ceil().max(0.0);
+        let symbols = PREAMBLE_SYMBOLS + 4.25 + 8.0 + blocks * self.coding_rate as f64;
+        Duration::from_secs_f64(symbols * symbol)
+    }
+}
+
+/// Percent of the time a radio may transmit in the region, by its
+/// Meshtastic name, e.g. `EU_868`
+pub fn duty_cycle(region: &str) -> f64 {
+    match region {
+        "EU_433" | "EU_868" | "UA_433" => 10.0,
+        "UA_868" => 1.0,
+        _ => 100.0,
+    }
+}
+
+/// Airtime the radio spent transmitting in the last hour, against the duty
+/// cycle allowed
+#[derive(Debug, Clone)]
+pub struct Airtime {
+    modem: Modem,
+    duty_cycle: f64,
+    // Set by the operator, wins over the one of the region
+    fixed_duty_cycle: Option<f64>,
+    spent: VecDeque<(Instant, Duration)>,
+}
+
+impl Default for Airtime {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Airtime {
+    /// Long fast without a duty cycle until the radio tells its settings,
+    /// or the one given
+    pub fn new(duty_cycle: Option<f64>) -> Self {
+        Self {
+            modem: Modem::LONG_FAST,
+            duty_cycle: duty_cycle.unwrap_or(100.0),
+            fixed_duty_cycle: duty_cycle,
+            spent: VecDeque::new(),
+        }
+    }
+
+    /// The LoRa settings of the radio, and its region by its Meshtastic name
+    pub fn radio(&mut self, modem: Modem, region: &str) {
+        self.modem = modem;
+        self.duty_cycle = self.fixed_duty_cycle.unwrap_or(duty_cycle(region));
+    }
+
+    /// A text of `bytes` went on air
+    pub fn transmitted(&mut self, bytes: usize, now: Instant) {
+        self.spent
+            .push_back((now, self.modem.airtime(bytes + PACKET_OVERHEAD)));
+    }
+
+    /// Time on air in the last hour
+    pub fn used(&mut self, now: Instant) -> Duration {
+        while let Some((ts, _)) = self.spent.front()
+            && now.saturating_duration_since(*ts) >= HOUR
+        {
+            self.spent.pop_front();
+        }
+        self.spent.iter().map(|(_, airtime)| *airtime).sum()
+    }
+
+    /// Percent of the hourly budget used, 0 without a duty cycle
+    pub fn utilization(&mut self, now: Instant) -> f64 {
+        if self.duty_cycle >= 100.0 {
+            return 0.0;
+        }
+        let budget = HOUR.as_secs_f64() * self.duty_cycle / 100.0;
+        self.used(now).as_secs_f64() * 100.0 / budget
+    }
+
+    /// Whether the radio is close to its duty cycle, and texts that can
+    /// wait should
+    pub fn is_congested(&mut self, now: Instant) -> bool {
+        self.utilization(now) >= NEAR_CAP
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_airtime() {
+        let ms = |modem: Modem, bytes| modem.airtime(bytes).as_millis();
+        assert_eq!(ms(Modem::LONG_FAST, 42), 559);
+        assert_eq!(ms(Modem::preset("SHORT_FAST").unwrap(), 42), 47);
+        assert!(ms(Modem::preset("VERY_LONG_SLOW").unwrap(), 42) > 4000);
+        assert_eq!(Modem::preset("FAST"), None);
+        assert_eq!(Modem::custom(11, 250_000, 5), Some(Modem::LONG_FAST));
+        assert_eq!(Modem::custom(0, 250_000, 5), None);
+        assert_eq!(Modem::custom(13, 250_000, 5), None);
+        assert_eq!(Modem::custom(11, 0, 5), None);
+        assert_eq!(Modem::custom(11, 250_000, 0), None);
+        assert_eq!(duty_cycle("EU_868"), 10.0);
+        assert_eq!(duty_cycle("US"), 100.0);
+
+        let start = Instant::now();
+        let mut airtime = Airtime::default();
+        airtime.transmitted(200, start);
+        assert_eq!(airtime.utilization(start), 0.0);
+
+        // 1% of an hour is 36s, 3 long slow texts of 200 bytes take 40s
+        airtime.radio(Modem::preset("LONG_SLOW").unwrap(), "UA_868");
+        for _ in 0..3 {
+            airtime.transmitted(200, start);
+        }
+        assert!(airtime.is_congested(start));
+        assert!(!airtime.is_congested(start + HOUR));
+        assert_eq!(airtime.used(start + HOUR), Duration::ZERO);
+
+        let mut fixed = Airtime::new(Some(50.0));
+        fixed.radio(Modem::LONG_FAST, "EU_868");
+        assert_eq!(fixed.duty_cycle, 50.0);
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, NaiveDate};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use tracing::{info, warn};
+
+// First bytes of every capture file, followed by the records:
+// timestamp in ms (u64 LE), length (u32 LE) and the encoded FromRadio
+const MAGIC: &[u8; 8] = b"MBCAP01\n";
+const PREFIX: &str = "packets-";
+const GZ_EXTENSION: &str = ".cap.gz";
+const EXTENSION: &str = ".cap";
+// Records larger than this are a corrupt file, radio frames are way smaller
+const MAX_RECORD: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct CaptureOptions {
+    pub dir: PathBuf,
+    /// Bytes of packets per file before starting a new one, 0 is unlimited
+    pub max_file_bytes: u64,
+    /// Disk space of all capture files before removing the oldest ones, 0 is
+    /// unlimited
+    pub max_total_bytes: u64,
+    /// Gzip the files
+    pub compress: bool,
+}
+
+struct CaptureFile {
+    day: NaiveDate,
+    path: PathBuf,
+    writer: Box<dyn Write + Send + Sync>,
+    // Packet bytes written so far
+    written: u64,
+}
+
+/// Writes the packets received from the radio to daily capture files,
+/// `packets-YYYYMMDD-NNN.cap[.gz]`, starting a new part when the current
+/// one is full. Read them back with [read].
+pub struct PacketLogger {
+    options: CaptureOptions,
+    current: Option<CaptureFile>,
+}
+
+impl PacketLogger {
+    pub fn new(options: CaptureOptions) -> Result<Self> {
+        std::fs::create_dir_all(&options.dir)?;
+        Ok(Self {
+            options,
+            current: None,
+        })
+    }
+
+    /// Appends a packet received at `ts` (ms)
+    pub fn log(&mut self, ts: u64, packet: &[u8]) -> Result<()> {
+        let Some(day) = DateTime::from_timestamp_millis(ts as i64).map(|ts| ts.date_naive()) else {
+            bail!("Invalid timestamp {ts}");
+        };
+        let full = |file: &CaptureFile| {
+            file.day != day
+                || (self.options.max_file_bytes > 0 && file.written >= self.options.max_file_bytes)
+        };
+        if self.current.as_ref().is_none_or(full) {
+            self.rotate(day)?;
+        }
+        let Some(file) = self.current.as_mut() else {
+            bail!("No capture file");
+        };
+        file.writer.write_all(&ts.to_le_bytes())?;
+        file.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        file.writer.write_all(packet)?;
+        // Keep the file readable if the board dies
+        file.writer.flush()?;
+        file.written += packet.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self, day: NaiveDate) -> Result<()> {
+        if let Some(mut file) = self.current.take() {
+            file.writer.flush()?;
+        }
+        let stem = format!("{PREFIX}{}-", day.format("%Y%m%d"));
+        // Parts of the day written before a restart are kept
+        let part = files(&self.options.dir)?
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str()?.strip_prefix(&stem))
+            .filter_map(|rest| rest.split('.').next()?.parse::<u32>().ok())
+            .max()
+            .map_or(0, |part| part + 1);
+        let extension = if self.options.compress {
+            GZ_EXTENSION
+        } else {
+            EXTENSION
+        };
+        let path = self.options.dir.join(format!("{stem}{part:03}{extension}"));
+        let file = File::create_new(&path)?;
+        let mut writer: Box<dyn Write + Send + Sync> = if self.options.compress {
+            Box::new(GzEncoder::new(file, Compression::default()))
+        } else {
+            Box::new(BufWriter::new(file))
+        };
+        writer.write_all(MAGIC)?;
+        info!("Capturing packets to {}", path.display());
+        self.current = Some(CaptureFile {
+            day,
+            path,
+            writer,
+            written: 0,
+        });
+        self.prune()
+    }
+
+    // Removes the oldest files, never the current one, until all fit in
+    // max_total_bytes
+    fn prune(&self) -> Result<()> {
+        if self.options.max_total_bytes == 0 {
+            return Ok(());
+        }
+        let mut files = files(&self.options.dir)?
+            .into_iter()
+            .map(|path| Ok((path.metadata()?.len(), path)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut total: u64 = files.iter().map(|(len, _)| len).sum();
+        files.retain(|(_, path)| self.current.as_ref().is_none_or(|file| file.path != *path));
+        for (len, path) in files {
+            if total <= self.options.max_total_bytes {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+// Capture files in the directory, oldest first
+fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_capture = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.starts_with(PREFIX)
+                    && (name.ends_with(EXTENSION) || name.ends_with(GZ_EXTENSION))
+            });
+        if is_capture {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Packets of a capture file as (timestamp in ms, encoded FromRadio). A
+/// truncated last record, as left by a crash, is skipped.
+pub fn read(path: &Path) -> Result<Vec<(u64, Vec<u8>)>> {
+    let file = File::open(path)?;
+    let mut reader: Box<dyn Read> = if path.to_string_lossy().ends_with(GZ_EXTENSION) {
+        Box::new(BufReader::new(GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let mut magic = [0u8; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("{} is not a packet capture", path.display());
+    }
+
+    let mut packets = Vec::new();
+    loop {
+        let mut header = [0u8; 12];
+        let mut packet = Vec::new();
+        let read = reader.read_exact(&mut header).and_then(|_| {
+            let len = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+            if len > MAX_RECORD {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            packet.resize(len, 0);
+            reader.read_exact(&mut packet)
+        });
+        match read {
+            Ok(()) => {
+                let ts = u64::from_le_bytes(header[..8].try_into().unwrap());
+                packets.push((ts, packet));
+            }
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                if header != [0u8; 12] {
+                    warn!("{} ends with a truncated packet", path.display());
+                }
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capture() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("meshboard-capture-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let day = 24 * 60 * 60 * 1000;
+
+        let mut logger = PacketLogger::new(CaptureOptions {
+            dir: dir.clone(),
+            max_file_bytes: 10,
+            max_total_bytes: 0,
+            compress: false,
+        })?;
+        logger.log(day, b"first")?;
+        logger.log(day + 1, b"second")?;
+        // Over 10 bytes, new part
+        logger.log(day + 2, b"third")?;
+        // Next day
+        logger.log(2 * day, b"fourth")?;
+
+        let names: Vec<_> = files(&dir)?
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "packets-19700102-000.cap",
+                "packets-19700102-001.cap",
+                "packets-19700103-000.cap"
+            ]
+        );
+        let first = dir.join(&names[0]);
+        assert_eq!(
+            read(&first)?,
+            vec![(day, b"first".to_vec()), (day + 1, b"second".to_vec())]
+        );
+
+        // Truncated by a crash
+        let len = first.metadata()?.len();
+        File::options().write(true).open(&first)?.set_len(len - 2)?;
+        assert_eq!(read(&first)?, vec![(day, b"first".to_vec())]);
+
+        // A restart carries on with the next part, and the total cap removes
+        // the oldest files
+        let mut logger = PacketLogger::new(CaptureOptions {
+            dir: dir.clone(),
+            max_file_bytes: 0,
+            max_total_bytes: 40,
+            compress: false,
+        })?;
+        logger.log(2 * day + 1, b"fifth")?;
+        assert_eq!(
+            files(&dir)?.last().unwrap().file_name().unwrap(),
+            "packets-19700103-001.cap"
+        );
+        assert_eq!(files(&dir)?.len(), 2);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+/// Default max text payload in bytes, Meshtastic packets cap slightly above this
+pub const DEFAULT_MAX_PAYLOAD: usize = 200;
+
+/// Split `text` into chunks of at most `max_payload` bytes, numbered "1/3 ..."
+/// when more than one chunk is needed. Splits on whitespace when possible and
+/// never inside a UTF-8 character.
+pub fn split(text: &str, max_payload: usize) -> Vec<String> {
+    if text.len() <= max_payload {
+        return vec![text.to_string()];
+    }
+
+    // The prefix length depends on the number of chunks, so retry until stable
+    let mut digits = 1;
+    loop {
+        let prefix_len = 2 * digits + 2; // "n/m "
+        let parts = split_raw(text, max_payload.saturating_sub(prefix_len).max(1));
+        let total = parts.len();
+        if total.to_string().len() <= digits {
+            return parts
+                .into_iter()
+                .enumerate()
+                .map(|(n, part)| format!("{}/{} {}", n + 1, total, part))
+                .collect();
+        }
+        digits = total.to_string().len();
+    }
+}
+
+/// Like [split] with chunks of at most `max` bytes, without numbering them
+pub fn split_raw(text: &str, max: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = text.trim();
+    while rest.len() > max {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            // A single character wider than max, take it anyway
+            end = rest
+                .chars()
+                .next()
+                .map(char::len_utf8)
+                .unwrap_or(rest.len());
+        } else if let Some(space) = rest[..end].rfind(char::is_whitespace)
+            && space > 0
+        {
+            end = space;
+        }
+        parts.push(rest[..end].trim_end().to_string());
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest.to_string());
+    }
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("hello", 10), vec!["hello"]);
+        assert_eq!(
+            split("hello world again", 12),
+            vec!["1/3 hello", "2/3 world", "3/3 again"]
+        );
+        for chunk in split(&"àéí ".repeat(100), 50) {
+            assert!(chunk.len() <= 50);
+        }
+        let chunks = split(&"x".repeat(100), 10);
+        assert_eq!(chunks.len(), 25);
+        assert!(chunks.iter().all(|c| c.len() <= 10));
+        assert!(chunks[24].starts_with("25/25 "));
+    }
+}
@@ -0,0 +1,62 @@
+use meshtastic::{Message, protobufs::User};
+
+const CONTACT_URL: &str = "https://meshtastic.org/v/#";
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// URL safe base64, without padding
+fn base64url(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..=chunk.len() {
+            text.push(BASE64URL[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+        }
+    }
+    text
+}
+
+/// Link the Meshtastic apps open to add the node as a contact, with its
+/// names and public key. It holds a SharedContact protobuf, which the
+/// protobufs of the meshtastic crate lack, so it is encoded by hand.
+pub fn contact_url(node: u32, user: &User) -> String {
+    let user = user.encode_to_vec();
+    // Field 1 is the node number, field 2 the user
+    let mut bytes = vec![0x08];
+    varint(&mut bytes, node as u64);
+    bytes.push(0x12);
+    varint(&mut bytes, user.len() as u64);
+    bytes.extend(user);
+    format!("{CONTACT_URL}{}", base64url(&bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_contact_url() {
+        assert_eq!(base64url(b"f"), "Zg");
+        assert_eq!(base64url(b"fo"), "Zm8");
+        assert_eq!(base64url(b"foo"), "Zm9v");
+        assert_eq!(base64url(&[0xfb, 0xff]), "-_8");
+
+        assert_eq!(
+            contact_url(1, &User::default()),
+            "https://meshtastic.org/v/#CAESAA"
+        );
+        let mut bytes = Vec::new();
+        varint(&mut bytes, 300);
+        assert_eq!(bytes, vec![0xac, 0x02]);
+    }
+}
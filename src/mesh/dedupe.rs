@@ -0,0 +1,97 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+/// Ring of recently seen packet ids, optionally persisted to disk so that
+/// packets replayed by the radio after a restart are not processed twice.
+pub struct SeenPackets {
+    ids: VecDeque<u32>,
+    capacity: usize,
+    path: Option<PathBuf>,
+}
+
+impl SeenPackets {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ids: VecDeque::with_capacity(capacity),
+            capacity,
+            path: None,
+        }
+    }
+
+    pub fn open(path: &Path, capacity: usize) -> Result<Self> {
+        let mut seen = Self::new(capacity);
+        if path.exists() {
+            for line in fs::read_to_string(path)?.lines() {
+                if let Ok(id) = u32::from_str_radix(line.trim(), 16) {
+                    seen.push(id);
+                }
+            }
+        }
+        seen.path = Some(path.to_path_buf());
+        Ok(seen)
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// Returns false if the id was already seen
+    pub fn insert(&mut self, id: u32) -> Result<bool> {
+        if self.contains(id) {
+            return Ok(false);
+        }
+        self.push(id);
+        self.save()?;
+        Ok(true)
+    }
+
+    fn push(&mut self, id: u32) {
+        if self.ids.len() == self.capacity {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(id);
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = self
+            .ids
+            .iter()
+            .map(|id| format!("{:08x}\n", id))
+            .collect::<String>();
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seen_packets() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("meshboard-seen-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut seen = SeenPackets::open(&path, 2)?;
+        assert!(seen.insert(1)?);
+        assert!(!seen.insert(1)?);
+        assert!(seen.insert(2)?);
+        assert!(seen.insert(3)?);
+        assert!(!seen.contains(1));
+
+        let seen = SeenPackets::open(&path, 2)?;
+        assert!(seen.contains(2));
+        assert!(seen.contains(3));
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
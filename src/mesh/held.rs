@@ -0,0 +1,128 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::types::TextMessage;
+
+/// A text waiting for its destination to be heard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Held {
+    pub to: u32,
+    // Meshtastic channel index
+    pub channel: u32,
+    pub text: String,
+    // Timestamp, in ms
+    pub ts: u64,
+}
+
+/// Texts to nodes not heard recently, by destination, optionally persisted
+/// to disk so they survive restarts. Only the newest `capacity` texts of each
+/// node are kept.
+pub struct HeldTexts {
+    queues: BTreeMap<u32, VecDeque<Held>>,
+    capacity: usize,
+    path: Option<PathBuf>,
+}
+
+impl HeldTexts {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queues: BTreeMap::new(),
+            capacity,
+            path: None,
+        }
+    }
+
+    /// One JSON text per line, lines that do not parse are skipped
+    pub fn open(path: &Path, capacity: usize) -> Result<Self> {
+        let mut held = Self::new(capacity);
+        if path.exists() {
+            for line in fs::read_to_string(path)?.lines() {
+                if let Ok(text) = serde_json::from_str(line) {
+                    held.push(text);
+                }
+            }
+        }
+        held.path = Some(path.to_path_buf());
+        Ok(held)
+    }
+
+    pub fn hold(&mut self, msg: &TextMessage, ts: u64) -> Result<()> {
+        self.push(Held {
+            to: msg.to,
+            channel: msg.channel,
+            text: msg.text.clone(),
+            ts,
+        });
+        self.save()
+    }
+
+    /// Nodes with texts waiting
+    pub fn nodes(&self) -> Vec<u32> {
+        self.queues.keys().copied().collect()
+    }
+
+    /// Texts held for the node, oldest first, which are no longer kept
+    pub fn release(&mut self, node: u32) -> Result<Vec<Held>> {
+        let Some(queue) = self.queues.remove(&node) else {
+            return Ok(Vec::new());
+        };
+        self.save()?;
+        Ok(queue.into())
+    }
+
+    fn push(&mut self, held: Held) {
+        let queue = self.queues.entry(held.to).or_default();
+        if queue.len() == self.capacity {
+            queue.pop_front();
+        }
+        queue.push_back(held);
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut content = String::new();
+        for held in self.queues.values().flatten() {
+            content += &serde_json::to_string(held)?;
+            content.push('\n');
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_held_texts() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("meshboard-held-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut held = HeldTexts::open(&path, 2)?;
+        for text in ["one", "two", "three"] {
+            held.hold(&TextMessage::sent(1, 7, text.into(), 0), 10)?;
+        }
+        held.hold(&TextMessage::sent(1, 8, "hi".into(), 1), 20)?;
+        assert_eq!(held.nodes(), vec![7, 8]);
+
+        let mut held = HeldTexts::open(&path, 2)?;
+        let texts: Vec<_> = held.release(7)?.into_iter().map(|h| h.text).collect();
+        assert_eq!(texts, vec!["two", "three"]);
+        assert!(held.release(7)?.is_empty());
+
+        let held = HeldTexts::open(&path, 2)?;
+        assert_eq!(held.nodes(), vec![8]);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
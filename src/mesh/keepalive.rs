@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+/// What to send to a radio that went quiet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Probe {
+    /// Keeps the BLE session of the radio alive, it may not answer
+    Heartbeat,
+    /// Asks for the config again, the radio always answers it with the whole
+    /// config and node database
+    WantConfig,
+}
+
+/// Probes a radio quiet for `interval` with a heartbeat, every interval. The
+/// config request costs a whole config dump, so it is only the last resort,
+/// one interval before the link is given up after `idle_timeout`
+pub struct Keepalive {
+    interval: Duration,
+    idle_timeout: Duration,
+    last_heard: Instant,
+    // Intervals quiet already probed
+    probed: u32,
+    asked_config: bool,
+}
+
+impl Keepalive {
+    /// A zero `interval` never probes, a zero `idle_timeout` never asks for
+    /// the config
+    pub fn new(interval: Duration, idle_timeout: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            idle_timeout,
+            last_heard: now,
+            probed: 0,
+            asked_config: false,
+        }
+    }
+
+    /// A packet came from the radio
+    pub fn heard(&mut self, now: Instant) {
+        self.last_heard = now;
+        self.probed = 0;
+        self.asked_config = false;
+    }
+
+    /// The probe due by now, if any
+    pub fn due(&mut self, now: Instant) -> Option<Probe> {
+        if self.interval.is_zero() {
+            return None;
+        }
+        let quiet = now.saturating_duration_since(self.last_heard);
+        let intervals = (quiet.as_millis() / self.interval.as_millis()) as u32;
+        if intervals == 0 {
+            return None;
+        }
+        let probe = if !self.idle_timeout.is_zero()
+            && !self.asked_config
+            && quiet + self.interval >= self.idle_timeout
+        {
+            self.asked_config = true;
+            Probe::WantConfig
+        } else if intervals > self.probed {
+            Probe::Heartbeat
+        } else {
+            return None;
+        };
+        self.probed = intervals;
+        Some(probe)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keepalive() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut keepalive =
+            Keepalive::new(Duration::from_secs(60), Duration::from_secs(300), start);
+        assert_eq!(keepalive.due(secs(59)), None);
+        assert_eq!(keepalive.due(secs(60)), Some(Probe::Heartbeat));
+        assert_eq!(keepalive.due(secs(61)), None);
+        assert_eq!(keepalive.due(secs(120)), Some(Probe::Heartbeat));
+        assert_eq!(keepalive.due(secs(180)), Some(Probe::Heartbeat));
+        // One interval before the idle timeout, once
+        assert_eq!(keepalive.due(secs(240)), Some(Probe::WantConfig));
+        assert_eq!(keepalive.due(secs(241)), None);
+        assert_eq!(keepalive.due(secs(299)), None);
+
+        keepalive.heard(secs(400));
+        assert_eq!(keepalive.due(secs(459)), None);
+        assert_eq!(keepalive.due(secs(460)), Some(Probe::Heartbeat));
+
+        // Without an idle timeout, heartbeats only
+        let mut heartbeats = Keepalive::new(Duration::from_secs(60), Duration::ZERO, start);
+        for n in 1..10 {
+            assert_eq!(heartbeats.due(secs(n * 60)), Some(Probe::Heartbeat));
+        }
+
+        let mut never = Keepalive::new(Duration::ZERO, Duration::from_secs(300), start);
+        assert_eq!(never.due(secs(1000)), None);
+    }
+}
@@ -1,3 +1,17 @@
+pub mod airtime;
+mod capture;
+pub mod chunker;
+mod contact;
+mod dedupe;
+mod held;
+mod keepalive;
+mod names;
+mod nodedb;
+mod outbox;
+pub mod radio_config;
+mod replay;
 mod router;
+mod scenario;
 pub mod service;
+mod traceroute;
 mod types;
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use super::types::format_node_id;
+
+/// Names a node is known by, empty when unknown
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Names {
+    pub short_name: String,
+    pub long_name: String,
+    /// BBS nickname of the user last seen from the node
+    pub nickname: String,
+}
+
+impl Names {
+    /// The nickname, else the short name, else the long name, else the node id
+    pub fn display(&self, node: u32) -> String {
+        [&self.nickname, &self.short_name, &self.long_name]
+            .into_iter()
+            .find(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format_node_id(node))
+    }
+}
+
+/// Names of the nodes, so they are shown the same way everywhere
+#[derive(Debug, Clone, Default)]
+pub struct NameResolver(HashMap<u32, Names>);
+
+impl NameResolver {
+    /// Records the names the node announced, empty ones keep their value
+    pub fn radio_names(&mut self, node: u32, short_name: &str, long_name: &str) {
+        let names = self.0.entry(node).or_default();
+        if !short_name.is_empty() {
+            names.short_name = short_name.to_string();
+        }
+        if !long_name.is_empty() {
+            names.long_name = long_name.to_string();
+        }
+    }
+
+    /// Records the BBS nickname of the node, empty if it has none
+    pub fn nickname(&mut self, node: u32, nickname: &str) {
+        self.0.entry(node).or_default().nickname = nickname.to_string();
+    }
+
+    /// Forgets the names of a node no longer in the node list
+    pub fn remove(&mut self, node: u32) {
+        self.0.remove(&node);
+    }
+
+    pub fn get(&self, node: u32) -> Option<&Names> {
+        self.0.get(&node)
+    }
+
+    pub fn short_name(&self, node: u32) -> Option<String> {
+        self.get(node)
+            .map(|names| names.short_name.clone())
+            .filter(|name| !name.is_empty())
+    }
+
+    pub fn long_name(&self, node: u32) -> Option<String> {
+        self.get(node)
+            .map(|names| names.long_name.clone())
+            .filter(|name| !name.is_empty())
+    }
+
+    pub fn display_name(&self, node: u32) -> String {
+        match self.get(node) {
+            Some(names) => names.display(node),
+            None => format_node_id(node),
+        }
+    }
+
+    /// Node known by the short name. Nicknames are picked by the users, they
+    /// do not address nodes.
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.0
+            .iter()
+            .find(|(_, names)| names.short_name == name)
+            .map(|(node, _)| *node)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        let mut names = NameResolver::default();
+        assert_eq!(names.display_name(2), "!00000002");
+
+        names.radio_names(2, "", "Ann's base");
+        assert_eq!(names.display_name(2), "Ann's base");
+        names.radio_names(2, "ann", "");
+        assert_eq!(names.short_name(2).as_deref(), Some("ann"));
+        assert_eq!(names.long_name(2).as_deref(), Some("Ann's base"));
+        assert_eq!(names.display_name(2), "ann");
+        names.nickname(2, "annie");
+        assert_eq!(names.display_name(2), "annie");
+        names.nickname(2, "");
+        assert_eq!(names.display_name(2), "ann");
+
+        names.radio_names(3, "bob", "");
+        names.nickname(3, "ann");
+        names.nickname(2, "bobby");
+        assert_eq!(names.find("ann"), Some(2));
+        assert_eq!(names.find("bob"), Some(3));
+        assert_eq!(names.find("bobby"), None);
+        assert_eq!(names.find("carol"), None);
+        names.remove(3);
+        assert_eq!(names.find("bob"), None);
+        assert_eq!(names.display_name(3), "!00000003");
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// A node of the radio as kept on disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeptNode {
+    pub num: u32,
+    pub id: String,
+    pub long_name: String,
+    pub short_name: String,
+    pub hw_model: i32,
+    // Hex
+    #[serde(default)]
+    pub public_key: String,
+}
+
+/// What the radio told about itself and the nodes it knows
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Kept {
+    pub my_node_num: Option<u32>,
+    pub nodes: Vec<KeptNode>,
+}
+
+/// The node database of the radio, optionally persisted to disk so names
+/// resolve before the radio streams it again after a restart. Changes are
+/// saved in batches, the radio streams every node at once on connect.
+#[derive(Default)]
+pub struct NodeDb {
+    path: Option<PathBuf>,
+    loaded: Kept,
+    changed: bool,
+}
+
+impl NodeDb {
+    /// A file that does not parse is started over
+    pub fn open(path: &Path) -> Result<Self> {
+        let loaded = match path.exists() {
+            true => serde_json::from_str(&fs::read_to_string(path)?).unwrap_or_else(|err| {
+                warn!("Starting {} over, it does not parse: {err}", path.display());
+                Kept::default()
+            }),
+            false => Kept::default(),
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            loaded,
+            changed: false,
+        })
+    }
+
+    /// What was on disk when opened, once
+    pub fn take_loaded(&mut self) -> Kept {
+        std::mem::take(&mut self.loaded)
+    }
+
+    /// The radio told something new, to be saved
+    pub fn changed(&mut self) {
+        self.changed = self.path.is_some();
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Writes a temporary file next to it and renames it over, so a crash
+    /// while saving leaves the last one whole
+    pub fn save(&mut self, kept: &Kept) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_string(kept)?)?;
+        fs::rename(&tmp, path)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_node_db() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("meshboard-nodes-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut db = NodeDb::open(&path)?;
+        assert_eq!(db.take_loaded(), Kept::default());
+        db.changed();
+        assert!(db.is_changed());
+        let kept = Kept {
+            my_node_num: Some(7),
+            nodes: vec![KeptNode {
+                num: 8,
+                id: "!00000008".into(),
+                long_name: "Ann".into(),
+                short_name: "ann".into(),
+                hw_model: 9,
+                public_key: String::new(),
+            }],
+        };
+        db.save(&kept)?;
+        assert!(!db.is_changed());
+        assert_eq!(NodeDb::open(&path)?.take_loaded(), kept);
+
+        fs::write(&path, "{")?;
+        assert_eq!(NodeDb::open(&path)?.take_loaded(), Kept::default());
+        let mut memory = NodeDb::default();
+        memory.changed();
+        assert!(!memory.is_changed());
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use super::types::TextMessage;
+
+// Queues, one per urgency
+const URGENCIES: usize = 3;
+/// Texts that go before a less urgent one waiting, before it goes anyway
+pub const FAIR_SHARE: u32 = 4;
+
+/// A text waiting to be sent, or resent
+#[derive(Debug, Clone)]
+pub struct Outgoing {
+    pub msg: TextMessage,
+    // Packet id of the first attempt, once sent
+    pub original_id: Option<u32>,
+    // When the first attempt went out, round trips count from it
+    pub first_sent: Option<Instant>,
+    pub attempts: u32,
+}
+
+struct PendingAck {
+    outgoing: Outgoing,
+    deadline: Instant,
+}
+
+fn backoff(base: Duration, attempts: u32) -> Duration {
+    base * 2u32.pow(attempts.saturating_sub(1).min(8))
+}
+
+/// Outgoing text queue that keeps unicast texts around until acked, resending
+/// them with exponential backoff on routing errors or timeouts. More urgent
+/// texts go first, see [Outbox::pop].
+pub struct Outbox {
+    queues: [VecDeque<Outgoing>; URGENCIES],
+    // Texts that went before the first of each queue since it got to wait
+    passed_over: [u32; URGENCIES],
+    pending: HashMap<u32, PendingAck>,
+    max_retries: u32,
+    ack_timeout: Duration,
+}
+
+impl Outbox {
+    pub fn new(max_retries: u32, ack_timeout: Duration) -> Self {
+        Self {
+            queues: Default::default(),
+            passed_over: [0; URGENCIES],
+            pending: HashMap::new(),
+            max_retries,
+            ack_timeout,
+        }
+    }
+
+    pub fn push(&mut self, msg: TextMessage) {
+        self.requeue(Outgoing {
+            msg,
+            original_id: None,
+            first_sent: None,
+            attempts: 0,
+        });
+    }
+
+    fn requeue(&mut self, outgoing: Outgoing) {
+        self.queues[outgoing.msg.urgency as usize].push_back(outgoing);
+    }
+
+    /// The most urgent text, unless a less urgent one was passed over
+    /// [FAIR_SHARE] times, so bulk texts still trickle out under a stream
+    /// of replies
+    pub fn pop(&mut self) -> Option<Outgoing> {
+        let waiting: Vec<usize> = (0..URGENCIES)
+            .filter(|urgency| !self.queues[*urgency].is_empty())
+            .collect();
+        let starved = waiting
+            .iter()
+            .rev()
+            .find(|urgency| self.passed_over[**urgency] >= FAIR_SHARE);
+        let next = *starved.or(waiting.first())?;
+        for urgency in waiting {
+            if urgency != next {
+                self.passed_over[urgency] += 1;
+            }
+        }
+        self.passed_over[next] = 0;
+        self.queues[next].pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// Registers a sent packet, returns the id of the first attempt
+    pub fn sent(&mut self, id: u32, mut outgoing: Outgoing, track: bool) -> u32 {
+        outgoing.attempts += 1;
+        let original_id = *outgoing.original_id.get_or_insert(id);
+        let sent = Instant::now();
+        outgoing.first_sent.get_or_insert(sent);
+        if track {
+            let deadline = sent + backoff(self.ack_timeout, outgoing.attempts);
+            self.pending.insert(id, PendingAck { outgoing, deadline });
+        }
+        original_id
+    }
+
+    /// Id of the first attempt of a tracked packet
+    pub fn original_id(&self, id: u32) -> Option<u32> {
+        self.pending
+            .get(&id)
+            .and_then(|pending| pending.outgoing.original_id)
+    }
+
+    /// Time since the first attempt of a tracked packet went out, the
+    /// retries count as part of the trip
+    pub fn round_trip(&self, id: u32) -> Option<Duration> {
+        self.pending
+            .get(&id)
+            .and_then(|pending| pending.outgoing.first_sent)
+            .map(|first_sent| first_sent.elapsed())
+    }
+
+    /// The packet was acked by its destination, stop tracking it
+    pub fn acked(&mut self, id: u32) -> Option<u32> {
+        self.pending
+            .remove(&id)
+            .and_then(|pending| pending.outgoing.original_id)
+    }
+
+    /// The packet could not be routed, retry it after a backoff
+    pub fn failed(&mut self, id: u32) -> Option<u32> {
+        let ack_timeout = self.ack_timeout;
+        let pending = self.pending.get_mut(&id)?;
+        pending.deadline = Instant::now() + backoff(ack_timeout / 4, pending.outgoing.attempts);
+        pending.outgoing.original_id
+    }
+
+    /// Requeues the packets whose deadline passed, returns the ids of the
+    /// ones that ran out of retries
+    pub fn expire(&mut self) -> Vec<u32> {
+        let now = Instant::now();
+        let ids: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut gave_up = Vec::new();
+        for id in ids {
+            let Some(PendingAck { outgoing, .. }) = self.pending.remove(&id) else {
+                continue;
+            };
+            if outgoing.attempts <= self.max_retries {
+                self.requeue(outgoing);
+            } else {
+                gave_up.push(outgoing.original_id.unwrap_or(id));
+            }
+        }
+        gave_up
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mesh::types::Urgency;
+
+    fn text(text: &str, urgency: Urgency) -> TextMessage {
+        TextMessage {
+            urgency,
+            ..TextMessage::sent(1, 2, text.into(), 0)
+        }
+    }
+
+    fn drain(outbox: &mut Outbox) -> Vec<String> {
+        std::iter::from_fn(|| outbox.pop())
+            .map(|outgoing| outgoing.msg.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_urgency() {
+        let mut outbox = Outbox::new(3, Duration::from_secs(30));
+        outbox.push(text("digest", Urgency::Bulk));
+        outbox.push(text("mail", Urgency::Notification));
+        outbox.push(text("ack", Urgency::Reply));
+        assert_eq!(drain(&mut outbox), vec!["ack", "mail", "digest"]);
+        assert!(outbox.is_empty());
+
+        // A stream of replies lets a bulk text through every FAIR_SHARE
+        outbox.push(text("bulk", Urgency::Bulk));
+        for n in 0..6 {
+            outbox.push(text(&n.to_string(), Urgency::Reply));
+        }
+        assert_eq!(
+            drain(&mut outbox),
+            vec!["0", "1", "2", "3", "bulk", "4", "5"]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut outbox = Outbox::new(3, Duration::ZERO);
+        outbox.push(text("hi", Urgency::Reply));
+        let first = outbox.pop().unwrap();
+        assert_eq!(outbox.sent(10, first, true), 10);
+        std::thread::sleep(Duration::from_millis(20));
+        // No ack in time, the retry goes out as another packet
+        assert!(outbox.expire().is_empty());
+        let retry = outbox.pop().unwrap();
+        assert_eq!(outbox.sent(11, retry, true), 10);
+        assert!(outbox.round_trip(11).unwrap() >= Duration::from_millis(20));
+        assert_eq!(outbox.acked(11), Some(10));
+        assert_eq!(outbox.round_trip(11), None);
+    }
+}
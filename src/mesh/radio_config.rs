@@ -0,0 +1,106 @@
+use anyhow::{Result, anyhow, bail};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+// Protobuf oneof holding the section in Config and ModuleConfig
+const VARIANT_FIELD: &str = "payloadVariant";
+
+/// Name and fields of a Config or ModuleConfig section, e.g. `lora` and its
+/// `hopLimit`, `region`...
+pub fn fields<T: Serialize>(section: &T) -> Result<(String, Map<String, Value>)> {
+    let Value::Object(mut section) = serde_json::to_value(section)? else {
+        bail!("Section is not an object");
+    };
+    match section.remove(VARIANT_FIELD) {
+        Some(Value::Object(variant)) => match variant.into_iter().next() {
+            Some((name, Value::Object(fields))) => Ok((name, fields)),
+            Some((name, _)) => bail!("Section {name} has no fields"),
+            None => bail!("Empty section"),
+        },
+        _ => bail!("Empty section"),
+    }
+}
+
+/// Copy of `section` with `field` set to `value`. The field may be given in
+/// snake case, the value is read as JSON and as a plain string otherwise.
+pub fn with_field<T: Serialize + DeserializeOwned>(
+    section: &T,
+    field: &str,
+    value: &str,
+) -> Result<T> {
+    let (name, mut fields) = fields(section)?;
+    let field = camel_case(field);
+    let slot = fields
+        .get_mut(&field)
+        .ok_or_else(|| anyhow!("Section {name} has no field {field}"))?;
+    *slot = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+
+    let mut variant = Map::new();
+    variant.insert(name, Value::Object(fields));
+    let mut section = Map::new();
+    section.insert(VARIANT_FIELD.to_string(), Value::Object(variant));
+    Ok(serde_json::from_value(Value::Object(section))?)
+}
+
+fn camel_case(field: &str) -> String {
+    let mut parts = field.split('_');
+    let first = parts.next().unwrap_or_default().to_string();
+    parts.fold(first, |mut out, part| {
+        let mut chars = part.chars();
+        if let Some(c) = chars.next() {
+            out.extend(c.to_uppercase());
+            out.push_str(chars.as_str());
+        }
+        out
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    // Same shape as the protobufs, which serialize in camel case
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Config {
+        payload_variant: Option<Variant>,
+    }
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    enum Variant {
+        Lora(Lora),
+    }
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Lora {
+        hop_limit: u32,
+        tx_enabled: bool,
+    }
+
+    #[test]
+    fn test_with_field() -> Result<()> {
+        let config = Config {
+            payload_variant: Some(Variant::Lora(Lora {
+                hop_limit: 3,
+                tx_enabled: true,
+            })),
+        };
+        let (name, values) = fields(&config)?;
+        assert_eq!(name, "lora");
+        assert_eq!(values["hopLimit"], 3);
+
+        let changed = with_field(&config, "hop_limit", "5")?;
+        let changed = with_field(&changed, "txEnabled", "false")?;
+        assert_eq!(
+            changed.payload_variant,
+            Some(Variant::Lora(Lora {
+                hop_limit: 5,
+                tx_enabled: false
+            }))
+        );
+        assert!(with_field(&config, "hops", "5").is_err());
+        assert!(with_field(&config, "hop_limit", "many").is_err());
+        Ok(())
+    }
+}
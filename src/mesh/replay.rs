@@ -0,0 +1,273 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use meshtastic::{
+    Message,
+    protobufs::{
+        Data, FromRadio, MeshPacket, MyNodeInfo, NodeInfo, PortNum, Position, User, from_radio,
+        mesh_packet,
+    },
+    utils::stream::StreamHandle,
+};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, WriteHalf};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use super::capture;
+use super::scenario::{self, Step, To};
+use super::types::{BROADCAST_ADDR, format_node_id};
+
+// Serial framing of the radio: two start bytes and the length, big endian
+const START1: u8 = 0x94;
+const START2: u8 = 0xc3;
+const MAX_FRAME: usize = 512;
+// Time for the handler to act on the last packet before it is stopped
+const LINGER: Duration = Duration::from_secs(2);
+
+/// Packets to play, from a capture or a scenario file
+pub struct Player {
+    steps: Vec<Played>,
+    radio: DuplexStream,
+}
+
+enum Played {
+    Packet(Vec<u8>),
+    Wait(Duration),
+}
+
+/// Stream that plays the radio, and the player that feeds it. Files named
+/// `*.cap` or `*.cap.gz` are captures, see [capture::PacketLogger], anything
+/// else is a scenario, see [scenario::Step]. Whatever is sent to the radio is
+/// dropped.
+pub fn open(path: &Path) -> Result<(StreamHandle<DuplexStream>, Player)> {
+    let name = path.to_string_lossy();
+    let steps = if name.ends_with(".cap") || name.ends_with(".cap.gz") {
+        capture::read(path)?
+            .into_iter()
+            .map(|(_, packet)| Played::Packet(packet))
+            .collect()
+    } else {
+        from_scenario(&scenario::parse(&std::fs::read_to_string(path)?)?)
+    };
+    let (radio, client) = tokio::io::duplex(64 * 1024);
+    Ok((StreamHandle::from_stream(client), Player { steps, radio }))
+}
+
+impl Player {
+    /// Plays every step, then stops the handler through `cancel`
+    pub async fn play(self, cancel: CancellationToken) {
+        let (mut rx, tx) = tokio::io::split(self.radio);
+        // Writes from the client would block once the buffer is full
+        tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_FRAME];
+            while rx.read(&mut buffer).await.is_ok_and(|len| len > 0) {}
+        });
+        if let Err(err) = play(tx, self.steps).await {
+            error!("Replay failed: {}", err);
+        }
+        tokio::time::sleep(LINGER).await;
+        cancel.cancel();
+    }
+}
+
+async fn play(mut tx: WriteHalf<DuplexStream>, steps: Vec<Played>) -> Result<()> {
+    for step in steps {
+        match step {
+            Played::Packet(packet) => {
+                let Some(frame) = frame(&packet) else {
+                    warn!("Skipping {} bytes packet, too large", packet.len());
+                    continue;
+                };
+                tx.write_all(&frame).await?;
+            }
+            Played::Wait(duration) => tokio::time::sleep(duration).await,
+        }
+    }
+    Ok(())
+}
+
+fn frame(packet: &[u8]) -> Option<Vec<u8>> {
+    if packet.len() > MAX_FRAME {
+        return None;
+    }
+    let mut frame = vec![
+        START1,
+        START2,
+        (packet.len() >> 8) as u8,
+        packet.len() as u8,
+    ];
+    frame.extend_from_slice(packet);
+    Some(frame)
+}
+
+fn node_info(num: u32, short_name: &str, long_name: &str) -> from_radio::PayloadVariant {
+    from_radio::PayloadVariant::NodeInfo(NodeInfo {
+        num,
+        user: Some(User {
+            id: format_node_id(num),
+            short_name: short_name.to_string(),
+            long_name: long_name.to_string(),
+            public_key: public_key(num),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+// Made up key, so each scenario node is a different BBS user
+fn public_key(num: u32) -> Vec<u8> {
+    Sha256::digest(num.to_le_bytes()).to_vec()
+}
+
+fn packet(id: u32, from: u32, to: u32, port: PortNum, payload: Vec<u8>) -> MeshPacket {
+    MeshPacket {
+        id,
+        from,
+        to,
+        public_key: public_key(from),
+        hop_start: 3,
+        hop_limit: 3,
+        rx_snr: 6.0,
+        rx_rssi: -80,
+        payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+            portnum: port as i32,
+            payload,
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+fn encoded(id: u32, payload: from_radio::PayloadVariant) -> Played {
+    Played::Packet(
+        FromRadio {
+            id,
+            payload_variant: Some(payload),
+        }
+        .encode_to_vec(),
+    )
+}
+
+/// The packets a radio would send for the scenario, the node database first
+fn from_scenario(steps: &[Step]) -> Vec<Played> {
+    let mut played = Vec::new();
+    let mut me = 0;
+    let mut config_complete = false;
+    for (id, step) in (1..).zip(steps) {
+        let is_node = matches!(step, Step::Me { .. } | Step::Node { .. });
+        if !is_node && !config_complete {
+            config_complete = true;
+            played.push(encoded(0, from_radio::PayloadVariant::ConfigCompleteId(1)));
+        }
+        let payload = match step {
+            Step::Me {
+                num,
+                short_name,
+                long_name,
+            } => {
+                me = *num;
+                let my_info = MyNodeInfo {
+                    my_node_num: *num,
+                    ..Default::default()
+                };
+                played.push(encoded(0, from_radio::PayloadVariant::MyInfo(my_info)));
+                node_info(*num, short_name, long_name)
+            }
+            Step::Node {
+                num,
+                short_name,
+                long_name,
+            } => node_info(*num, short_name, long_name),
+            Step::Text { from, to, text } => {
+                let to = match to {
+                    To::Me => me,
+                    To::Broadcast => BROADCAST_ADDR,
+                    To::Node(num) => *num,
+                };
+                let text = text.as_bytes().to_vec();
+                let packet = packet(id, *from, to, PortNum::TextMessageApp, text);
+                from_radio::PayloadVariant::Packet(packet)
+            }
+            Step::Position {
+                from,
+                latitude,
+                longitude,
+            } => {
+                let position = Position {
+                    latitude_i: Some((latitude * 1e7) as i32),
+                    longitude_i: Some((longitude * 1e7) as i32),
+                    time: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs() as u32,
+                    ..Default::default()
+                };
+                let payload = position.encode_to_vec();
+                let packet = packet(id, *from, BROADCAST_ADDR, PortNum::PositionApp, payload);
+                from_radio::PayloadVariant::Packet(packet)
+            }
+            Step::Wait(duration) => {
+                played.push(Played::Wait(*duration));
+                continue;
+            }
+        };
+        played.push(encoded(id, payload));
+    }
+    if !config_complete {
+        played.push(encoded(0, from_radio::PayloadVariant::ConfigCompleteId(1)));
+    }
+    played
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mesh::service::{Options, Service, Status};
+
+    #[test]
+    fn test_replay_scenario() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("meshboard-{}.scenario", std::process::id()));
+        std::fs::write(
+            &path,
+            "me !000000aa MB MeshBoard\nnode 2 ann Ann\ntext 2 me hello board\n",
+        )?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        let text = runtime.block_on(async {
+            let mut handler = Service::from_replay(&path, Options::default()).await?;
+            let text = tokio::time::timeout(Duration::from_secs(10), async {
+                loop {
+                    match handler.status_rx.recv().await {
+                        Some(Status::NewMessage(id)) => {
+                            let state = handler.state.read().await;
+                            break state.message(id);
+                        }
+                        Some(_) => {}
+                        None => break None,
+                    }
+                }
+            })
+            .await?;
+            {
+                let state = handler.state.read().await;
+                assert_eq!(state.my_node_num()?, 0xaa);
+                assert_eq!(state.get_node_id_by_short_name("ann"), Some(2));
+            }
+            handler.finish().await;
+            anyhow::Ok(text)
+        })?;
+        std::fs::remove_file(&path)?;
+
+        let text = text.expect("No text replayed");
+        assert_eq!((text.from, text.to), (2, 0xaa));
+        assert_eq!(text.text, "hello board");
+        assert_eq!(
+            text.pk_hash.to_vec(),
+            Sha256::digest(public_key(2)).to_vec()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+
+use super::types::parse_node_id;
+
+/// Receiver of a scenario text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum To {
+    /// The node of the scenario, see [Step::Me]
+    Me,
+    Broadcast,
+    Node(u32),
+}
+
+/// What the radio reports next, one line of a scenario file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// `me <node> <short_name> [long name]`, the radio itself, goes first
+    Me {
+        num: u32,
+        short_name: String,
+        long_name: String,
+    },
+    /// `node <node> <short_name> [long name]`, a node in the node database
+    Node {
+        num: u32,
+        short_name: String,
+        long_name: String,
+    },
+    /// `text <from> <me|broadcast|node> <text>`
+    Text { from: u32, to: To, text: String },
+    /// `position <node> <lat> <lon>`, in degrees
+    Position {
+        from: u32,
+        latitude: f64,
+        longitude: f64,
+    },
+    /// `wait <ms>`
+    Wait(Duration),
+}
+
+fn node(id: Option<&str>) -> Result<u32> {
+    let id = id.ok_or_else(|| anyhow!("Missing node"))?;
+    parse_node_id(id).ok_or_else(|| anyhow!("Invalid node {id}"))
+}
+
+impl FromStr for Step {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut parts = line.trim().splitn(3, char::is_whitespace);
+        let step = parts.next().unwrap_or_default();
+        match step {
+            "me" | "node" => {
+                let num = node(parts.next())?;
+                let mut names = parts.next().unwrap_or_default().trim().splitn(2, ' ');
+                let short_name = names
+                    .next()
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| anyhow!("Missing short name"))?
+                    .to_string();
+                let long_name = names.next().unwrap_or(&short_name).trim().to_string();
+                Ok(if step == "me" {
+                    Step::Me {
+                        num,
+                        short_name,
+                        long_name,
+                    }
+                } else {
+                    Step::Node {
+                        num,
+                        short_name,
+                        long_name,
+                    }
+                })
+            }
+            "text" => {
+                let from = node(parts.next())?;
+                let mut rest = parts.next().unwrap_or_default().trim().splitn(2, ' ');
+                let to = match rest.next() {
+                    Some("me") => To::Me,
+                    Some("broadcast") => To::Broadcast,
+                    to => To::Node(node(to)?),
+                };
+                let Some(text) = rest.next().map(str::trim).filter(|text| !text.is_empty()) else {
+                    bail!("Missing text");
+                };
+                Ok(Step::Text {
+                    from,
+                    to,
+                    text: text.to_string(),
+                })
+            }
+            "position" => {
+                let from = node(parts.next())?;
+                let coordinates: Vec<f64> = parts
+                    .next()
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?;
+                let [latitude, longitude] = coordinates[..] else {
+                    bail!("Expected position <node> <lat> <lon>");
+                };
+                Ok(Step::Position {
+                    from,
+                    latitude,
+                    longitude,
+                })
+            }
+            "wait" => {
+                let ms = parts.next().ok_or_else(|| anyhow!("Missing ms"))?;
+                Ok(Step::Wait(Duration::from_millis(ms.parse()?)))
+            }
+            step => bail!("Unknown step {step}"),
+        }
+    }
+}
+
+/// Reads a scenario, blank lines and lines starting with # are ignored
+pub fn parse(scenario: &str) -> Result<Vec<Step>> {
+    let steps = scenario
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(n, line)| {
+            line.parse()
+                .map_err(|err| anyhow!("line {}: {}", n + 1, err))
+        })
+        .collect::<Result<Vec<Step>>>()?;
+    if !matches!(steps.first(), Some(Step::Me { .. })) {
+        bail!("A scenario starts with `me <node> <short_name>`");
+    }
+    if steps[1..]
+        .iter()
+        .any(|step| matches!(step, Step::Me { .. }))
+    {
+        bail!("Only one `me` step is allowed");
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scenario() -> Result<()> {
+        let steps = parse(
+            "# Ann asks the board
+me !000000aa MB MeshBoard
+node 2 ann Ann's node
+
+text !00000002 me  l 2
+text 2 broadcast hi all
+position 2 41.38 2.17
+wait 500",
+        )?;
+        assert_eq!(
+            steps,
+            vec![
+                Step::Me {
+                    num: 0xaa,
+                    short_name: "MB".into(),
+                    long_name: "MeshBoard".into()
+                },
+                Step::Node {
+                    num: 2,
+                    short_name: "ann".into(),
+                    long_name: "Ann's node".into()
+                },
+                Step::Text {
+                    from: 2,
+                    to: To::Me,
+                    text: "l 2".into()
+                },
+                Step::Text {
+                    from: 2,
+                    to: To::Broadcast,
+                    text: "hi all".into()
+                },
+                Step::Position {
+                    from: 2,
+                    latitude: 41.38,
+                    longitude: 2.17
+                },
+                Step::Wait(Duration::from_millis(500)),
+            ]
+        );
+
+        assert!(parse("node 2 ann").is_err());
+        assert!(parse("me 1 MB\nme 2 MB").is_err());
+        let err = parse("me 1 MB\ntext 2 me").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: Missing text");
+        assert!(parse("me 1 MB\nposition 2 41.38").is_err());
+        Ok(())
+    }
+}
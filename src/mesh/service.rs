@@ -1,59 +1,98 @@
 use anyhow::{Result, anyhow, bail};
-use log::{debug, error};
+use chrono::{DateTime, Local};
 use sha2::{Digest, Sha256};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     sync::{
-        RwLock,
-        mpsc::{UnboundedReceiver, UnboundedSender},
+        RwLock, broadcast,
+        mpsc::{self, UnboundedReceiver, error::TrySendError},
         oneshot,
     },
 };
 use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, debug_span, error, field, warn};
 
 use meshtastic::{
     Message,
     api::{ConnectedStreamApi, StreamApi, StreamHandle, state::Configured},
     packet::PacketDestination,
     protobufs::{
-        Data, FromRadio, MeshPacket, MyNodeInfo, PortNum, Routing, User, from_radio,
+        AdminMessage, Channel, Config, Data, DeviceMetadata, FromRadio, Heartbeat, MeshPacket,
+        ModuleConfig, MyNodeInfo, PortNum, Position, RouteDiscovery, Routing, Telemetry, User,
+        Waypoint,
+        admin_message::{self, ConfigType},
+        config, from_radio,
         mesh_packet::{self, Priority},
-        routing,
+        routing, to_radio,
     },
-    types::{MeshChannel, NodeId},
+    types::{EncodedPayload, MeshChannel, NodeId},
     utils::{
         generate_rand_id,
         stream::{BleId, build_ble_stream},
     },
 };
 
+use super::airtime::{Airtime, Modem};
+pub use super::capture::CaptureOptions;
+use super::capture::PacketLogger;
+use super::chunker;
+pub use super::contact::contact_url;
+use super::dedupe::SeenPackets;
+use super::held::HeldTexts;
+use super::keepalive::{Keepalive, Probe};
+pub use super::names::{NameResolver, Names};
+use super::nodedb::{Kept, KeptNode, NodeDb};
+use super::outbox::{Outbox, Outgoing};
+use super::replay;
 use super::router::*;
+pub use super::traceroute::Hop;
+use super::traceroute::hops;
 pub use super::types::*;
 
-macro_rules! r {
-    ($slf:ident . $field:ident) => {
-        $slf.state.read().await.$field
-    };
-}
-macro_rules! w {
-    ($slf:ident . $field:ident) => {
-        $slf.state.write().await.$field
-    };
-}
 macro_rules! check {
     ($expr:expr) => {
         if let Err(err) = $expr {
-            error!("Failed `{}` : {:?}", stringify!($expr), err);
+            error!(target: "meshloop", "Failed `{}` : {:?}", stringify!($expr), err);
         }
     };
 }
 use TextMessageStatus::*;
 
+// Followed by the BLE device name, radios running at once keep apart
+const SEEN_PACKETS_PREFIX: &str = "./meshboard.seen.";
+// Where a single radio kept them before, taken over by the first radio
+const SEEN_PACKETS_LEGACY_PATH: &str = "./meshboard.seen";
+const SEEN_PACKETS_CAPACITY: usize = 64;
+const HELD_PREFIX: &str = "./meshboard.held.";
+const NODES_PREFIX: &str = "./meshboard.nodes.";
+// Texts kept per offline node, the oldest are dropped
+const HELD_CAPACITY: usize = 32;
+// Events a subscriber can fall behind before losing the oldest ones, the
+// radio does not wait for slow subscribers
+const STATUS_CAPACITY: usize = 1024;
+// Texts and requests queued for the service. Senders do not wait, a text
+// that does not fit is dropped and told with [Status::Dropped]. While
+// reconnecting the texts move on to the outbox, so the queue stays free.
+const COMMAND_CAPACITY: usize = 64;
+// Packets from the radio waiting to be handled, the oldest are dropped past
+// this
+const PACKET_CAPACITY: usize = 1024;
+// Telemetry samples and positions kept per node
+const TELEMETRY_HISTORY: usize = 64;
+const TRACK_HISTORY: usize = 64;
+// Route discovery goes back and forth across the mesh, give it time
+const TRACEROUTE_TIMEOUT: Duration = Duration::from_secs(60);
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(60);
+// Pause between attempts to reconnect to the radio
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Status {
     Heartbeat(usize),
@@ -61,43 +100,281 @@ pub enum Status {
     NewMessage(u32),
     UpdatedMessage(u32),
     FromRadio(FromRadio),
+    /// This many events were lost: the subscriber or the service fell
+    /// behind and the oldest ones were dropped, or the texts queued did not
+    /// fit
+    Dropped(u64),
+}
+
+/// Tunables of the mesh service
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Longer texts are split in numbered chunks
+    pub max_payload: usize,
+    /// Minimum delay between consecutive outgoing texts
+    pub send_delay: Duration,
+    /// Retransmissions of unacked direct texts
+    pub max_retries: u32,
+    /// Time to wait for an ack before retransmitting
+    pub ack_timeout: Duration,
+    /// Where to write the packets received, None does not capture them
+    pub capture: Option<CaptureOptions>,
+    /// Reconnect to the radio after this long without a packet from it, zero
+    /// never does
+    pub idle_timeout: Duration,
+    /// Send the radio a heartbeat after each this long without a packet from
+    /// it, and a config request one interval before `idle_timeout`. Zero
+    /// never does
+    pub keepalive: Duration,
+    /// Direct texts to nodes not heard for this long are held until the node
+    /// is heard again, zero sends them right away
+    pub offline_after: Duration,
+    /// Percent of the hour the radio may transmit, None for the one of its
+    /// region
+    pub duty_cycle: Option<f64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_payload: chunker::DEFAULT_MAX_PAYLOAD,
+            send_delay: Duration::from_secs(1),
+            max_retries: 3,
+            ack_timeout: Duration::from_secs(30),
+            capture: None,
+            idle_timeout: Duration::from_secs(300),
+            keepalive: Duration::from_secs(60),
+            offline_after: Duration::ZERO,
+            duty_cycle: None,
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct HandlerState {
-    pub my_node_info: Option<MyNodeInfo>,
-    pub nodes: HashMap<u32, User>,
-    pub messages: HashMap<u32, TextMessage>,
+    my_node_info: Option<MyNodeInfo>,
+    my_metadata: Option<DeviceMetadata>,
+    /// Settings of the connected radio, one entry per section
+    configs: Vec<Config>,
+    module_configs: Vec<ModuleConfig>,
+    /// Channels of the connected radio, by index
+    channels: Vec<Channel>,
+    nodes: HashMap<u32, User>,
+    /// Names of the nodes, the ones they announce and their BBS nicknames
+    names: NameResolver,
+    positions: HashMap<u32, Position>,
+    heard: HashMap<u32, Heard>,
+    /// Recent (timestamp in ms, metrics) per node, oldest first
+    telemetry: HashMap<u32, VecDeque<(u64, Metrics)>>,
+    /// Recent (timestamp in ms, (latitude, longitude)) per node, oldest first
+    tracks: HashMap<u32, VecDeque<(u64, (f64, f64))>>,
+    messages: HashMap<u32, TextMessage>,
+    /// Time the radio spent transmitting, against the duty cycle of its
+    /// region
+    airtime: Airtime,
+    /// Nodes kept from the last run that the radio did not stream again yet
+    stale: HashSet<u32>,
 }
 
 pub type State = Arc<RwLock<HandlerState>>;
 
+/// Receiver of the service status events, each subscriber gets all of them
+pub struct StatusReceiver(broadcast::Receiver<Status>);
+
+impl StatusReceiver {
+    /// Next event, None once the service is gone. Events lost because this
+    /// subscriber fell behind are told with [Status::Dropped].
+    pub async fn recv(&mut self) -> Option<Status> {
+        match self.0.recv().await {
+            Ok(status) => Some(status),
+            Err(broadcast::error::RecvError::Lagged(lost)) => {
+                debug!(target: "meshloop", "Status subscriber lagged, {} events lost", lost);
+                Some(Status::Dropped(lost))
+            }
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    }
+}
+
+/// The service is gone, nothing asked of it will ever be done
+#[derive(Debug)]
+pub struct ServiceFinished;
+
+impl fmt::Display for ServiceFinished {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Service finished")
+    }
+}
+
+impl std::error::Error for ServiceFinished {}
+
+/// Where the service gets its packets from
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// A radio, by BLE device name
+    Ble(String),
+    /// A capture or scenario file, see [Service::from_replay]
+    Replay(PathBuf),
+}
+
+/// Requests from the handler that wait for an answer from the mesh
+enum Request {
+    Traceroute {
+        to: u32,
+        reply: oneshot::Sender<Vec<Hop>>,
+    },
+    Admin {
+        to: u32,
+        message: AdminMessage,
+        // None when no answer is expected
+        reply: Option<oneshot::Sender<AdminMessage>>,
+    },
+}
+
 pub struct Handler {
     pub state: State,
-    pub msg_tx: UnboundedSender<TextMessage>,
-    pub status_rx: UnboundedReceiver<Status>,
+    pub msg_tx: mpsc::Sender<TextMessage>,
+    request_tx: mpsc::Sender<Request>,
+    pub status_rx: StatusReceiver,
+    // To tell the subscribers about the texts dropped
+    status_tx: broadcast::Sender<Status>,
 
     pub cancel: CancellationToken,
     finished_rx: tokio::sync::oneshot::Receiver<()>,
+    max_payload: usize,
 }
 
 pub struct Service {
     state: State,
     cancel: CancellationToken,
+    transport: Transport,
     packet_rx: UnboundedReceiver<FromRadio>,
-    stream_api: ConnectedStreamApi<Configured>,
-    msg_rx: UnboundedReceiver<TextMessage>,
-    status_tx: UnboundedSender<Status>,
+    // None while reconnecting
+    stream_api: Option<ConnectedStreamApi<Configured>>,
+    msg_rx: mpsc::Receiver<TextMessage>,
+    request_rx: mpsc::Receiver<Request>,
+    // Traceroutes waiting for a reply, by request packet id
+    traceroutes: HashMap<u32, oneshot::Sender<Vec<Hop>>>,
+    // Admin requests waiting for a reply, by request packet id
+    admin_replies: HashMap<u32, oneshot::Sender<AdminMessage>>,
+    // Last session passkey of each node, remote nodes reject admin
+    // messages that do not carry it
+    session_passkeys: HashMap<u32, Vec<u8>>,
+    status_tx: broadcast::Sender<Status>,
     finished_tx: tokio::sync::oneshot::Sender<()>,
     config_complete: bool,
+    seen_packets: SeenPackets,
+    send_delay: Duration,
+    outbox: Outbox,
+    capture: Option<PacketLogger>,
+    idle_timeout: Duration,
+    keepalive: Duration,
+    held: HeldTexts,
+    offline_after: Duration,
+    node_db: NodeDb,
 }
 
 impl HandlerState {
+    /// Replaces the config section of the same kind
+    pub fn update_config(&mut self, config: Config) {
+        if let Some(config::PayloadVariant::Lora(lora)) = &config.payload_variant {
+            let modem = match lora.use_preset {
+                true => Modem::preset(lora.modem_preset().as_str_name()),
+                false => Modem::custom(
+                    lora.spread_factor,
+                    lora.bandwidth.saturating_mul(1000),
+                    lora.coding_rate,
+                ),
+            };
+            self.airtime.radio(
+                modem.unwrap_or(Modem::LONG_FAST),
+                lora.region().as_str_name(),
+            );
+        }
+        let kind = config.payload_variant.as_ref().map(std::mem::discriminant);
+        self.configs
+            .retain(|c| c.payload_variant.as_ref().map(std::mem::discriminant) != kind);
+        self.configs.push(config);
+    }
+    /// Replaces the module config section of the same kind
+    pub fn update_module_config(&mut self, config: ModuleConfig) {
+        let kind = config.payload_variant.as_ref().map(std::mem::discriminant);
+        self.module_configs
+            .retain(|c| c.payload_variant.as_ref().map(std::mem::discriminant) != kind);
+        self.module_configs.push(config);
+    }
+    /// Replaces the channel with the same index
+    pub fn update_channel(&mut self, channel: Channel) {
+        self.channels.retain(|c| c.index != channel.index);
+        self.channels.push(channel);
+        self.channels.sort_by_key(|c| c.index);
+    }
+    /// Records a node and the names it announced
+    pub fn add_node(&mut self, num: u32, user: User) {
+        self.names.radio_names(num, &user.short_name, &user.long_name);
+        self.nodes.insert(num, user);
+        self.stale.remove(&num);
+    }
+    /// Loads the nodes kept from the last run, stale until the radio streams
+    /// them again
+    fn restore(&mut self, kept: Kept) {
+        if self.my_node_info.is_none()
+            && let Some(my_node_num) = kept.my_node_num
+        {
+            self.my_node_info = Some(MyNodeInfo {
+                my_node_num,
+                ..Default::default()
+            });
+        }
+        for node in kept.nodes {
+            let user = User {
+                id: node.id,
+                long_name: node.long_name,
+                short_name: node.short_name,
+                hw_model: node.hw_model,
+                public_key: hex::decode(&node.public_key).unwrap_or_default(),
+                ..Default::default()
+            };
+            self.add_node(node.num, user);
+            self.stale.insert(node.num);
+        }
+    }
+    /// Forgets the kept nodes the radio did not stream again, returns how
+    /// many
+    fn evict_stale(&mut self) -> usize {
+        for num in &self.stale {
+            self.nodes.remove(num);
+            self.names.remove(*num);
+        }
+        self.stale.drain().count()
+    }
+    /// The node and the nodes to keep for the next run
+    fn kept(&self) -> Kept {
+        Kept {
+            my_node_num: self.my_node_info.as_ref().map(|info| info.my_node_num),
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(num, user)| KeptNode {
+                    num: *num,
+                    id: user.id.clone(),
+                    long_name: user.long_name.clone(),
+                    short_name: user.short_name.clone(),
+                    hw_model: user.hw_model,
+                    public_key: hex::encode(&user.public_key),
+                })
+                .collect(),
+        }
+    }
     pub fn get_long_name_by_node_id(&self, user_id: u32) -> Option<String> {
-        self.nodes.get(&user_id).map(|user| user.long_name.clone())
+        self.names.long_name(user_id)
     }
     pub fn get_short_name_by_node_id(&self, user_id: u32) -> Option<String> {
-        self.nodes.get(&user_id).map(|user| user.long_name.clone())
+        self.names.short_name(user_id)
+    }
+    /// Last known (latitude, longitude) of the node, in degrees
+    pub fn get_position_by_node_id(&self, node_id: u32) -> Option<(f64, f64)> {
+        coordinates(self.positions.get(&node_id)?)
     }
     pub fn get_node_id_by_short_name(&self, short_name: &str) -> Option<u32> {
         for (id, user) in &self.nodes {
@@ -107,29 +384,40 @@ impl HandlerState {
         }
         None
     }
+    /// Node id from a short name, a "!a4c13b9f" hex id or a decimal node
+    /// number
+    pub fn resolve_node(&self, name: &str) -> Option<u32> {
+        self.names.find(name).or_else(|| parse_node_id(name))
+    }
 
     pub fn format_msg(&self, msg: &TextMessage) -> String {
-        let me = self.my_node_info.as_ref().unwrap().my_node_num;
-        let name = |id| {
-            self.get_long_name_by_node_id(id)
-                .unwrap_or(format!("NodeId({})", id))
-        };
+        let me = self.my_node_num().ok();
+        let name = |id| self.node_name(id);
+        let time = DateTime::from_timestamp_millis(msg.ts as i64)
+            .map(|ts| ts.with_timezone(&Local).format("%H:%M").to_string())
+            .unwrap_or_default();
 
         let status = match msg.status {
             Sent => "📤".into(),
-            Recieved => "".into(),
+            Recieved => match msg.signal {
+                Some(signal) => format!("({signal})"),
+                None => "".into(),
+            },
             ImplicitAck => "✔️".into(),
             ExplicitAck => "✔️✔️".into(),
             RoutingError(error) => format!("❌ {:?}", error),
+            Retrying(attempt) => format!("🔁{}", attempt),
+            Failed => "❌".into(),
         };
 
-        if msg.to == 0xffffffff {
-            format!("💬 {} : {} {} ", name(msg.from), msg.text, status)
-        } else if msg.to == me {
-            format!("👤 {} : {} {}", name(msg.from), msg.text, status)
+        if msg.to == BROADCAST_ADDR {
+            format!("{} 💬 {} : {} {} ", time, name(msg.from), msg.text, status)
+        } else if Some(msg.to) == me {
+            format!("{} 👤 {} : {} {}", time, name(msg.from), msg.text, status)
         } else {
             format!(
-                "📩 {} → {} : {} {}",
+                "{} 📩 {} → {} : {} {}",
+                time,
                 name(msg.from),
                 name(msg.to),
                 msg.text,
@@ -138,21 +426,89 @@ impl HandlerState {
         }
     }
 
-    pub async fn msg(&self, id: u32) -> Option<TextMessage> {
+    /// Number of the connected node, known once the radio tells it
+    pub fn my_node_num(&self) -> Result<u32> {
+        match &self.my_node_info {
+            Some(info) => Ok(info.my_node_num),
+            None => bail!("The radio did not tell its node number yet"),
+        }
+    }
+    pub fn my_short_name(&self) -> Option<String> {
+        self.get_short_name_by_node_id(self.my_node_num().ok()?)
+    }
+    /// What the connected node announced about itself
+    pub fn my_user(&self) -> Option<&User> {
+        self.nodes.get(&self.my_node_num().ok()?)
+    }
+    pub fn my_metadata(&self) -> Option<&DeviceMetadata> {
+        self.my_metadata.as_ref()
+    }
+    /// Settings of the connected radio, one entry per section
+    pub fn configs(&self) -> &[Config] {
+        &self.configs
+    }
+    pub fn module_configs(&self) -> &[ModuleConfig] {
+        &self.module_configs
+    }
+    /// Channels of the connected radio, by index
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+    pub fn names(&self) -> &NameResolver {
+        &self.names
+    }
+    /// Nickname, short name or hex id of the node, whichever is known
+    pub fn node_name(&self, id: u32) -> String {
+        self.names.display_name(id)
+    }
+    pub fn set_nickname(&mut self, id: u32, nickname: &str) {
+        self.names.nickname(id, nickname);
+    }
+    pub fn user(&self, id: u32) -> Option<&User> {
+        self.nodes.get(&id)
+    }
+    pub fn users(&self) -> impl Iterator<Item = (u32, &User)> {
+        self.nodes.iter().map(|(num, user)| (*num, user))
+    }
+    /// Last time each node was heard, and how well
+    pub fn heard(&self) -> impl Iterator<Item = (u32, &Heard)> {
+        self.heard.iter().map(|(num, heard)| (*num, heard))
+    }
+    /// Recent (timestamp in ms, metrics) the node reported, oldest first
+    pub fn telemetry(&self, id: u32) -> impl DoubleEndedIterator<Item = (u64, &Metrics)> {
+        self.telemetry
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|(ts, metrics)| (*ts, metrics))
+    }
+    /// Newest (timestamp in ms, (latitude, longitude)) of the node track
+    pub fn last_position(&self, id: u32) -> Option<(u64, (f64, f64))> {
+        self.tracks.get(&id)?.back().copied()
+    }
+    /// Newest (timestamp in ms, (latitude, longitude)) of each node tracked
+    pub fn last_positions(&self) -> impl Iterator<Item = (u32, u64, (f64, f64))> {
+        self.tracks
+            .iter()
+            .filter_map(|(num, track)| track.back().map(|(ts, coords)| (*num, *ts, *coords)))
+    }
+    pub fn message(&self, id: u32) -> Option<TextMessage> {
         self.messages.get(&id).cloned()
     }
-
-    pub async fn my_node_num(&self) -> u32 {
-        self.my_node_info.as_ref().unwrap().my_node_num
+    pub fn messages(&self) -> impl Iterator<Item = &TextMessage> {
+        self.messages.values()
     }
-    pub async fn my_short_name(&self) -> Option<String> {
-        self.my_node_info
-            .as_ref()
-            .and_then(|n| self.get_short_name_by_node_id(n.my_node_num))
+    /// Whether the radio is close to its duty cycle
+    pub fn is_congested(&mut self, now: Instant) -> bool {
+        self.airtime.is_congested(now)
     }
 }
 
 impl Handler {
+    /// Independent receiver of the status events sent from now on
+    pub fn subscribe(&self) -> StatusReceiver {
+        StatusReceiver(self.status_rx.0.resubscribe())
+    }
     pub async fn wait_for_boot_ready(&mut self, timeout_secs: u64) -> Result<()> {
         let now = tokio::time::Instant::now();
         loop {
@@ -173,32 +529,213 @@ impl Handler {
         }
         Ok(())
     }
-    pub async fn send_text<T: Into<String>, D: Into<Destination>>(
-        &self,
-        text: T,
-        to: D,
-    ) -> Result<()> {
-        let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
-        let to = match to.into() {
+    async fn resolve(&self, to: Destination) -> Result<u32> {
+        Ok(match to {
             Destination::Node(node_num) => node_num,
-            Destination::Broadcast => 0xffffffff,
+            Destination::Broadcast => BROADCAST_ADDR,
             Destination::ShortName(short_name) => {
-                let mut id = None;
-                for (node_id, node) in &r!(self.nodes) {
-                    if node.short_name == short_name {
-                        id = Some(*node_id);
-                        break;
-                    }
-                }
-                let Some(id) = id else {
+                let Some(id) = self.state.read().await.resolve_node(&short_name) else {
                     bail!("Node '{short_name}' not found")
                 };
                 id
             }
+        })
+    }
+    pub async fn send_text<T: Into<String>, D: Into<Destination>>(
+        &self,
+        text: T,
+        to: D,
+    ) -> Result<()> {
+        self.send_text_on(text, to, 0).await
+    }
+    /// Sends on the given Meshtastic channel index instead of the primary one
+    pub async fn send_text_on<T: Into<String>, D: Into<Destination>>(
+        &self,
+        text: T,
+        to: D,
+        channel: u32,
+    ) -> Result<()> {
+        self.send_text_as(text, to, channel, Urgency::Reply).await
+    }
+    /// Queues the text behind the more urgent ones waiting, see [Urgency]
+    pub async fn send_text_as<T: Into<String>, D: Into<Destination>>(
+        &self,
+        text: T,
+        to: D,
+        channel: u32,
+        urgency: Urgency,
+    ) -> Result<()> {
+        MeshChannel::new(channel)?;
+        let from = self.state.read().await.my_node_num()?;
+        let to = self.resolve(to.into()).await?;
+        let text: String = text.into();
+        let chunks = chunker::split(&text, self.max_payload);
+        // The whole text is queued or none of it, a text cut short would
+        // still read "1/3"
+        let permits = match self.msg_tx.try_reserve_many(chunks.len()) {
+            Ok(permits) => permits,
+            Err(TrySendError::Full(())) => {
+                warn!(target: "meshloop", "Text queue full, dropping a text to {}", format_node_id(to));
+                let _ = self.status_tx.send(Status::Dropped(chunks.len() as u64));
+                bail!("Too many texts queued, dropped");
+            }
+            Err(TrySendError::Closed(())) => return Err(ServiceFinished.into()),
         };
-        self.msg_tx.send(TextMessage::sent(from, to, text.into()))?;
+        for (permit, chunk) in permits.zip(chunks) {
+            permit.send(TextMessage {
+                urgency,
+                ..TextMessage::sent(from, to, chunk, channel)
+            });
+        }
         Ok(())
     }
+    // Queues the request without waiting, the service may be reconnecting
+    fn request(&self, request: Request) -> Result<()> {
+        match self.request_tx.try_send(request) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => bail!("Too many requests queued, try again later"),
+            Err(TrySendError::Closed(_)) => Err(ServiceFinished.into()),
+        }
+    }
+    /// Shares the waypoint with every node, one with the same id replaces it.
+    /// It waits in the outbox behind the texts, as [Urgency::Bulk].
+    pub async fn send_waypoint(&self, waypoint: Waypoint) -> Result<()> {
+        let from = self.state.read().await.my_node_num()?;
+        let msg = TextMessage {
+            urgency: Urgency::Bulk,
+            waypoint: Some(waypoint),
+            ..TextMessage::sent(from, BROADCAST_ADDR, String::new(), 0)
+        };
+        match self.msg_tx.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => bail!("Too many texts queued, dropped"),
+            Err(TrySendError::Closed(_)) => Err(ServiceFinished.into()),
+        }
+    }
+    /// Hops to the node and the SNR each one heard the request with
+    pub async fn traceroute<D: Into<Destination>>(&self, to: D) -> Result<Vec<Hop>> {
+        let to = self.resolve(to.into()).await?;
+        let (reply, reply_rx) = oneshot::channel();
+        self.request(Request::Traceroute { to, reply })?;
+        match tokio::time::timeout(TRACEROUTE_TIMEOUT, reply_rx).await {
+            Ok(Ok(hops)) => Ok(hops),
+            Ok(Err(_)) => Err(ServiceFinished.into()),
+            Err(_) => bail!("No traceroute reply from {}", format_node_id(to)),
+        }
+    }
+    /// Reboots the node after `seconds`
+    pub async fn admin_reboot<D: Into<Destination>>(&self, to: D, seconds: i32) -> Result<()> {
+        let to = self.resolve(to.into()).await?;
+        self.admin_set(to, admin_message::PayloadVariant::RebootSeconds(seconds))
+            .await
+    }
+    pub async fn admin_set_owner<D: Into<Destination>>(
+        &self,
+        to: D,
+        short_name: &str,
+        long_name: &str,
+    ) -> Result<()> {
+        let to = self.resolve(to.into()).await?;
+        let user = User {
+            short_name: short_name.to_string(),
+            long_name: long_name.to_string(),
+            ..Default::default()
+        };
+        self.admin_set(to, admin_message::PayloadVariant::SetOwner(user))
+            .await
+    }
+    /// Fixes the node position, in degrees and meters, and stops it from
+    /// using its GPS
+    pub async fn admin_set_fixed_position<D: Into<Destination>>(
+        &self,
+        to: D,
+        latitude: f64,
+        longitude: f64,
+        altitude: i32,
+    ) -> Result<()> {
+        let to = self.resolve(to.into()).await?;
+        let position = Position {
+            latitude_i: Some((latitude * 1e7) as i32),
+            longitude_i: Some((longitude * 1e7) as i32),
+            altitude: Some(altitude),
+            ..Default::default()
+        };
+        self.admin_set(
+            to,
+            admin_message::PayloadVariant::SetFixedPosition(position),
+        )
+        .await
+    }
+    pub async fn admin_get_config<D: Into<Destination>>(
+        &self,
+        to: D,
+        config_type: ConfigType,
+    ) -> Result<Config> {
+        let to = self.resolve(to.into()).await?;
+        let request = admin_message::PayloadVariant::GetConfigRequest(config_type as i32);
+        match self.admin_request(to, request).await?.payload_variant {
+            Some(admin_message::PayloadVariant::GetConfigResponse(config)) => Ok(config),
+            other => bail!("Unexpected admin reply {:?}", other),
+        }
+    }
+    /// Writes a config section to the connected radio, which may reboot
+    pub async fn set_config(&self, config: Config) -> Result<()> {
+        let to = self.state.read().await.my_node_num()?;
+        self.admin_set(to, admin_message::PayloadVariant::SetConfig(config.clone()))
+            .await?;
+        self.state.write().await.update_config(config);
+        Ok(())
+    }
+    /// Writes a module config section to the connected radio, which may reboot
+    pub async fn set_module_config(&self, config: ModuleConfig) -> Result<()> {
+        let to = self.state.read().await.my_node_num()?;
+        self.admin_set(
+            to,
+            admin_message::PayloadVariant::SetModuleConfig(config.clone()),
+        )
+        .await?;
+        self.state.write().await.update_module_config(config);
+        Ok(())
+    }
+    // Remote nodes only take changes along with a passkey they handed out in
+    // a recent reply, ask for their metadata first to get one
+    async fn admin_set(&self, to: u32, variant: admin_message::PayloadVariant) -> Result<()> {
+        if to != self.state.read().await.my_node_num()? {
+            self.admin_request(
+                to,
+                admin_message::PayloadVariant::GetDeviceMetadataRequest(true),
+            )
+            .await?;
+        }
+        self.request(Request::Admin {
+            to,
+            message: AdminMessage {
+                payload_variant: Some(variant),
+                ..Default::default()
+            },
+            reply: None,
+        })
+    }
+    async fn admin_request(
+        &self,
+        to: u32,
+        variant: admin_message::PayloadVariant,
+    ) -> Result<AdminMessage> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.request(Request::Admin {
+            to,
+            message: AdminMessage {
+                payload_variant: Some(variant),
+                ..Default::default()
+            },
+            reply: Some(reply),
+        })?;
+        match tokio::time::timeout(ADMIN_TIMEOUT, reply_rx).await {
+            Ok(Ok(message)) => Ok(message),
+            Ok(Err(_)) => Err(ServiceFinished.into()),
+            Err(_) => bail!("No admin reply from {}", format_node_id(to)),
+        }
+    }
     pub async fn finish(mut self) {
         self.cancel.cancel();
         loop {
@@ -213,48 +750,160 @@ impl Handler {
 }
 
 impl Service {
-    pub async fn from_ble(ble_device: &str) -> Result<Handler> {
-        let ble_stream =
-            build_ble_stream(&BleId::from_name(&ble_device), Duration::from_secs(5)).await?;
-        Self::build(ble_stream).await
+    pub async fn from_ble(ble_device: &str, options: Options) -> Result<Handler> {
+        let ble_stream = Self::ble_stream(ble_device).await?;
+        let device: String = ble_device
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let seen_path = PathBuf::from(format!("{SEEN_PACKETS_PREFIX}{device}"));
+        let legacy_path = Path::new(SEEN_PACKETS_LEGACY_PATH);
+        if !seen_path.exists()
+            && legacy_path.exists()
+            && let Err(err) = std::fs::rename(legacy_path, &seen_path)
+        {
+            error!(
+                target: "meshloop",
+                "Cannot move the seen packets to {}: {}",
+                seen_path.display(),
+                err
+            );
+        }
+        let seen_packets =
+            SeenPackets::open(&seen_path, SEEN_PACKETS_CAPACITY).unwrap_or_else(|err| {
+                error!(target: "meshloop", "Cannot load seen packets: {}", err);
+                SeenPackets::new(SEEN_PACKETS_CAPACITY)
+            });
+        let held_path = PathBuf::from(format!("{HELD_PREFIX}{device}"));
+        let held = HeldTexts::open(&held_path, HELD_CAPACITY).unwrap_or_else(|err| {
+            error!(target: "meshloop", "Cannot load held texts: {}", err);
+            HeldTexts::new(HELD_CAPACITY)
+        });
+        let nodes_path = PathBuf::from(format!("{NODES_PREFIX}{device}"));
+        let node_db = NodeDb::open(&nodes_path).unwrap_or_else(|err| {
+            error!(target: "meshloop", "Cannot load the nodes: {}", err);
+            NodeDb::default()
+        });
+        let transport = Transport::Ble(ble_device.to_string());
+        Self::build(ble_stream, options, seen_packets, held, node_db, transport).await
+    }
+
+    async fn ble_stream(
+        ble_device: &str,
+    ) -> Result<StreamHandle<impl AsyncReadExt + AsyncWriteExt + Send + 'static>> {
+        Ok(build_ble_stream(&BleId::from_name(ble_device), Duration::from_secs(5)).await?)
     }
 
-    async fn build<S>(stream_handle: StreamHandle<S>) -> Result<Handler>
+    /// Service fed with the packets of a capture or scenario file instead of
+    /// a radio, see [replay::open]. What it sends goes nowhere, and it
+    /// finishes shortly after the last packet.
+    pub async fn from_replay(path: &Path, options: Options) -> Result<Handler> {
+        let (stream, player) = replay::open(path)?;
+        let options = Options {
+            capture: None,
+            idle_timeout: Duration::ZERO,
+            keepalive: Duration::ZERO,
+            ..options
+        };
+        let transport = Transport::Replay(path.to_path_buf());
+        let seen_packets = SeenPackets::new(SEEN_PACKETS_CAPACITY);
+        let held = HeldTexts::new(HELD_CAPACITY);
+        let handler = Self::build(
+            stream,
+            options,
+            seen_packets,
+            held,
+            NodeDb::default(),
+            transport,
+        )
+        .await?;
+        tokio::spawn(player.play(handler.cancel.clone()));
+        Ok(handler)
+    }
+
+    pub async fn connect(transport: &Transport, options: Options) -> Result<Handler> {
+        match transport {
+            Transport::Ble(device) => Self::from_ble(device, options).await,
+            Transport::Replay(path) => Self::from_replay(path, options).await,
+        }
+    }
+
+    // Connects to the radio and asks for its config and node database
+    async fn attach<S>(
+        stream_handle: StreamHandle<S>,
+    ) -> Result<(UnboundedReceiver<FromRadio>, ConnectedStreamApi<Configured>)>
     where
         S: AsyncReadExt + AsyncWriteExt + Send + 'static,
     {
-        let stream_api = StreamApi::new();
-        let config_id = generate_rand_id();
+        let (packet_rx, stream_api) = StreamApi::new().connect(stream_handle).await;
+        let stream_api = stream_api.configure(generate_rand_id()).await?;
+        Ok((packet_rx, stream_api))
+    }
 
-        let (packet_rx, stream_api) = stream_api.connect(stream_handle).await;
-        let stream_api = stream_api.configure(config_id).await?;
+    async fn build<S>(
+        stream_handle: StreamHandle<S>,
+        options: Options,
+        seen_packets: SeenPackets,
+        held: HeldTexts,
+        mut node_db: NodeDb,
+        transport: Transport,
+    ) -> Result<Handler>
+    where
+        S: AsyncReadExt + AsyncWriteExt + Send + 'static,
+    {
+        let (packet_rx, stream_api) = Self::attach(stream_handle).await?;
 
-        let (status_tx, status_rx) = tokio::sync::mpsc::unbounded_channel::<Status>();
-        let (msg_tx, msg_rx) = tokio::sync::mpsc::unbounded_channel::<TextMessage>();
+        let (status_tx, status_rx) = broadcast::channel::<Status>(STATUS_CAPACITY);
+        let (msg_tx, msg_rx) = mpsc::channel::<TextMessage>(COMMAND_CAPACITY);
+        let (request_tx, request_rx) = mpsc::channel::<Request>(COMMAND_CAPACITY);
 
         let (finished_tx, finished_rx) = oneshot::channel::<()>();
 
-        let state = Arc::new(RwLock::new(HandlerState::default()));
+        let mut state = HandlerState {
+            airtime: Airtime::new(options.duty_cycle),
+            ..Default::default()
+        };
+        state.restore(node_db.take_loaded());
+        let state = Arc::new(RwLock::new(state));
 
         let cancel = CancellationToken::new();
 
+        let capture = options.capture.map(PacketLogger::new).transpose()?;
+
         let handler = Handler {
             state: state.clone(),
             cancel: cancel.clone(),
             msg_tx,
-            status_rx,
+            request_tx,
+            status_rx: StatusReceiver(status_rx),
+            status_tx: status_tx.clone(),
             finished_rx,
+            max_payload: options.max_payload,
         };
 
         let service = Service {
             state,
             cancel,
+            transport,
             packet_rx,
-            stream_api,
+            stream_api: Some(stream_api),
             msg_rx,
+            request_rx,
+            traceroutes: HashMap::new(),
+            admin_replies: HashMap::new(),
+            session_passkeys: HashMap::new(),
             status_tx,
             finished_tx,
             config_complete: false,
+            seen_packets,
+            send_delay: options.send_delay,
+            outbox: Outbox::new(options.max_retries, options.ack_timeout),
+            capture,
+            idle_timeout: options.idle_timeout,
+            keepalive: options.keepalive,
+            held,
+            offline_after: options.offline_after,
+            node_db,
         };
 
         tokio::spawn(service.start());
@@ -262,20 +911,105 @@ impl Service {
         Ok(handler)
     }
 
-    pub async fn start(self) -> Result<()> {
-        if let Err(error) = self.start1().await {
-            error!("Process finished with error: {}", error);
-            Err(error)
+    /// Runs until cancelled. A radio connection that is lost or goes idle
+    /// is reconnected, state and pending texts are kept meanwhile.
+    pub async fn start(mut self) -> Result<()> {
+        let ret = loop {
+            let ret = self.run().await;
+            self.packet_rx.close();
+            if let Some(stream_api) = self.stream_api.take() {
+                check!(stream_api.disconnect().await);
+            }
+            let Err(error) = ret else {
+                break Ok(());
+            };
+            let Transport::Ble(ble_device) = self.transport.clone() else {
+                break Err(error);
+            };
+            warn!(target: "meshloop", "Radio connection lost: {}, reconnecting", error);
+            if !self.reconnect(&ble_device).await {
+                break Ok(());
+            }
+        };
+        if let Err(error) = &ret {
+            error!(target: "meshloop", "Process finished with error: {}", error);
+        }
+        check!(self.finished_tx.send(()));
+        ret
+    }
+
+    // Retries until connected, false if cancelled first. Texts sent meanwhile
+    // wait in the outbox, requests need the radio and are dropped.
+    async fn reconnect(&mut self, ble_device: &str) -> bool {
+        loop {
+            let delay = tokio::time::sleep(RECONNECT_DELAY);
+            tokio::pin!(delay);
+            loop {
+                tokio::select! {
+                    _ = self.cancel.cancelled() => return false,
+                    _ = &mut delay => break,
+                    Some(msg) = self.msg_rx.recv() => self.queue_text(msg).await,
+                    Some(_) = self.request_rx.recv() => {
+                        warn!(target: "meshloop", "Not connected to the radio, dropping a request");
+                    }
+                }
+            }
+            let attach = async {
+                match Self::ble_stream(ble_device).await {
+                    Ok(stream) => Self::attach(stream).await,
+                    Err(error) => Err(error),
+                }
+            };
+            tokio::pin!(attach);
+            let attached = loop {
+                tokio::select! {
+                    _ = self.cancel.cancelled() => return false,
+                    attached = &mut attach => break attached,
+                    Some(msg) = self.msg_rx.recv() => self.queue_text(msg).await,
+                    Some(_) = self.request_rx.recv() => {
+                        warn!(target: "meshloop", "Not connected to the radio, dropping a request");
+                    }
+                }
+            };
+            match attached {
+                Ok((packet_rx, stream_api)) => {
+                    self.packet_rx = packet_rx;
+                    self.stream_api = Some(stream_api);
+                    self.config_complete = false;
+                    return true;
+                }
+                Err(error) => {
+                    warn!(target: "meshloop", "Cannot reconnect to {}: {}", ble_device, error)
+                }
+            }
+        }
+    }
+
+    // Texts to nodes not heard lately are held, the rest wait in the outbox
+    async fn queue_text(&mut self, msg: TextMessage) {
+        if self.is_offline(msg.to).await {
+            debug!(target: "meshloop", "Holding a text to {} until it is heard", format_node_id(msg.to));
+            check!(self.held.hold(&msg, msg.ts));
         } else {
-            Ok(())
+            self.outbox.push(msg);
         }
     }
 
-    pub async fn start1(mut self) -> Result<()> {
+    fn api(&mut self) -> Result<&mut ConnectedStreamApi<Configured>> {
+        self.stream_api
+            .as_mut()
+            .ok_or_else(|| anyhow!("Not connected to the radio"))
+    }
+
+    // Handles packets, texts and requests until cancelled, or an error if the
+    // radio connection is lost
+    async fn run(&mut self) -> Result<()> {
         let mut buffer_flushed = false;
         let mut packet_count = 0;
         let mut hearthbeat_counter = 0;
-        let mut send_msg_queue = VecDeque::new();
+        let mut next_send = tokio::time::Instant::now();
+        let mut last_packet = tokio::time::Instant::now();
+        let mut keepalive = Keepalive::new(self.keepalive, self.idle_timeout, Instant::now());
         let mut ret = Ok(());
 
         check!(self.status_tx.send(Status::Heartbeat(0)));
@@ -285,15 +1019,38 @@ impl Service {
                 from_radio = self.packet_rx.recv() => {
                     packet_count += 1;
                     let Some(from_radio) = from_radio else {
-                        debug!(target: "meshloop","BLE stream closed");
+                        debug!(target: "meshloop", "BLE stream closed");
                         ret = Err(anyhow!("BLE stream closed"));
                         break;
                     };
-                    debug!(target: "meshloop","Radio Rx: {:?}", from_radio);
+                    last_packet = tokio::time::Instant::now();
+                    keepalive.heard(Instant::now());
+                    // The radio does not wait, past the capacity the oldest
+                    // packets go
+                    let mut lost = 0;
+                    while self.packet_rx.len() > PACKET_CAPACITY && self.packet_rx.try_recv().is_ok() {
+                        lost += 1;
+                    }
+                    if lost > 0 {
+                        warn!(target: "meshloop", "Fell behind the radio, {} packets dropped", lost);
+                        check!(self.status_tx.send(Status::Dropped(lost)));
+                    }
+                    debug!(target: "meshloop", "Radio Rx: {:?}", from_radio);
+                    if let Some(capture) = self.capture.as_mut() {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_millis() as u64;
+                        check!(capture.log(now, &from_radio.encode_to_vec()));
+                    }
                     check!(self.status_tx.send(Status::FromRadio(from_radio.clone())));
 
-                    if let Err(error) = self.process_from_radio(from_radio.clone()).await {
-                        error!("Error processing packet: {:?} : {}", from_radio, error);
+                    let span = debug_span!(target: "meshloop", "packet", id = from_radio.id, from = field::Empty);
+                    if let Some(from_radio::PayloadVariant::Packet(packet)) = &from_radio.payload_variant {
+                        span.record("from", format_node_id(packet.from).as_str());
+                    }
+                    if let Err(error) = self.process_from_radio(from_radio.clone()).instrument(span).await {
+                        error!(target: "meshloop", "Error processing packet: {:?} : {}", from_radio, error);
                     }
                 }
                 msg = self.msg_rx.recv() => {
@@ -301,22 +1058,45 @@ impl Service {
                         ret = Err(anyhow!("Text message stream closed"));
                         break;
                     };
-                    send_msg_queue.push_back(msg);
+                    self.queue_text(msg).await;
+                }
+                Some(request) = self.request_rx.recv() => {
+                    check!(self.process_request(request).await);
+                }
+                // Each send_delay while texts wait, the most urgent first
+                _ = tokio::time::sleep_until(next_send), if !self.outbox.is_empty() => {
+                    if let Some(outgoing) = self.outbox.pop() {
+                        check!(self.process_send_text(outgoing).await);
+                    }
+                    next_send = tokio::time::Instant::now() + self.send_delay;
                 }
                 _ = tokio::time::sleep(Duration::from_millis(500)) => {
                     hearthbeat_counter += 1;
 
                     // Each 500 ms
+                    if !self.idle_timeout.is_zero() && last_packet.elapsed() >= self.idle_timeout {
+                        ret = Err(anyhow!("No packets for {}s", self.idle_timeout.as_secs()));
+                        break;
+                    }
+                    if let Some(probe) = keepalive.due(Instant::now()) {
+                        check!(self.send_keepalive(probe).await);
+                    }
                     if !buffer_flushed && self.config_complete {
                         buffer_flushed = true;
                         check!(self.status_tx.send(Status::Ready));
                     }
 
-                    // Each second
-                    if hearthbeat_counter % 2 == 0 {
-                        if let Some(msg) = send_msg_queue.pop_front() {
-                            check!(self.process_send_text(msg.clone()).await);
-                        }
+                    // Traceroutes that timed out
+                    self.traceroutes.retain(|_, reply| !reply.is_closed());
+                    self.admin_replies.retain(|_, reply| !reply.is_closed());
+
+                    for id in self.outbox.expire() {
+                        check!(self.update_message_status(id, Failed).await);
+                    }
+                    check!(self.release_held().await);
+                    if self.node_db.is_changed() {
+                        let kept = self.state.read().await.kept();
+                        check!(self.node_db.save(&kept));
                     }
 
                     // Each 10 second
@@ -331,32 +1111,183 @@ impl Service {
             }
         }
 
-        self.packet_rx.close();
-        check!(self.stream_api.disconnect().await);
-        check!(self.finished_tx.send(()));
-
         ret
     }
 
-    async fn process_send_text(&mut self, msg: TextMessage) -> Result<()> {
-        let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
+    // Some firmwares drop BLE sessions without traffic. A heartbeat keeps
+    // them up, a config request is always answered, with the whole config
+    async fn send_keepalive(&mut self, probe: Probe) -> Result<()> {
+        debug!(target: "meshloop", "Radio quiet, sending {:?}", probe);
+        let payload = match probe {
+            Probe::WantConfig => to_radio::PayloadVariant::WantConfigId(generate_rand_id()),
+            Probe::Heartbeat => to_radio::PayloadVariant::Heartbeat(Heartbeat::default()),
+        };
+        self.api()?.send_to_radio_packet(Some(payload)).await?;
+        Ok(())
+    }
+
+    // Direct texts to a node not heard within `offline_after`. Nodes never
+    // heard, e.g. right after a restart, are taken as online
+    async fn is_offline(&self, node: u32) -> bool {
+        if self.offline_after.is_zero() || node == BROADCAST_ADDR {
+            return false;
+        }
+        let offline_after = self.offline_after.as_millis() as u64;
+        match self.state.read().await.heard.get(&node) {
+            Some(heard) => now_ms().saturating_sub(heard.ts) > offline_after,
+            None => false,
+        }
+    }
+
+    // Queues the texts held for the nodes heard again
+    async fn release_held(&mut self) -> Result<()> {
+        // Nothing is sent before the radio says who it is
+        let Ok(from) = self.state.read().await.my_node_num() else {
+            return Ok(());
+        };
+        for node in self.held.nodes() {
+            if self.is_offline(node).await {
+                continue;
+            }
+            let held = self.held.release(node)?;
+            debug!(target: "meshloop", "Sending {} held texts to {}", held.len(), format_node_id(node));
+            // Nobody is waiting for them by now. They are the same texts, as
+            // of when they were sent
+            for held in held {
+                self.outbox.push(TextMessage {
+                    ts: held.ts,
+                    urgency: Urgency::Notification,
+                    ..TextMessage::sent(from, held.to, held.text, held.channel)
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn process_send_text(&mut self, outgoing: Outgoing) -> Result<()> {
+        let from = self.state.read().await.my_node_num()?;
         let mut packet_router = Router::new(NodeId::new(from));
-        self.stream_api
+        let msg = outgoing.msg.clone();
+        if let Some(waypoint) = msg.waypoint {
+            let payload = waypoint.encode_to_vec();
+            let len = payload.len();
+            self.api()?
+                .send_mesh_packet(
+                    &mut packet_router,
+                    EncodedPayload::new(payload),
+                    PortNum::WaypointApp,
+                    PacketDestination::Broadcast,
+                    MeshChannel::new(msg.channel)?,
+                    false,
+                    false,
+                    false,
+                    None,
+                    None,
+                )
+                .await?;
+            self.state
+                .write()
+                .await
+                .airtime
+                .transmitted(len, Instant::now());
+            return Ok(());
+        }
+        // Nobody acks a broadcast, a neighbour rebroadcasting it is all we hear
+        let (destination, want_ack) = match msg.to {
+            BROADCAST_ADDR => (PacketDestination::Broadcast, false),
+            to => (PacketDestination::Node(NodeId::new(to)), true),
+        };
+        self.api()?
             .send_text(
                 &mut packet_router,
                 msg.text.clone(),
-                PacketDestination::Node(NodeId::new(msg.to)),
-                true,
-                MeshChannel::new(0).unwrap(),
+                destination,
+                want_ack,
+                MeshChannel::new(msg.channel)?,
             )
             .await?;
         let id = packet_router.last_sent().unwrap().id;
-        w!(self.messages).insert(id, msg);
-        self.status_tx.send(Status::NewMessage(id))?;
+        self.state
+            .write()
+            .await
+            .airtime
+            .transmitted(msg.text.len(), Instant::now());
+        let attempt = outgoing.attempts;
+        let original_id = self.outbox.sent(id, outgoing, want_ack);
+        if attempt == 0 {
+            self.state.write().await.messages.insert(id, msg);
+            self.status_tx.send(Status::NewMessage(id))?;
+        } else {
+            self.update_message_status(original_id, Retrying(attempt))
+                .await?;
+        }
 
         Ok(())
     }
 
+    async fn process_request(&mut self, request: Request) -> Result<()> {
+        match request {
+            Request::Traceroute { to, reply } => {
+                let from = self.state.read().await.my_node_num()?;
+                let mut packet_router = Router::new(NodeId::new(from));
+                self.api()?
+                    .send_mesh_packet(
+                        &mut packet_router,
+                        EncodedPayload::new(RouteDiscovery::default().encode_to_vec()),
+                        PortNum::TracerouteApp,
+                        PacketDestination::Node(NodeId::new(to)),
+                        MeshChannel::new(0).unwrap(),
+                        true,
+                        true,
+                        false,
+                        None,
+                        None,
+                    )
+                    .await?;
+                let id = packet_router.last_sent().unwrap().id;
+                self.traceroutes.insert(id, reply);
+            }
+            Request::Admin {
+                to,
+                mut message,
+                reply,
+            } => {
+                let from = self.state.read().await.my_node_num()?;
+                if let Some(passkey) = self.session_passkeys.get(&to) {
+                    message.session_passkey = passkey.clone();
+                }
+                let mut packet_router = Router::new(NodeId::new(from));
+                self.api()?
+                    .send_mesh_packet(
+                        &mut packet_router,
+                        EncodedPayload::new(message.encode_to_vec()),
+                        PortNum::AdminApp,
+                        PacketDestination::Node(NodeId::new(to)),
+                        MeshChannel::new(0).unwrap(),
+                        true,
+                        reply.is_some(),
+                        false,
+                        None,
+                        None,
+                    )
+                    .await?;
+                if let Some(reply) = reply {
+                    let id = packet_router.last_sent().unwrap().id;
+                    self.admin_replies.insert(id, reply);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn update_message_status(&self, id: u32, status: TextMessageStatus) -> Result<()> {
+        if let Some(msg) = self.state.write().await.messages.get_mut(&id) {
+            msg.status = status;
+            self.status_tx.send(Status::UpdatedMessage(id))?;
+        }
+        Ok(())
+    }
+
     async fn process_from_radio(&mut self, from_radio: FromRadio) -> Result<()> {
         let Some(payload) = from_radio.payload_variant else {
             bail!("No payload");
@@ -364,17 +1295,62 @@ impl Service {
         match payload {
             // Load for information about my node
             from_radio::PayloadVariant::MyInfo(node_info) => {
-                w!(self.my_node_info) = Some(node_info);
+                self.state.write().await.my_node_info = Some(node_info);
+                self.node_db.changed();
             }
             // Local for the data in NodeDB
-            from_radio::PayloadVariant::NodeInfo(node_info) if node_info.user.is_some() => {
-                w!(self.nodes).insert(node_info.num, node_info.user.unwrap());
+            from_radio::PayloadVariant::NodeInfo(node_info) => {
+                if let Some(position) = node_info.position {
+                    // Only positions with a GPS time can be placed in the track
+                    if position.time > 0 {
+                        self.track(node_info.num, position.time as u64 * 1000, &position)
+                            .await;
+                    }
+                    self.state
+                        .write()
+                        .await
+                        .positions
+                        .insert(node_info.num, position);
+                }
+                if let Some(heard) = Heard::from_node_info(&node_info) {
+                    self.state.write().await.heard.insert(node_info.num, heard);
+                }
+                if let Some(user) = node_info.user {
+                    self.state.write().await.add_node(node_info.num, user);
+                    self.node_db.changed();
+                }
+            }
+            from_radio::PayloadVariant::Metadata(metadata) => {
+                self.state.write().await.my_metadata = Some(metadata);
+            }
+            from_radio::PayloadVariant::Config(config) => {
+                self.state.write().await.update_config(config);
+            }
+            from_radio::PayloadVariant::ModuleConfig(config) => {
+                self.state.write().await.update_module_config(config);
+            }
+            from_radio::PayloadVariant::Channel(channel) => {
+                self.state.write().await.update_channel(channel);
             }
             from_radio::PayloadVariant::ConfigCompleteId(_) => {
                 self.config_complete = true;
+                let stale = self.state.write().await.evict_stale();
+                if stale > 0 {
+                    debug!(target: "meshloop", "Forgot {stale} kept nodes no longer known to the radio");
+                    self.node_db.changed();
+                }
             }
             // Mesh packet loaded
             from_radio::PayloadVariant::Packet(mesh_packet) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                self.state
+                    .write()
+                    .await
+                    .heard
+                    .insert(mesh_packet.from, Heard::from_packet(&mesh_packet, now));
                 if let Some(mesh_packet::PayloadVariant::Decoded(ref data)) =
                     mesh_packet.payload_variant
                 {
@@ -382,10 +1358,18 @@ impl Service {
                         Ok(PortNum::NodeinfoApp) => {
                             self.handle_nodeinfo(&mesh_packet, data).await?
                         }
+                        Ok(PortNum::PositionApp) => {
+                            self.handle_position(&mesh_packet, data, now).await?
+                        }
                         Ok(PortNum::TextMessageApp) => {
                             self.handle_textmessage(&mesh_packet, data).await?
                         }
                         Ok(PortNum::RoutingApp) => self.handle_routing(&mesh_packet, &data).await?,
+                        Ok(PortNum::TracerouteApp) => self.handle_traceroute(&mesh_packet, data)?,
+                        Ok(PortNum::AdminApp) => self.handle_admin(&mesh_packet, data)?,
+                        Ok(PortNum::TelemetryApp) => {
+                            self.handle_telemetry(&mesh_packet, data, now).await?
+                        }
                         _ => {}
                     }
                 }
@@ -395,28 +1379,93 @@ impl Service {
         Ok(())
     }
 
-    async fn handle_nodeinfo(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+    async fn handle_nodeinfo(&mut self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
         let user = User::decode(data.payload.as_slice())?;
-        w!(self.nodes).insert(mesh_packet.from, user);
+        self.state.write().await.add_node(mesh_packet.from, user);
+        self.node_db.changed();
         Ok(())
     }
 
-    async fn handle_textmessage(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+    async fn handle_position(&self, mesh_packet: &MeshPacket, data: &Data, ts: u64) -> Result<()> {
+        let position = Position::decode(data.payload.as_slice())?;
+        self.track(mesh_packet.from, ts, &position).await;
+        self.state
+            .write()
+            .await
+            .positions
+            .insert(mesh_packet.from, position);
+        Ok(())
+    }
+
+    async fn track(&self, node: u32, ts: u64, position: &Position) {
+        let Some(coordinates) = coordinates(position) else {
+            return;
+        };
+        let mut state = self.state.write().await;
+        let track = state.tracks.entry(node).or_default();
+        if track.len() == TRACK_HISTORY {
+            track.pop_front();
+        }
+        track.push_back((ts, coordinates));
+    }
+
+    fn handle_traceroute(&mut self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        let Some(reply) = self.traceroutes.remove(&data.request_id) else {
+            return Ok(());
+        };
+        let discovery = RouteDiscovery::decode(data.payload.as_slice())?;
+        // The handler may have given up waiting
+        let _ = reply.send(hops(&discovery, mesh_packet.from));
+        Ok(())
+    }
+
+    fn handle_admin(&mut self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        let message = AdminMessage::decode(data.payload.as_slice())?;
+        if !message.session_passkey.is_empty() {
+            self.session_passkeys
+                .insert(mesh_packet.from, message.session_passkey.clone());
+        }
+        if let Some(reply) = self.admin_replies.remove(&data.request_id) {
+            // The handler may have given up waiting
+            let _ = reply.send(message);
+        }
+        Ok(())
+    }
+
+    async fn handle_telemetry(&self, mesh_packet: &MeshPacket, data: &Data, ts: u64) -> Result<()> {
+        let telemetry = Telemetry::decode(data.payload.as_slice())?;
+        let Some(metrics) = Metrics::from_telemetry(&telemetry) else {
+            return Ok(());
+        };
+        let mut state = self.state.write().await;
+        let history = state.telemetry.entry(mesh_packet.from).or_default();
+        if history.len() == TELEMETRY_HISTORY {
+            history.pop_front();
+        }
+        history.push_back((ts, metrics));
+        Ok(())
+    }
+
+    async fn handle_textmessage(&mut self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        if !self.seen_packets.insert(mesh_packet.id)? {
+            debug!(target: "meshloop", "Duplicate packet {}", mesh_packet.id);
+            return Ok(());
+        }
         let msg = String::from_utf8(data.payload.clone())?;
         let pk_hash: [u8; 32] = Sha256::digest(&mesh_packet.public_key)
             .to_vec()
             .try_into()
             .unwrap();
-        w!(self.messages).insert(
+        self.state.write().await.messages.insert(
             mesh_packet.id,
-            TextMessage::recieved(mesh_packet.from, mesh_packet.to, msg, pk_hash),
+            TextMessage::recieved(mesh_packet, msg, pk_hash),
         );
         self.status_tx.send(Status::NewMessage(mesh_packet.id))?;
 
         Ok(())
     }
 
-    async fn handle_routing(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+    async fn handle_routing(&mut self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
         let Routing { variant } = Routing::decode(data.payload.as_slice())?;
         let Some(routing::Variant::ErrorReason(routing_error)) = variant else {
             return Ok(());
@@ -432,14 +1481,97 @@ impl Service {
             status = Some(ExplicitAck);
         }
 
-        if let Some(msg) = w!(self.messages).get_mut(&data.request_id)
-            && let Some(status) = status
+        let Some(status) = status else {
+            return Ok(());
+        };
+
+        // Before the ack stops tracking it, duplicate acks have none
+        let round_trip = self.outbox.round_trip(data.request_id);
+        // Retransmissions report on the id of the first attempt
+        let id = match status {
+            ExplicitAck => self.outbox.acked(data.request_id),
+            RoutingError(_) => self.outbox.failed(data.request_id),
+            _ => self.outbox.original_id(data.request_id),
+        }
+        .unwrap_or(data.request_id);
+        if matches!(status, ExplicitAck)
+            && let Some(round_trip) = round_trip
+            && let Some(msg) = self.state.write().await.messages.get_mut(&id)
         {
-            msg.status = status;
-            self.status_tx
-                .send(Status::UpdatedMessage(data.request_id))?;
+            msg.round_trip = Some(round_trip.as_millis() as u64);
         }
+        self.update_message_status(id, status).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_full_queue() -> Result<()> {
+        let (msg_tx, mut msg_rx) = mpsc::channel(1);
+        let (request_tx, _request_rx) = mpsc::channel(1);
+        let (status_tx, status_rx) = broadcast::channel(STATUS_CAPACITY);
+        let (_finished_tx, finished_rx) = oneshot::channel();
+        let state = HandlerState {
+            my_node_info: Some(MyNodeInfo {
+                my_node_num: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut handler = Handler {
+            state: Arc::new(RwLock::new(state)),
+            msg_tx,
+            request_tx,
+            status_rx: StatusReceiver(status_rx),
+            status_tx,
+            cancel: CancellationToken::new(),
+            finished_rx,
+            max_payload: 200,
+        };
+        futures::executor::block_on(async {
+            handler.send_text("first", 2u32).await?;
+            // The service is not taking them, the sender does not wait
+            assert!(handler.send_text("second", 2u32).await.is_err());
+            assert_eq!(handler.status_rx.recv().await, Some(Status::Dropped(1)));
+            let first = msg_rx.recv().await.map(|msg| msg.text);
+            assert_eq!(first.as_deref(), Some("first"));
+            handler.send_text("third", 2u32).await?;
+            msg_rx.recv().await;
+            // A text in two chunks does not fit, none of it is queued
+            assert!(handler.send_text("x".repeat(300), 2u32).await.is_err());
+            assert_eq!(handler.status_rx.recv().await, Some(Status::Dropped(2)));
+            assert!(msg_rx.try_recv().is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_stale_nodes() {
+        let kept_node = |num| KeptNode {
+            num,
+            id: format_node_id(num),
+            long_name: String::new(),
+            short_name: format!("n{num}"),
+            hw_model: 0,
+            public_key: String::new(),
+        };
+        let mut state = HandlerState::default();
+        state.restore(Kept {
+            my_node_num: Some(1),
+            nodes: vec![kept_node(8), kept_node(9)],
+        });
+        // The radio streams node 8 again, node 9 is gone
+        state.add_node(8, User::default());
+        assert_eq!(state.evict_stale(), 1);
+        let nums: Vec<u32> = state.kept().nodes.iter().map(|node| node.num).collect();
+        assert_eq!(nums, vec![8]);
+        assert_eq!(state.resolve_node("n8"), Some(8));
+        assert_eq!(state.resolve_node("n9"), None);
+        assert_eq!(state.evict_stale(), 0);
+    }
+}
@@ -0,0 +1,69 @@
+use meshtastic::protobufs::RouteDiscovery;
+
+// SNR placeholder for hops that did not report it
+const UNKNOWN_SNR: i32 = i8::MIN as i32;
+
+/// A node on the way to the traceroute destination
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub node: u32,
+    /// dB, as received by this node from the previous one
+    pub snr: Option<f32>,
+}
+
+/// Hops from us to `to`, the destination included, out of its traceroute
+/// reply. The radio reports SNRs in quarters of dB.
+pub fn hops(discovery: &RouteDiscovery, to: u32) -> Vec<Hop> {
+    discovery
+        .route
+        .iter()
+        .copied()
+        .chain(std::iter::once(to))
+        .enumerate()
+        .map(|(n, node)| Hop {
+            node,
+            snr: discovery
+                .snr_towards
+                .get(n)
+                .filter(|snr| **snr != UNKNOWN_SNR)
+                .map(|snr| *snr as f32 / 4.0),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hops() {
+        let direct = RouteDiscovery {
+            snr_towards: vec![25],
+            ..Default::default()
+        };
+        assert_eq!(
+            hops(&direct, 9),
+            vec![Hop {
+                node: 9,
+                snr: Some(6.25)
+            }]
+        );
+
+        let relayed = RouteDiscovery {
+            route: vec![3, 4],
+            snr_towards: vec![-10, UNKNOWN_SNR],
+            ..Default::default()
+        };
+        assert_eq!(
+            hops(&relayed, 9),
+            vec![
+                Hop {
+                    node: 3,
+                    snr: Some(-2.5)
+                },
+                Hop { node: 4, snr: None },
+                Hop { node: 9, snr: None },
+            ]
+        );
+    }
+}
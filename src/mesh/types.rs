@@ -1,7 +1,37 @@
 #[allow(dead_code)]
-use std::time::Instant;
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use meshtastic::protobufs::routing;
+use meshtastic::{
+    Message,
+    protobufs::{MeshPacket, NodeInfo, Position, Telemetry, Waypoint, routing, telemetry},
+};
+use serde::{Deserialize, Serialize};
+
+/// Node number texts to everyone are sent to
+pub const BROADCAST_ADDR: u32 = 0xffffffff;
+
+/// Milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// How soon a text should go out, when several wait for the radio
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Urgency {
+    /// Answers someone is waiting for, and texts typed by hand
+    #[default]
+    Reply,
+    /// Pushed to a node that did not ask for it just now
+    Notification,
+    /// Digests, scheduled broadcasts and syncs
+    Bulk,
+}
 
 #[derive(Debug, Clone)]
 pub enum TextMessageStatus {
@@ -10,42 +40,240 @@ pub enum TextMessageStatus {
     ImplicitAck,
     ExplicitAck,
     RoutingError(routing::Error),
+    Retrying(u32),
+    Failed,
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct TextMessage {
-    pub ts: Instant,
+    /// When the text was sent, or received by the radio, in ms since the
+    /// Unix epoch
+    pub ts: u64,
     pub from: u32,
     pub to: u32,
     pub text: String,
+    // Meshtastic channel index
+    pub channel: u32,
     pub status: TextMessageStatus,
     pub pk_hash: [u8; 32],
+    // Whether a received text came end-to-end encrypted with the public key
+    // of the radio (PKC), false for the ones sent
+    pub pki_encrypted: bool,
+    // How a received text was heard, None for the ones sent
+    pub signal: Option<Signal>,
+    // The MeshPacket of a received text as protobuf, empty for the ones sent
+    pub raw: Vec<u8>,
+    // Ms from the first attempt going out to the ack, for the ones sent
+    pub round_trip: Option<u64>,
+    pub urgency: Urgency,
+    // Sent instead of the text when set, broadcast and never acked
+    pub waypoint: Option<Waypoint>,
 }
 
 impl TextMessage {
-    pub fn sent(from: u32, to: u32, text: String) -> Self {
+    pub fn sent(from: u32, to: u32, text: String, channel: u32) -> Self {
         Self {
-            ts: Instant::now(),
+            ts: now_ms(),
             from,
             to,
             text,
+            channel,
             pk_hash: [0; 32],
+            pki_encrypted: false,
             status: TextMessageStatus::Sent,
+            signal: None,
+            raw: Vec::new(),
+            round_trip: None,
+            urgency: Urgency::Reply,
+            waypoint: None,
         }
     }
-    pub fn recieved(from: u32, to: u32, text: String, pk_hash: [u8; 32]) -> Self {
+    /// The text of the packet, `pk_hash` the hash of the public key of the
+    /// sender
+    pub fn recieved(packet: &MeshPacket, text: String, pk_hash: [u8; 32]) -> Self {
         Self {
-            ts: Instant::now(),
-            from,
-            to,
+            // Radios without a clock leave rx_time unset
+            ts: match packet.rx_time {
+                0 => now_ms(),
+                secs => secs as u64 * 1000,
+            },
+            from: packet.from,
+            to: packet.to,
             text,
+            channel: packet.channel,
             pk_hash,
+            pki_encrypted: packet.pki_encrypted,
             status: TextMessageStatus::Recieved,
+            signal: Signal::from_packet(packet),
+            raw: packet.encode_to_vec(),
+            round_trip: None,
+            urgency: Urgency::Reply,
+            waypoint: None,
+        }
+    }
+}
+
+/// Hops taken by the packet, unknown for old firmwares
+fn hops_taken(packet: &MeshPacket) -> Option<u32> {
+    (packet.hop_start > 0).then(|| packet.hop_start.saturating_sub(packet.hop_limit))
+}
+
+/// Link quality of a packet as the radio received it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signal {
+    pub snr: f32,
+    pub rssi: i32,
+    pub hops: Option<u32>,
+}
+
+impl Signal {
+    /// None for packets not received over the air, e.g. from the radio itself
+    pub fn from_packet(packet: &MeshPacket) -> Option<Self> {
+        (packet.rx_snr != 0.0 || packet.rx_rssi != 0).then(|| Self {
+            snr: packet.rx_snr,
+            rssi: packet.rx_rssi,
+            hops: hops_taken(packet),
+        })
+    }
+}
+
+/// `SNR 7.5, RSSI -90, 2 hops`
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SNR {:.1}, RSSI {}", self.snr, self.rssi)?;
+        match self.hops {
+            Some(0) => write!(f, ", direct"),
+            Some(1) => write!(f, ", 1 hop"),
+            Some(hops) => write!(f, ", {hops} hops"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Radio side view of the last time a node was heard
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heard {
+    // Timestamp, in ms
+    pub ts: u64,
+    pub snr: f32,
+    pub rssi: i32,
+    // Unknown for old firmwares
+    pub hops: Option<u32>,
+}
+
+impl Heard {
+    pub fn from_packet(packet: &MeshPacket, ts: u64) -> Self {
+        Self {
+            ts,
+            snr: packet.rx_snr,
+            rssi: packet.rx_rssi,
+            hops: hops_taken(packet),
+        }
+    }
+
+    /// From the node database of the radio, None if never heard
+    pub fn from_node_info(node_info: &NodeInfo) -> Option<Self> {
+        (node_info.last_heard > 0).then(|| Self {
+            ts: node_info.last_heard as u64 * 1000,
+            snr: node_info.snr,
+            rssi: 0,
+            hops: node_info.hops_away,
+        })
+    }
+}
+
+/// Device and environment readings of a telemetry packet, None if not reported
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    // Percent, over 100 when powered
+    pub battery_level: Option<u32>,
+    pub voltage: Option<f32>,
+    // Percent of airtime in use, by anyone and by the node
+    pub channel_utilization: Option<f32>,
+    pub air_util_tx: Option<f32>,
+    // Celsius
+    pub temperature: Option<f32>,
+    pub relative_humidity: Option<f32>,
+    // hPa
+    pub barometric_pressure: Option<f32>,
+}
+
+impl Metrics {
+    /// None for the telemetry variants not kept
+    pub fn from_telemetry(telemetry: &Telemetry) -> Option<Self> {
+        match telemetry.variant.as_ref()? {
+            telemetry::Variant::DeviceMetrics(device) => Some(Self {
+                battery_level: device.battery_level,
+                voltage: device.voltage,
+                channel_utilization: device.channel_utilization,
+                air_util_tx: device.air_util_tx,
+                ..Default::default()
+            }),
+            telemetry::Variant::EnvironmentMetrics(environment) => Some(Self {
+                temperature: environment.temperature,
+                relative_humidity: environment.relative_humidity,
+                barometric_pressure: environment.barometric_pressure,
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(level) = self.battery_level {
+            parts.push(format!("bat {level}%"));
+        }
+        if let Some(voltage) = self.voltage {
+            parts.push(format!("{voltage:.2}V"));
+        }
+        if let Some(utilization) = self.channel_utilization {
+            parts.push(format!("ch {utilization:.1}%"));
         }
+        if let Some(air_util_tx) = self.air_util_tx {
+            parts.push(format!("tx {air_util_tx:.1}%"));
+        }
+        if let Some(temperature) = self.temperature {
+            parts.push(format!("{temperature:.1}C"));
+        }
+        if let Some(humidity) = self.relative_humidity {
+            parts.push(format!("{humidity:.0}%RH"));
+        }
+        if let Some(pressure) = self.barometric_pressure {
+            parts.push(format!("{pressure:.1}hPa"));
+        }
+        f.write_str(&parts.join(" "))
     }
 }
 
+/// (latitude, longitude) in degrees, None if the position has no fix
+pub fn coordinates(position: &Position) -> Option<(f64, f64)> {
+    match (position.latitude_i, position.longitude_i) {
+        (Some(lat), Some(lon)) if lat != 0 || lon != 0 => {
+            Some((lat as f64 / 1e7, lon as f64 / 1e7))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a node id, Meshtastic style ("!a4c13b9f") or as a decimal node number
+pub fn parse_node_id(id: &str) -> Option<u32> {
+    match id.strip_prefix('!') {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => id.parse().ok(),
+    }
+}
+
+/// Formats a node id Meshtastic style, "!a4c13b9f"
+pub fn format_node_id(id: u32) -> String {
+    format!("!{id:08x}")
+}
+
+/// Where to send a text. Short names that match no known node are also tried
+/// as node ids, see [parse_node_id]
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Destination {
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use meshtastic::{
+    Message,
+    protobufs::{FromRadio, PortNum, Telemetry, from_radio, mesh_packet},
+};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc::UnboundedSender};
+use tracing::{info, warn};
+
+use crate::bbs::service::Post;
+use crate::config::Config;
+use crate::mesh::service::{State, Status, StatusReceiver, format_node_id};
+
+const CLIENT_ID: &str = "meshboard";
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct Sighting {
+    id: String,
+    short_name: Option<String>,
+    long_name: Option<String>,
+    position: Option<(f64, f64)>,
+    snr: f32,
+    rssi: i32,
+}
+
+/// Relays the BBS to an MQTT broker: publishes channel posts to
+/// `<topic>/channels/<channel>`, node sightings to `<topic>/nodes/<!id>` and
+/// telemetry to `<topic>/telemetry/<!id>`. With `MQTT_INBOUND` set, texts
+/// published to `<topic>/in/<channel>` are posted to the channel.
+struct Bridge {
+    client: AsyncClient,
+    topic: String,
+    inbound: bool,
+    state: State,
+}
+
+impl Bridge {
+    fn publish<P: Into<Vec<u8>>>(&self, topic: String, payload: P) {
+        if let Err(err) = self
+            .client
+            .try_publish(&topic, QoS::AtLeastOnce, false, payload)
+        {
+            warn!("Cannot publish to {topic}: {err}");
+        }
+    }
+
+    fn publish_post(&self, post: Post) {
+        self.publish(
+            format!("{}/channels/{}", self.topic, post.channel),
+            format!("{}: {}", post.author, post.text),
+        );
+    }
+
+    async fn publish_from_radio(&self, from_radio: FromRadio) -> Result<()> {
+        let Some(from_radio::PayloadVariant::Packet(mesh_packet)) = from_radio.payload_variant
+        else {
+            return Ok(());
+        };
+        let Some(mesh_packet::PayloadVariant::Decoded(data)) = &mesh_packet.payload_variant else {
+            return Ok(());
+        };
+        let id = format_node_id(mesh_packet.from);
+
+        let sighting = {
+            let state = self.state.read().await;
+            Sighting {
+                id: id.clone(),
+                short_name: state
+                    .user(mesh_packet.from)
+                    .map(|user| user.short_name.clone()),
+                long_name: state.get_long_name_by_node_id(mesh_packet.from),
+                position: state.get_position_by_node_id(mesh_packet.from),
+                snr: mesh_packet.rx_snr,
+                rssi: mesh_packet.rx_rssi,
+            }
+        };
+        self.publish(
+            format!("{}/nodes/{}", self.topic, id),
+            serde_json::to_vec(&sighting)?,
+        );
+
+        if PortNum::try_from(data.portnum) == Ok(PortNum::TelemetryApp) {
+            let telemetry = Telemetry::decode(data.payload.as_slice())?;
+            self.publish(
+                format!("{}/telemetry/{}", self.topic, id),
+                serde_json::to_vec(&telemetry)?,
+            );
+        }
+        Ok(())
+    }
+
+    fn inbound_post(&self, topic: &str, payload: &[u8]) -> Option<Post> {
+        let channel = topic.strip_prefix(&format!("{}/in/", self.topic))?;
+        let text = String::from_utf8_lossy(payload).trim().to_string();
+        if !self.inbound || channel.is_empty() || text.is_empty() {
+            return None;
+        }
+        Some(Post {
+            channel: channel.to_string(),
+            author: CLIENT_ID.to_string(),
+            text,
+        })
+    }
+}
+
+/// Runs the bridge until the mesh service is gone. `posts_rx` gets the BBS
+/// posts to publish, inbound posts are sent to `inbound_tx`.
+pub async fn run_bridge(
+    config: Config,
+    mut status_rx: StatusReceiver,
+    state: State,
+    mut posts_rx: broadcast::Receiver<Post>,
+    inbound_tx: UnboundedSender<Post>,
+) -> Result<()> {
+    let mut options = MqttOptions::new(CLIENT_ID, &config.mqtt_host, config.mqtt_port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if !config.mqtt_user.is_empty() {
+        options.set_credentials(&config.mqtt_user, &config.mqtt_password);
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    let bridge = Bridge {
+        client,
+        topic: config.mqtt_topic.clone(),
+        inbound: config.mqtt_inbound,
+        state,
+    };
+
+    loop {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("MQTT connected to {}:{}", config.mqtt_host, config.mqtt_port);
+                    // Subscriptions do not survive a reconnection
+                    if bridge.inbound {
+                        bridge
+                            .client
+                            .try_subscribe(format!("{}/in/+", bridge.topic), QoS::AtLeastOnce)?;
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    if let Some(post) = bridge.inbound_post(&publish.topic, &publish.payload) {
+                        inbound_tx.send(post)?;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("MQTT connection error: {err}");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            },
+            status = status_rx.recv() => match status {
+                Some(Status::FromRadio(from_radio)) => {
+                    if let Err(err) = bridge.publish_from_radio(from_radio).await {
+                        warn!("Cannot relay packet: {err}");
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            },
+            Ok(post) = posts_rx.recv() => bridge.publish_post(post),
+        }
+    }
+    Ok(())
+}
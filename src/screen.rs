@@ -1,11 +1,66 @@
-use anyhow::Result;
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+
+pub mod pages;
+pub mod qr;
+pub mod term;
+
+use qr::Qr;
 
 pub trait Screen {
     fn clear(&mut self) -> Result<()>;
     fn refresh(&mut self) -> Result<()>;
     fn draw_text(&mut self, text: &str, x: i32, y: i32);
     fn draw_text_at(&mut self, text: &str, row: i32, col: i32);
+    /// Draws the code with its top left corner at the pixel, `scale` pixels
+    /// per module
+    fn draw_qr(&mut self, qr: &Qr, x: i32, y: i32, scale: u32);
     fn sleep(&mut self) -> Result<()>;
+    /// Whether the button was pressed since last asked, screens without one
+    /// never are
+    fn button_pressed(&mut self) -> bool {
+        false
+    }
+    /// Text rows and columns that fit, those of the 2.13" e-paper unless
+    /// told otherwise
+    fn text_size(&self) -> (usize, usize) {
+        (pages::ROWS, pages::COLS)
+    }
+}
+
+impl<S: Screen + ?Sized> Screen for Box<S> {
+    fn clear(&mut self) -> Result<()> {
+        (**self).clear()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        (**self).refresh()
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32) {
+        (**self).draw_text(text, x, y)
+    }
+
+    fn draw_text_at(&mut self, text: &str, row: i32, col: i32) {
+        (**self).draw_text_at(text, row, col)
+    }
+
+    fn draw_qr(&mut self, qr: &Qr, x: i32, y: i32, scale: u32) {
+        (**self).draw_qr(qr, x, y, scale)
+    }
+
+    fn sleep(&mut self) -> Result<()> {
+        (**self).sleep()
+    }
+
+    fn button_pressed(&mut self) -> bool {
+        (**self).button_pressed()
+    }
+
+    fn text_size(&self) -> (usize, usize) {
+        (**self).text_size()
+    }
 }
 
 pub struct NoScreen {}
@@ -22,25 +77,80 @@ impl Screen for NoScreen {
 
     fn draw_text_at(&mut self, _text: &str, _row: i32, _col: i32) {}
 
+    fn draw_qr(&mut self, _qr: &Qr, _x: i32, _y: i32, _scale: u32) {}
+
     fn sleep(&mut self) -> Result<()> {
         Ok(())
     }
 }
 
+/// Waveshare e-paper panels the screen can drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Panel {
+    Epd2in13V2,
+    /// Driven as the v2, epd-waveshare has no driver of its own for it
+    Epd2in13V3,
+    Epd2in9,
+    Epd4in2,
+}
+
+impl FromStr for Panel {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "2in13_v2" => Ok(Panel::Epd2in13V2),
+            "2in13_v3" => Ok(Panel::Epd2in13V3),
+            "2in9" => Ok(Panel::Epd2in9),
+            "4in2" => Ok(Panel::Epd4in2),
+            _ => bail!("Unknown e-paper panel '{s}', use 2in13_v2, 2in13_v3, 2in9 or 4in2"),
+        }
+    }
+}
+
+impl fmt::Display for Panel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Panel::Epd2in13V2 => write!(f, "2in13_v2"),
+            Panel::Epd2in13V3 => write!(f, "2in13_v3"),
+            Panel::Epd2in9 => write!(f, "2in9"),
+            Panel::Epd4in2 => write!(f, "4in2"),
+        }
+    }
+}
+
+/// How the e-paper HAT is wired, pins are BCM numbers
+#[derive(Debug, Clone)]
+pub struct EpdOptions {
+    pub panel: Panel,
+    /// SPI device, e.g. `/dev/spidev0.0`
+    pub spi: String,
+    /// Sysfs number of BCM 0, see `ls /sys/class/gpio`
+    pub gpio_base: u64,
+    pub cs: u64,
+    pub busy: u64,
+    pub dc: u64,
+    pub rst: u64,
+    /// GPIO with a push button to ground
+    pub button: Option<u64>,
+}
+
 #[cfg(target_os = "linux")]
 pub mod epd {
     use std::path::Path;
 
     use super::*;
-    use anyhow::bail;
     use embedded_graphics::{
         mono_font::MonoTextStyleBuilder,
         prelude::*,
+        primitives::{PrimitiveStyle, Rectangle},
         text::{Baseline, Text, TextStyleBuilder},
     };
     use epd_waveshare::{
         color::*,
+        epd2in9::{Display2in9, Epd2in9},
         epd2in13_v2::{Display2in13, Epd2in13},
+        epd4in2::{Display4in2, Epd4in2},
+        graphics::Display,
         prelude::*,
     };
 
@@ -50,69 +160,114 @@ pub mod epd {
         sysfs_gpio::Direction,
     };
 
-    // Check ls /sys/class/gpio -> export gpiochip512 unexport ?
-    const GPIO_BASE: u64 = 512;
+    /// Sets the panel up for quick refreshes
+    type Setup<E> = fn(&mut E, &mut SpidevDevice, &mut Delay) -> Result<()>;
 
-    pub struct EpdScreen {
+    /// Opens the panel wired as in `options`
+    pub fn open(options: &EpdOptions) -> Result<Box<dyn Screen>> {
+        let screen: Box<dyn Screen> = match options.panel {
+            Panel::Epd2in13V2 | Panel::Epd2in13V3 => Box::new(EpdScreen::open(
+                options,
+                Display2in13::default(),
+                DisplayRotation::Rotate90,
+                |epd: &mut Epd2in13<_, _, _, _, _>, spi, delay| {
+                    Ok(epd.set_refresh(spi, delay, RefreshLut::Quick)?)
+                },
+            )?),
+            Panel::Epd2in9 => Box::new(EpdScreen::open(
+                options,
+                Display2in9::default(),
+                DisplayRotation::Rotate90,
+                |epd: &mut Epd2in9<_, _, _, _, _>, spi, delay| {
+                    Ok(epd.set_lut(spi, delay, Some(RefreshLut::Quick))?)
+                },
+            )?),
+            Panel::Epd4in2 => Box::new(EpdScreen::open(
+                options,
+                Display4in2::default(),
+                DisplayRotation::Rotate0,
+                |epd: &mut Epd4in2<_, _, _, _, _>, spi, delay| {
+                    Ok(epd.set_lut(spi, delay, Some(RefreshLut::Quick))?)
+                },
+            )?),
+        };
+        Ok(screen)
+    }
+
+    fn pin(options: &EpdOptions, gpio: u64, direction: Direction) -> Result<SysfsPin> {
+        let pin = SysfsPin::new(options.gpio_base + gpio);
+        pin.export()?;
+        while !pin.is_exported() {}
+        pin.set_direction(direction)?;
+        Ok(pin)
+    }
+
+    pub struct EpdScreen<E, const W: u32, const H: u32, const N: usize> {
         spi: SpidevDevice,
-        epd: Epd2in13<SpidevDevice, SysfsPin, SysfsPin, SysfsPin, Delay>,
-        display: Display2in13,
+        epd: E,
+        display: Display<W, H, false, N, Color>,
+        // Active low, with its last value
+        button: Option<(SysfsPin, u8)>,
     }
 
-    impl EpdScreen {
-        pub fn new() -> Result<Self> {
+    impl<E, const W: u32, const H: u32, const N: usize> EpdScreen<E, W, H, N>
+    where
+        E: WaveshareDisplay<SpidevDevice, SysfsPin, SysfsPin, SysfsPin, Delay>,
+    {
+        fn open(
+            options: &EpdOptions,
+            mut display: Display<W, H, false, N, Color>,
+            rotation: DisplayRotation,
+            setup: Setup<E>,
+        ) -> Result<Self> {
             // Configure SPI
-            if !Path::new("/dev/spidev0.0").exists() {
-                bail!("/dev/spidev0.0 device not found, enable SPI");
+            if !Path::new(&options.spi).exists() {
+                bail!("{} device not found, enable SPI", options.spi);
             }
-            let mut spi = SpidevDevice::open("/dev/spidev0.0")?;
-            let options = SpidevOptions::new()
+            let mut spi = SpidevDevice::open(&options.spi)?;
+            let spi_options = SpidevOptions::new()
                 .bits_per_word(8)
                 .max_speed_hz(4_000_000)
                 .mode(spidev::SpiModeFlags::SPI_MODE_0)
                 .build();
-            spi.configure(&options)?;
+            spi.configure(&spi_options)?;
 
             // Configure Digital I/O Pin to be used as Chip Select for SPI
-            let cs = SysfsPin::new(GPIO_BASE + 26); //BCM7 CE0
-            cs.export()?;
-            while !cs.is_exported() {}
-            cs.set_direction(Direction::Out)?;
+            let cs = pin(options, options.cs, Direction::Out)?;
             cs.set_value(1)?;
-
-            let busy = SysfsPin::new(GPIO_BASE + 24); // GPIO 24, board J-18
-            busy.export()?;
-            while !busy.is_exported() {}
-            busy.set_direction(Direction::In)?;
-
-            let dc = SysfsPin::new(GPIO_BASE + 25); // GPIO 25, board J-22
-            dc.export().expect("dc export");
-            while !dc.is_exported() {}
-            dc.set_direction(Direction::Out)?;
+            let busy = pin(options, options.busy, Direction::In)?;
+            let dc = pin(options, options.dc, Direction::Out)?;
             dc.set_value(1)?;
-
-            let rst = SysfsPin::new(GPIO_BASE + 17); // GPIO 17, board J-11
-            rst.export()?;
-            while !rst.is_exported() {}
-            rst.set_direction(Direction::Out)?;
+            let rst = pin(options, options.rst, Direction::Out)?;
             rst.set_value(1)?;
 
             let mut delay = Delay {};
-            let mut epd = Epd2in13::new(&mut spi, busy, dc, rst, &mut delay, None)?;
-            let mut display = Display2in13::default();
-            display.set_rotation(DisplayRotation::Rotate90);
-            epd.set_refresh(&mut spi, &mut delay, RefreshLut::Quick)
-                .unwrap();
-            epd.clear_frame(&mut spi, &mut delay).unwrap();
+            let mut epd = E::new(&mut spi, busy, dc, rst, &mut delay, None)?;
+            display.set_rotation(rotation);
+            setup(&mut epd, &mut spi, &mut delay)?;
+            epd.clear_frame(&mut spi, &mut delay)?;
 
             let _ = display.clear(Color::White);
             epd.update_and_display_frame(&mut spi, display.buffer(), &mut delay)?;
 
-            Ok(Self { spi, epd, display })
+            let button = match options.button {
+                Some(gpio) => Some((pin(options, gpio, Direction::In)?, 1)),
+                None => None,
+            };
+
+            Ok(Self {
+                spi,
+                epd,
+                display,
+                button,
+            })
         }
     }
 
-    impl Screen for EpdScreen {
+    impl<E, const W: u32, const H: u32, const N: usize> Screen for EpdScreen<E, W, H, N>
+    where
+        E: WaveshareDisplay<SpidevDevice, SysfsPin, SysfsPin, SysfsPin, Delay>,
+    {
         fn clear(&mut self) -> Result<()> {
             let mut delay = Delay {};
             let _ = self.display.clear(Color::White);
@@ -143,10 +298,48 @@ pub mod epd {
         fn draw_text_at(&mut self, text: &str, row: i32, col: i32) {
             self.draw_text(text, col * 6, row * 10);
         }
+        fn draw_qr(&mut self, qr: &Qr, x: i32, y: i32, scale: u32) {
+            for qy in 0..qr.width() {
+                for qx in 0..qr.width() {
+                    let color = if qr.is_dark(qx, qy) {
+                        Color::Black
+                    } else {
+                        Color::White
+                    };
+                    let corner = Point::new(
+                        x + (qx as u32 * scale) as i32,
+                        y + (qy as u32 * scale) as i32,
+                    );
+                    let _ = Rectangle::new(corner, Size::new(scale, scale))
+                        .into_styled(PrimitiveStyle::with_fill(color))
+                        .draw(&mut self.display);
+                }
+            }
+        }
         fn sleep(&mut self) -> Result<()> {
             let mut delay = Delay {};
             let _ = self.epd.sleep(&mut self.spi, &mut delay);
             Ok(())
         }
+        fn button_pressed(&mut self) -> bool {
+            let Some((pin, last)) = &mut self.button else {
+                return false;
+            };
+            let Ok(value) = pin.get_value() else {
+                return false;
+            };
+            // Pressed when it goes down
+            let pressed = *last == 1 && value == 0;
+            *last = value;
+            pressed
+        }
+        fn text_size(&self) -> (usize, usize) {
+            // As rotated
+            let size = self.display.size();
+            (
+                size.height as usize / pages::FONT_HEIGHT,
+                size.width as usize / pages::FONT_WIDTH,
+            )
+        }
     }
 }
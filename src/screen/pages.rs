@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+
+use super::Screen;
+use super::qr::Qr;
+use crate::codec::TextCodec;
+
+// Text rows and columns of the 2.13" e-paper with the 6x10 font, for
+// screens that do not tell theirs
+pub const ROWS: usize = 12;
+pub const COLS: usize = 42;
+pub const FONT_WIDTH: usize = 6;
+pub const FONT_HEIGHT: usize = 10;
+const SPINNER: [&str; 4] = ["-", "\\", "|", "/"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    /// Connection state and packets received
+    Status,
+    /// Commands, replies and alerts, newest last
+    Messages,
+    /// Nodes heard, most recent first
+    Nodes,
+    Stats,
+    /// Host readings and the battery and airtime of the radio
+    System,
+    /// Names of the BBS node and a QR code to add it as a contact
+    Contact,
+}
+
+const PAGES: [Page; 6] = [
+    Page::Status,
+    Page::Messages,
+    Page::Nodes,
+    Page::Stats,
+    Page::System,
+    Page::Contact,
+];
+
+impl Page {
+    fn title(&self) -> &'static str {
+        match self {
+            Page::Status => "Status",
+            Page::Messages => "Messages",
+            Page::Nodes => "Nodes",
+            Page::Stats => "Stats",
+            Page::System => "System",
+            Page::Contact => "Contact",
+        }
+    }
+}
+
+/// Pages shown one at a time on the screen, what they show is kept up to
+/// date even while another page is shown
+pub struct Pages<S: Screen> {
+    screen: S,
+    // Text rows and columns of the screen
+    rows: usize,
+    cols: usize,
+    current: usize,
+    status: String,
+    packets: usize,
+    heartbeats: usize,
+    messages: VecDeque<String>,
+    nodes: Vec<String>,
+    stats: Vec<String>,
+    system: Vec<String>,
+    contact: Vec<String>,
+    qr: Option<Qr>,
+    // The current page changed since it was last drawn
+    dirty: bool,
+}
+
+impl<S: Screen> Pages<S> {
+    pub fn new(screen: S) -> Self {
+        let (rows, cols) = screen.text_size();
+        Self {
+            screen,
+            rows,
+            cols,
+            current: 0,
+            status: String::new(),
+            packets: 0,
+            heartbeats: 0,
+            messages: VecDeque::new(),
+            nodes: Vec::new(),
+            stats: Vec::new(),
+            system: Vec::new(),
+            contact: Vec::new(),
+            qr: None,
+            dirty: true,
+        }
+    }
+
+    pub fn page(&self) -> Page {
+        PAGES[self.current]
+    }
+
+    /// Shows the next page, wrapping around
+    pub fn next_page(&mut self) {
+        self.current = (self.current + 1) % PAGES.len();
+        self.dirty = true;
+    }
+
+    fn changed(&mut self, page: Page) {
+        self.dirty |= self.page() == page;
+    }
+
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+        self.changed(Page::Status);
+    }
+
+    pub fn heartbeat(&mut self, packets: usize) {
+        self.packets = packets;
+        self.heartbeats += 1;
+        self.changed(Page::Status);
+    }
+
+    pub fn message(&mut self, line: &str) {
+        if self.messages.len() == self.rows - 1 {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(line.to_string());
+        self.changed(Page::Messages);
+    }
+
+    pub fn set_nodes(&mut self, nodes: Vec<String>) {
+        if self.nodes != nodes {
+            self.nodes = nodes;
+            self.changed(Page::Nodes);
+        }
+    }
+
+    pub fn set_stats(&mut self, stats: Vec<String>) {
+        if self.stats != stats {
+            self.stats = stats;
+            self.changed(Page::Stats);
+        }
+    }
+
+    pub fn set_system(&mut self, system: Vec<String>) {
+        if self.system != system {
+            self.system = system;
+            self.changed(Page::System);
+        }
+    }
+
+    /// Lines shown next to the QR code, keep them short
+    pub fn set_contact(&mut self, contact: Vec<String>, qr: Qr) {
+        self.contact = contact;
+        self.qr = Some(qr);
+        self.changed(Page::Contact);
+    }
+
+    /// Rows of the current page, a title and a line for each other row of
+    /// the screen
+    pub fn render(&self) -> Vec<String> {
+        let page = self.page();
+        let title = format!("{} {}/{}", page.title(), self.current + 1, PAGES.len());
+        let lines: Vec<String> = match page {
+            Page::Status => vec![
+                self.status.clone(),
+                format!(
+                    "Packets {} {}",
+                    self.packets,
+                    SPINNER[self.heartbeats % SPINNER.len()]
+                ),
+            ],
+            Page::Messages => self.messages.iter().cloned().collect(),
+            Page::Nodes => self.nodes.clone(),
+            Page::Stats => self.stats.clone(),
+            Page::System => self.system.clone(),
+            Page::Contact => self.contact.clone(),
+        };
+        std::iter::once(title)
+            .chain(lines.into_iter().take(self.rows - 1))
+            .collect()
+    }
+
+    /// Draws the current page if it changed, every row so nothing of the
+    /// previous page is left
+    pub fn draw(&mut self, codec: &dyn TextCodec) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut rows = self.render();
+        rows.resize(self.rows, String::new());
+        let cols = self.cols;
+        for (row, text) in rows.iter().enumerate() {
+            let text: String = codec.encode(text).chars().take(cols).collect();
+            self.screen
+                .draw_text_at(&format!("{text:<cols$}"), row as i32, 0);
+        }
+        // On the right, as big as it fits
+        if let (Page::Contact, Some(qr)) = (self.page(), &self.qr) {
+            let scale = (self.rows * FONT_HEIGHT / qr.width()).max(1);
+            let x = (cols * FONT_WIDTH).saturating_sub(qr.width() * scale);
+            self.screen.draw_qr(qr, x as i32, 0, scale as u32);
+        }
+        self.dirty = false;
+        self.screen.refresh()
+    }
+
+    /// Whether the page button was pressed since last asked
+    pub fn button_pressed(&mut self) -> bool {
+        self.screen.button_pressed()
+    }
+
+    pub fn sleep(&mut self) -> Result<()> {
+        self.screen.sleep()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::screen::NoScreen;
+
+    #[test]
+    fn test_pages() {
+        let mut pages = Pages::new(NoScreen {});
+        pages.set_status("Ready");
+        pages.heartbeat(42);
+        assert_eq!(pages.render(), vec!["Status 1/6", "Ready", "Packets 42 \\"]);
+
+        for n in 0..ROWS {
+            pages.message(&format!("> {n}"));
+        }
+        pages.next_page();
+        let rows = pages.render();
+        assert_eq!(rows.len(), ROWS);
+        assert_eq!(
+            (rows[0].as_str(), rows[1].as_str()),
+            ("Messages 2/6", "> 1")
+        );
+
+        pages.set_nodes(vec!["ann 5m".into()]);
+        pages.next_page();
+        assert_eq!(pages.page(), Page::Nodes);
+        assert_eq!(pages.render(), vec!["Nodes 3/6", "ann 5m"]);
+        pages.next_page();
+        pages.set_system(vec!["CPU 48.3C".into()]);
+        pages.next_page();
+        assert_eq!(pages.render(), vec!["System 5/6", "CPU 48.3C"]);
+        pages.next_page();
+        assert_eq!(pages.render(), vec!["Contact 6/6"]);
+        pages.next_page();
+        assert_eq!(pages.page(), Page::Status);
+    }
+
+    // A 4.2" panel, 400x300 with the 6x10 font
+    struct Big(Vec<String>);
+
+    impl Screen for Big {
+        fn clear(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn refresh(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn draw_text(&mut self, _text: &str, _x: i32, _y: i32) {}
+        fn draw_text_at(&mut self, text: &str, _row: i32, _col: i32) {
+            self.0.push(text.to_string());
+        }
+        fn draw_qr(&mut self, _qr: &Qr, _x: i32, _y: i32, _scale: u32) {}
+        fn sleep(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn text_size(&self) -> (usize, usize) {
+            (30, 66)
+        }
+    }
+
+    #[test]
+    fn test_screen_size() -> Result<()> {
+        let mut pages = Pages::new(Big(Vec::new()));
+        for n in 0..40 {
+            pages.message(&format!("> {n}"));
+        }
+        pages.next_page();
+        assert_eq!(pages.render().len(), 30);
+        assert_eq!(pages.render()[1], "> 11");
+        pages.draw(&*crate::codec::by_name("utf8")?)?;
+        assert_eq!(pages.screen.0.len(), 30);
+        assert_eq!(pages.screen.0[0].len(), 66);
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::Result;
+use qrcode::{Color, QrCode};
+
+// Light modules around the code, scanners need some
+const QUIET_ZONE: usize = 2;
+
+/// QR code modules, quiet zone included
+pub struct Qr {
+    width: usize,
+    dark: Vec<bool>,
+}
+
+impl Qr {
+    pub fn new(text: &str) -> Result<Self> {
+        let code = QrCode::new(text.as_bytes())?;
+        let width = code.width() + 2 * QUIET_ZONE;
+        let mut dark = vec![false; width * width];
+        for (n, color) in code.to_colors().into_iter().enumerate() {
+            let (x, y) = (n % code.width(), n / code.width());
+            dark[(y + QUIET_ZONE) * width + x + QUIET_ZONE] = color == Color::Dark;
+        }
+        Ok(Self { width, dark })
+    }
+
+    /// Modules per side
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.width && self.dark[y * self.width + x]
+    }
+
+    /// Two rows of modules per line, light ones as blocks so it scans on
+    /// terminals with a dark background
+    pub fn to_text(&self) -> Vec<String> {
+        (0..self.width)
+            .step_by(2)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| match (self.is_dark(x, y), self.is_dark(x, y + 1)) {
+                        (false, false) => '█',
+                        (false, true) => '▀',
+                        (true, false) => '▄',
+                        (true, true) => ' ',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::{IsTerminal, Write};
+
+use anyhow::Result;
+use crossterm::{
+    cursor::MoveTo,
+    execute,
+    terminal::{Clear, ClearType},
+};
+
+use super::Screen;
+use super::pages::{COLS, FONT_HEIGHT, FONT_WIDTH, ROWS};
+use super::qr::Qr;
+
+/// Draws on stdout what the e-paper would show, in a box. When stdout is a
+/// terminal each refresh redraws it in place, so logs are best sent elsewhere.
+pub struct TermScreen {
+    rows: Vec<Vec<char>>,
+    // Too big for the box, shown under it until the next refresh
+    qr: Vec<String>,
+}
+
+impl Default for TermScreen {
+    fn default() -> Self {
+        Self {
+            rows: vec![vec![' '; COLS]; ROWS],
+            qr: Vec::new(),
+        }
+    }
+}
+
+impl TermScreen {
+    /// The text box, with its border, and the QR code drawn since the last
+    /// refresh
+    pub fn render(&self) -> String {
+        let border = format!("+{}+\n", "-".repeat(COLS));
+        let mut text = border.clone();
+        for row in &self.rows {
+            text.push('|');
+            text.extend(row);
+            text.push_str("|\n");
+        }
+        text += &border;
+        for line in &self.qr {
+            text += line;
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl Screen for TermScreen {
+    fn clear(&mut self) -> Result<()> {
+        self.rows.iter_mut().for_each(|row| row.fill(' '));
+        self.refresh()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let mut stdout = std::io::stdout();
+        if stdout.is_terminal() {
+            execute!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+        }
+        stdout.write_all(self.render().as_bytes())?;
+        stdout.flush()?;
+        self.qr.clear();
+        Ok(())
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32) {
+        self.draw_text_at(text, y / FONT_HEIGHT as i32, x / FONT_WIDTH as i32);
+    }
+
+    /// Text past the edges is cut, like on the e-paper
+    fn draw_text_at(&mut self, text: &str, row: i32, col: i32) {
+        let (Ok(row), Ok(col)) = (usize::try_from(row), usize::try_from(col)) else {
+            return;
+        };
+        let Some(row) = self.rows.get_mut(row) else {
+            return;
+        };
+        for (cell, c) in row.iter_mut().skip(col).zip(text.chars()) {
+            *cell = c;
+        }
+    }
+
+    fn draw_qr(&mut self, qr: &Qr, _x: i32, _y: i32, _scale: u32) {
+        self.qr = qr.to_text();
+    }
+
+    fn sleep(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_term_screen() {
+        let mut screen = TermScreen::default();
+        screen.draw_text_at("Ready", 0, 0);
+        screen.draw_text_at("> h", 1, COLS as i32 - 2);
+        screen.draw_text("x", 12, 20);
+        screen.draw_text_at("gone", ROWS as i32, 0);
+
+        let text = screen.render();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), ROWS + 2);
+        assert_eq!(lines[0], format!("+{}+", "-".repeat(COLS)));
+        assert_eq!(lines[1], format!("|{:<COLS$}|", "Ready"));
+        assert_eq!(lines[2], format!("|{:>COLS$}|", "> "));
+        assert_eq!(lines[3], format!("|  {:<w$}|", "x", w = COLS - 2));
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::bbs::service::{BBS, Options as BbsOptions, Sender};
+use crate::bbs::storage::Storage;
+use crate::config::Config;
+use crate::mesh::service::{Handler, Service, Status, TextMessageStatus};
+
+fn report(step: &str, result: &Result<()>) {
+    match result {
+        Ok(()) => println!("[PASS] {step}"),
+        Err(err) => println!("[FAIL] {step}: {err}"),
+    }
+}
+
+async fn mesh_roundtrip(handler: &mut Handler, peer: &str, timeout: Duration) -> Result<()> {
+    let text = format!("meshboard selftest {}", std::process::id());
+    let me = handler.state.read().await.my_node_num()?;
+    handler.send_text(text.clone(), peer).await?;
+
+    let wait = async {
+        let mut sent_id = None;
+        loop {
+            let Some(status) = handler.status_rx.recv().await else {
+                bail!("Channel closed");
+            };
+            match status {
+                Status::NewMessage(id) => {
+                    let state = handler.state.read().await;
+                    let Some(msg) = state.message(id) else {
+                        continue;
+                    };
+                    if msg.from == me && msg.text == text {
+                        sent_id = Some(id);
+                    } else if msg.to == me && state.resolve_node(peer) == Some(msg.from) {
+                        // Any reply from the peer counts as an echo
+                        return Ok(());
+                    }
+                }
+                Status::UpdatedMessage(id) if Some(id) == sent_id => {
+                    let state = handler.state.read().await;
+                    let Some(msg) = state.message(id) else {
+                        continue;
+                    };
+                    match msg.status {
+                        TextMessageStatus::ExplicitAck => return Ok(()),
+                        TextMessageStatus::RoutingError(error) => bail!("{:?}", error),
+                        TextMessageStatus::Failed => bail!("No ack"),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    };
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| anyhow!("Timeout waiting for ack or echo"))?
+}
+
+async fn bbs_roundtrip() -> Result<()> {
+    let mut bbs = BBS::new(Storage::memory(), BbsOptions::default());
+    bbs.init().await?;
+    let sender = Sender {
+        node: 1,
+        pk_hash: [0xaa; 32],
+        short_name: "test".into(),
+        position: None,
+        signal: None,
+        encrypted: false,
+        received: None,
+        packet: None,
+    };
+    bbs.handle(&sender, "p selftest").await?;
+    let listing = bbs.handle(&sender, "l").await?;
+    if !listing.iter().any(|line| line.ends_with("test: selftest")) {
+        bail!("Posted message not listed: {:?}", listing);
+    }
+    Ok(())
+}
+
+/// Checks a field install end to end, returns false if any step failed
+pub async fn run_selftest(config: Config, peer: &str, timeout: Duration) -> Result<bool> {
+    let ble_device = crate::bbs::radios::ble_devices(&config)?.remove(0);
+    let mut handler = Service::from_ble(&ble_device, config.mesh_options()).await?;
+
+    let boot = handler.wait_for_boot_ready(30).await;
+    report(&format!("Connect to {ble_device}"), &boot);
+    if boot.is_err() {
+        return Ok(false);
+    }
+
+    let mesh = mesh_roundtrip(&mut handler, peer, timeout).await;
+    report(&format!("Message to {peer}"), &mesh);
+
+    let bbs = bbs_roundtrip().await;
+    report("BBS post and list", &bbs);
+
+    handler.finish().await;
+    Ok(mesh.is_ok() && bbs.is_ok())
+}
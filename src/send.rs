@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::codec;
+use crate::config::Config;
+use crate::mesh::chunker;
+use crate::mesh::service::{
+    BROADCAST_ADDR, Handler, Service, Status, TextMessage, TextMessageStatus, format_node_id,
+};
+
+/// Exit codes of `send`, besides 0
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_TIMEOUT: i32 = 2;
+
+/// The chunks of the text as they go out and, with `wait_ack`, are acked
+/// by the node. Broadcasts are not acked, going out is enough.
+struct Delivery {
+    me: u32,
+    to: u32,
+    chunks: Vec<String>,
+    wait_ack: bool,
+    sent: Vec<u32>,
+    acked: HashSet<u32>,
+}
+
+impl Delivery {
+    fn new(me: u32, to: u32, chunks: Vec<String>, wait_ack: bool) -> Self {
+        Self {
+            me,
+            to,
+            chunks,
+            wait_ack,
+            sent: Vec::new(),
+            acked: HashSet::new(),
+        }
+    }
+
+    /// Takes the news on a text, true once the whole text went through. Err
+    /// if the mesh could not deliver one of its chunks.
+    fn update(&mut self, id: u32, msg: &TextMessage) -> Result<bool> {
+        if !self.sent.contains(&id) {
+            // One of ours, not seen yet
+            let chunk = self.chunks.iter().position(|chunk| *chunk == msg.text);
+            match chunk {
+                Some(n) if msg.from == self.me && msg.to == self.to => {
+                    self.chunks.remove(n);
+                    self.sent.push(id);
+                }
+                _ => return Ok(false),
+            }
+        }
+        match &msg.status {
+            TextMessageStatus::ExplicitAck => {
+                self.acked.insert(id);
+            }
+            TextMessageStatus::RoutingError(error) => bail!("{:?}", error),
+            TextMessageStatus::Failed => bail!("No ack"),
+            _ => {}
+        }
+        let acked =
+            !self.wait_ack || self.to == BROADCAST_ADDR || self.acked.len() == self.sent.len();
+        Ok(self.chunks.is_empty() && acked)
+    }
+}
+
+/// Waits for the delivery to go through
+async fn wait_delivery(handler: &mut Handler, mut delivery: Delivery) -> Result<()> {
+    loop {
+        let Some(status) = handler.status_rx.recv().await else {
+            bail!("Channel closed");
+        };
+        let (Status::NewMessage(id) | Status::UpdatedMessage(id)) = status else {
+            continue;
+        };
+        let Some(msg) = handler.state.read().await.message(id) else {
+            continue;
+        };
+        if delivery.update(id, &msg)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Sends a text to the node, by name or id, or to everyone with `broadcast`.
+/// Returns the exit code: 0 once sent, or acked with `wait_ack`,
+/// [EXIT_FAILED] if the mesh could not deliver it and [EXIT_TIMEOUT] if it
+/// did not happen in time.
+pub async fn run_send(
+    config: Config,
+    to: &str,
+    text: &str,
+    wait_ack: bool,
+    timeout: Duration,
+) -> Result<i32> {
+    let ble_device = crate::bbs::radios::ble_devices(&config)?.remove(0);
+    let mut handler = Service::from_ble(&ble_device, config.mesh_options()).await?;
+    handler.wait_for_boot_ready(30).await?;
+
+    let (me, to) = {
+        let state = handler.state.read().await;
+        let to = match to {
+            "broadcast" => BROADCAST_ADDR,
+            name => state
+                .resolve_node(name)
+                .ok_or_else(|| anyhow!("Node '{name}' not found"))?,
+        };
+        (state.my_node_num()?, to)
+    };
+    let text = codec::by_name(&config.mesh_codec)?.encode(text);
+    let chunks = chunker::split(&text, config.max_payload);
+    handler.send_text(text, to).await?;
+
+    let delivery = wait_delivery(&mut handler, Delivery::new(me, to, chunks, wait_ack));
+    let code = match tokio::time::timeout(timeout, delivery).await {
+        Ok(Ok(())) if wait_ack => {
+            println!("Delivered to {}", format_node_id(to));
+            0
+        }
+        Ok(Ok(())) => {
+            println!("Sent to {}", format_node_id(to));
+            0
+        }
+        Ok(Err(err)) => {
+            eprintln!("Failed: {err}");
+            EXIT_FAILED
+        }
+        Err(_) => {
+            eprintln!("Timeout");
+            EXIT_TIMEOUT
+        }
+    };
+    handler.finish().await;
+    Ok(code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delivery() -> Result<()> {
+        let msg = |text: &str, status| TextMessage {
+            status,
+            ..TextMessage::sent(1, 2, text.into(), 0)
+        };
+        let mut delivery = Delivery::new(1, 2, vec!["1/2 a".into(), "2/2 b".into()], true);
+        // Texts of others, or to another node, are not ours
+        assert!(!delivery.update(10, &TextMessage::sent(3, 2, "1/2 a".into(), 0))?);
+        assert!(!delivery.update(10, &TextMessage::sent(1, 4, "1/2 a".into(), 0))?);
+        assert!(!delivery.update(11, &msg("1/2 a", TextMessageStatus::Sent))?);
+        assert!(!delivery.update(12, &msg("2/2 b", TextMessageStatus::Sent))?);
+        assert!(!delivery.update(11, &msg("1/2 a", TextMessageStatus::ExplicitAck))?);
+        assert!(delivery.update(12, &msg("2/2 b", TextMessageStatus::ExplicitAck))?);
+
+        // Without waiting for the ack, going out is enough
+        let mut delivery = Delivery::new(1, 2, vec!["a".into()], false);
+        assert!(delivery.update(11, &msg("a", TextMessageStatus::Sent))?);
+
+        let mut delivery = Delivery::new(1, 2, vec!["a".into()], true);
+        assert!(!delivery.update(11, &msg("a", TextMessageStatus::Sent))?);
+        assert!(
+            delivery
+                .update(11, &msg("a", TextMessageStatus::Failed))
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use std::{fs, path::Path, process::Command, time::Duration};
+
+/// Host readings, None when the host does not expose them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SysInfo {
+    // Celsius
+    pub cpu_temp: Option<f32>,
+    // (used, total) in kB
+    pub memory: Option<(u64, u64)>,
+    pub disk: Option<(u64, u64)>,
+    pub uptime: Option<Duration>,
+}
+
+impl SysInfo {
+    /// Reads /proc and /sys, and asks `df` for the disk holding `path`.
+    /// Blocking, run it off the async tasks.
+    pub fn collect(path: &Path) -> Self {
+        let read = |file: &str| fs::read_to_string(file).ok();
+        let disk = Command::new("df")
+            .arg("-Pk")
+            .arg(path)
+            .output()
+            .ok()
+            .and_then(|output| parse_df(&String::from_utf8_lossy(&output.stdout)));
+        Self {
+            cpu_temp: read("/sys/class/thermal/thermal_zone0/temp")
+                .and_then(|temp| temp.trim().parse::<f32>().ok())
+                .map(|millis| millis / 1000.0),
+            memory: read("/proc/meminfo").and_then(|meminfo| parse_meminfo(&meminfo)),
+            disk,
+            uptime: read("/proc/uptime").and_then(|uptime| parse_uptime(&uptime)),
+        }
+    }
+
+    /// One short line per reading known
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(temp) = self.cpu_temp {
+            lines.push(format!("CPU {temp:.1}C"));
+        }
+        if let Some((used, total)) = self.memory {
+            lines.push(format!("Mem {}/{}MB", used / 1024, total / 1024));
+        }
+        if let Some((used, total)) = self.disk {
+            let gb = |kb: u64| kb as f64 / (1024.0 * 1024.0);
+            lines.push(format!("Disk {:.1}/{:.1}GB", gb(used), gb(total)));
+        }
+        if let Some(uptime) = self.uptime {
+            let hours = uptime.as_secs() / 3600;
+            lines.push(format!("Up {}d {}h", hours / 24, hours % 24));
+        }
+        lines
+    }
+}
+
+/// (used, total) kB, counting the available memory as free
+fn parse_meminfo(meminfo: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+    };
+    let total = field("MemTotal")?;
+    let available = field("MemAvailable")?;
+    Some((total.saturating_sub(available), total))
+}
+
+/// (used, total) kB of the filesystem in POSIX `df -Pk` output
+fn parse_df(df: &str) -> Option<(u64, u64)> {
+    let mut fields = df.lines().nth(1)?.split_whitespace().skip(1);
+    let total = fields.next()?.parse().ok()?;
+    let used = fields.next()?.parse().ok()?;
+    Some((used, total))
+}
+
+fn parse_uptime(uptime: &str) -> Option<Duration> {
+    let secs: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_secs(secs as u64))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let meminfo = "MemTotal:        1024000 kB\nMemFree:          100000 kB\nMemAvailable:     724000 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some((300000, 1024000)));
+        assert_eq!(parse_meminfo("MemTotal: 10 kB\n"), None);
+
+        let df = "Filesystem     1024-blocks    Used Available Capacity Mounted on\n/dev/root         30000000 3000000  25000000      11% /\n";
+        assert_eq!(parse_df(df), Some((3000000, 30000000)));
+        assert_eq!(parse_df(""), None);
+
+        assert_eq!(
+            parse_uptime("266400.52 1000.00\n"),
+            Some(Duration::from_secs(266400))
+        );
+
+        let info = SysInfo {
+            cpu_temp: Some(48.3),
+            memory: Some((300000, 1024000)),
+            disk: None,
+            uptime: Some(Duration::from_secs(266400)),
+        };
+        assert_eq!(
+            info.lines(),
+            vec!["CPU 48.3C", "Mem 292/1000MB", "Up 3d 2h"]
+        );
+    }
+}
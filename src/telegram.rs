@@ -0,0 +1,225 @@
+use std::{collections::VecDeque, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+use tokio::sync::{broadcast, mpsc::UnboundedSender};
+use tracing::{info, warn};
+
+use crate::bbs::service::Post;
+use crate::config::Config;
+use crate::mesh::service::{State, Status, StatusReceiver};
+
+// Telegram allows about one message per second to the same chat
+const SEND_INTERVAL: Duration = Duration::from_secs(1);
+const POLL_TIMEOUT_SECS: u64 = 30;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Texts waiting to be sent, the oldest are dropped when Telegram is unreachable
+const MAX_QUEUED: usize = 100;
+// Appended to the authors of the posts from Telegram, so they cannot pass for
+// users of the mesh
+const AUTHOR_SUFFIX: &str = "@telegram";
+
+#[derive(Deserialize)]
+struct Response<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    from: Option<User>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct User {
+    first_name: String,
+    username: Option<String>,
+}
+
+/// Minimal Telegram Bot API client, bound to a single chat
+#[derive(Clone)]
+pub struct TelegramBot {
+    client: reqwest::Client,
+    token: String,
+    chat_id: i64,
+}
+
+impl TelegramBot {
+    pub fn new(token: &str, chat_id: i64) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            token: token.to_string(),
+            chat_id,
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, body: serde_json::Value) -> Result<T> {
+        let response: Response<T> = self
+            .client
+            .post(format!(
+                "https://api.telegram.org/bot{}/{}",
+                self.token, method
+            ))
+            .json(&body)
+            .timeout(Duration::from_secs(POLL_TIMEOUT_SECS + 10))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if !response.ok {
+            bail!(
+                "Telegram {method} failed: {}",
+                response.description.unwrap_or_default()
+            );
+        }
+        response
+            .result
+            .ok_or_else(|| anyhow!("Telegram {method} returned no result"))
+    }
+
+    pub async fn send_message(&self, text: &str) -> Result<()> {
+        let _: serde_json::Value = self
+            .call(
+                "sendMessage",
+                json!({ "chat_id": self.chat_id, "text": text }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Long polls the updates after `offset`
+    pub async fn get_updates(&self, offset: i64) -> Result<Vec<Update>> {
+        self.call(
+            "getUpdates",
+            json!({
+                "offset": offset,
+                "timeout": POLL_TIMEOUT_SECS,
+                "allowed_updates": ["message"],
+            }),
+        )
+        .await
+    }
+}
+
+/// Posts the texts written in the bot chat to `channel`, named after their
+/// sender with [AUTHOR_SUFFIX]
+async fn poll_replies(bot: TelegramBot, channel: String, inbound_tx: UnboundedSender<Post>) {
+    let mut offset = 0;
+    loop {
+        let updates = match bot.get_updates(offset).await {
+            Ok(updates) => updates,
+            Err(err) => {
+                warn!("Telegram poll failed: {err}");
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        for update in updates {
+            offset = offset.max(update.update_id + 1);
+            let Some(message) = update.message else {
+                continue;
+            };
+            let (Some(text), Some(from)) = (message.text, message.from) else {
+                continue;
+            };
+            if message.chat.id != bot.chat_id {
+                continue;
+            }
+            let post = Post {
+                channel: channel.clone(),
+                author: format!(
+                    "{}{AUTHOR_SUFFIX}",
+                    from.username.unwrap_or(from.first_name)
+                ),
+                text,
+            };
+            if inbound_tx.send(post).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Forwards BBS posts, watch alerts and the direct messages to our node to a
+/// Telegram chat, and posts the chat replies to `TELEGRAM_CHANNEL`. Runs until
+/// the mesh service is gone.
+pub async fn run_bridge(
+    config: Config,
+    mut status_rx: StatusReceiver,
+    state: State,
+    mut posts_rx: broadcast::Receiver<Post>,
+    mut alerts_rx: broadcast::Receiver<String>,
+    inbound_tx: UnboundedSender<Post>,
+) -> Result<()> {
+    if config.telegram_token.is_empty() || config.telegram_chat_id == 0 {
+        bail!("Telegram needs TELEGRAM_TOKEN and TELEGRAM_CHAT_ID");
+    }
+    let bot = TelegramBot::new(&config.telegram_token, config.telegram_chat_id);
+    let poller = tokio::spawn(poll_replies(
+        bot.clone(),
+        config.telegram_channel.clone(),
+        inbound_tx,
+    ));
+    info!("Telegram bridge to chat {}", config.telegram_chat_id);
+
+    let mut queue = VecDeque::new();
+    let mut send_interval = tokio::time::interval(SEND_INTERVAL);
+    loop {
+        let text = tokio::select! {
+            status = status_rx.recv() => match status {
+                Some(Status::NewMessage(id)) => {
+                    let state = state.read().await;
+                    let Some(msg) = state.message(id) else {
+                        continue;
+                    };
+                    if state.my_node_num().ok() != Some(msg.to) {
+                        continue;
+                    }
+                    let name = state.node_name(msg.from);
+                    format!("📩 {}: {}", name, msg.text)
+                }
+                Some(_) => continue,
+                None => break,
+            },
+            // Posts from the chat came through here, they are not echoed back
+            Ok(post) = posts_rx.recv() => {
+                if post.author.ends_with(AUTHOR_SUFFIX) {
+                    continue;
+                }
+                format!("#{} {}: {}", post.channel, post.author, post.text)
+            }
+            Ok(alert) = alerts_rx.recv() => alert,
+            _ = send_interval.tick() => {
+                if let Some(text) = queue.front()
+                    && let Err(err) = bot.send_message(text).await
+                {
+                    warn!("Telegram send failed: {err}");
+                    continue;
+                }
+                queue.pop_front();
+                continue;
+            }
+        };
+        if queue.len() == MAX_QUEUED {
+            queue.pop_front();
+        }
+        queue.push_back(text);
+    }
+    poller.abort();
+    Ok(())
+}
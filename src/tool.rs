@@ -1,9 +1,25 @@
-use std::{io::Write, time::Duration};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Result, bail};
+use meshtastic::protobufs::{self, admin_message::ConfigType};
+use reedline::{
+    ColumnarMenu, Completer, DefaultPrompt, DefaultPromptSegment, Emacs, FileBackedHistory,
+    KeyCode, KeyModifiers, MenuBuilder, Reedline, ReedlineEvent, ReedlineMenu, Signal, Span,
+    Suggestion, default_emacs_keybindings,
+};
+use serde::Serialize;
 use tokio::signal;
 
-use crate::mesh::service::{self, Handler, Service};
+use crate::config;
+use crate::mesh::{
+    radio_config,
+    service::{self, Destination, Handler, Options, Service, format_node_id},
+};
 
 pub async fn dump_ble_devices() -> Result<()> {
     let devices = meshtastic::utils::stream::available_ble_devices(Duration::from_secs(2)).await?;
@@ -17,6 +33,53 @@ pub async fn dump_ble_devices() -> Result<()> {
     Ok(())
 }
 
+#[derive(Serialize)]
+struct Found {
+    name: Option<String>,
+    mac: String,
+}
+
+/// `scan` subcommand: lists the BLE devices found in `secs` whose name
+/// starts with `prefix`, as text or JSON. With `save` the only one found
+/// becomes `BLE_DEVICE` in the .env file.
+pub async fn run_scan(secs: u64, prefix: &str, json: bool, save: bool) -> Result<()> {
+    let devices =
+        meshtastic::utils::stream::available_ble_devices(Duration::from_secs(secs)).await?;
+    let found: Vec<_> = devices
+        .into_iter()
+        .filter(|device| device.name.as_deref().unwrap_or("").starts_with(prefix))
+        .map(|device| Found {
+            name: device.name,
+            mac: device.mac_address.to_string(),
+        })
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&found)?);
+    } else {
+        for device in &found {
+            println!(
+                "- Found BLE device: name={:?} mac={}",
+                device.name, device.mac
+            );
+        }
+    }
+    if save {
+        let name = match found.as_slice() {
+            [
+                Found {
+                    name: Some(name), ..
+                },
+            ] => name,
+            [] => bail!("No BLE devices found."),
+            [_] => bail!("The device found has no name."),
+            _ => bail!("Multiple devices found, narrow them with --prefix."),
+        };
+        config::save_var(Path::new(config::ENV_PATH), "BLE_DEVICE", name)?;
+        eprintln!("Saved BLE_DEVICE={name} to {}", config::ENV_PATH);
+    }
+    Ok(())
+}
+
 pub async fn ble_device_auto() -> Result<String> {
     let mut devices =
         meshtastic::utils::stream::available_ble_devices(Duration::from_secs(2)).await?;
@@ -34,102 +97,697 @@ pub async fn ble_device_auto() -> Result<String> {
     }
 }
 
-pub async fn run_tool() -> Result<()> {
+const ADMIN_USAGE: &str = "Usage: admin [--to <node>] <reboot [secs]|set-owner <short_name> <long_name>|get-config <device|position|power|network|display|lora|bluetooth>|set-position <lat> <lon> [alt]>";
+
+/// `admin` REPL command, acts on the connected radio unless --to is given
+async fn admin(handler: &Handler, args: &[&str]) -> Result<()> {
+    let mut args = args;
+    let to = match args {
+        ["--to", node, rest @ ..] => {
+            args = rest;
+            node.to_string()
+        }
+        _ => format_node_id(handler.state.read().await.my_node_num()?),
+    };
+    match args {
+        ["reboot"] => handler.admin_reboot(to, 5).await?,
+        ["reboot", seconds] => handler.admin_reboot(to, seconds.parse()?).await?,
+        ["set-owner", short_name, long_name @ ..] if !long_name.is_empty() => {
+            handler
+                .admin_set_owner(to, short_name, &long_name.join(" "))
+                .await?
+        }
+        ["get-config", name] => {
+            let Some(config_type) =
+                ConfigType::from_str_name(&format!("{}_CONFIG", name.to_uppercase()))
+            else {
+                bail!("Unknown config {name}");
+            };
+            println!("{:#?}", handler.admin_get_config(to, config_type).await?);
+        }
+        ["set-position", lat, lon] => {
+            handler
+                .admin_set_fixed_position(to, lat.parse()?, lon.parse()?, 0)
+                .await?
+        }
+        ["set-position", lat, lon, alt] => {
+            handler
+                .admin_set_fixed_position(to, lat.parse()?, lon.parse()?, alt.parse()?)
+                .await?
+        }
+        _ => println!("{ADMIN_USAGE}"),
+    }
+    Ok(())
+}
+
+/// `config` REPL command, reads and writes the settings of the connected radio.
+/// Writing them is an admin command, only available with `enable_admin`.
+async fn config(handler: &Handler, args: &[&str], enable_admin: bool) -> Result<()> {
+    match args {
+        ["get"] | ["get", _] => {
+            let state = handler.state.read().await;
+            let sections = state
+                .configs()
+                .iter()
+                .map(radio_config::fields)
+                .chain(state.module_configs().iter().map(radio_config::fields));
+            for section in sections {
+                let (name, fields) = section?;
+                if args.get(1).is_some_and(|wanted| *wanted != name) {
+                    continue;
+                }
+                println!("{name}:");
+                for (field, value) in fields {
+                    println!("  {field} = {value}");
+                }
+            }
+        }
+        ["set", _, value @ ..] if !value.is_empty() && !enable_admin => {
+            println!("Admin commands are disabled, restart with --enable-admin");
+        }
+        ["set", path, value @ ..] if !value.is_empty() => {
+            let Some((name, field)) = path.split_once('.') else {
+                bail!("Expected <section>.<field>");
+            };
+            let value = value.join(" ");
+            let (config, module_config) = {
+                let state = handler.state.read().await;
+                let named = |fields: Result<(String, _)>| fields.is_ok_and(|(n, _)| n == name);
+                (
+                    state
+                        .configs()
+                        .iter()
+                        .find(|c| named(radio_config::fields(*c)))
+                        .cloned(),
+                    state
+                        .module_configs()
+                        .iter()
+                        .find(|c| named(radio_config::fields(*c)))
+                        .cloned(),
+                )
+            };
+            if let Some(config) = config {
+                handler
+                    .set_config(radio_config::with_field(&config, field, &value)?)
+                    .await?;
+            } else if let Some(config) = module_config {
+                handler
+                    .set_module_config(radio_config::with_field(&config, field, &value)?)
+                    .await?;
+            } else {
+                bail!("Unknown section {name}");
+            }
+            println!("Written, the radio may reboot to apply it");
+        }
+        _ => println!("Usage: config get [section] | config set <section>.<field> <value>"),
+    }
+    Ok(())
+}
+
+const HISTORY_PATH: &str = "./meshboard.history";
+const HISTORY_SIZE: usize = 1000;
+const COMPLETION_MENU: &str = "completion_menu";
+const COMMANDS: [&str; 12] = [
+    "ble",
+    "nodes",
+    "config",
+    "telemetry",
+    "where",
+    "traceroute",
+    "listen",
+    "send",
+    "broadcast",
+    "admin",
+    "help",
+    "exit",
+];
+
+/// Completes the command, then node short names
+struct ToolCompleter {
+    // Short names of the nodes known to the radio, updated before each line
+    nodes: Arc<Mutex<Vec<String>>>,
+}
+
+impl Completer for ToolCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let nodes = self.nodes.lock().unwrap();
+        complete(&line[..pos], &nodes)
+    }
+}
+
+fn complete(line: &str, nodes: &[String]) -> Vec<Suggestion> {
+    let start = line.rfind(' ').map_or(0, |space| space + 1);
+    let word = &line[start..];
+    let candidates: Vec<&str> = if start == 0 {
+        COMMANDS.to_vec()
+    } else {
+        nodes.iter().map(String::as_str).collect()
+    };
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .map(|candidate| Suggestion {
+            value: candidate.to_string(),
+            span: Span::new(start, line.len()),
+            append_whitespace: true,
+            ..Default::default()
+        })
+        .collect()
+}
+
+// Line editor with the history of previous sessions and tab completion
+fn editor(nodes: Arc<Mutex<Vec<String>>>) -> Result<Reedline> {
+    let history = FileBackedHistory::with_file(HISTORY_SIZE, PathBuf::from(HISTORY_PATH))?;
+    let mut keybindings = default_emacs_keybindings();
+    keybindings.add_binding(
+        KeyModifiers::NONE,
+        KeyCode::Tab,
+        ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(COMPLETION_MENU.to_string()),
+            ReedlineEvent::MenuNext,
+        ]),
+    );
+    let menu = ColumnarMenu::default().with_name(COMPLETION_MENU);
+    Ok(Reedline::create()
+        .with_history(Box::new(history))
+        .with_completer(Box::new(ToolCompleter { nodes }))
+        .with_menu(ReedlineMenu::EngineCompleter(Box::new(menu)))
+        .with_edit_mode(Box::new(Emacs::new(keybindings))))
+}
+
+/// Runs the REPL, `admin` commands are only available with `enable_admin`.
+/// Ctrl+C drops the line being typed or interrupts the running command, Ctrl+D
+/// or `exit` quit.
+pub async fn run_tool(enable_admin: bool) -> Result<()> {
     println!("Starting Tool. Type 'help' for commands.");
+    let nodes = Arc::new(Mutex::new(Vec::new()));
+    let mut editor = editor(nodes.clone())?;
     let mut handler: Option<Handler> = None;
     loop {
-        if let Some(handler) = &handler
-            && let Some(short_name) = handler.state.read().await.my_short_name().await
-        {
-            print!("{short_name}");
-        }
-        print!(">");
-        std::io::stdout().flush()?; // ensure prompt shows before blocking
-        let mut command = String::new();
-        std::io::stdin().read_line(&mut command)?; // reads until '\n'
-        let line: Vec<&str> = command.trim().split(" ").collect(); // remove trailing newline
-        match line[0] {
-            "exit" => break,
-            "ble" => {
-                if line.len() < 2 {
-                    println!("Usage: ble <device_name|auto>");
-                    println!("Available devices:");
-                    dump_ble_devices().await?;
-                    continue;
-                }
-                let mut device_name = line[1].to_string();
-                if device_name == String::from("auto") {
-                    match ble_device_auto().await {
-                        Ok(name) => device_name = name,
-                        Err(e) => {
-                            println!("Error: {}", e);
-                            continue;
-                        }
+        let mut short_name = String::new();
+        if let Some(handler) = &handler {
+            let state = handler.state.read().await;
+            short_name = state.my_short_name().unwrap_or_default();
+            *nodes.lock().unwrap() = state
+                .users()
+                .map(|(_, user)| user.short_name.clone())
+                .collect();
+        }
+        let prompt = DefaultPrompt::new(
+            DefaultPromptSegment::Basic(short_name),
+            DefaultPromptSegment::Empty,
+        );
+        let command = match tokio::task::block_in_place(|| editor.read_line(&prompt))? {
+            Signal::Success(command) => command,
+            Signal::CtrlC => continue,
+            Signal::CtrlD => break,
+        };
+        let line: Vec<&str> = command.split_whitespace().collect();
+        match line.first() {
+            None => continue,
+            Some(&"exit") => break,
+            Some(_) => {}
+        }
+        tokio::select! {
+            result = run_command(&mut handler, &line, enable_admin) => result?,
+            _ = signal::ctrl_c() => println!("Interrupted"),
+        }
+    }
+    Ok(())
+}
+
+async fn run_command(
+    handler: &mut Option<Handler>,
+    line: &[&str],
+    enable_admin: bool,
+) -> Result<()> {
+    match line[0] {
+        "ble" => {
+            if line.len() < 2 {
+                println!("Usage: ble <device_name|auto>");
+                println!("Available devices:");
+                dump_ble_devices().await?;
+                return Ok(());
+            }
+            let mut device_name = line[1].to_string();
+            if device_name == String::from("auto") {
+                match ble_device_auto().await {
+                    Ok(name) => device_name = name,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return Ok(());
                     }
                 }
-                if let Some(h) = handler.take() {
-                    println!("Disconnecting from previous device...");
-                    h.finish().await;
-                    println!("Disconnected.");
-                }
+            }
+            if let Some(h) = handler.take() {
+                println!("Disconnecting from previous device...");
+                h.finish().await;
+                println!("Disconnected.");
+            }
+
+            let mut new_handler = Service::from_ble(&device_name, Options::default()).await?;
+            println!("Using device: {}, booting..", device_name);
+            if let Err(err) = new_handler.wait_for_boot_ready(30).await {
+                println!("Error: {}", err);
+            }
+
+            *handler = Some(new_handler);
+        }
+        "listen" => {
+            if let Some(mut handler) = handler.as_mut() {
+                let all = line.len() > 1 && line[1] == "all";
+                listen(&mut handler, all).await?;
+            }
+        }
+        "send" => {
+            let mut args = &line[1..];
+            let mut channel = 0;
+            if args.first() == Some(&"--ch") {
+                let Some(Ok(index)) = args.get(1).map(|index| index.parse()) else {
+                    println!("Usage: send --ch <channel_index> ...");
+                    return Ok(());
+                };
+                channel = index;
+                args = &args[2..];
+            }
+            if args.len() < 2 {
+                println!("Usage: send [--ch N] <short_name|!hex_id|node_num> <message>");
+                return Ok(());
+            }
+            let short_name = args[0];
+            let message = args[1..].join(" ");
 
-                let mut new_handler = Service::from_ble(&device_name).await?;
-                println!("Using device: {}, booting..", device_name);
-                if let Err(err) = new_handler.wait_for_boot_ready(30).await {
+            if let Some(mut handler) = handler.as_mut() {
+                let user_id = {
+                    let state = handler.state.read().await;
+                    let Some(user_id) = state.resolve_node(short_name) else {
+                        println!("Node not found: {}", short_name);
+                        return Ok(());
+                    };
+                    user_id
+                };
+
+                println!("Sending message to{}...", short_name);
+                if let Err(err) = handler.send_text_on(message, user_id, channel).await {
                     println!("Error: {}", err);
+                    return Ok(());
                 }
+                listen(&mut handler, false).await?;
+            }
+        }
+        "broadcast" => {
+            let mut args = &line[1..];
+            let mut channel = 0;
+            if args.first() == Some(&"--ch") {
+                let Some(Ok(index)) = args.get(1).map(|index| index.parse()) else {
+                    println!("Usage: broadcast --ch <channel_index> ...");
+                    return Ok(());
+                };
+                channel = index;
+                args = &args[2..];
+            }
+            if args.is_empty() {
+                println!("Usage: broadcast [--ch N] <message>");
+                return Ok(());
+            }
+            let message = args.join(" ");
 
-                handler = Some(new_handler);
+            if let Some(mut handler) = handler.as_mut() {
+                println!("Broadcasting message...");
+                if let Err(err) = handler
+                    .send_text_on(message, Destination::Broadcast, channel)
+                    .await
+                {
+                    println!("Error: {}", err);
+                    return Ok(());
+                }
+                listen(&mut handler, false).await?;
             }
-            "listen" => {
-                if let Some(mut handler) = handler.as_mut() {
-                    let all = line.len() > 1 && line[1] == "all";
-                    listen(&mut handler, all).await?;
+        }
+        "traceroute" => {
+            if line.len() < 2 {
+                println!("Usage: traceroute <short_name|!hex_id|node_num>");
+                return Ok(());
+            }
+            if let Some(handler) = handler.as_ref() {
+                println!("Tracing route to {}...", line[1]);
+                let hops = match handler.traceroute(line[1]).await {
+                    Ok(hops) => hops,
+                    Err(err) => {
+                        println!("Error: {}", err);
+                        return Ok(());
+                    }
+                };
+                let state = handler.state.read().await;
+                for (n, hop) in hops.iter().enumerate() {
+                    let name = state
+                        .user(hop.node)
+                        .map(|user| user.short_name.clone())
+                        .unwrap_or_default();
+                    let snr = hop
+                        .snr
+                        .map(|snr| format!("{snr:.2}dB"))
+                        .unwrap_or("?dB".into());
+                    println!(
+                        "{:>2} {} {:<4} {}",
+                        n + 1,
+                        format_node_id(hop.node),
+                        name,
+                        snr
+                    );
                 }
             }
-            "send" => {
-                if line.len() < 3 {
-                    println!("Usage: send <node_short_name> <message>");
-                    continue;
+        }
+        "admin" => {
+            if !enable_admin {
+                println!("Admin commands are disabled, restart with --enable-admin");
+                return Ok(());
+            }
+            if let Some(handler) = handler.as_ref()
+                && let Err(err) = admin(handler, &line[1..]).await
+            {
+                println!("Error: {}", err);
+            }
+        }
+        "config" => {
+            if let Some(handler) = handler.as_ref()
+                && let Err(err) = config(handler, &line[1..], enable_admin).await
+            {
+                println!("Error: {}", err);
+            }
+        }
+        "where" => {
+            if line.len() < 2 {
+                println!("Usage: where <short_name|!hex_id|node_num>");
+                return Ok(());
+            }
+            if let Some(handler) = handler.as_ref() {
+                let state = handler.state.read().await;
+                let Some(node) = state.resolve_node(line[1]) else {
+                    println!("Node not found: {}", line[1]);
+                    return Ok(());
+                };
+                let Some((lat, lon)) = state.get_position_by_node_id(node) else {
+                    println!("No position for {}", format_node_id(node));
+                    return Ok(());
+                };
+                match state.last_position(node) {
+                    Some((ts, _)) => {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_millis() as u64;
+                        println!("{lat:.5},{lon:.5} {}s ago", now.saturating_sub(ts) / 1000);
+                    }
+                    None => println!("{lat:.5},{lon:.5}"),
                 }
-                let short_name = line[1];
-                let message = line[2..].join(" ");
-
-                if let Some(mut handler) = handler.as_mut() {
-                    let user_id = {
-                        let state = handler.state.read().await;
-                        let Some(user_id) = state.get_node_id_by_short_name(short_name) else {
-                            println!("Node not found: {}", short_name);
-                            continue;
-                        };
-                        user_id
-                    };
-
-                    println!("Sending message to{}...", short_name);
-                    handler.send_text(message, user_id).await?;
-                    listen(&mut handler, false).await?;
+            }
+        }
+        "telemetry" => {
+            if line.len() < 2 {
+                println!("Usage: telemetry <short_name|!hex_id|node_num>");
+                return Ok(());
+            }
+            if let Some(handler) = handler.as_ref() {
+                let state = handler.state.read().await;
+                let Some(node) = state.resolve_node(line[1]) else {
+                    println!("Node not found: {}", line[1]);
+                    return Ok(());
+                };
+                let history: Vec<_> = state.telemetry(node).collect();
+                if history.is_empty() {
+                    println!("No telemetry from {}", format_node_id(node));
+                    return Ok(());
+                }
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                for (ts, metrics) in history {
+                    println!("{:>6}s ago {}", now.saturating_sub(ts) / 1000, metrics);
                 }
             }
-            "nodes" => {
-                if let Some(handler) = handler.as_ref() {
-                    let state = handler.state.read().await;
-                    let mut nodes: Vec<_> = state
-                        .nodes
-                        .iter()
-                        .map(|(_, user)| &user.short_name)
-                        .collect();
-                    nodes.sort();
-                    println!("{:?}", nodes);
+        }
+        "nodes" if line.get(1) == Some(&"-v") => {
+            if let Some(handler) = handler.as_ref() {
+                let state = handler.state.read().await;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                let mut heard: Vec<_> = state.heard().collect();
+                heard.sort_by_key(|(_, heard)| std::cmp::Reverse(heard.ts));
+                for (id, heard) in heard {
+                    let (short_name, long_name) = state
+                        .user(id)
+                        .map(|user| (user.short_name.as_str(), user.long_name.as_str()))
+                        .unwrap_or(("?", ""));
+                    let hops = heard
+                        .hops
+                        .map(|hops| hops.to_string())
+                        .unwrap_or("?".into());
+                    println!(
+                        "{:>6}s ago {} {:<4} {:<24} snr {:>5.1} rssi {:>4} hops {}",
+                        now.saturating_sub(heard.ts) / 1000,
+                        format_node_id(id),
+                        short_name,
+                        long_name,
+                        heard.snr,
+                        heard.rssi,
+                        hops
+                    );
                 }
             }
-            "help" => {
-                println!("Available commands: ble, nodes, listen, send, exit");
+        }
+        "nodes" => {
+            if let Some(handler) = handler.as_ref() {
+                let state = handler.state.read().await;
+                let mut nodes: Vec<_> = state
+                    .users()
+                    .map(|(id, user)| format!("{} {}", user.short_name, format_node_id(id)))
+                    .collect();
+                nodes.sort();
+                println!("{:?}", nodes);
+            }
+        }
+        "help" => {
+            println!(
+                "Available commands: ble, nodes [-v], config, telemetry, where, traceroute, listen, send, broadcast, exit"
+            );
+            if enable_admin {
+                println!("{ADMIN_USAGE}");
+            }
+        }
+        _ => {
+            println!("Unknown command: {}", line.join(" "));
+        }
+    }
+    Ok(())
+}
+
+/// Connects to the primary radio and waits for it to load its node database
+async fn connect_primary(config: &config::Config) -> Result<Handler> {
+    let ble_device = crate::bbs::radios::ble_devices(config)?.remove(0);
+    let mut handler = Service::from_ble(&ble_device, config.mesh_options()).await?;
+    handler.wait_for_boot_ready(30).await?;
+    Ok(handler)
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct NodeRow {
+    id: String,
+    short_name: String,
+    long_name: String,
+    // Seconds since it was last heard
+    last_heard: Option<u64>,
+    snr: Option<f32>,
+    hops: Option<u32>,
+}
+
+/// The known and heard nodes, most recently heard first, `now` in ms
+fn node_rows(
+    users: HashMap<u32, &protobufs::User>,
+    heard: HashMap<u32, &service::Heard>,
+    now: u64,
+) -> Vec<NodeRow> {
+    let mut ids: Vec<u32> = users.keys().chain(heard.keys()).copied().collect();
+    ids.sort();
+    ids.dedup();
+    ids.sort_by_key(|id| std::cmp::Reverse(heard.get(id).map(|heard| heard.ts)));
+    ids.into_iter()
+        .map(|id| {
+            let heard = heard.get(&id);
+            let user = users.get(&id);
+            NodeRow {
+                id: format_node_id(id),
+                short_name: user.map(|user| user.short_name.clone()).unwrap_or_default(),
+                long_name: user.map(|user| user.long_name.clone()).unwrap_or_default(),
+                last_heard: heard.map(|heard| now.saturating_sub(heard.ts) / 1000),
+                snr: heard.map(|heard| heard.snr),
+                hops: heard.and_then(|heard| heard.hops),
+            }
+        })
+        .collect()
+}
+
+/// `nodes` subcommand: the node database of the primary radio, most
+/// recently heard first, as a table or JSON
+pub async fn run_nodes(config: &config::Config, json: bool) -> Result<()> {
+    let handler = connect_primary(config).await?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let rows = {
+        let state = handler.state.read().await;
+        node_rows(state.users().collect(), state.heard().collect(), now)
+    };
+    handler.finish().await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+    println!(
+        "{:<9} {:<4} {:<24} {:>8} {:>5} {:>4}",
+        "ID", "NAME", "LONG NAME", "HEARD", "SNR", "HOPS"
+    );
+    let or_dash = |value: Option<String>| value.unwrap_or("-".into());
+    for row in rows {
+        println!(
+            "{:<9} {:<4} {:<24} {:>8} {:>5} {:>4}",
+            row.id,
+            row.short_name,
+            row.long_name,
+            or_dash(row.last_heard.map(|secs| format!("{secs}s"))),
+            or_dash(row.snr.map(|snr| format!("{snr:.1}"))),
+            or_dash(row.hops.map(|hops| hops.to_string())),
+        );
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ChannelRow {
+    index: i32,
+    name: String,
+    role: String,
+}
+
+#[derive(Serialize)]
+struct Info {
+    id: String,
+    short_name: String,
+    long_name: String,
+    firmware: Option<String>,
+    hardware: Option<String>,
+    region: Option<String>,
+    channels: Vec<ChannelRow>,
+}
+
+/// `info` subcommand: the node, firmware, region and channels of the
+/// primary radio, as text or JSON
+pub async fn run_info(config: &config::Config, json: bool) -> Result<()> {
+    let handler = connect_primary(config).await?;
+    let info = {
+        let state = handler.state.read().await;
+        let num = state.my_node_num()?;
+        let user = state.user(num);
+        let region = state
+            .configs()
+            .iter()
+            .find_map(|config| match &config.payload_variant {
+                Some(protobufs::config::PayloadVariant::Lora(lora)) => {
+                    Some(lora.region().as_str_name().to_string())
+                }
+                _ => None,
+            });
+        Info {
+            id: format_node_id(num),
+            short_name: user.map(|user| user.short_name.clone()).unwrap_or_default(),
+            long_name: user.map(|user| user.long_name.clone()).unwrap_or_default(),
+            firmware: state
+                .my_metadata()
+                .map(|metadata| metadata.firmware_version.clone()),
+            hardware: state
+                .my_metadata()
+                .map(|metadata| metadata.hw_model().as_str_name().to_string()),
+            region,
+            channels: state
+                .channels()
+                .iter()
+                .filter(|channel| channel.role() != protobufs::channel::Role::Disabled)
+                .map(|channel| ChannelRow {
+                    index: channel.index,
+                    name: channel
+                        .settings
+                        .as_ref()
+                        .map(|settings| settings.name.clone())
+                        .unwrap_or_default(),
+                    role: channel.role().as_str_name().to_string(),
+                })
+                .collect(),
+        }
+    };
+    handler.finish().await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    let or_unknown = |value: Option<String>| value.unwrap_or("?".into());
+    println!(
+        "Node     {} {} {}",
+        info.id, info.short_name, info.long_name
+    );
+    println!("Firmware {}", or_unknown(info.firmware));
+    println!("Hardware {}", or_unknown(info.hardware));
+    println!("Region   {}", or_unknown(info.region));
+    for channel in info.channels {
+        println!(
+            "Channel  {} {} {}",
+            channel.index, channel.name, channel.role
+        );
+    }
+    Ok(())
+}
+
+/// Plays a capture or scenario file through the mesh handler, as if it came
+/// from the radio
+pub async fn run_replay(path: &Path, options: Options, all: bool) -> Result<()> {
+    let mut handler = Service::from_replay(path, options).await?;
+    let mut packets = 0;
+    loop {
+        let status = tokio::select! {
+            status = handler.status_rx.recv() => status,
+            _ = handler.cancel.cancelled() => None,
+        };
+        let Some(status) = status else {
+            break;
+        };
+        match status {
+            service::Status::NewMessage(id) | service::Status::UpdatedMessage(id) => {
+                let state = handler.state.read().await;
+                if let Some(msg) = state.message(id) {
+                    println!("{}", state.format_msg(&msg));
+                }
             }
-            _ => {
-                println!("Unknown command: {}", command);
+            service::Status::FromRadio(from_radio) => {
+                packets += 1;
+                if all {
+                    println!("{:?}\n", from_radio);
+                }
             }
+            _ => {}
         }
     }
+    {
+        let state = handler.state.read().await;
+        println!(
+            "Replayed {} packets, {} nodes, {} texts",
+            packets,
+            state.users().count(),
+            state.messages().count()
+        );
+    }
+    handler.finish().await;
     Ok(())
 }
 
@@ -145,15 +803,15 @@ pub async fn listen(handler: &mut Handler, all: bool) -> Result<()> {
                     },
                     service::Status::NewMessage(id) => {
                         let state = handler.state.read().await;
-                        let msg = state.msg(id).await.unwrap();
+                        let msg = state.message(id).unwrap();
                         println!("{}", state.format_msg(&msg));
-                        if state.my_node_num().await == msg.to {
-                            handler.send_text(format!("Got {}", msg.text), msg.from).await?;
+                        if state.my_node_num().ok() == Some(msg.to) {
+                            handler.send_text_on(format!("Got {}", msg.text), msg.from, msg.channel).await?;
                         }
                     },
                     service::Status::UpdatedMessage(id) => {
                         let state = handler.state.read().await;
-                        let msg = state.msg(id).await.unwrap();
+                        let msg = state.message(id).unwrap();
                         println!("{}", state.format_msg(&msg));
                     },
                     service::Status::Heartbeat(_packet_count) => {
@@ -164,6 +822,9 @@ pub async fn listen(handler: &mut Handler, all: bool) -> Result<()> {
                             println!("{:?}\n", from_radio);
                         }
                     },
+                    service::Status::Dropped(lost) => {
+                        println!("Fell behind, {lost} events lost");
+                    },
                 }
             }
             _ = handler.cancel.cancelled() => break,
@@ -174,3 +835,63 @@ pub async fn listen(handler: &mut Handler, all: bool) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let nodes = vec!["ann".to_string(), "bob".to_string(), "anx".to_string()];
+        let values = |line: &str| -> Vec<String> {
+            complete(line, &nodes)
+                .into_iter()
+                .map(|suggestion| suggestion.value)
+                .collect()
+        };
+        assert_eq!(values("tr"), vec!["traceroute"]);
+        assert_eq!(values("send an"), vec!["ann", "anx"]);
+        assert!(values("send x").is_empty());
+        let suggestion = &complete("where b", &nodes)[0];
+        assert_eq!((suggestion.span.start, suggestion.span.end), (6, 7));
+    }
+
+    #[test]
+    fn test_node_rows() {
+        let ann = protobufs::User {
+            short_name: "ann".into(),
+            long_name: "Ann".into(),
+            ..Default::default()
+        };
+        let heard = |ts| service::Heard {
+            ts,
+            snr: 6.5,
+            rssi: -80,
+            hops: Some(1),
+        };
+        let (old, recent) = (heard(10_000), heard(50_000));
+        // Node 3 was heard but never told its name, node 4 was not heard
+        let rows = node_rows(
+            HashMap::from([(2, &ann), (4, &ann)]),
+            HashMap::from([(2, &old), (3, &recent)]),
+            60_000,
+        );
+        let ids: Vec<&str> = rows.iter().map(|row| row.id.as_str()).collect();
+        assert_eq!(ids, vec!["!00000003", "!00000002", "!00000004"]);
+        assert_eq!(
+            rows[1],
+            NodeRow {
+                id: "!00000002".into(),
+                short_name: "ann".into(),
+                long_name: "Ann".into(),
+                last_heard: Some(50),
+                snr: Some(6.5),
+                hops: Some(1),
+            }
+        );
+        assert_eq!(
+            (rows[0].short_name.as_str(), rows[2].last_heard),
+            ("", None)
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use std::iter;
+
+use anyhow::Result;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use ratatui::{
+    DefaultTerminal, Frame,
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListState, Paragraph},
+};
+
+use crate::mesh::service::{Destination, Handler, HandlerState, Status, format_node_id};
+
+// Lines kept in the message pane
+const MAX_LINES: usize = 500;
+const NODES_WIDTH: u16 = 28;
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    None,
+    Send(String),
+    Quit,
+}
+
+/// What the TUI shows besides the radio state
+#[derive(Default)]
+struct App {
+    // Short name of the radio, once known
+    me: Option<String>,
+    // Known nodes as (node, label), by short name
+    nodes: Vec<(u32, String)>,
+    // Destination of the texts typed, None is broadcast
+    to: Option<u32>,
+    // Texts by packet id, and notices without one, oldest first
+    lines: Vec<(Option<u32>, String)>,
+    input: String,
+    ready: bool,
+}
+
+impl App {
+    fn destinations(&self) -> Vec<Option<u32>> {
+        iter::once(None)
+            .chain(self.nodes.iter().map(|(node, _)| Some(*node)))
+            .collect()
+    }
+
+    // Moves the destination `step` places down the node list, wrapping around
+    fn select(&mut self, step: isize) {
+        let destinations = self.destinations();
+        let current = destinations
+            .iter()
+            .position(|to| *to == self.to)
+            .unwrap_or(0);
+        let next = (current as isize + step).rem_euclid(destinations.len() as isize);
+        self.to = destinations[next as usize];
+    }
+
+    fn destination_name(&self) -> String {
+        match self.to {
+            None => "broadcast".to_string(),
+            Some(node) => self
+                .nodes
+                .iter()
+                .find(|(id, _)| *id == node)
+                .map(|(_, label)| label.clone())
+                .unwrap_or_else(|| format_node_id(node)),
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::None;
+        }
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Action::Quit;
+            }
+            KeyCode::Up | KeyCode::BackTab => self.select(-1),
+            KeyCode::Down | KeyCode::Tab => self.select(1),
+            KeyCode::Enter if !self.input.trim().is_empty() => {
+                let text = std::mem::take(&mut self.input);
+                return Action::Send(text.trim().to_string());
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            _ => {}
+        }
+        Action::None
+    }
+
+    // Adds a text, or replaces it when its status changes
+    fn message(&mut self, id: u32, line: String) {
+        match self.lines.iter_mut().find(|(msg, _)| *msg == Some(id)) {
+            Some((_, old)) => *old = line,
+            None => self.lines.push((Some(id), line)),
+        }
+        self.trim();
+    }
+
+    fn notice(&mut self, line: String) {
+        self.lines.push((None, line));
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let excess = self.lines.len().saturating_sub(MAX_LINES);
+        self.lines.drain(..excess);
+    }
+
+    async fn update_nodes(&mut self, state: &HandlerState) {
+        self.me = state.my_short_name();
+        let mut nodes: Vec<_> = state
+            .users()
+            .map(|(id, user)| (id, format!("{} {}", user.short_name, format_node_id(id))))
+            .collect();
+        nodes.sort_by(|a, b| a.1.cmp(&b.1));
+        self.nodes = nodes;
+    }
+}
+
+fn draw(frame: &mut Frame, app: &App) {
+    let [nodes_area, main] =
+        Layout::horizontal([Constraint::Length(NODES_WIDTH), Constraint::Min(20)])
+            .areas(frame.area());
+    let [lines_area, input_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(main);
+
+    let destinations = iter::once("broadcast").chain(app.nodes.iter().map(|(_, l)| l.as_str()));
+    let selected = app.destinations().iter().position(|to| *to == app.to);
+    let nodes = List::new(destinations)
+        .block(Block::bordered().title("Nodes (Up/Down)"))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    let mut nodes_state = ListState::default().with_selected(selected);
+    frame.render_stateful_widget(nodes, nodes_area, &mut nodes_state);
+
+    // Newest lines at the bottom
+    let height = lines_area.height.saturating_sub(2) as usize;
+    let skip = app.lines.len().saturating_sub(height);
+    let lines: Vec<Line> = app.lines[skip..]
+        .iter()
+        .map(|(_, line)| Line::raw(line.as_str()))
+        .collect();
+    let title = app.me.as_deref().unwrap_or("Connecting...");
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        lines_area,
+    );
+
+    let to = format!("To {} (Enter sends, Esc quits)", app.destination_name());
+    frame.render_widget(
+        Paragraph::new(app.input.as_str()).block(Block::bordered().title(to)),
+        input_area,
+    );
+    frame.set_cursor_position((
+        input_area.x + 1 + app.input.chars().count() as u16,
+        input_area.y + 1,
+    ));
+}
+
+/// Full screen view of the radio: known nodes, texts as they come and go, and
+/// an input line to text the node selected, or everyone
+pub async fn run_tui(mut handler: Handler) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut handler).await;
+    ratatui::restore();
+    handler.finish().await;
+    result
+}
+
+async fn run(terminal: &mut DefaultTerminal, handler: &mut Handler) -> Result<()> {
+    let mut app = App::default();
+    let mut events = EventStream::new();
+    loop {
+        terminal.draw(|frame| draw(frame, &app))?;
+        tokio::select! {
+            status = handler.status_rx.recv() => {
+                let Some(status) = status else { break; };
+                let state = handler.state.read().await;
+                match status {
+                    Status::NewMessage(id) | Status::UpdatedMessage(id) => {
+                        if let Some(msg) = state.message(id) {
+                            app.message(id, state.format_msg(&msg));
+                        }
+                    }
+                    Status::Ready => {
+                        app.ready = true;
+                        app.notice("Ready".to_string());
+                    }
+                    Status::Dropped(lost) => app.notice(format!("Fell behind, {lost} events lost")),
+                    Status::Heartbeat(_) | Status::FromRadio(_) => {}
+                }
+                app.update_nodes(&state).await;
+            }
+            event = events.next() => {
+                let Some(event) = event else { break; };
+                let Event::Key(key) = event? else { continue; };
+                match app.key(key) {
+                    Action::Quit => break,
+                    Action::Send(_) if !app.ready => app.notice("The radio is not ready yet".to_string()),
+                    Action::Send(text) => {
+                        let to = app.to.map_or(Destination::Broadcast, Destination::Node);
+                        if let Err(err) = handler.send_text(text, to).await {
+                            app.notice(format!("Error: {err}"));
+                        }
+                    }
+                    Action::None => {}
+                }
+            }
+            _ = handler.cancel.cancelled() => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn press(app: &mut App, code: KeyCode) -> Action {
+        app.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut app = App {
+            nodes: vec![(2, "ann !00000002".into()), (3, "bob !00000003".into())],
+            ..Default::default()
+        };
+        assert_eq!(app.to, None);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.to, Some(2));
+        press(&mut app, KeyCode::Tab);
+        press(&mut app, KeyCode::Tab);
+        assert_eq!(app.to, None);
+        press(&mut app, KeyCode::Up);
+        assert_eq!(app.destination_name(), "bob !00000003");
+
+        assert_eq!(press(&mut app, KeyCode::Enter), Action::None);
+        for c in "hi!".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(press(&mut app, KeyCode::Enter), Action::Send("hi".into()));
+        assert!(app.input.is_empty());
+        assert_eq!(press(&mut app, KeyCode::Esc), Action::Quit);
+
+        app.message(7, "sent".into());
+        app.notice("Ready".into());
+        app.message(7, "acked".into());
+        assert_eq!(
+            app.lines,
+            vec![(Some(7), "acked".to_string()), (None, "Ready".to_string())]
+        );
+    }
+}
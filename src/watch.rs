@@ -0,0 +1,271 @@
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Local};
+use meshtastic::{
+    Message,
+    protobufs::{
+        FromRadio, MeshPacket, PortNum, Position, Routing, Telemetry, User, from_radio,
+        mesh_packet, routing,
+    },
+};
+use tracing::warn;
+
+use crate::bbs::storage::Storage;
+use crate::codec;
+use crate::config::Config;
+use crate::mesh::service::{
+    BROADCAST_ADDR, Metrics, NameResolver, Service, Signal, Status, coordinates,
+};
+use crate::telegram::TelegramBot;
+
+/// Short name of the port, as `--ports` takes it: `text_message`,
+/// `position`, `nodeinfo`, `telemetry`...
+fn port_name(port: PortNum) -> String {
+    port.as_str_name().trim_end_matches("_APP").to_lowercase()
+}
+
+/// What the packet carries, in a line
+fn describe(port: PortNum, payload: &[u8]) -> String {
+    match port {
+        PortNum::TextMessageApp => String::from_utf8_lossy(payload).into_owned(),
+        PortNum::PositionApp => match Position::decode(payload) {
+            Ok(position) => match coordinates(&position) {
+                Some((lat, lon)) => format!("{lat:.5},{lon:.5}"),
+                None => "no fix".to_string(),
+            },
+            Err(err) => format!("bad position: {err}"),
+        },
+        PortNum::NodeinfoApp => match User::decode(payload) {
+            Ok(user) => format!("{} {}", user.short_name, user.long_name),
+            Err(err) => format!("bad user: {err}"),
+        },
+        PortNum::TelemetryApp => match Telemetry::decode(payload) {
+            Ok(telemetry) => Metrics::from_telemetry(&telemetry)
+                .map(|metrics| metrics.to_string())
+                .unwrap_or_default(),
+            Err(err) => format!("bad telemetry: {err}"),
+        },
+        PortNum::RoutingApp => match Routing::decode(payload) {
+            Ok(Routing {
+                variant: Some(routing::Variant::ErrorReason(reason)),
+                ..
+            }) => routing::Error::try_from(reason)
+                .map(|error| error.as_str_name().to_string())
+                .unwrap_or_default(),
+            Ok(_) => String::new(),
+            Err(err) => format!("bad routing: {err}"),
+        },
+        _ => format!("{} bytes", payload.len()),
+    }
+}
+
+/// Port of the packet, None if we cannot decrypt it
+fn port(packet: &MeshPacket) -> Option<PortNum> {
+    match &packet.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+            Some(PortNum::try_from(data.portnum).unwrap_or(PortNum::UnknownApp))
+        }
+        _ => None,
+    }
+}
+
+/// Whether the packet is on one of the ports, all are when none is given.
+/// `encrypted` stands for the packets we cannot decrypt.
+fn wanted(packet: &MeshPacket, ports: &[String]) -> bool {
+    let name = port(packet).map_or("encrypted".to_string(), port_name);
+    ports.is_empty() || ports.contains(&name)
+}
+
+/// Fails on a name in `ports` that is no port, as a typo would watch nothing
+fn check_ports(ports: &[String]) -> Result<()> {
+    for name in ports {
+        let upper = name.to_uppercase();
+        let known = name == "encrypted"
+            || PortNum::from_str_name(&format!("{upper}_APP")).is_some()
+            || PortNum::from_str_name(&upper).is_some();
+        if !known {
+            bail!("Unknown port {name}, e.g. text_message, position or encrypted");
+        }
+    }
+    Ok(())
+}
+
+/// `<from> -> <to> <port> <what it carries>`
+fn format_packet(packet: &MeshPacket, names: &NameResolver) -> String {
+    let to = match packet.to {
+        BROADCAST_ADDR => "all".to_string(),
+        to => names.display_name(to),
+    };
+    let route = format!("{} -> {}", names.display_name(packet.from), to);
+    match (&packet.payload_variant, port(packet)) {
+        (Some(mesh_packet::PayloadVariant::Decoded(data)), Some(port)) => {
+            format!(
+                "{route} {} {}",
+                port_name(port),
+                describe(port, &data.payload)
+            )
+        }
+        _ => format!("{route} encrypted"),
+    }
+}
+
+/// Prints the packets the primary radio hears, those of `ports` only when
+/// given, as text or as JSON lines. Texts are forwarded to the Telegram chat
+/// with `telegram`. Captures follow CAPTURE_DIR. Runs until the radio is
+/// gone or Ctrl+C.
+pub async fn run_watch(
+    config: Config,
+    ports: Vec<String>,
+    json: bool,
+    telegram: bool,
+) -> Result<()> {
+    let bot = match telegram {
+        true if config.telegram_token.is_empty() || config.telegram_chat_id == 0 => {
+            bail!("Telegram needs TELEGRAM_TOKEN and TELEGRAM_CHAT_ID")
+        }
+        true => Some(TelegramBot::new(
+            &config.telegram_token,
+            config.telegram_chat_id,
+        )),
+        false => None,
+    };
+    check_ports(&ports)?;
+    let ble_device = crate::bbs::radios::ble_devices(&config)?.remove(0);
+    let mut handler = Service::from_ble(&ble_device, config.mesh_options()).await?;
+    loop {
+        let status = tokio::select! {
+            status = handler.status_rx.recv() => status,
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let Some(status) = status else {
+            break;
+        };
+        let Status::FromRadio(FromRadio {
+            payload_variant: Some(from_radio::PayloadVariant::Packet(packet)),
+            ..
+        }) = status
+        else {
+            continue;
+        };
+        if !wanted(&packet, &ports) {
+            continue;
+        }
+        let line = format_packet(&packet, handler.state.read().await.names());
+        if json {
+            println!("{}", serde_json::to_string(&packet)?);
+        } else {
+            println!("{line}");
+        }
+        if let Some(bot) = &bot
+            && port(&packet) == Some(PortNum::TextMessageApp)
+            && let Err(err) = bot.send_message(&line).await
+        {
+            warn!("Telegram send failed: {err}");
+        }
+    }
+    handler.finish().await;
+    Ok(())
+}
+
+/// Decodes again a packet kept with RAW_PACKETS: the line `watch` prints,
+/// when and how it was heard, and the text as the board reads it through
+/// MESH_CODEC. With the board stopped.
+pub fn run_decode(config: &Config, id: u32, json: bool) -> Result<()> {
+    let storage = Storage::with_backend(config.storage, Path::new(&config.db_path))?;
+    let Some(raw) = storage.get_raw_packet(id)? else {
+        bail!("Packet {id} not found, only kept with RAW_PACKETS=true");
+    };
+    let packet = MeshPacket::decode(raw.bytes.as_slice())?;
+    if json {
+        println!("{}", serde_json::to_string(&packet)?);
+        return Ok(());
+    }
+    let mut names = NameResolver::default();
+    for node in storage.get_nodes()? {
+        names.radio_names(node.num, &node.short_name, "");
+    }
+    println!("{}", format_packet(&packet, &names));
+    let received = DateTime::from_timestamp_millis(raw.ts as i64)
+        .map(|ts| {
+            ts.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|| "?".into());
+    let signal = Signal::from_packet(&packet)
+        .map(|signal| format!(", {signal}"))
+        .unwrap_or_default();
+    println!("Received {received} on channel {}{signal}", packet.channel);
+    if let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant
+        && port(&packet) == Some(PortNum::TextMessageApp)
+    {
+        let text = String::from_utf8_lossy(&data.payload);
+        println!("> {}", codec::by_name(&config.mesh_codec)?.decode(&text));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use meshtastic::protobufs::Data;
+
+    #[test]
+    fn test_format_packet() {
+        let mut names = NameResolver::default();
+        names.radio_names(2, "ann", "");
+        let packet = |port: PortNum, payload: &[u8]| MeshPacket {
+            from: 2,
+            to: BROADCAST_ADDR,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+                portnum: port as i32,
+                payload: payload.to_vec(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        let text = packet(PortNum::TextMessageApp, b"hi");
+        assert_eq!(format_packet(&text, &names), "ann -> all text_message hi");
+        let ports = vec!["position".to_string()];
+        assert!(wanted(&text, &[]));
+        assert!(!wanted(&text, &ports));
+
+        let position = Position {
+            latitude_i: Some(415_000_000),
+            longitude_i: Some(21_000_000),
+            ..Default::default()
+        };
+        let position = packet(PortNum::PositionApp, &position.encode_to_vec());
+        assert!(wanted(&position, &ports));
+        assert_eq!(
+            format_packet(&position, &names),
+            "ann -> all position 41.50000,2.10000"
+        );
+
+        let encrypted = MeshPacket {
+            from: 3,
+            to: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_packet(&encrypted, &names),
+            "!00000003 -> ann encrypted"
+        );
+        assert!(wanted(&encrypted, &["encrypted".to_string()]));
+    }
+
+    #[test]
+    fn test_check_ports() {
+        let ports = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(check_ports(&ports(&["text_message", "nodeinfo", "encrypted"])).is_ok());
+        assert!(check_ports(&ports(&[port_name(PortNum::PrivateApp).as_str()])).is_ok());
+        assert!(check_ports(&ports(&["position", "text"])).is_err());
+    }
+}
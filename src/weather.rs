@@ -0,0 +1,297 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow, bail};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{info, warn};
+
+use crate::bbs::plugins::{BbsCommand, Context};
+use crate::bbs::storage::Storage;
+
+const URL: &str = "https://api.open-meteo.com/v1/forecast";
+// Variables of the daily forecast, as in [Daily]
+const DAILY: [&str; 5] = [
+    "weather_code",
+    "temperature_2m_min",
+    "temperature_2m_max",
+    "precipitation_sum",
+    "wind_speed_10m_max",
+];
+const RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+// Setting keeping the last forecast, as JSON
+const SETTING: &str = "weather";
+/// Replaced by the forecast in scheduled texts, e.g.
+/// `daily 07:00 general {weather}`
+pub const PLACEHOLDER: &str = "{weather}";
+
+/// The forecast of a day, metric
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Day {
+    // YYYY-MM-DD, local to the location
+    pub date: String,
+    // WMO weather code
+    pub code: u32,
+    pub min: f32,
+    pub max: f32,
+    // mm
+    pub rain: f32,
+    // km/h
+    pub wind: f32,
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = NaiveDate::parse_from_str(&self.date, "%Y-%m-%d")
+            .map(|date| date.format("%a %d").to_string())
+            .unwrap_or_else(|_| self.date.clone());
+        write!(
+            f,
+            "{date} {} {:.0}..{:.0}C",
+            describe(self.code),
+            self.min,
+            self.max
+        )?;
+        if self.rain >= 0.5 {
+            write!(f, " {:.0}mm", self.rain)?;
+        }
+        write!(f, " wind {:.0}km/h", self.wind)
+    }
+}
+
+/// The days of a forecast, a line each
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Forecast {
+    // When it was fetched, in ms
+    pub ts: u64,
+    pub days: Vec<Day>,
+}
+
+impl fmt::Display for Forecast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days: Vec<String> = self.days.iter().map(Day::to_string).collect();
+        write!(f, "{}", days.join("\n"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    daily: Daily,
+}
+
+// Open-Meteo sends a list per variable, nulls where a value is missing
+#[derive(Deserialize)]
+struct Daily {
+    time: Vec<String>,
+    weather_code: Vec<Option<u32>>,
+    temperature_2m_min: Vec<Option<f32>>,
+    temperature_2m_max: Vec<Option<f32>>,
+    precipitation_sum: Vec<Option<f32>>,
+    wind_speed_10m_max: Vec<Option<f32>>,
+}
+
+impl Forecast {
+    /// From the JSON of the Open-Meteo daily forecast, leaving out the days
+    /// with missing values
+    fn parse(json: &str, ts: u64) -> Result<Self> {
+        let daily = serde_json::from_str::<Response>(json)?.daily;
+        let days = daily
+            .time
+            .iter()
+            .enumerate()
+            .filter_map(|(n, date)| {
+                Some(Day {
+                    date: date.clone(),
+                    code: (*daily.weather_code.get(n)?)?,
+                    min: (*daily.temperature_2m_min.get(n)?)?,
+                    max: (*daily.temperature_2m_max.get(n)?)?,
+                    rain: (*daily.precipitation_sum.get(n)?).unwrap_or(0.0),
+                    wind: (*daily.wind_speed_10m_max.get(n)?)?,
+                })
+            })
+            .collect::<Vec<_>>();
+        if days.is_empty() {
+            bail!("Empty forecast");
+        }
+        Ok(Self { ts, days })
+    }
+
+    /// The last forecast fetched, None before the first one
+    pub fn load(storage: &Storage) -> Result<Option<Self>> {
+        match storage.get_setting(SETTING)? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn store(&self, storage: &Storage) -> Result<()> {
+        storage.set_setting(SETTING, &serde_json::to_string(self)?)
+    }
+}
+
+/// Short text for the WMO weather code
+fn describe(code: u32) -> &'static str {
+    match code {
+        0 => "Clear",
+        1 => "Mostly clear",
+        2 => "Partly cloudy",
+        3 => "Overcast",
+        45 | 48 => "Fog",
+        51 | 53 | 55 => "Drizzle",
+        56 | 57 | 66 | 67 => "Freezing rain",
+        61 | 63 | 65 => "Rain",
+        71 | 73 | 75 | 77 => "Snow",
+        80..=82 => "Showers",
+        85 | 86 => "Snow showers",
+        95..=99 => "Storm",
+        _ => "?",
+    }
+}
+
+/// `lat,lon` in degrees
+pub fn parse_location(s: &str) -> Result<(f64, f64)> {
+    let (lat, lon) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow!("Expected <lat>,<lon>: {s}"))?;
+    let (lat, lon): (f64, f64) = (lat.trim().parse()?, lon.trim().parse()?);
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        bail!("Invalid location {s}");
+    }
+    Ok((lat, lon))
+}
+
+/// The text with the forecast in place of [PLACEHOLDER], None if it has
+/// the placeholder and there is no forecast yet
+pub fn expand(text: &str, forecast: Option<&Forecast>) -> Option<String> {
+    if !text.contains(PLACEHOLDER) {
+        return Some(text.to_string());
+    }
+    forecast.map(|forecast| text.replace(PLACEHOLDER, &forecast.to_string()))
+}
+
+async fn fetch(client: &reqwest::Client, (lat, lon): (f64, f64), days: u32) -> Result<Forecast> {
+    let json = client
+        .get(URL)
+        .query(&[
+            ("latitude", lat.to_string()),
+            ("longitude", lon.to_string()),
+            ("daily", DAILY.join(",")),
+            ("timezone", "auto".to_string()),
+            ("forecast_days", days.to_string()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    Forecast::parse(&json, ts)
+}
+
+/// Fetches the forecast of `days` days for the location every `every`, and
+/// sooner again when it fails. Runs until the receiver is gone.
+pub async fn run_updater(
+    location: (f64, f64),
+    days: u32,
+    every: Duration,
+    forecast_tx: UnboundedSender<Forecast>,
+) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    info!(
+        "Weather for {},{} every {:?}",
+        location.0, location.1, every
+    );
+    loop {
+        let delay = match fetch(&client, location, days).await {
+            Ok(forecast) => {
+                if forecast_tx.send(forecast).is_err() {
+                    return Ok(());
+                }
+                every
+            }
+            Err(err) => {
+                warn!("Cannot fetch the weather: {err}");
+                RETRY_DELAY.min(every)
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// The `weather` command, answering with the last forecast fetched
+pub struct Weather;
+
+impl BbsCommand for Weather {
+    fn name(&self) -> &str {
+        "weather"
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &["wx"]
+    }
+
+    fn help(&self) -> String {
+        "weather".into()
+    }
+
+    fn handle(&self, ctx: &Context, _args: &[&str]) -> Result<Vec<String>> {
+        let Some(forecast) = Forecast::load(ctx.storage)? else {
+            return Ok(vec!["No forecast yet".into()]);
+        };
+        let age = crate::bbs::service::format_age(ctx.now.saturating_sub(forecast.ts));
+        Ok(vec![format!("{forecast}\n({age} ago)")])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const JSON: &str = r#"{"latitude":41.4,"longitude":2.2,"daily_units":{},"daily":{
+        "time":["2025-06-01","2025-06-02","2025-06-03"],
+        "weather_code":[61,1,null],
+        "temperature_2m_min":[14.2,15.0,16.1],
+        "temperature_2m_max":[21.6,25.4,27.0],
+        "precipitation_sum":[4.8,0.0,0.0],
+        "wind_speed_10m_max":[22.3,10.1,9.0]}}"#;
+
+    #[test]
+    fn test_forecast() -> Result<()> {
+        let forecast = Forecast::parse(JSON, 1000)?;
+        assert_eq!(forecast.days.len(), 2);
+        assert_eq!(
+            forecast.to_string(),
+            "Sun 01 Rain 14..22C 5mm wind 22km/h\nMon 02 Mostly clear 15..25C wind 10km/h"
+        );
+        let empty = JSON.replace("[61,1,null]", "[null,null,null]");
+        assert!(Forecast::parse(&empty, 0).is_err());
+
+        let storage = Storage::memory();
+        let ctx = Context {
+            sender: &Default::default(),
+            now: 3_601_000,
+            uptime: Duration::ZERO,
+            storage: &storage,
+        };
+        assert_eq!(Weather.handle(&ctx, &[])?, vec!["No forecast yet"]);
+        assert_eq!(expand("Today {weather}", None), None);
+        assert_eq!(expand("Hi", None).as_deref(), Some("Hi"));
+        forecast.store(&storage)?;
+        assert_eq!(Forecast::load(&storage)?.as_ref(), Some(&forecast));
+        assert!(Weather.handle(&ctx, &[])?[0].ends_with("wind 10km/h\n(1h ago)"));
+        assert_eq!(
+            expand("Today {weather}", Some(&forecast)).unwrap(),
+            format!("Today {forecast}")
+        );
+
+        assert_eq!(parse_location("41.39, 2.17")?, (41.39, 2.17));
+        assert!(parse_location("41.39").is_err());
+        assert!(parse_location("91,0").is_err());
+        Ok(())
+    }
+}